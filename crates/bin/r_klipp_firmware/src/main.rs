@@ -3,7 +3,7 @@
 
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
-use r_klipp_api::{HostToMcu, McuToHost, LinkHealth};
+use r_klipp_api::{frame_header, HostFrame, HostToMcu, LinkHealth, McuToHost, Response, Telemetry};
 use r_klipp_api::hal::SerialLink; // Assuming a mock implementation for now
 
 struct MockSerialLink;
@@ -24,41 +24,100 @@ async fn main(spawner: Spawner) {
     spawner.spawn(link_monitor(MockSerialLink)).unwrap();
 }
 
+/// Largest COBS frame accepted from the host; longer frames are dropped.
+const MAX_FRAME: usize = 256;
+
 #[embassy_executor::task]
 async fn link_monitor(mut serial: impl SerialLink + 'static) {
-    let mut last_sync_time = 0;
-    let mut rtt_us = 0;
+    let mut link = HostLink::default();
+    let mut frame: heapless::Vec<u8, MAX_FRAME> = heapless::Vec::new();
+    let mut overflowed = false;
 
     loop {
         let mut buf = [0u8; 128];
         if let Ok(len) = embassy_time::with_timeout(Duration::from_millis(1), serial.recv(&mut buf)).await {
-            if len > 0 {
-                if let Ok(msg) = postcard::from_bytes::<HostToMcu>(&buf[..len]) {
-                    if let HostToMcu::SyncClock(host_time) = msg {
-                        last_sync_time = embassy_time::Instant::now().as_micros() as u64;
-                        rtt_us = (last_sync_time - host_time) as u32;
-                    }
+            // Frames are COBS-encoded and end with a zero byte.
+            for &byte in &buf[..len] {
+                if byte != 0 {
+                    overflowed |= frame.push(byte).is_err();
+                    continue;
+                }
+                if !overflowed && !frame.is_empty() {
+                    link.handle_frame(&mut frame, &mut serial).await;
                 }
+                frame.clear();
+                overflowed = false;
             }
         }
 
         let link_health = LinkHealth {
-            rtt_us,
+            rtt_us: link.rtt_us,
             buffer_fill_percent: 50, // Mock value
             dropped_packets: 0, // Mock value
         };
 
-        let telemetry = McuToHost::Telemetry {
-            pos: [0.0; 6], // Mock
-            temps: [0.0; 4], // Mock
+        let telemetry = McuToHost::Telemetry(Telemetry {
+            nozzle_temp: 0.0, // Mock
+            bed_temp: 0.0,
+            nozzle_target: 0.0,
+            bed_target: 0.0,
+            nozzle_pwm: 0.0,
+            bed_pwm: 0.0,
+            x_pos: 0.0,
+            y_pos: 0.0,
+            z_pos: 0.0,
             link_health,
-        };
+        });
+        send(&mut serial, &telemetry).await;
+
+        Timer::after(Duration::from_millis(100)).await;
+    }
+}
+
+async fn send(serial: &mut impl SerialLink, msg: &McuToHost) {
+    let mut send_buf = [0u8; 128];
+    if let Ok(encoded) = postcard::to_slice_cobs(msg, &mut send_buf) {
+        serial.send(encoded).await;
+    }
+}
+
+/// The host end of the link. The host numbers the frames of a session consecutively from 0
+/// and resends the unacknowledged ones after a reconnect, so a frame of `session` before
+/// `next_seq` has already run.
+#[derive(Default)]
+struct HostLink {
+    session: Option<u32>,
+    next_seq: u32,
+    rtt_us: u32,
+}
 
-        let mut send_buf = [0u8; 128];
-        if let Ok(encoded) = postcard::to_slice(&telemetry, &mut send_buf) {
-            serial.send(encoded).await;
+impl HostLink {
+    /// Runs a frame unless it is a repeat, then acknowledges it. A frame whose message
+    /// cannot be decoded is reported and acknowledged anyway, so the host's window of
+    /// unacknowledged frames never fills up with it.
+    async fn handle_frame(&mut self, frame: &mut [u8], serial: &mut impl SerialLink) {
+        let Ok(len) = cobs::decode_in_place(frame) else { return };
+        let Some((session, seq)) = frame_header(&frame[..len]) else { return };
+
+        let repeat = self.session == Some(session) && (seq.wrapping_sub(self.next_seq) as i32) < 0;
+        if !repeat {
+            self.session = Some(session);
+            self.next_seq = seq.wrapping_add(1);
+            match postcard::from_bytes::<HostFrame>(&frame[..len]) {
+                Ok(HostFrame { msg, .. }) => self.run(msg),
+                Err(_) => {
+                    let error = heapless::String::try_from("Unsupported message").unwrap_or_default();
+                    send(serial, &McuToHost::Error(error)).await;
+                }
+            }
         }
+        send(serial, &McuToHost::Response { seq, response: Response::Ok }).await;
+    }
 
-        Timer::after(Duration::from_millis(100)).await;
+    fn run(&mut self, msg: HostToMcu) {
+        if let HostToMcu::SyncClock(host_time) = msg {
+            let now = embassy_time::Instant::now().as_micros() as u64;
+            self.rtt_us = (now - host_time) as u32;
+        }
     }
 }
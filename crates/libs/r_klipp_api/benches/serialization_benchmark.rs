@@ -1,19 +1,25 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use r_klipp_api::{LinkHealth, McuToHost};
+use r_klipp_api::{LinkHealth, McuToHost, Telemetry};
 use postcard::to_vec;
 use heapless::Vec;
 
 fn benchmark_telemetry_serialization(c: &mut Criterion) {
-    let telemetry = McuToHost::Telemetry {
-        pos: [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
-        temps: [25.0, 26.0, 27.0, 28.0],
-        currents: [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8],
+    let telemetry = McuToHost::Telemetry(Telemetry {
+        nozzle_temp: 215.0,
+        bed_temp: 60.0,
+        nozzle_target: 215.0,
+        bed_target: 60.0,
+        nozzle_pwm: 0.4,
+        bed_pwm: 0.7,
+        x_pos: 1.0,
+        y_pos: 2.0,
+        z_pos: 3.0,
         link_health: LinkHealth {
             rtt_us: 1500,
             buffer_fill_percent: 75,
             dropped_packets: 1,
         },
-    };
+    });
 
     c.bench_function("telemetry_serialization", |b| {
         b.iter(|| {
@@ -1,7 +1,6 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use heapless;
-use postcard;
 
 // Re-export postcard for convenience
 pub use postcard;
@@ -33,6 +32,24 @@ pub enum HostToMcu {
     SyncClock(u64),
 }
 
+/// Envelope for every `HostToMcu` message. `session` and `seq` come first so the MCU can
+/// read them with [`frame_header`] even from a message it cannot decode. Each frame is
+/// answered with a `McuToHost::Response` carrying its `seq`; the host resends
+/// unacknowledged frames after a reconnect, so a `seq` that already ran in the same
+/// `session` is acknowledged again but not run twice. A host that starts over picks a new
+/// `session` and numbers its frames from 0 again.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct HostFrame {
+    pub session: u32,
+    pub seq: u32,
+    pub msg: HostToMcu,
+}
+
+/// The session and sequence number of a decoded (not COBS-encoded) `HostFrame`.
+pub fn frame_header(frame: &[u8]) -> Option<(u32, u32)> {
+    postcard::take_from_bytes::<(u32, u32)>(frame).ok().map(|(header, _)| header)
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
 pub struct LinkHealth {
     pub rtt_us: u32,
@@ -41,21 +58,47 @@ pub struct LinkHealth {
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+/// Sent as its variant index, so new codes are appended and never inserted.
 pub enum FaultCode {
     Unknown,
     ThermalRunaway,
     BufferStarvation,
     GcodeError,
+    EmergencyStop,
+}
+
+/// Sent periodically. The host meters its frames by `link_health.buffer_fill_percent`,
+/// so telemetry must keep coming while frames are queued.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct Telemetry {
+    pub nozzle_temp: f32,
+    pub bed_temp: f32,
+    pub nozzle_target: f32,
+    pub bed_target: f32,
+    /// Heater PWM duty cycle from 0.0 to 1.0.
+    pub nozzle_pwm: f32,
+    pub bed_pwm: f32,
+    pub x_pos: f32,
+    pub y_pos: f32,
+    pub z_pos: f32,
+    pub link_health: LinkHealth,
+}
+
+/// Longest text the MCU sends in a `Response::Value` or `McuToHost::Error`.
+pub const MAX_TEXT: usize = 64;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub enum Response {
+    Ok,
+    Value(heapless::String<MAX_TEXT>),
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
 pub enum McuToHost {
-    Telemetry {
-        pos: [f32; 6],
-        temps: [f32; 4],
-        link_health: LinkHealth,
-    },
-    Ack,
+    Telemetry(Telemetry),
+    /// Acknowledges the `HostFrame` with sequence number `seq`.
+    Response { seq: u32, response: Response },
+    Error(heapless::String<MAX_TEXT>),
     Fault(FaultCode),
 }
 
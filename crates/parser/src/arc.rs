@@ -0,0 +1,561 @@
+//! G2/G3 arc geometry, and conversion of arcs into straight segments for the planners.
+//!
+//! Arcs lie in the plane selected by G17/G18/G19, with optional helical travel along the
+//! plane normal and extra turns from `P`. All positions are absolute.
+//!
+//! The dialects only take straight moves: `ArcLinearizer` turns each arc into G1 chords
+//! just before the dialect sees it.
+
+use core::f32::consts::{FRAC_PI_2, TAU};
+use core::fmt;
+use libm::{acosf, atan2f, ceilf, copysignf, cosf, fabsf, hypotf, sinf, sqrtf, truncf};
+
+use crate::ast::AstNode;
+use crate::coordinates::{Position, AXES};
+use crate::modal::{FeedrateMode, ModalState, Plane};
+
+/// How far the end point may sit off the circle through the start point, in program units,
+/// before an arc is rejected. Added to `RADIUS_TOLERANCE_RELATIVE` times the radius.
+pub const RADIUS_TOLERANCE: f32 = 0.005;
+const RADIUS_TOLERANCE_RELATIVE: f32 = 0.001;
+/// Start and end angles closer than this describe a full circle.
+const FULL_CIRCLE_EPSILON: f32 = 1e-5;
+/// Longest segment whatever the chord tolerance, so a coarse tolerance still keeps four
+/// segments per turn.
+const MAX_SEGMENT_ANGLE: f32 = FRAC_PI_2;
+
+/// Chord tolerance `ArcLinearizer` starts with, in millimetres.
+pub const DEFAULT_CHORD_TOLERANCE: f32 = 0.01;
+
+/// Centre-offset word for each axis.
+const OFFSET_WORDS: [char; 3] = ['I', 'J', 'K'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArcGeometry {
+    /// `I`/`J`/`K` offsets from the start point to the centre. Missing words are 0.
+    Centre { i: Option<f32>, j: Option<f32>, k: Option<f32> },
+    /// `R`. A negative radius selects the arc longer than half a turn.
+    Radius(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArcError {
+    /// The node is not a G2/G3 move.
+    NotAnArc,
+    /// Neither `R` nor any of `I`/`J`/`K` was given.
+    MissingGeometry,
+    /// `R` was given together with `I`/`J`/`K`.
+    AmbiguousGeometry,
+    /// A centre offset along the plane normal, e.g. `K` in G17.
+    OffsetOutOfPlane(char),
+    ZeroRadius,
+    /// `R` is shorter than half the distance to the end point.
+    RadiusTooSmall { radius: f32, half_chord: f32 },
+    /// `R` with the end point at the start point, where any centre on the circle would do.
+    RadiusFullCircle,
+    /// The end point is not on the circle through the start point around the centre.
+    RadiusMismatch { start: f32, end: f32 },
+    /// `P` is not a whole number of turns of at least 1.
+    InvalidTurns(f32),
+    InvalidTolerance(f32),
+}
+
+impl fmt::Display for ArcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArcError::NotAnArc => write!(f, "not a G2/G3 arc"),
+            ArcError::MissingGeometry => write!(f, "arc needs R or I/J/K centre offsets"),
+            ArcError::AmbiguousGeometry => write!(f, "arc cannot have both R and I/J/K centre offsets"),
+            ArcError::OffsetOutOfPlane(word) => write!(f, "{} offset is not in the selected arc plane", word),
+            ArcError::ZeroRadius => write!(f, "arc radius is zero"),
+            ArcError::RadiusTooSmall { radius, half_chord } => {
+                write!(f, "arc radius {} is shorter than half the distance to the end point ({})", radius, half_chord)
+            }
+            ArcError::RadiusFullCircle => write!(f, "full circle with R is ambiguous, use I/J/K"),
+            ArcError::RadiusMismatch { start, end } => {
+                write!(f, "arc end point is {} from the centre but the start point is {}", end, start)
+            }
+            ArcError::InvalidTurns(p) => write!(f, "arc turns P{} must be a whole number of at least 1", p),
+            ArcError::InvalidTolerance(t) => write!(f, "chord tolerance {} must be positive", t),
+        }
+    }
+}
+
+/// Axes of the plane, ordered so that positive rotation is counter-clockwise when seen from
+/// the positive plane normal, and the normal (helical) axis.
+pub(crate) fn plane_axes(plane: Plane) -> [usize; 3] {
+    match plane {
+        Plane::XY => [0, 1, 2],
+        Plane::XZ => [2, 0, 1],
+        Plane::YZ => [1, 2, 0],
+    }
+}
+
+fn radius_tolerance(radius: f32) -> f32 {
+    RADIUS_TOLERANCE + RADIUS_TOLERANCE_RELATIVE * radius
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arc {
+    start: [f32; 3],
+    end: [f32; 3],
+    axes: [usize; 3],
+    /// Centre in plane coordinates.
+    centre: [f32; 2],
+    radius: f32,
+    start_angle: f32,
+    /// Signed angle swept, positive counter-clockwise, including extra turns.
+    travel: f32,
+}
+
+impl Arc {
+    pub fn new(
+        start: [f32; 3],
+        end: [f32; 3],
+        geometry: ArcGeometry,
+        plane: Plane,
+        clockwise: bool,
+        turns: Option<f32>,
+    ) -> Result<Self, ArcError> {
+        let turns = match turns {
+            None => 1.0,
+            Some(p) if p >= 1.0 && truncf(p) == p => p,
+            Some(p) => return Err(ArcError::InvalidTurns(p)),
+        };
+        let axes = plane_axes(plane);
+        let [u, v, normal] = axes;
+        let (su, sv, eu, ev) = (start[u], start[v], end[u], end[v]);
+
+        let centre = match geometry {
+            ArcGeometry::Centre { i, j, k } => {
+                let offsets = [i, j, k];
+                if offsets[normal].is_some() {
+                    return Err(ArcError::OffsetOutOfPlane(OFFSET_WORDS[normal]));
+                }
+                let centre = [su + offsets[u].unwrap_or(0.0), sv + offsets[v].unwrap_or(0.0)];
+                let start_radius = hypotf(su - centre[0], sv - centre[1]);
+                let end_radius = hypotf(eu - centre[0], ev - centre[1]);
+                if start_radius <= f32::EPSILON {
+                    return Err(ArcError::ZeroRadius);
+                }
+                if fabsf(end_radius - start_radius) > radius_tolerance(start_radius) {
+                    return Err(ArcError::RadiusMismatch { start: start_radius, end: end_radius });
+                }
+                centre
+            }
+            ArcGeometry::Radius(r) => {
+                let radius = fabsf(r);
+                if radius <= f32::EPSILON {
+                    return Err(ArcError::ZeroRadius);
+                }
+                let (du, dv) = (eu - su, ev - sv);
+                let chord = hypotf(du, dv);
+                if chord <= f32::EPSILON {
+                    return Err(ArcError::RadiusFullCircle);
+                }
+                let half_chord = chord / 2.0;
+                if radius < half_chord - radius_tolerance(radius) {
+                    return Err(ArcError::RadiusTooSmall { radius, half_chord });
+                }
+                // The centre sits on the chord's perpendicular bisector: left of the chord for
+                // a short counter-clockwise arc, right for a short clockwise one, and the other
+                // side for the long arc.
+                let height = sqrtf((radius * radius - half_chord * half_chord).max(0.0));
+                let side = if clockwise == (r < 0.0) { 1.0 } else { -1.0 };
+                [
+                    su + du / 2.0 - side * height * dv / chord,
+                    sv + dv / 2.0 + side * height * du / chord,
+                ]
+            }
+        };
+
+        let radius = hypotf(su - centre[0], sv - centre[1]);
+        let start_angle = atan2f(sv - centre[1], su - centre[0]);
+        let end_angle = atan2f(ev - centre[1], eu - centre[0]);
+        let mut travel = end_angle - start_angle;
+        if clockwise {
+            if travel >= -FULL_CIRCLE_EPSILON {
+                travel -= TAU;
+            }
+        } else if travel <= FULL_CIRCLE_EPSILON {
+            travel += TAU;
+        }
+        travel += copysignf(TAU * (turns - 1.0), travel);
+
+        Ok(Self { start, end, axes, centre, radius, start_angle, travel })
+    }
+
+    /// The arc of a G2/G3 node starting at `start`; axes the node leaves out stay put.
+    pub fn from_node(node: &AstNode, start: [f32; 3]) -> Result<Self, ArcError> {
+        let AstNode::ArcMove { clockwise, plane, x, y, z, i, j, k, r, turns, .. } = *node else {
+            return Err(ArcError::NotAnArc);
+        };
+        let has_offsets = i.is_some() || j.is_some() || k.is_some();
+        let geometry = match r {
+            Some(_) if has_offsets => return Err(ArcError::AmbiguousGeometry),
+            Some(r) => ArcGeometry::Radius(r),
+            None if has_offsets => ArcGeometry::Centre { i, j, k },
+            None => return Err(ArcError::MissingGeometry),
+        };
+        let end = [x.unwrap_or(start[0]), y.unwrap_or(start[1]), z.unwrap_or(start[2])];
+        Self::new(start, end, geometry, plane, clockwise, turns)
+    }
+
+    pub fn start(&self) -> [f32; 3] {
+        self.start
+    }
+
+    pub fn end(&self) -> [f32; 3] {
+        self.end
+    }
+
+    pub fn centre(&self) -> [f32; 3] {
+        let [u, v, _] = self.axes;
+        let mut centre = self.start;
+        centre[u] = self.centre[0];
+        centre[v] = self.centre[1];
+        centre
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Signed angle swept in radians, positive counter-clockwise.
+    pub fn travel(&self) -> f32 {
+        self.travel
+    }
+
+    /// Path length, including helical travel.
+    pub fn length(&self) -> f32 {
+        let normal = self.axes[2];
+        hypotf(self.radius * fabsf(self.travel), self.end[normal] - self.start[normal])
+    }
+
+    /// The arc as chords that stray at most `tolerance` from it.
+    pub fn segments(&self, tolerance: f32) -> Result<ArcSegments, ArcError> {
+        if tolerance.is_nan() || tolerance <= 0.0 {
+            return Err(ArcError::InvalidTolerance(tolerance));
+        }
+        // A chord spanning angle `a` sits `r * (1 - cos(a / 2))` inside the arc at its middle.
+        let max_angle = if tolerance < self.radius {
+            (2.0 * acosf(1.0 - tolerance / self.radius)).min(MAX_SEGMENT_ANGLE)
+        } else {
+            MAX_SEGMENT_ANGLE
+        };
+        let count = (ceilf(fabsf(self.travel) / max_angle) as u32).max(1);
+        Ok(ArcSegments { arc: *self, count, index: 0 })
+    }
+}
+
+/// End point of one chord, and how far along the arc it is, from 0 to 1, for interpolating
+/// extrusion and other axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArcPoint {
+    pub position: [f32; 3],
+    pub fraction: f32,
+}
+
+/// The chord end points of an arc, in order, ending exactly on the arc's end point.
+#[derive(Debug, Clone)]
+pub struct ArcSegments {
+    arc: Arc,
+    count: u32,
+    index: u32,
+}
+
+impl Iterator for ArcSegments {
+    type Item = ArcPoint;
+
+    fn next(&mut self) -> Option<ArcPoint> {
+        if self.index == self.count {
+            return None;
+        }
+        self.index += 1;
+        let arc = &self.arc;
+        if self.index == self.count {
+            return Some(ArcPoint { position: arc.end, fraction: 1.0 });
+        }
+        let fraction = self.index as f32 / self.count as f32;
+        let angle = arc.start_angle + arc.travel * fraction;
+        let [u, v, normal] = arc.axes;
+        let mut position = arc.start;
+        position[u] = arc.centre[0] + arc.radius * cosf(angle);
+        position[v] = arc.centre[1] + arc.radius * sinf(angle);
+        position[normal] = arc.start[normal] + (arc.end[normal] - arc.start[normal]) * fraction;
+        Some(ArcPoint { position, fraction })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.count - self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for ArcSegments {}
+
+/// Turns G2/G3 nodes into G1 chords, following where each move ends since an arc only
+/// gives its end point.
+#[derive(Debug, Clone)]
+pub struct ArcLinearizer {
+    tolerance: f32,
+    position: Position,
+}
+
+impl ArcLinearizer {
+    pub fn new(tolerance: f32) -> Self {
+        Self { tolerance, position: [0.0; AXES] }
+    }
+
+    pub fn tolerance(&self) -> f32 {
+        self.tolerance
+    }
+
+    /// How far a chord may stray from its arc, in millimetres. Arcs fail with
+    /// `ArcError::InvalidTolerance` unless it is positive.
+    pub fn set_tolerance(&mut self, tolerance: f32) {
+        self.tolerance = tolerance;
+    }
+
+    /// Sets the machine position, e.g. after homing.
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
+    /// The chords of an arc, or any other node as it is. Takes resolved nodes, in the
+    /// order they run.
+    pub fn linearize(&mut self, node: AstNode, state: &ModalState) -> Result<Chords, ArcError> {
+        let start = self.position;
+        let AstNode::ArcMove { x, y, z, a, b, c, e, feedrate, spindle_speed, .. } = node else {
+            if let AstNode::RapidMove { x, y, z, a, b, c, e } | AstNode::LinearMove { x, y, z, a, b, c, e, .. } = node {
+                self.position = end_of([x, y, z, a, b, c, e], start);
+            }
+            return Ok(Chords::Node(Some(node)));
+        };
+        let segments = Arc::from_node(&node, [start[0], start[1], start[2]])?.segments(self.tolerance)?;
+        let end = end_of([x, y, z, a, b, c, e], start);
+        self.position = end;
+        // In G93 `F` is moves per minute, so each chord gets its share of the arc's time.
+        let feedrate = match state.feedrate_mode {
+            FeedrateMode::InverseTime => feedrate.or(state.feedrate).map(|f| f * segments.len() as f32),
+            _ => feedrate,
+        };
+        Ok(Chords::Arc { segments, start, end, feedrate, spindle_speed })
+    }
+}
+
+impl Default for ArcLinearizer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHORD_TOLERANCE)
+    }
+}
+
+fn end_of(axes: [Option<f32>; AXES], start: Position) -> Position {
+    core::array::from_fn(|axis| axes[axis].unwrap_or(start[axis]))
+}
+
+/// What `ArcLinearizer::linearize` makes of one node.
+pub enum Chords {
+    Node(Option<AstNode>),
+    /// G1 moves along the arc; axes outside the arc, such as `E`, move in step with it.
+    Arc {
+        segments: ArcSegments,
+        start: Position,
+        end: Position,
+        feedrate: Option<f32>,
+        spindle_speed: Option<f32>,
+    },
+}
+
+impl Iterator for Chords {
+    type Item = AstNode;
+
+    fn next(&mut self) -> Option<AstNode> {
+        match self {
+            Chords::Node(node) => node.take(),
+            Chords::Arc { segments, start, end, feedrate, spindle_speed } => {
+                let point = segments.next()?;
+                let mut target = if point.fraction == 1.0 {
+                    *end
+                } else {
+                    core::array::from_fn(|axis| start[axis] + (end[axis] - start[axis]) * point.fraction)
+                };
+                target[..3].copy_from_slice(&point.position);
+                let [x, y, z, a, b, c, e] = target.map(Some);
+                Some(AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: *feedrate, spindle_speed: *spindle_speed })
+            }
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use core::f32::consts::PI;
+
+    fn assert_point(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert_abs_diff_eq!(*a, e, epsilon = 1e-4);
+        }
+    }
+
+    fn centre(i: f32, j: f32) -> ArcGeometry {
+        ArcGeometry::Centre { i: Some(i), j: Some(j), k: None }
+    }
+
+    #[test]
+    fn quarter_circles_in_both_directions() {
+        let ccw = Arc::new([10.0, 0.0, 0.0], [0.0, 10.0, 0.0], centre(-10.0, 0.0), Plane::XY, false, None).unwrap();
+        assert_abs_diff_eq!(ccw.travel(), PI / 2.0, epsilon = 1e-5);
+        let cw = Arc::new([10.0, 0.0, 0.0], [0.0, 10.0, 0.0], centre(-10.0, 0.0), Plane::XY, true, None).unwrap();
+        assert_abs_diff_eq!(cw.travel(), -3.0 * PI / 2.0, epsilon = 1e-5);
+        assert_abs_diff_eq!(cw.length(), 15.0 * PI, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn radius_form_picks_the_short_or_long_arc() {
+        let start = [0.0, 0.0, 0.0];
+        let end = [10.0, 10.0, 0.0];
+        let short = Arc::new(start, end, ArcGeometry::Radius(10.0), Plane::XY, true, None).unwrap();
+        assert_point(short.centre(), [10.0, 0.0, 0.0]);
+        assert_abs_diff_eq!(short.travel(), -PI / 2.0, epsilon = 1e-5);
+        let long = Arc::new(start, end, ArcGeometry::Radius(-10.0), Plane::XY, true, None).unwrap();
+        assert_point(long.centre(), [0.0, 10.0, 0.0]);
+        assert_abs_diff_eq!(long.travel(), -3.0 * PI / 2.0, epsilon = 1e-5);
+        let ccw = Arc::new(start, end, ArcGeometry::Radius(10.0), Plane::XY, false, None).unwrap();
+        assert_point(ccw.centre(), [0.0, 10.0, 0.0]);
+    }
+
+    #[test]
+    fn planes_follow_the_right_hand_rule() {
+        // G18: seen from +Y, counter-clockwise runs from +Z towards +X.
+        let geometry = ArcGeometry::Centre { i: None, j: None, k: Some(-5.0) };
+        let xz = Arc::new([0.0, 0.0, 5.0], [5.0, 0.0, 0.0], geometry, Plane::XZ, false, None).unwrap();
+        assert_abs_diff_eq!(xz.travel(), PI / 2.0, epsilon = 1e-5);
+        let mid = xz.segments(0.01).unwrap().nth(1).unwrap().position;
+        assert_abs_diff_eq!(mid[1], 0.0);
+        assert!(mid[0] > 0.0 && mid[2] > 0.0);
+
+        let geometry = ArcGeometry::Centre { i: None, j: Some(-5.0), k: None };
+        let yz = Arc::new([0.0, 5.0, 0.0], [0.0, 0.0, 5.0], geometry, Plane::YZ, false, None).unwrap();
+        assert_abs_diff_eq!(yz.travel(), PI / 2.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn helical_multi_turn_arcs() {
+        let arc = Arc::new([5.0, 0.0, 0.0], [5.0, 0.0, -2.0], centre(-5.0, 0.0), Plane::XY, true, Some(2.0)).unwrap();
+        assert_abs_diff_eq!(arc.travel(), -2.0 * TAU, epsilon = 1e-4);
+        let points: heapless::Vec<ArcPoint, 256> = arc.segments(0.01).unwrap().collect();
+        // Halfway round the second turn's start the tool is back at X5, one millimetre down.
+        let half = points.iter().find(|p| p.fraction == 0.5).unwrap();
+        assert_abs_diff_eq!(half.position[0], 5.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(half.position[2], -1.0, epsilon = 1e-5);
+        assert_eq!(points.last().unwrap().position, [5.0, 0.0, -2.0]);
+    }
+
+    #[test]
+    fn chords_stay_within_tolerance() {
+        let arc = Arc::new([50.0, 0.0, 0.0], [-50.0, 0.0, 0.0], centre(-50.0, 0.0), Plane::XY, false, None).unwrap();
+        for tolerance in [0.001, 0.01, 0.1, 100.0] {
+            let segments = arc.segments(tolerance).unwrap();
+            let count = segments.len();
+            let mut previous = arc.start();
+            for point in segments {
+                let p = point.position;
+                let mid = [(previous[0] + p[0]) / 2.0, (previous[1] + p[1]) / 2.0];
+                assert!(50.0 - hypotf(mid[0], mid[1]) <= tolerance + 1e-4, "{} segments for {}", count, tolerance);
+                previous = p;
+            }
+        }
+        assert_eq!(arc.segments(100.0).unwrap().len(), 2, "at least four segments per turn");
+        assert_eq!(arc.segments(0.0).unwrap_err(), ArcError::InvalidTolerance(0.0));
+    }
+
+    #[test]
+    fn linearized_arcs_carry_the_other_axes_along() {
+        let half_circle = |x: f32, i: f32, e: f32, feedrate: Option<f32>| AstNode::ArcMove {
+            clockwise: false,
+            plane: Plane::XY,
+            x: Some(x),
+            y: Some(0.0),
+            z: Some(0.0),
+            a: Some(0.0),
+            b: Some(0.0),
+            c: Some(0.0),
+            e: Some(e),
+            i: Some(i),
+            j: None,
+            k: None,
+            r: None,
+            turns: None,
+            feedrate,
+            spindle_speed: None,
+        };
+        let mut arcs = ArcLinearizer::default();
+        let mut state = ModalState::default();
+        let [x, y, z, a, b, c, e] = [10.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0].map(Some);
+        let line = AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: Some(600.0), spindle_speed: None };
+        let nodes: heapless::Vec<AstNode, 1> = arcs.linearize(line, &state).unwrap().collect();
+        assert!(matches!(nodes[..], [AstNode::LinearMove { x: Some(x), feedrate: Some(f), .. }] if x == 10.0 && f == 600.0));
+
+        // Filament goes out evenly along the arc.
+        let chords: heapless::Vec<AstNode, 64> = arcs.linearize(half_circle(-10.0, -10.0, 2.0, None), &state).unwrap().collect();
+        for (n, chord) in chords.iter().enumerate() {
+            let AstNode::LinearMove { x: Some(x), y: Some(y), e: Some(e), feedrate: None, .. } = *chord else { panic!("{:?}", chord) };
+            assert_abs_diff_eq!(hypotf(x, y), 10.0, epsilon = 1e-4);
+            assert!(y >= 0.0);
+            assert_abs_diff_eq!(e, 2.0 * (n + 1) as f32 / chords.len() as f32, epsilon = 1e-5);
+        }
+        assert!(matches!(chords.last(), Some(AstNode::LinearMove { x: Some(x), e: Some(e), .. }) if *x == -10.0 && *e == 2.0));
+
+        // The next arc starts where that one ended. In G93 each chord takes its share of the time.
+        state.feedrate_mode = FeedrateMode::InverseTime;
+        let chords: heapless::Vec<AstNode, 64> = arcs.linearize(half_circle(10.0, 10.0, 2.0, Some(2.0)), &state).unwrap().collect();
+        let count = chords.len() as f32;
+        for chord in &chords {
+            let AstNode::LinearMove { y: Some(y), feedrate: Some(feedrate), .. } = *chord else { panic!("{:?}", chord) };
+            assert!(y <= 0.0);
+            assert_eq!(feedrate, 2.0 * count);
+        }
+    }
+
+    #[test]
+    fn invalid_arcs_are_rejected() {
+        let arc = |x: Option<f32>, i: Option<f32>, k: Option<f32>, r: Option<f32>, turns: Option<f32>| AstNode::ArcMove {
+            clockwise: true,
+            plane: Plane::XY,
+            x,
+            y: Some(0.0),
+            z: None,
+            a: None,
+            b: None,
+            c: None,
+            e: None,
+            i,
+            j: None,
+            k,
+            r,
+            turns,
+            feedrate: None,
+            spindle_speed: None,
+        };
+        let start = [0.0; 3];
+        let from = |node| Arc::from_node(&node, start).unwrap_err();
+        assert_eq!(from(arc(Some(10.0), None, None, None, None)), ArcError::MissingGeometry);
+        assert_eq!(from(arc(Some(10.0), Some(5.0), None, Some(5.0), None)), ArcError::AmbiguousGeometry);
+        assert_eq!(from(arc(Some(10.0), Some(5.0), Some(1.0), None, None)), ArcError::OffsetOutOfPlane('K'));
+        assert_eq!(from(arc(Some(10.0), None, None, Some(4.0), None)), ArcError::RadiusTooSmall { radius: 4.0, half_chord: 5.0 });
+        assert_eq!(from(arc(None, None, None, Some(4.0), None)), ArcError::RadiusFullCircle);
+        assert_eq!(from(arc(Some(10.0), None, None, Some(0.0), None)), ArcError::ZeroRadius);
+        assert_eq!(from(arc(Some(10.0), Some(0.0), None, None, None)), ArcError::ZeroRadius);
+        assert_eq!(from(arc(Some(12.0), Some(5.0), None, None, None)), ArcError::RadiusMismatch { start: 5.0, end: 7.0 });
+        assert_eq!(from(arc(Some(10.0), Some(5.0), None, None, Some(1.5))), ArcError::InvalidTurns(1.5));
+        assert_eq!(Arc::from_node(&AstNode::Dwell(1.0), start).unwrap_err(), ArcError::NotAnArc);
+
+        // A radius a hair short of the half chord, as CAM rounding produces, is a half circle.
+        let node = arc(Some(10.0), None, None, Some(4.999), None);
+        assert_abs_diff_eq!(Arc::from_node(&node, start).unwrap().travel(), -PI, epsilon = 1e-5);
+    }
+}
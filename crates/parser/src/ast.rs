@@ -1,5 +1,12 @@
+use crate::coordinates::MM_PER_INCH;
+use crate::cycles::{CycleWords, DrillingCycle};
 use crate::lexer::Token;
-use crate::modal::ModalState;
+use crate::modal::{
+    CompensationSide, CoordinateSystem, Cycle, CycleParameters, DistanceMode, FeedrateMode, ModalState, Motion, Plane,
+    RetractMode, Spindle, ToolDimension, Units,
+};
+use crate::tool_table::MAX_TOOLS;
+use core::fmt;
 use heapless::Vec;
 
 #[derive(Debug, PartialEq)]
@@ -13,6 +20,8 @@ pub enum AstNode {
         c: Option<f32>,
         e: Option<f32>,
         feedrate: Option<f32>,
+        /// The line's `S`: spindle speed, or laser power, from this move on.
+        spindle_speed: Option<f32>,
     },
     RapidMove {
         x: Option<f32>,
@@ -23,18 +32,255 @@ pub enum AstNode {
         c: Option<f32>,
         e: Option<f32>,
     },
+    /// G2 (clockwise) or G3 (counter-clockwise) in the active plane, given by `I`/`J`/`K`
+    /// centre offsets or an `R` radius. See `crate::arc` for the geometry.
     ArcMove {
-        // ...
+        clockwise: bool,
+        plane: Plane,
+        x: Option<f32>,
+        y: Option<f32>,
+        z: Option<f32>,
+        a: Option<f32>,
+        b: Option<f32>,
+        c: Option<f32>,
+        e: Option<f32>,
+        i: Option<f32>,
+        j: Option<f32>,
+        k: Option<f32>,
+        r: Option<f32>,
+        /// `P`: number of turns, 1 when absent.
+        turns: Option<f32>,
+        feedrate: Option<f32>,
+        spindle_speed: Option<f32>,
+    },
+    /// G53 with G0 (`rapid`) or G1: a move in machine coordinates, ignoring every offset.
+    MachineMove {
+        rapid: bool,
+        x: Option<f32>,
+        y: Option<f32>,
+        z: Option<f32>,
+        a: Option<f32>,
+        b: Option<f32>,
+        c: Option<f32>,
+        feedrate: Option<f32>,
+    },
+    /// G10 L2 sets a work offset outright; G10 L20 (`to_current_position`) sets it so the
+    /// current position reads as the given coordinates. `system` is `None` for `P0`, the
+    /// active one.
+    SetWorkOffset {
+        system: Option<CoordinateSystem>,
+        to_current_position: bool,
+        x: Option<f32>,
+        y: Option<f32>,
+        z: Option<f32>,
+        a: Option<f32>,
+        b: Option<f32>,
+        c: Option<f32>,
+    },
+    /// G92: shifts all work coordinates so the current position reads as given.
+    SetAxisOffset {
+        x: Option<f32>,
+        y: Option<f32>,
+        z: Option<f32>,
+        a: Option<f32>,
+        b: Option<f32>,
+        c: Option<f32>,
+        e: Option<f32>,
+    },
+    /// G92.1
+    ClearAxisOffset,
+    /// G73 or G81–G89 as written; `crate::coordinates` resolves it into a `DrillingCycle`.
+    CannedCycle(CycleWords),
+    DrillingCycle(DrillingCycle),
+    /// Feeds down to `z` in step with the spindle at `pitch` per revolution, reverses the
+    /// spindle and comes back out the same way.
+    RigidTap {
+        z: f32,
+        pitch: f32,
     },
     Dwell(f32),
     ToolChange(u16),
+    /// An `S` word on its own line.
     SpindleControl(f32),
+    /// M3 (clockwise) or M4, with the line's `S` if any.
+    SpindleOn {
+        clockwise: bool,
+        speed: Option<f32>,
+    },
+    /// M5
+    SpindleOff,
+    /// M0
+    ProgramPause,
+    /// G28: `x`, `y` and `z` are the axes to home, all three when the line names none.
+    Home {
+        x: bool,
+        y: bool,
+        z: bool,
+    },
+    /// G10 without `L` or `P`: firmware retraction. G11 (`Unretract`) undoes it.
+    Retract,
+    Unretract,
+    /// A `T` word on its own line. Printers switch extruders on it; a machine with a tool
+    /// changer waits for M6.
+    SelectTool(u16),
+    /// An M-code without a node of its own, such as M104 or M106, with the words a dialect
+    /// needs to read it. Values are as written.
+    Auxiliary {
+        code: u16,
+        s: Option<f32>,
+        p: Option<f32>,
+        r: Option<f32>,
+        t: Option<f32>,
+    },
     SetModalState(ModalState),
     Comment(heapless::String<64>),
 }
 
-pub fn parse_line<'a>(tokens: &Vec<Token<'a>, 64>, state: &mut ModalState) -> Result<Option<AstNode>, ()> {
-    let mut g_code = None;
+/// G81 to G89 in order.
+const CYCLES: [Cycle; 9] = [
+    Cycle::Drill,
+    Cycle::DwellDrill,
+    Cycle::PeckDrill,
+    Cycle::Tap,
+    Cycle::Bore,
+    Cycle::BoreStopRapidOut,
+    Cycle::BackBore,
+    Cycle::BoreManualOut,
+    Cycle::BoreDwell,
+];
+
+/// The G- or M-code an error is about.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Code {
+    G(u16, u8),
+    M(u16),
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Code::G(code, 0) => write!(f, "G{}", code),
+            Code::G(code, sub) => write!(f, "G{}.{}", code, sub),
+            Code::M(code) => write!(f, "M{}", code),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SyntaxErrorKind {
+    /// The code needs a word the line does not have.
+    MissingWord(char),
+    /// A word's value is out of range for the code, e.g. a tool number past the table.
+    InvalidValue(char, f32),
+    /// G41/G42 while compensation is already on.
+    CompensationActive,
+    /// Cutter compensation, taper and canned cycles only work in the XY plane (G17).
+    NotInXyPlane,
+    /// A canned cycle while G41/G42 is on.
+    CycleWithCompensation,
+    /// G53 on a line without G0 or G1.
+    MachineCoordinatesWithoutMove,
+    /// G84 without the spindle turning clockwise.
+    TapWithoutClockwiseSpindle,
+    /// G84 in G93, where the feedrate gives no pitch.
+    TapInInverseTime,
+}
+
+/// Why `parse_line` rejected a line, and the code on it the error is about, if any.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SyntaxError {
+    pub code: Option<Code>,
+    pub kind: SyntaxErrorKind,
+}
+
+impl fmt::Display for SyntaxErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxErrorKind::MissingWord(word) => write!(f, "{} word is missing", word),
+            SyntaxErrorKind::InvalidValue(word, value) => write!(f, "{}{} is out of range", word, value),
+            SyntaxErrorKind::CompensationActive => write!(f, "cutter compensation is already on, use G40 first"),
+            SyntaxErrorKind::NotInXyPlane => write!(f, "only works in the XY plane (G17)"),
+            SyntaxErrorKind::CycleWithCompensation => write!(f, "canned cycles cannot run with cutter compensation on"),
+            SyntaxErrorKind::MachineCoordinatesWithoutMove => write!(f, "needs G0 or G1 on the same line"),
+            SyntaxErrorKind::TapWithoutClockwiseSpindle => write!(f, "rigid tapping needs the spindle on clockwise (M3)"),
+            SyntaxErrorKind::TapInInverseTime => write!(f, "rigid tapping cannot use inverse time feed (G93)"),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{}: {}", code, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+fn error(code: Option<Code>, kind: SyntaxErrorKind) -> SyntaxError {
+    SyntaxError { code, kind }
+}
+
+/// `value` of `word` as a tool number.
+fn tool_number(code: Option<Code>, word: char, value: f32) -> Result<u16, SyntaxError> {
+    if value >= 0.0 && value < MAX_TOOLS as f32 && value == (value as u16) as f32 {
+        Ok(value as u16)
+    } else {
+        Err(error(code, SyntaxErrorKind::InvalidValue(word, value)))
+    }
+}
+
+/// The code of a canned cycle.
+fn cycle_code(cycle: Cycle) -> Code {
+    match CYCLES.iter().position(|&c| c == cycle) {
+        Some(index) => Code::G(81 + index as u16, 0),
+        None => Code::G(73, 0),
+    }
+}
+
+fn millimetres(value: f32, units: Units) -> f32 {
+    match units {
+        Units::Inches => value * MM_PER_INCH,
+        Units::Millimeters => value,
+    }
+}
+
+/// The nodes of one line, in the order they run: a G-code's and an M-code's at most.
+pub type Nodes = Vec<AstNode, 2>;
+
+/// The node for M-code `code`. Modal M-codes have already changed `state`.
+fn m_code_node(
+    code: u16,
+    state: &mut ModalState,
+    s: Option<f32>,
+    p: Option<f32>,
+    r: Option<f32>,
+    t: Option<f32>,
+) -> Result<AstNode, SyntaxError> {
+    Ok(match code {
+        0 => AstNode::ProgramPause,
+        3 | 4 => AstNode::SpindleOn { clockwise: code == 3, speed: s },
+        5 => AstNode::SpindleOff,
+        6 => {
+            state.tool = state.next_tool.ok_or(error(Some(Code::M(6)), SyntaxErrorKind::MissingWord('T')))?;
+            AstNode::ToolChange(state.tool)
+        }
+        82 | 83 => AstNode::SetModalState(*state),
+        _ => AstNode::Auxiliary { code, s, p, r, t },
+    })
+}
+
+/// Parses one line. Modal codes on the line update `state` before its command is read, as
+/// in `G91 G0 X5`. Axis words without a motion code repeat the last G0–G3 or canned cycle.
+/// An M-code beside a G-code runs before it, as in `G0 X5 S3500 M3`, unless it stops the
+/// program.
+pub fn parse_line<'a>(tokens: &Vec<Token<'a>, 64>, state: &mut ModalState) -> Result<Nodes, SyntaxError> {
+    let mut command = None;
+    let mut has_g = false;
+    let mut machine_coordinates = false;
+    let mut cutter_compensation = None;
+    let mut tool_length_offset = None;
+    let mut taper = None;
     let mut m_code = None;
     let mut x = None;
     let mut y = None;
@@ -43,15 +289,75 @@ pub fn parse_line<'a>(tokens: &Vec<Token<'a>, 64>, state: &mut ModalState) -> Re
     let mut b = None;
     let mut c = None;
     let mut e = None;
+    let mut i = None;
+    let mut j = None;
+    let mut k = None;
+    let mut r = None;
+    let mut l = None;
+    let mut q = None;
+    let mut d = None;
+    let mut h = None;
+    let mut t = None;
     let mut feedrate = None;
     let mut spindle_speed = None;
     let mut p_val = None;
     let mut comment = None;
 
     for token in tokens {
+        let code = match *token {
+            Token::G(code) => Some((code, 0)),
+            Token::GSub(code, sub) => Some((code, sub)),
+            _ => None,
+        };
+        if let Some(code) = code {
+            has_g = true;
+            match code {
+                (17, 0) => state.plane = Plane::XY,
+                (18, 0) => state.plane = Plane::XZ,
+                (19, 0) => state.plane = Plane::YZ,
+                (20, 0) => state.units = Units::Inches,
+                (21, 0) => state.units = Units::Millimeters,
+                (40, 0) | (41 | 42, 0 | 1) => cutter_compensation = Some(code),
+                (43, 0 | 1) | (49, 0) => tool_length_offset = Some(code),
+                (50..=52, 0) => taper = Some(code.0),
+                (53, 0) => machine_coordinates = true,
+                (80, 0) => {
+                    state.motion = None;
+                    state.cycle = CycleParameters::default();
+                }
+                (54..=59, 0) => state.coordinate_system = CoordinateSystem::ALL[usize::from(code.0 - 54)],
+                (59, 1..=3) => state.coordinate_system = CoordinateSystem::ALL[5 + usize::from(code.1)],
+                (90, 0) => {
+                    state.distance_mode = DistanceMode::Absolute;
+                    state.extrusion_mode = DistanceMode::Absolute;
+                }
+                (91, 0) => {
+                    state.distance_mode = DistanceMode::Relative;
+                    state.extrusion_mode = DistanceMode::Relative;
+                }
+                (90, 1) => state.arc_distance_mode = DistanceMode::Absolute,
+                (91, 1) => state.arc_distance_mode = DistanceMode::Relative,
+                (93, 0) => state.feedrate_mode = FeedrateMode::InverseTime,
+                (94, 0) => state.feedrate_mode = FeedrateMode::UnitsPerMinute,
+                (95, 0) => state.feedrate_mode = FeedrateMode::UnitsPerRevolution,
+                (98, 0) => state.retract_mode = RetractMode::InitialLevel,
+                (99, 0) => state.retract_mode = RetractMode::RPlane,
+                _ => command = Some(code),
+            }
+            continue;
+        }
         match *token {
-            Token::G(code) => g_code = Some(code),
-            Token::M(code) => m_code = Some(code),
+            Token::M(code) => {
+                m_code = Some(code);
+                match code {
+                    3 => state.spindle = Spindle::Clockwise,
+                    4 => state.spindle = Spindle::CounterClockwise,
+                    5 => state.spindle = Spindle::Off,
+                    82 => state.extrusion_mode = DistanceMode::Absolute,
+                    83 => state.extrusion_mode = DistanceMode::Relative,
+                    _ => {}
+                }
+            }
             Token::Axis(axis, value) => match axis {
                 'X' => x = Some(value),
                 'Y' => y = Some(value),
@@ -62,43 +368,283 @@ pub fn parse_line<'a>(tokens: &Vec<Token<'a>, 64>, state: &mut ModalState) -> Re
                 'E' => e = Some(value),
                 _ => {}
             },
-            Token::Feedrate(value) => feedrate = Some(value),
-            Token::SpindleSpeed(value) => spindle_speed = Some(value),
+            Token::ArcOffset(word, value) => match word {
+                'I' => i = Some(value),
+                'J' => j = Some(value),
+                'K' => k = Some(value),
+                _ => {}
+            },
+            Token::Radius(value) => r = Some(value),
+            Token::Feedrate(value) => {
+                feedrate = Some(value);
+                state.feedrate = feedrate;
+            }
+            Token::SpindleSpeed(value) => {
+                spindle_speed = Some(value);
+                state.spindle_speed = spindle_speed;
+            }
             Token::P(value) => p_val = Some(value),
+            Token::Word('L', value) => l = Some(value),
+            Token::Word('Q', value) => q = Some(value),
+            Token::Word('D', value) => d = Some(value),
+            Token::Word('H', value) => h = Some(value),
+            Token::Word('T', value) => t = Some(value),
             Token::Comment(text) => comment = Some(text),
-            Token::LineNumber(_) => {}
+            _ => {}
         }
     }
 
-    if let Some(text) = comment {
-        let mut s = heapless::String::new();
-        s.push_str(text).unwrap();
-        return Ok(Some(AstNode::Comment(s)));
+    // On other M-codes `T` names a heater or extruder, as in `M104 T1 S200`.
+    let tool = match (t, m_code) {
+        (Some(t), None | Some(6)) => Some(tool_number(m_code.map(Code::M), 'T', t)?),
+        _ => None,
+    };
+    if tool.is_some() {
+        state.next_tool = tool;
+    }
+    let m_node = match m_code {
+        Some(code) => Some(m_code_node(code, state, spindle_speed, p_val, r, t)?),
+        None => None,
+    };
+    // G41/G42 take the diameter of tool `D`, or of the tool in the spindle; G41.1/G42.1
+    // take `D` as the diameter.
+    if let Some(code) = cutter_compensation {
+        let at = Some(Code::G(code.0, code.1));
+        state.cutter_compensation = match code {
+            (40, _) => None,
+            (side, sub) => {
+                if state.cutter_compensation.is_some() {
+                    return Err(error(at, SyntaxErrorKind::CompensationActive));
+                }
+                if state.plane != Plane::XY {
+                    return Err(error(at, SyntaxErrorKind::NotInXyPlane));
+                }
+                let side = if side == 41 { CompensationSide::Left } else { CompensationSide::Right };
+                let diameter = match (sub, d) {
+                    (1, Some(d)) if d >= 0.0 => ToolDimension::Given(millimetres(d, state.units)),
+                    (1, Some(d)) => return Err(error(at, SyntaxErrorKind::InvalidValue('D', d))),
+                    (1, None) => return Err(error(at, SyntaxErrorKind::MissingWord('D'))),
+                    (_, Some(d)) => ToolDimension::Tool(tool_number(at, 'D', d)?),
+                    (_, None) => ToolDimension::Tool(state.tool),
+                };
+                Some((side, diameter))
+            }
+        };
+    }
+    // G43 takes the length of tool `H`, or of the tool in the spindle; G43.1 takes `Z` as
+    // the length.
+    if let Some(code) = tool_length_offset {
+        let at = Some(Code::G(code.0, code.1));
+        state.tool_length_offset = match code {
+            (49, _) => None,
+            (_, 1) => {
+                let length = z.take().ok_or(error(at, SyntaxErrorKind::MissingWord('Z')))?;
+                Some(ToolDimension::Given(millimetres(length, state.units)))
+            }
+            _ => Some(ToolDimension::Tool(h.map_or(Ok(state.tool), |h| tool_number(at, 'H', h))?)),
+        };
+    }
+
+    // G51 and G52 take `A` as the taper angle, in degrees.
+    if let Some(code) = taper {
+        let at = Some(Code::G(code, 0));
+        state.taper = match code {
+            50 => None,
+            side => {
+                if state.plane != Plane::XY {
+                    return Err(error(at, SyntaxErrorKind::NotInXyPlane));
+                }
+                let angle = match a.take() {
+                    Some(angle) if (0.0..45.0).contains(&angle) => angle,
+                    Some(angle) => return Err(error(at, SyntaxErrorKind::InvalidValue('A', angle))),
+                    None => return Err(error(at, SyntaxErrorKind::MissingWord('A'))),
+                };
+                let side = if side == 51 { CompensationSide::Left } else { CompensationSide::Right };
+                Some((side, angle))
+            }
+        };
     }
 
-    match g_code {
-        Some(0) => Ok(Some(AstNode::RapidMove { x, y, z, a, b, c, e })),
-        Some(1) => Ok(Some(AstNode::LinearMove { x, y, z, a, b, c, e, feedrate })),
-        Some(4) => {
-            if let Some(p) = p_val {
-                Ok(Some(AstNode::Dwell(p)))
-            } else {
-                Err(())
+    let g_node = 'g: {
+        let has_axes = [x, y, z, a, b, c, e].iter().any(Option::is_some);
+        let motion = match command {
+            Some((0, 0)) => Some(Motion::Rapid),
+            Some((1, 0)) => Some(Motion::Linear),
+            Some((2, 0)) => Some(Motion::ClockwiseArc),
+            Some((3, 0)) => Some(Motion::CounterClockwiseArc),
+            Some((73, 0)) => Some(Motion::Cycle(Cycle::ChipBreakDrill)),
+            Some((code @ 81..=89, 0)) => Some(Motion::Cycle(CYCLES[usize::from(code - 81)])),
+            Some(_) => None,
+            None if has_axes => state.motion,
+            None => None,
+        };
+        if let Some(motion) = motion {
+            state.motion = Some(motion);
+            if machine_coordinates {
+                break 'g match motion {
+                    Motion::Rapid | Motion::Linear => Some(AstNode::MachineMove {
+                        rapid: motion == Motion::Rapid,
+                        x,
+                        y,
+                        z,
+                        a,
+                        b,
+                        c,
+                        feedrate,
+                    }),
+                    _ => return Err(error(Some(Code::G(53, 0)), SyntaxErrorKind::MachineCoordinatesWithoutMove)),
+                };
             }
+            break 'g Some(match motion {
+                Motion::Rapid => AstNode::RapidMove { x, y, z, a, b, c, e },
+                Motion::Linear => AstNode::LinearMove { x, y, z, a, b, c, e, feedrate, spindle_speed },
+                Motion::ClockwiseArc | Motion::CounterClockwiseArc => AstNode::ArcMove {
+                    clockwise: motion == Motion::ClockwiseArc,
+                    plane: state.plane,
+                    x,
+                    y,
+                    z,
+                    a,
+                    b,
+                    c,
+                    e,
+                    i,
+                    j,
+                    k,
+                    r,
+                    turns: p_val,
+                    feedrate,
+                    spindle_speed,
+                },
+                Motion::Cycle(cycle) => {
+                    let at = Some(cycle_code(cycle));
+                    let fail = |kind| Err(error(at, kind));
+                    if state.plane != Plane::XY {
+                        return fail(SyntaxErrorKind::NotInXyPlane);
+                    }
+                    if state.cutter_compensation.is_some() {
+                        return fail(SyntaxErrorKind::CycleWithCompensation);
+                    }
+                    // Z, R, Q and P carry over to the following holes.
+                    let sticky = &mut state.cycle;
+                    sticky.z = z.or(sticky.z);
+                    sticky.r = r.or(sticky.r);
+                    sticky.q = q.or(sticky.q);
+                    sticky.p = p_val.or(sticky.p);
+                    let CycleParameters { z, r, q, p } = *sticky;
+                    let (Some(z), Some(r)) = (z, r) else {
+                        return fail(SyntaxErrorKind::MissingWord(if z.is_none() { 'Z' } else { 'R' }));
+                    };
+                    let pecks = matches!(cycle, Cycle::ChipBreakDrill | Cycle::PeckDrill);
+                    match q {
+                        Some(q) if pecks && q <= 0.0 => return fail(SyntaxErrorKind::InvalidValue('Q', q)),
+                        None if pecks => return fail(SyntaxErrorKind::MissingWord('Q')),
+                        _ => {}
+                    }
+                    let dwells = matches!(cycle, Cycle::DwellDrill | Cycle::BoreStopRapidOut | Cycle::BoreManualOut | Cycle::BoreDwell);
+                    if dwells && p.is_none() {
+                        return fail(SyntaxErrorKind::MissingWord('P'));
+                    }
+                    if cycle == Cycle::BackBore && k.is_none() {
+                        return fail(SyntaxErrorKind::MissingWord('K'));
+                    }
+                    let repeats = match l {
+                        None => 1,
+                        Some(l) if l >= 1.0 && l == (l as u32) as f32 => l as u32,
+                        Some(l) => return fail(SyntaxErrorKind::InvalidValue('L', l)),
+                    };
+                    let pitch = if cycle == Cycle::Tap {
+                        if state.spindle != Spindle::Clockwise {
+                            return fail(SyntaxErrorKind::TapWithoutClockwiseSpindle);
+                        }
+                        let Some(feed) = state.feedrate else { return fail(SyntaxErrorKind::MissingWord('F')) };
+                        match (state.feedrate_mode, state.spindle_speed) {
+                            (FeedrateMode::UnitsPerRevolution, _) => Some(feed),
+                            (FeedrateMode::UnitsPerMinute, Some(speed)) if speed > 0.0 => Some(feed / speed),
+                            (FeedrateMode::UnitsPerMinute, Some(speed)) => return fail(SyntaxErrorKind::InvalidValue('S', speed)),
+                            (FeedrateMode::UnitsPerMinute, None) => return fail(SyntaxErrorKind::MissingWord('S')),
+                            (FeedrateMode::InverseTime, _) => return fail(SyntaxErrorKind::TapInInverseTime),
+                        }
+                    } else {
+                        None
+                    };
+                    AstNode::CannedCycle(CycleWords {
+                        cycle,
+                        retract_mode: state.retract_mode,
+                        x,
+                        y,
+                        z,
+                        r,
+                        q,
+                        p,
+                        repeats,
+                        i,
+                        j,
+                        k,
+                        feedrate,
+                        pitch,
+                        spindle: state.spindle,
+                    })
+                }
+            });
         }
-        Some(17) => {
-            state.plane = crate::modal::Plane::XY;
-            Ok(Some(AstNode::SetModalState(*state)))
-        }
-        Some(18) => {
-            state.plane = crate::modal::Plane::XZ;
-            Ok(Some(AstNode::SetModalState(*state)))
+        if machine_coordinates {
+            return Err(error(Some(Code::G(53, 0)), SyntaxErrorKind::MachineCoordinatesWithoutMove));
         }
-        Some(19) => {
-            state.plane = crate::modal::Plane::YZ;
-            Ok(Some(AstNode::SetModalState(*state)))
+
+        let at = command.map(|(code, sub)| Code::G(code, sub));
+        match command {
+            Some((4, 0)) => {
+                let Some(p) = p_val else { return Err(error(at, SyntaxErrorKind::MissingWord('P'))) };
+                Some(AstNode::Dwell(p))
+            }
+            Some((10, 0)) if l.is_none() && p_val.is_none() => Some(AstNode::Retract),
+            Some((10, 0)) => {
+                let to_current_position = match l {
+                    Some(2.0) => false,
+                    Some(20.0) => true,
+                    Some(l) => return Err(error(at, SyntaxErrorKind::InvalidValue('L', l))),
+                    None => return Err(error(at, SyntaxErrorKind::MissingWord('L'))),
+                };
+                let system = match p_val {
+                    Some(0.0) => None,
+                    Some(p) if (1.0..=9.0).contains(&p) && p == (p as u8) as f32 => CoordinateSystem::from_index(p as usize - 1),
+                    Some(p) => return Err(error(at, SyntaxErrorKind::InvalidValue('P', p))),
+                    None => return Err(error(at, SyntaxErrorKind::MissingWord('P'))),
+                };
+                Some(AstNode::SetWorkOffset { system, to_current_position, x, y, z, a, b, c })
+            }
+            Some((11, 0)) => Some(AstNode::Unretract),
+            Some((28, 0)) => {
+                let all = x.is_none() && y.is_none() && z.is_none();
+                Some(AstNode::Home { x: all || x.is_some(), y: all || y.is_some(), z: all || z.is_some() })
+            }
+            Some((92, 0)) => Some(AstNode::SetAxisOffset { x, y, z, a, b, c, e }),
+            Some((92, 1)) => Some(AstNode::ClearAxisOffset),
+            // ... and so on for all G-codes
+            Some(_) => None,
+            // The M-code's node stands for the line.
+            None if m_node.is_some() => None,
+            None if tool.is_some() => tool.map(AstNode::SelectTool),
+            None if !has_g && spindle_speed.is_some() => spindle_speed.map(AstNode::SpindleControl),
+            None if has_g => Some(AstNode::SetModalState(*state)),
+            // A comment beside a command, like `G1 X10 (cut)`, annotates it; only a comment
+            // on its own becomes a node. Long comments are cut to fit.
+            None => comment.map(|text| {
+                let mut s = heapless::String::new();
+                for ch in text.chars() {
+                    if s.push(ch).is_err() {
+                        break;
+                    }
+                }
+                AstNode::Comment(s)
+            }),
         }
-        // ... and so on for all G-codes
-        _ => Ok(None),
-    }
+    };
+
+    let mut nodes = Nodes::new();
+    let stops = matches!(m_code, Some(0 | 1 | 2 | 30 | 60));
+    let order = if stops { [g_node, m_node] } else { [m_node, g_node] };
+    nodes.extend(order.into_iter().flatten());
+    Ok(nodes)
 }
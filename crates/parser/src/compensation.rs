@@ -0,0 +1,704 @@
+//! Cutter radius compensation (G41/G42/G40), applied to resolved moves in the XY plane.
+//!
+//! The tool runs parallel to the programmed path, a tool radius to its left (G41) or right
+//! (G42). Where two offset segments meet on the inside of a corner the tool stops where they
+//! cross; round the outside of a corner it rolls on an arc about the programmed corner,
+//! which `crate::arc::ArcLinearizer` cuts into chords like any other arc. A segment's end is
+//! only known once the next segment arrives, so each one is held back until then, and moves
+//! without XY motion, like a plunge, wait behind it.
+//!
+//! The first XY move after G41/G42 is the lead-in: a straight line, at least a tool radius
+//! long, from the uncompensated position onto the offset path. The first XY move after G40
+//! is the lead-out, also straight, from the offset path back to the programmed position.
+
+use core::f32::consts::TAU;
+use core::fmt;
+use heapless::Vec;
+use libm::{atan2f, fabsf, hypotf, sqrtf};
+
+use crate::arc::{Arc, ArcError};
+use crate::ast::AstNode;
+use crate::coordinates::Position;
+use crate::modal::{CompensationSide, ModalState, Plane};
+use crate::tool_table::ToolTable;
+
+/// Moves without XY motion that may wait behind one compensated segment.
+pub const MAX_HELD: usize = 8;
+/// Most nodes one call to [`CutterCompensator::push`] returns: a segment, the nodes held
+/// behind it and either a corner arc or the node that turned compensation off.
+pub const MAX_OUTPUT: usize = MAX_HELD + 2;
+
+/// Directions whose sine apart is below this carry straight on.
+const TANGENT_EPSILON: f32 = 1e-4;
+/// Slack when comparing arc sweeps, in radians.
+const SWEEP_EPSILON: f32 = 1e-3;
+/// Offset arcs smaller than this, in millimetres, have collapsed.
+const RADIUS_EPSILON: f32 = 1e-4;
+
+pub type Compensated = Vec<AstNode, MAX_OUTPUT>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompensationError {
+    /// The first XY move after G41/G42 is an arc.
+    ArcLeadIn,
+    /// The first XY move after G41/G42 is shorter than the tool radius.
+    LeadInTooShort,
+    /// The first XY move after G40 is an arc.
+    ArcLeadOut,
+    /// An arc that curves towards the tool with a radius no larger than the tool's.
+    ArcTooSmall,
+    /// The offset path would run backwards, as in a slot narrower than the tool.
+    Gouge,
+    /// An arc outside the XY plane with compensation on.
+    OutOfPlaneArc,
+    TooManyHeldNodes,
+    Arc(ArcError),
+}
+
+impl fmt::Display for CompensationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ArcLeadIn => write!(f, "the move after G41/G42 must be a straight line"),
+            Self::LeadInTooShort => write!(f, "the move after G41/G42 is shorter than the tool radius"),
+            Self::ArcLeadOut => write!(f, "the move after G40 must be a straight line"),
+            Self::ArcTooSmall => write!(f, "arc radius is not larger than the tool radius"),
+            Self::Gouge => write!(f, "the compensated path would gouge the part"),
+            Self::OutOfPlaneArc => write!(f, "cutter compensation only works in the XY plane"),
+            Self::TooManyHeldNodes => write!(f, "more than {} moves without XY motion in a row", MAX_HELD),
+            Self::Arc(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<ArcError> for CompensationError {
+    fn from(error: ArcError) -> Self {
+        Self::Arc(error)
+    }
+}
+
+type Point = [f32; 2];
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    Line { rapid: bool },
+    Arc { centre: Point, clockwise: bool, turns: Option<f32> },
+}
+
+/// A programmed move with XY motion.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    shape: Shape,
+    from: Point,
+    to: Position,
+    feedrate: Option<f32>,
+    spindle_speed: Option<f32>,
+}
+
+/// A segment's path moved sideways by the tool radius.
+#[derive(Debug, Clone, Copy)]
+enum Offset {
+    Line { point: Point, direction: Point },
+    Circle { centre: Point, radius: f32 },
+}
+
+impl Segment {
+    fn end(&self) -> Point {
+        [self.to[0], self.to[1]]
+    }
+
+    /// Direction of travel at `point`, which lies on the segment.
+    fn tangent(&self, point: Point) -> Point {
+        match self.shape {
+            Shape::Line { .. } => unit(sub(self.end(), self.from)),
+            Shape::Arc { centre, clockwise, .. } => {
+                let radial = unit(sub(point, centre));
+                if clockwise {
+                    [radial[1], -radial[0]]
+                } else {
+                    left(radial)
+                }
+            }
+        }
+    }
+
+    /// `distance` to the left of the segment, or to the right when negative.
+    fn offset(&self, distance: f32) -> Offset {
+        match self.shape {
+            Shape::Line { .. } => {
+                let direction = self.tangent(self.from);
+                Offset::Line { point: add(self.from, scale(left(direction), distance)), direction }
+            }
+            Shape::Arc { centre, clockwise, .. } => {
+                let radius = hypot(sub(self.from, centre));
+                Offset::Circle { centre, radius: if clockwise { radius + distance } else { radius - distance } }
+            }
+        }
+    }
+}
+
+pub struct CutterCompensator {
+    side: Option<CompensationSide>,
+    radius: f32,
+    /// The next XY move is the lead-in.
+    lead_in: bool,
+    /// The next XY move is the lead-out.
+    lead_out: bool,
+    pending: Option<Segment>,
+    held: Vec<AstNode, MAX_HELD>,
+    /// Where the program has got to.
+    position: Position,
+    /// Where the tool has got to.
+    tool: Position,
+}
+
+impl Default for CutterCompensator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CutterCompensator {
+    pub fn new() -> Self {
+        Self {
+            side: None,
+            radius: 0.0,
+            lead_in: false,
+            lead_out: false,
+            pending: None,
+            held: Vec::new(),
+            position: [0.0; crate::coordinates::AXES],
+            tool: [0.0; crate::coordinates::AXES],
+        }
+    }
+
+    /// For homing. Only meaningful with compensation off.
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+        self.tool = position;
+    }
+
+    /// Takes a node from `crate::coordinates::CoordinateResolver` and returns the nodes
+    /// that are ready to run, which may be none while a segment waits for the next one.
+    pub fn push(&mut self, node: AstNode, state: &ModalState, tools: &ToolTable) -> Result<Compensated, CompensationError> {
+        let mut out = Compensated::new();
+        match (self.side, state.cutter_compensation) {
+            (None, Some((side, diameter))) => {
+                self.side = Some(side);
+                self.radius = tools.diameter(diameter) / 2.0;
+                self.lead_in = true;
+            }
+            (Some(_), None) => {
+                if let Some(pending) = self.pending.take() {
+                    let end = add(pending.end(), scale(left(pending.tangent(pending.end())), self.offset()));
+                    self.emit(&pending, end, &mut out)?;
+                }
+                self.release(&mut out);
+                self.lead_out = !self.lead_in;
+                self.lead_in = false;
+                self.side = None;
+            }
+            _ => {}
+        }
+
+        if let AstNode::DrillingCycle(cycle) = &node {
+            // The parser refuses cycles with compensation on.
+            self.set_position(cycle.end());
+            push(&mut out, node);
+            return Ok(out);
+        }
+
+        let Some(segment) = self.segment(&node)? else {
+            if let Some(end) = end_of(&node, &self.position) {
+                self.position = end;
+            }
+            if self.pending.is_some() {
+                self.held.push(node).map_err(|_| CompensationError::TooManyHeldNodes)?;
+            } else {
+                let node = self.place(node);
+                push(&mut out, node);
+            }
+            return Ok(out);
+        };
+        self.position = segment.to;
+
+        if self.side.is_none() {
+            if self.lead_out && matches!(segment.shape, Shape::Arc { .. }) {
+                return Err(CompensationError::ArcLeadOut);
+            }
+            self.lead_out = false;
+            self.tool = segment.to;
+            push(&mut out, node);
+            return Ok(out);
+        }
+
+        if let (Shape::Arc { .. }, Offset::Circle { radius, .. }) = (segment.shape, segment.offset(self.offset())) {
+            if radius <= RADIUS_EPSILON {
+                return Err(CompensationError::ArcTooSmall);
+            }
+        }
+        if self.lead_in {
+            if matches!(segment.shape, Shape::Arc { .. }) {
+                return Err(CompensationError::ArcLeadIn);
+            }
+            if hypot(sub(segment.end(), segment.from)) < self.radius {
+                return Err(CompensationError::LeadInTooShort);
+            }
+            self.lead_in = false;
+        } else if let Some(pending) = self.pending.take() {
+            self.join(pending, &segment, &mut out)?;
+        }
+        self.pending = Some(segment);
+        Ok(out)
+    }
+
+    /// Signed distance from the programmed path to the tool's centre, positive to the left.
+    fn offset(&self) -> f32 {
+        match self.side {
+            Some(CompensationSide::Left) => self.radius,
+            Some(CompensationSide::Right) => -self.radius,
+            None => 0.0,
+        }
+    }
+
+    /// The move's XY motion, or `None` for nodes without any.
+    fn segment(&self, node: &AstNode) -> Result<Option<Segment>, CompensationError> {
+        let from = [self.position[0], self.position[1]];
+        let Some(to) = end_of(node, &self.position) else {
+            return Ok(None);
+        };
+        let segment = match *node {
+            AstNode::RapidMove { .. } | AstNode::LinearMove { .. } if to[0] == from[0] && to[1] == from[1] => return Ok(None),
+            AstNode::RapidMove { .. } => Segment { shape: Shape::Line { rapid: true }, from, to, feedrate: None, spindle_speed: None },
+            AstNode::LinearMove { feedrate, spindle_speed, .. } => {
+                Segment { shape: Shape::Line { rapid: false }, from, to, feedrate, spindle_speed }
+            }
+            AstNode::ArcMove { plane, clockwise, turns, feedrate, spindle_speed, .. } => {
+                if plane != Plane::XY && self.side.is_some() {
+                    return Err(CompensationError::OutOfPlaneArc);
+                }
+                let centre = Arc::from_node(node, [self.position[0], self.position[1], self.position[2]])?.centre();
+                Segment { shape: Shape::Arc { centre: [centre[0], centre[1]], clockwise, turns }, from, to, feedrate, spindle_speed }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(segment))
+    }
+
+    /// Emits `pending` up to where the tool meets `next`, then whatever joins them.
+    fn join(&mut self, pending: Segment, next: &Segment, out: &mut Compensated) -> Result<(), CompensationError> {
+        let offset = self.offset();
+        let corner = pending.end();
+        let (incoming, outgoing) = (pending.tangent(corner), next.tangent(next.from));
+        let end = add(corner, scale(left(incoming), offset));
+        let turn = cross(incoming, outgoing);
+
+        if offset == 0.0 || (fabsf(turn) < TANGENT_EPSILON && dot(incoming, outgoing) > 0.0) {
+            self.emit(&pending, end, out)?;
+            self.release(out);
+        } else if turn * offset > 0.0 {
+            // Inside corner: stop where the offset paths cross.
+            let meet = intersect(pending.offset(offset), next.offset(offset), end).ok_or(CompensationError::Gouge)?;
+            self.emit(&pending, meet, out)?;
+            self.release(out);
+        } else {
+            // Outside corner: roll round it.
+            self.emit(&pending, end, out)?;
+            self.release(out);
+            let start = add(corner, scale(left(outgoing), offset));
+            let mut to = self.tool;
+            to[..2].copy_from_slice(&start);
+            let [x, y, z, a, b, c, e] = to.map(Some);
+            push(
+                out,
+                AstNode::ArcMove {
+                    clockwise: offset > 0.0,
+                    plane: Plane::XY,
+                    x,
+                    y,
+                    z,
+                    a,
+                    b,
+                    c,
+                    e,
+                    i: Some(corner[0] - end[0]),
+                    j: Some(corner[1] - end[1]),
+                    k: None,
+                    r: None,
+                    turns: None,
+                    feedrate: next.feedrate,
+                    spindle_speed: next.spindle_speed,
+                },
+            );
+            self.tool = to;
+        }
+        Ok(())
+    }
+
+    /// Emits `segment` from the tool's position to `end`, refusing to run it backwards.
+    fn emit(&mut self, segment: &Segment, end: Point, out: &mut Compensated) -> Result<(), CompensationError> {
+        let start = [self.tool[0], self.tool[1]];
+        let mut to = segment.to;
+        to[..2].copy_from_slice(&end);
+        let [x, y, z, a, b, c, e] = to.map(Some);
+        let node = match segment.shape {
+            Shape::Line { rapid } => {
+                if dot(sub(end, start), sub(segment.end(), segment.from)) < 0.0 {
+                    return Err(CompensationError::Gouge);
+                }
+                if rapid {
+                    AstNode::RapidMove { x, y, z, a, b, c, e }
+                } else {
+                    AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: segment.feedrate, spindle_speed: segment.spindle_speed }
+                }
+            }
+            Shape::Arc { centre, clockwise, turns } => {
+                if sweep(start, end, centre, clockwise) > sweep(segment.from, segment.end(), centre, clockwise) + SWEEP_EPSILON {
+                    return Err(CompensationError::Gouge);
+                }
+                AstNode::ArcMove {
+                    clockwise,
+                    plane: Plane::XY,
+                    x,
+                    y,
+                    z,
+                    a,
+                    b,
+                    c,
+                    e,
+                    i: Some(centre[0] - start[0]),
+                    j: Some(centre[1] - start[1]),
+                    k: None,
+                    r: None,
+                    turns,
+                    feedrate: segment.feedrate,
+                    spindle_speed: segment.spindle_speed,
+                }
+            }
+        };
+        push(out, node);
+        self.tool = to;
+        Ok(())
+    }
+
+    fn release(&mut self, out: &mut Compensated) {
+        for node in core::mem::take(&mut self.held) {
+            let node = self.place(node);
+            push(out, node);
+        }
+    }
+
+    /// Moves a node without XY motion to the tool's XY position.
+    fn place(&mut self, node: AstNode) -> AstNode {
+        let (x, y) = (Some(self.tool[0]), Some(self.tool[1]));
+        match node {
+            AstNode::RapidMove { z, a, b, c, e, .. } => {
+                self.track([z, a, b, c, e]);
+                AstNode::RapidMove { x, y, z, a, b, c, e }
+            }
+            AstNode::LinearMove { z, a, b, c, e, feedrate, spindle_speed, .. } => {
+                self.track([z, a, b, c, e]);
+                AstNode::LinearMove { x, y, z, a, b, c, e, feedrate, spindle_speed }
+            }
+            AstNode::ArcMove { z, a, b, c, e, .. } => {
+                // Only out-of-plane arcs with compensation off get here; they end where
+                // they were programmed.
+                self.track([z, a, b, c, e]);
+                node
+            }
+            other => other,
+        }
+    }
+
+    fn track(&mut self, axes: [Option<f32>; 5]) {
+        for (axis, value) in axes.into_iter().enumerate() {
+            if let Some(value) = value {
+                self.tool[axis + 2] = value;
+            }
+        }
+    }
+}
+
+fn push(out: &mut Compensated, node: AstNode) {
+    // MAX_OUTPUT covers the most one push can produce.
+    let _ = out.push(node);
+}
+
+/// Where a move ends; resolved moves carry every axis.
+fn end_of(node: &AstNode, position: &Position) -> Option<Position> {
+    let axes = match *node {
+        AstNode::RapidMove { x, y, z, a, b, c, e }
+        | AstNode::LinearMove { x, y, z, a, b, c, e, .. }
+        | AstNode::ArcMove { x, y, z, a, b, c, e, .. } => [x, y, z, a, b, c, e],
+        _ => return None,
+    };
+    let mut end = *position;
+    for (axis, value) in axes.into_iter().enumerate() {
+        if let Some(value) = value {
+            end[axis] = value;
+        }
+    }
+    Some(end)
+}
+
+/// The crossing of `a` and `b` nearest `near`.
+fn intersect(a: Offset, b: Offset, near: Point) -> Option<Point> {
+    let candidates = match (a, b) {
+        (Offset::Line { point: p, direction: d }, Offset::Line { point: q, direction: e }) => {
+            let denominator = cross(d, e);
+            if fabsf(denominator) < 1e-9 {
+                return None;
+            }
+            [Some(add(p, scale(d, cross(sub(q, p), e) / denominator))), None]
+        }
+        (Offset::Line { point, direction }, Offset::Circle { centre, radius })
+        | (Offset::Circle { centre, radius }, Offset::Line { point, direction }) => line_circle(point, direction, centre, radius),
+        (Offset::Circle { centre: c1, radius: r1 }, Offset::Circle { centre: c2, radius: r2 }) => {
+            circle_circle(c1, r1, c2, r2)
+        }
+    };
+    candidates
+        .into_iter()
+        .flatten()
+        .min_by(|p, q| hypot(sub(*p, near)).total_cmp(&hypot(sub(*q, near))))
+}
+
+fn line_circle(point: Point, direction: Point, centre: Point, radius: f32) -> [Option<Point>; 2] {
+    let from_centre = sub(point, centre);
+    let b = dot(direction, from_centre);
+    let discriminant = b * b - (dot(from_centre, from_centre) - radius * radius);
+    if discriminant < 0.0 {
+        return [None, None];
+    }
+    let root = sqrtf(discriminant);
+    [Some(add(point, scale(direction, -b - root))), Some(add(point, scale(direction, -b + root)))]
+}
+
+fn circle_circle(c1: Point, r1: f32, c2: Point, r2: f32) -> [Option<Point>; 2] {
+    let between = sub(c2, c1);
+    let distance = hypot(between);
+    if distance < 1e-9 || distance > r1 + r2 || distance < fabsf(r1 - r2) {
+        return [None, None];
+    }
+    let along = (r1 * r1 - r2 * r2 + distance * distance) / (2.0 * distance);
+    let across = sqrtf((r1 * r1 - along * along).max(0.0));
+    let direction = scale(between, 1.0 / distance);
+    let middle = add(c1, scale(direction, along));
+    [Some(add(middle, scale(left(direction), across))), Some(sub(middle, scale(left(direction), across)))]
+}
+
+/// Angle swept from `from` to `to` about `centre`; coincident points sweep a full turn.
+fn sweep(from: Point, to: Point, centre: Point, clockwise: bool) -> f32 {
+    let start = atan2f(from[1] - centre[1], from[0] - centre[0]);
+    let end = atan2f(to[1] - centre[1], to[0] - centre[0]);
+    let mut sweep = if clockwise { start - end } else { end - start };
+    if sweep < 0.0 {
+        sweep += TAU;
+    }
+    if sweep < 1e-6 {
+        TAU
+    } else {
+        sweep
+    }
+}
+
+fn add(a: Point, b: Point) -> Point {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+fn sub(a: Point, b: Point) -> Point {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn scale(a: Point, k: f32) -> Point {
+    [a[0] * k, a[1] * k]
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn hypot(a: Point) -> f32 {
+    hypotf(a[0], a[1])
+}
+
+fn unit(a: Point) -> Point {
+    scale(a, 1.0 / hypot(a))
+}
+
+/// `a` turned a quarter turn anticlockwise.
+fn left(a: Point) -> Point {
+    [-a[1], a[0]]
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::parse_line;
+    use crate::coordinates::CoordinateResolver;
+    use crate::lexer::lexer;
+    use crate::tool_table::Tool;
+    use approx::assert_relative_eq;
+    use heapless::String;
+
+    struct Machine {
+        state: ModalState,
+        resolver: CoordinateResolver,
+        compensator: CutterCompensator,
+    }
+
+    impl Machine {
+        fn new(diameter: f32) -> Self {
+            let mut resolver = CoordinateResolver::new(Default::default());
+            resolver.tools_mut().set(1, Tool { diameter, length: 0.0 });
+            Self { state: ModalState::default(), resolver, compensator: CutterCompensator::new() }
+        }
+
+        fn run(&mut self, line: &str) -> Result<Compensated, CompensationError> {
+            let tokens = lexer(line.as_bytes()).unwrap();
+            let node = parse_line(&tokens, &mut self.state).unwrap().pop().unwrap();
+            let node = self.resolver.resolve(node, &self.state).unwrap();
+            self.compensator.push(node, &self.state, self.resolver.tools())
+        }
+
+        /// Runs `program` and renders the compensated XY path one node per line.
+        fn program(&mut self, program: &str) -> String<1024> {
+            let mut rendered = String::new();
+            for line in program.lines() {
+                for node in self.run(line).unwrap() {
+                    let text: String<64> = match node {
+                        AstNode::RapidMove { x, y, .. } => format(format_args!("G0 X{} Y{}", round(x), round(y))),
+                        AstNode::LinearMove { x, y, z, .. } => {
+                            format(format_args!("G1 X{} Y{} Z{}", round(x), round(y), round(z)))
+                        }
+                        AstNode::ArcMove { clockwise, x, y, i, j, .. } => format(format_args!(
+                            "G{} X{} Y{} I{} J{}",
+                            if clockwise { 2 } else { 3 },
+                            round(x),
+                            round(y),
+                            round(i),
+                            round(j)
+                        )),
+                        _ => continue,
+                    };
+                    rendered.push_str(&text).unwrap();
+                    rendered.push('\n').unwrap();
+                }
+            }
+            rendered
+        }
+    }
+
+    fn round(value: Option<f32>) -> f32 {
+        let value = libm::roundf(value.unwrap() * 1000.0) / 1000.0;
+        // No negative zeros in the golden output.
+        value + 0.0
+    }
+
+    fn format(args: fmt::Arguments) -> String<64> {
+        let mut text = String::new();
+        fmt::write(&mut text, args).unwrap();
+        text
+    }
+
+    #[test]
+    fn outside_profile_of_a_rectangle() {
+        let mut machine = Machine::new(4.0);
+        let path = machine.program(
+            "G0 X-10 Y5\n\
+             G41 D1 G1 X0 Y5 F100\n\
+             G1 Z-1\n\
+             G1 X0 Y10\n\
+             G1 X20 Y10\n\
+             G1 X20 Y0\n\
+             G1 X0 Y0\n\
+             G1 X0 Y5\n\
+             G40 G1 X-10 Y5",
+        );
+        assert_eq!(
+            path.as_str(),
+            "G0 X-10 Y5\n\
+             G1 X-2 Y7 Z0\n\
+             G1 X-2 Y7 Z-1\n\
+             G1 X-2 Y10 Z-1\n\
+             G2 X0 Y12 I2 J0\n\
+             G1 X20 Y12 Z-1\n\
+             G2 X22 Y10 I0 J-2\n\
+             G1 X22 Y0 Z-1\n\
+             G2 X20 Y-2 I-2 J0\n\
+             G1 X0 Y-2 Z-1\n\
+             G2 X-2 Y0 I0 J2\n\
+             G1 X-2 Y5 Z-1\n\
+             G1 X-10 Y5 Z-1\n"
+        );
+    }
+
+    #[test]
+    fn inside_a_circular_pocket() {
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X0 Y0").unwrap();
+        assert!(machine.run("G41 D1 G1 X10 F100").unwrap().is_empty());
+        let entry = machine.run("G3 X10 Y0 I-10 J0").unwrap();
+        let AstNode::LinearMove { x, y, .. } = entry[0] else { panic!("{:?}", entry) };
+        // Onto the offset circle, radius 8 about the origin, along the line Y2.
+        assert_relative_eq!(x.unwrap(), sqrtf(60.0), epsilon = 1e-4);
+        assert_relative_eq!(y.unwrap(), 2.0, epsilon = 1e-4);
+
+        let circle = machine.run("G40 G1 X0 Y0").unwrap();
+        let AstNode::ArcMove { clockwise: false, x, y, i, j, .. } = circle[0] else { panic!("{:?}", circle) };
+        assert_relative_eq!(x.unwrap(), 8.0, epsilon = 1e-4);
+        assert_relative_eq!(y.unwrap(), 0.0, epsilon = 1e-4);
+        assert_relative_eq!(i.unwrap(), -sqrtf(60.0), epsilon = 1e-4);
+        assert_relative_eq!(j.unwrap(), -2.0, epsilon = 1e-4);
+        assert_eq!(circle[1], AstNode::LinearMove {
+            x: Some(0.0),
+            y: Some(0.0),
+            z: Some(0.0),
+            a: Some(0.0),
+            b: Some(0.0),
+            c: Some(0.0),
+            e: Some(0.0),
+            feedrate: None,
+            spindle_speed: None,
+        });
+    }
+
+    #[test]
+    fn refuses_to_gouge() {
+        // A notch 1 mm wide is too narrow for a 4 mm cutter.
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X-10 Y0").unwrap();
+        machine.run("G41 D1 G1 X0 F100").unwrap();
+        machine.run("G1 X10").unwrap();
+        machine.run("G1 Y1").unwrap();
+        assert_eq!(machine.run("G1 X0"), Err(CompensationError::Gouge));
+
+        // An inside arc no larger than the tool.
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X0 Y0").unwrap();
+        machine.run("G41 D1 G1 X10 F100").unwrap();
+        assert_eq!(machine.run("G3 X12 Y0 I1 J0"), Err(CompensationError::ArcTooSmall));
+    }
+
+    #[test]
+    fn lead_in_and_lead_out_rules() {
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X0 Y0").unwrap();
+        assert_eq!(machine.run("G41 D1 G1 X1 F100"), Err(CompensationError::LeadInTooShort));
+
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X0 Y0").unwrap();
+        assert_eq!(machine.run("G41 D1 G2 X10 Y0 R5"), Err(CompensationError::ArcLeadIn));
+
+        let mut machine = Machine::new(4.0);
+        machine.run("G0 X0 Y0").unwrap();
+        machine.run("G41 D1 G1 X10 F100").unwrap();
+        machine.run("G40").unwrap();
+        assert_eq!(machine.run("G2 X20 Y0 R5"), Err(CompensationError::ArcLeadOut));
+    }
+}
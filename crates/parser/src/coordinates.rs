@@ -0,0 +1,443 @@
+//! Coordinate resolution: turns the words of a parsed line into absolute machine coordinates,
+//! applying units, distance modes and work offsets.
+//!
+//! Machine coordinates are program coordinates plus the active G54–G59.3 offset plus the
+//! G92 offset. `X`, `Y`, `Z` and `E` are lengths and come out in millimetres; `A`, `B` and
+//! `C` are degrees in either unit system. `E` takes G92 offsets (`G92 E0`) but no work
+//! offsets. With G43 active the tool length offset from the tool table joins the Z offsets;
+//! G53 moves ignore all of them.
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::arc::plane_axes;
+use crate::ast::AstNode;
+use crate::cycles::{CycleWords, DrillingCycle};
+use crate::modal::{DistanceMode, FeedrateMode, ModalState, RetractMode, Units};
+use crate::tool_table::ToolTable;
+
+pub const MM_PER_INCH: f32 = 25.4;
+/// `X`, `Y`, `Z`, `A`, `B`, `C` and `E`, in that order.
+pub const AXES: usize = 7;
+/// Axes with work offsets: `X` to `C`.
+pub const OFFSET_AXES: usize = 6;
+const E: usize = 6;
+
+pub type Position = [f32; AXES];
+
+/// The offsets that outlive a program. Save them whenever
+/// [`CoordinateResolver::take_changed`] says so and hand them back to
+/// [`CoordinateResolver::new`] at boot.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct OffsetTables {
+    /// G54 to G59.3, indexed by [`CoordinateSystem::index`].
+    pub work: [[f32; OFFSET_AXES]; 9],
+    /// G92.
+    pub axis: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolveError {
+    /// G53 moves only take absolute coordinates.
+    MachineMoveInRelativeMode,
+    /// A canned cycle whose hole bottom is above its R plane.
+    CycleBottomAboveRetract,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::MachineMoveInRelativeMode => write!(f, "G53 cannot be used with G91"),
+            ResolveError::CycleBottomAboveRetract => write!(f, "canned cycle Z is above its R plane"),
+        }
+    }
+}
+
+fn to_millimetres(axis: usize, value: f32, units: Units) -> f32 {
+    match units {
+        Units::Inches if axis < 3 || axis == E => value * MM_PER_INCH,
+        _ => value,
+    }
+}
+
+fn length(value: Option<f32>, units: Units) -> Option<f32> {
+    value.map(|value| to_millimetres(0, value, units))
+}
+
+fn feedrate(value: Option<f32>, state: &ModalState) -> Option<f32> {
+    match state.feedrate_mode {
+        FeedrateMode::InverseTime => value,
+        _ => length(value, state.units),
+    }
+}
+
+/// The modal `F` in millimetres, for moves that give none of their own.
+pub fn modal_feedrate(state: &ModalState) -> Option<f32> {
+    feedrate(state.feedrate, state)
+}
+
+pub struct CoordinateResolver {
+    offsets: OffsetTables,
+    tools: ToolTable,
+    position: Position,
+    changed: bool,
+}
+
+impl CoordinateResolver {
+    pub fn new(offsets: OffsetTables) -> Self {
+        Self { offsets, tools: ToolTable::default(), position: [0.0; AXES], changed: false }
+    }
+
+    pub fn offsets(&self) -> &OffsetTables {
+        &self.offsets
+    }
+
+    pub fn tools(&self) -> &ToolTable {
+        &self.tools
+    }
+
+    pub fn tools_mut(&mut self) -> &mut ToolTable {
+        &mut self.tools
+    }
+
+    /// True once after G10, G92 or G92.1 changed the tables.
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+
+    /// Machine position, in millimetres and degrees.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Sets the machine position, e.g. after homing.
+    pub fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
+    /// The machine position as the program sees it in the active coordinate system, still
+    /// in millimetres.
+    pub fn work_position(&self, state: &ModalState) -> Position {
+        core::array::from_fn(|axis| self.position[axis] - self.offset(axis, state))
+    }
+
+    /// Resolves `node` against `state`. Moves come back with every axis set, in machine
+    /// coordinates, arc centres as offsets from the start point and feedrates in
+    /// millimetres; G53 moves come back as plain rapid or linear moves and canned cycles as
+    /// a `DrillingCycle`. Offset-setting nodes update the tables and G28 zeroes the axes it
+    /// homes; anything else passes through unchanged.
+    pub fn resolve(&mut self, node: AstNode, state: &ModalState) -> Result<AstNode, ResolveError> {
+        let units = state.units;
+        match node {
+            AstNode::RapidMove { x, y, z, a, b, c, e } => {
+                let [x, y, z, a, b, c, e] = self.move_to([x, y, z, a, b, c, e], state);
+                Ok(AstNode::RapidMove { x, y, z, a, b, c, e })
+            }
+            AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: f, spindle_speed } => {
+                let [x, y, z, a, b, c, e] = self.move_to([x, y, z, a, b, c, e], state);
+                Ok(AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: feedrate(f, state), spindle_speed })
+            }
+            AstNode::ArcMove { clockwise, plane, x, y, z, a, b, c, e, i, j, k, r, turns, feedrate: f, spindle_speed } => {
+                let start = self.position;
+                let [x, y, z, a, b, c, e] = self.move_to([x, y, z, a, b, c, e], state);
+                let mut centre = [length(i, units), length(j, units), length(k, units)];
+                if state.arc_distance_mode == DistanceMode::Absolute && centre.iter().any(Option::is_some) {
+                    for &axis in &plane_axes(plane)[..2] {
+                        let absolute = centre[axis].unwrap_or(0.0) + self.offset(axis, state);
+                        centre[axis] = Some(absolute - start[axis]);
+                    }
+                }
+                let [i, j, k] = centre;
+                let r = length(r, units);
+                Ok(AstNode::ArcMove { clockwise, plane, x, y, z, a, b, c, e, i, j, k, r, turns, feedrate: feedrate(f, state), spindle_speed })
+            }
+            AstNode::MachineMove { rapid, x, y, z, a, b, c, feedrate: f } => {
+                if state.distance_mode == DistanceMode::Relative {
+                    return Err(ResolveError::MachineMoveInRelativeMode);
+                }
+                for (axis, word) in [x, y, z, a, b, c].into_iter().enumerate() {
+                    if let Some(value) = word {
+                        self.position[axis] = to_millimetres(axis, value, units);
+                    }
+                }
+                let [x, y, z, a, b, c, e] = self.position.map(Some);
+                Ok(if rapid {
+                    AstNode::RapidMove { x, y, z, a, b, c, e }
+                } else {
+                    AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: feedrate(f, state), spindle_speed: None }
+                })
+            }
+            AstNode::CannedCycle(words) => {
+                let cycle = self.drilling_cycle(words, state)?;
+                self.position = cycle.end();
+                Ok(AstNode::DrillingCycle(cycle))
+            }
+            AstNode::SetWorkOffset { system, to_current_position, x, y, z, a, b, c } => {
+                let table = system.unwrap_or(state.coordinate_system).index();
+                for (axis, word) in [x, y, z, a, b, c].into_iter().enumerate() {
+                    if let Some(value) = word {
+                        let value = to_millimetres(axis, value, units);
+                        self.offsets.work[table][axis] = if to_current_position {
+                            self.position[axis] - self.offsets.axis[axis] - self.tool_length(axis, state) - value
+                        } else {
+                            value
+                        };
+                    }
+                }
+                self.changed = true;
+                Ok(node)
+            }
+            AstNode::SetAxisOffset { x, y, z, a, b, c, e } => {
+                for (axis, word) in [x, y, z, a, b, c, e].into_iter().enumerate() {
+                    if let Some(value) = word {
+                        let work = self.offset(axis, state) - self.offsets.axis[axis];
+                        self.offsets.axis[axis] = self.position[axis] - work - to_millimetres(axis, value, units);
+                    }
+                }
+                self.changed = true;
+                Ok(node)
+            }
+            AstNode::ClearAxisOffset => {
+                self.offsets.axis = [0.0; AXES];
+                self.changed = true;
+                Ok(node)
+            }
+            AstNode::Home { x, y, z } => {
+                for (axis, homed) in [x, y, z].into_iter().enumerate() {
+                    if homed {
+                        self.position[axis] = 0.0;
+                    }
+                }
+                Ok(node)
+            }
+            node => Ok(node),
+        }
+    }
+
+    /// In G90 `X`, `Y`, `R` and `Z` are coordinates. In G91 `X` and `Y` step from hole to
+    /// hole, `R` is measured from the current Z and `Z` from R.
+    fn drilling_cycle(&self, words: CycleWords, state: &ModalState) -> Result<DrillingCycle, ResolveError> {
+        let units = state.units;
+        let start = self.position;
+        let z = |value: f32| to_millimetres(2, value, units);
+        let (hole, step, r, bottom, top) = match state.distance_mode {
+            DistanceMode::Absolute => {
+                let [x, y] = [(0, words.x), (1, words.y)]
+                    .map(|(axis, word)| word.map_or(start[axis], |v| to_millimetres(axis, v, units) + self.offset(axis, state)));
+                let at = |value: f32| z(value) + self.offset(2, state);
+                ([x, y], [0.0; 2], at(words.r), at(words.z), words.k.map(at))
+            }
+            DistanceMode::Relative => {
+                let step = [length(words.x, units).unwrap_or(0.0), length(words.y, units).unwrap_or(0.0)];
+                let r = start[2] + z(words.r);
+                ([start[0] + step[0], start[1] + step[1]], step, r, r + z(words.z), words.k.map(|k| r + z(k)))
+            }
+        };
+        if bottom > r {
+            return Err(ResolveError::CycleBottomAboveRetract);
+        }
+        let clear = match words.retract_mode {
+            RetractMode::InitialLevel => start[2].max(r),
+            RetractMode::RPlane => r,
+        };
+        let offset = [length(words.i, units).unwrap_or(0.0), length(words.j, units).unwrap_or(0.0)];
+        Ok(DrillingCycle {
+            cycle: words.cycle,
+            start,
+            hole,
+            step,
+            repeats: words.repeats,
+            r,
+            bottom,
+            clear,
+            peck: length(words.q, units),
+            dwell: words.p,
+            back_bore: top.map(|top| (offset, top)),
+            feedrate: feedrate(words.feedrate, state),
+            pitch: length(words.pitch, units),
+            spindle: words.spindle,
+        })
+    }
+
+    /// Moves to the given program words and returns the new machine position.
+    fn move_to(&mut self, words: [Option<f32>; AXES], state: &ModalState) -> [Option<f32>; AXES] {
+        for (axis, word) in words.into_iter().enumerate() {
+            if let Some(value) = word {
+                let value = to_millimetres(axis, value, state.units);
+                let mode = if axis == E { state.extrusion_mode } else { state.distance_mode };
+                self.position[axis] = match mode {
+                    DistanceMode::Absolute => value + self.offset(axis, state),
+                    DistanceMode::Relative => self.position[axis] + value,
+                };
+            }
+        }
+        self.position.map(Some)
+    }
+
+    /// Everything between program and machine coordinates on `axis`.
+    fn offset(&self, axis: usize, state: &ModalState) -> f32 {
+        let work = self.offsets.work[state.coordinate_system.index()].get(axis).copied().unwrap_or(0.0);
+        work + self.offsets.axis[axis] + self.tool_length(axis, state)
+    }
+
+    fn tool_length(&self, axis: usize, state: &ModalState) -> f32 {
+        match state.tool_length_offset {
+            Some(dimension) if axis == 2 => self.tools.length(dimension),
+            _ => 0.0,
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arc::Arc;
+    use crate::ast::{parse_line, Code, SyntaxError, SyntaxErrorKind};
+    use crate::lexer::lexer;
+    use crate::modal::CoordinateSystem;
+    use crate::tool_table::Tool;
+
+    struct Machine {
+        state: ModalState,
+        resolver: CoordinateResolver,
+    }
+
+    impl Machine {
+        fn new() -> Self {
+            Self { state: ModalState::default(), resolver: CoordinateResolver::new(OffsetTables::default()) }
+        }
+
+        fn run(&mut self, line: &str) -> Result<Option<AstNode>, ResolveError> {
+            let tokens = lexer(line.as_bytes()).unwrap();
+            match parse_line(&tokens, &mut self.state).unwrap().pop() {
+                Some(node) => self.resolver.resolve(node, &self.state).map(Some),
+                None => Ok(None),
+            }
+        }
+
+        fn xyz(&self) -> [f32; 3] {
+            let [x, y, z, ..] = self.resolver.position();
+            [x, y, z]
+        }
+    }
+
+    #[test]
+    fn units_and_distance_modes() {
+        let mut m = Machine::new();
+        m.run("G20 G0 X1 Y2").unwrap();
+        assert_eq!(m.xyz(), [25.4, 50.8, 0.0]);
+        let node = m.run("G91 G1 X1 A90 F10").unwrap();
+        assert_eq!(
+            node,
+            Some(AstNode::LinearMove {
+                x: Some(50.8),
+                y: Some(50.8),
+                z: Some(0.0),
+                a: Some(90.0),
+                b: Some(0.0),
+                c: Some(0.0),
+                e: Some(0.0),
+                feedrate: Some(254.0),
+                spindle_speed: None,
+            })
+        );
+        // Axis words alone repeat G1.
+        m.run("G21 Z-2").unwrap();
+        assert_eq!(m.xyz(), [50.8, 50.8, -2.0]);
+    }
+
+    #[test]
+    fn work_offsets_and_machine_moves() {
+        let mut m = Machine::new();
+        m.run("G10 L2 P2 X100 Y50").unwrap();
+        m.run("G55 G0 X1 Y1").unwrap();
+        assert_eq!(m.xyz(), [101.0, 51.0, 0.0]);
+        assert_eq!(m.resolver.work_position(&m.state)[..2], [1.0, 1.0]);
+
+        m.run("G53 G0 Z-10").unwrap();
+        assert_eq!(m.xyz(), [101.0, 51.0, -10.0]);
+        m.run("G54 X0").unwrap();
+        assert_eq!(m.xyz(), [0.0, 51.0, -10.0]);
+
+        // Touch off: the current position becomes G56 X5 Z0.
+        m.run("G10 L20 P3 X5 Z0").unwrap();
+        assert_eq!(m.resolver.offsets().work[2][..3], [-5.0, 0.0, -10.0]);
+        m.run("G59.3").unwrap();
+        assert_eq!(m.state.coordinate_system, CoordinateSystem::G59_3);
+
+        m.run("G91").unwrap();
+        assert_eq!(m.run("G53 G0 X0"), Err(ResolveError::MachineMoveInRelativeMode));
+    }
+
+    #[test]
+    fn axis_offsets_apply_on_top_of_work_offsets() {
+        let mut m = Machine::new();
+        m.run("G10 L2 P1 X10").unwrap();
+        m.run("G0 X5 E3").unwrap();
+        m.run("G92 X0 E0").unwrap();
+        assert_eq!(m.resolver.work_position(&m.state), [0.0; AXES]);
+        m.run("G1 X2 E1").unwrap();
+        assert_eq!(m.resolver.position()[0], 17.0);
+        assert_eq!(m.resolver.position()[E], 4.0);
+
+        m.run("G92.1").unwrap();
+        assert_eq!(m.resolver.work_position(&m.state)[0], 7.0);
+        assert_eq!(m.resolver.offsets().axis, [0.0; AXES]);
+    }
+
+    #[test]
+    fn tool_length_offsets() {
+        let mut m = Machine::new();
+        m.resolver.tools_mut().set(1, Tool { diameter: 6.0, length: 50.0 });
+        m.run("T1 M6").unwrap();
+        // Without `H`, the tool in the spindle.
+        m.run("G0 G43 Z10").unwrap();
+        assert_eq!(m.xyz()[2], 60.0);
+        assert_eq!(m.resolver.work_position(&m.state)[2], 10.0);
+
+        m.run("G20 G43.1 Z1").unwrap();
+        m.run("Z0").unwrap();
+        assert_eq!(m.xyz()[2], 25.4);
+        m.run("G21 G49 Z0").unwrap();
+        assert_eq!(m.xyz()[2], 0.0);
+
+        m.run("G43 H1 G53 G0 Z5").unwrap();
+        assert_eq!(m.xyz()[2], 5.0);
+        assert_eq!(m.resolver.work_position(&m.state)[2], -45.0);
+        let error = parse_line(&lexer(b"G43 H99").unwrap(), &mut m.state).unwrap_err();
+        assert_eq!(error, SyntaxError { code: Some(Code::G(43, 0)), kind: SyntaxErrorKind::InvalidValue('H', 99.0) });
+    }
+
+    #[test]
+    fn absolute_arc_centres() {
+        let mut m = Machine::new();
+        m.run("G10 L2 P1 X100 Y100").unwrap();
+        m.run("G0 X10 Y0").unwrap();
+        let start = m.xyz();
+        let node = m.run("G90.1 G3 X0 Y10 I0 J0").unwrap().unwrap();
+        let arc = Arc::from_node(&node, start).unwrap();
+        assert_eq!(arc.centre(), [100.0, 100.0, 0.0]);
+        assert_eq!(arc.end(), [100.0, 110.0, 0.0]);
+
+        // Back to offsets from the start point.
+        let node = m.run("G91.1 G18 G2 X10 Z0 I0 K-10").unwrap().unwrap();
+        assert!(matches!(node, AstNode::ArcMove { i: Some(i), k: Some(k), .. } if i == 0.0 && k == -10.0));
+    }
+
+    #[test]
+    fn offset_tables_persist() {
+        let mut m = Machine::new();
+        assert!(!m.resolver.take_changed());
+        m.run("G20 G10 L2 P9 X1 B45").unwrap();
+        m.run("G92 Z0").unwrap();
+        assert!(m.resolver.take_changed());
+        assert!(!m.resolver.take_changed());
+
+        let mut buffer = [0u8; 256];
+        let bytes = postcard::to_slice(m.resolver.offsets(), &mut buffer).unwrap();
+        let restored: OffsetTables = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(restored.work[CoordinateSystem::G59_3.index()][..OFFSET_AXES], [25.4, 0.0, 0.0, 0.0, 45.0, 0.0]);
+        assert_eq!(&restored, m.resolver.offsets());
+    }
+}
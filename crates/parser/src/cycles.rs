@@ -0,0 +1,500 @@
+//! Canned drilling cycles, G73 and G81–G89, expanded into primitive moves the way LinuxCNC
+//! does it.
+//!
+//! A cycle line drills one hole, or `L` holes: in G91 each repeat steps by the line's `X`/`Y`
+//! words, in G90 it drills the same hole again. Every hole starts with a rapid to the hole
+//! at the current level and down to the R plane, and ends at the clear level: the initial Z
+//! or R, whichever is higher, under G98, and R under G99.
+
+use heapless::Vec;
+
+use crate::ast::AstNode;
+use crate::coordinates::Position;
+use crate::modal::{Cycle, RetractMode, Spindle};
+
+/// How far above the last peck G73 and G83 come back down to, in millimetres (0.010").
+pub const PECK_CLEARANCE: f32 = 0.254;
+
+/// A cycle line as written, with the words that carry over filled in from earlier lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CycleWords {
+    pub cycle: Cycle,
+    pub retract_mode: RetractMode,
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    /// Bottom of the hole.
+    pub z: f32,
+    /// The R plane.
+    pub r: f32,
+    /// Peck depth, G73 and G83.
+    pub q: Option<f32>,
+    /// Dwell at the bottom, in seconds.
+    pub p: Option<f32>,
+    /// `L`.
+    pub repeats: u32,
+    /// G87: offset of the boring bar from the hole centre, and the Z it bores back up to.
+    pub i: Option<f32>,
+    pub j: Option<f32>,
+    pub k: Option<f32>,
+    pub feedrate: Option<f32>,
+    /// G84: thread pitch, per spindle revolution.
+    pub pitch: Option<f32>,
+    /// The spindle as the cycle found it, for the cycles that stop and restart it.
+    pub spindle: Spindle,
+}
+
+/// A canned cycle in machine coordinates and millimetres, ready to expand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrillingCycle {
+    pub cycle: Cycle,
+    /// Machine position before the cycle.
+    pub start: Position,
+    /// The first hole, and the step from each hole to the next.
+    pub hole: [f32; 2],
+    pub step: [f32; 2],
+    pub repeats: u32,
+    pub r: f32,
+    pub bottom: f32,
+    /// Where each hole ends.
+    pub clear: f32,
+    pub peck: Option<f32>,
+    pub dwell: Option<f32>,
+    /// G87: offset of the boring bar from the hole centre, and the Z it bores back up to.
+    pub back_bore: Option<([f32; 2], f32)>,
+    pub feedrate: Option<f32>,
+    pub pitch: Option<f32>,
+    pub spindle: Spindle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Rapid([f32; 3]),
+    Feed([f32; 3]),
+    Tap(f32),
+    Dwell(f32),
+    SpindleOff,
+    SpindleOn,
+    Pause,
+}
+
+impl DrillingCycle {
+    pub fn moves(&self) -> CycleMoves {
+        let mut moves = CycleMoves { cycle: *self, hole: 0, before: Vec::new(), pecks: 0, after: Vec::new(), index: 0 };
+        moves.plan_hole();
+        moves
+    }
+
+    /// Machine position once every hole is done.
+    pub fn end(&self) -> Position {
+        let [x, y] = self.hole_at(self.repeats - 1);
+        let mut end = self.start;
+        end[..3].copy_from_slice(&[x, y, self.clear]);
+        end
+    }
+
+    fn hole_at(&self, index: u32) -> [f32; 2] {
+        let n = index as f32;
+        [self.hole[0] + self.step[0] * n, self.hole[1] + self.step[1] * n]
+    }
+
+    /// How many pecks G73 and G83 make before the final feed to the bottom.
+    fn pecks(&self) -> u32 {
+        let Some(peck) = self.peck else { return 0 };
+        let mut pecks = 0;
+        while self.r - (pecks + 1) as f32 * peck > self.bottom {
+            pecks += 1;
+        }
+        pecks
+    }
+}
+
+/// The moves of a [`DrillingCycle`], produced one at a time so that deep pecked holes need
+/// no buffer. Yields resolved rapid and linear moves, rigid taps, dwells, spindle stops and
+/// restarts and, for G88, a program pause.
+pub struct CycleMoves {
+    cycle: DrillingCycle,
+    hole: u32,
+    before: Vec<Step, 4>,
+    pecks: u32,
+    after: Vec<Step, 12>,
+    index: usize,
+}
+
+impl CycleMoves {
+    fn plan_hole(&mut self) {
+        let c = self.cycle;
+        let [x, y] = c.hole_at(self.hole);
+        let (r, bottom, clear) = (c.r, c.bottom, c.clear);
+        let mut before = Vec::<Step, 4>::new();
+        let mut after = Vec::<Step, 12>::new();
+
+        let level = if self.hole == 0 {
+            let start_z = c.start[2];
+            if start_z < r {
+                before.push(Step::Rapid([c.start[0], c.start[1], r])).ok();
+            }
+            start_z.max(r)
+        } else {
+            clear
+        };
+        before.push(Step::Rapid([x, y, level])).ok();
+        if level != r {
+            before.push(Step::Rapid([x, y, r])).ok();
+        }
+
+        let restart = if c.spindle == Spindle::Off { None } else { Some(Step::SpindleOn) };
+        let dwell = c.dwell.map(Step::Dwell);
+        match c.cycle {
+            Cycle::Drill | Cycle::ChipBreakDrill | Cycle::PeckDrill => {
+                after.extend([Step::Feed([x, y, bottom]), Step::Rapid([x, y, clear])]);
+            }
+            Cycle::DwellDrill => {
+                after.push(Step::Feed([x, y, bottom])).ok();
+                after.extend(dwell);
+                after.push(Step::Rapid([x, y, clear])).ok();
+            }
+            Cycle::Tap => {
+                after.push(Step::Tap(bottom)).ok();
+                if clear != r {
+                    after.push(Step::Rapid([x, y, clear])).ok();
+                }
+            }
+            Cycle::Bore | Cycle::BoreDwell => {
+                after.push(Step::Feed([x, y, bottom])).ok();
+                after.extend(dwell);
+                after.push(Step::Feed([x, y, r])).ok();
+                if clear != r {
+                    after.push(Step::Rapid([x, y, clear])).ok();
+                }
+            }
+            Cycle::BoreStopRapidOut => {
+                after.push(Step::Feed([x, y, bottom])).ok();
+                after.extend(dwell);
+                after.extend([Step::SpindleOff, Step::Rapid([x, y, clear])]);
+                after.extend(restart);
+            }
+            Cycle::BackBore => {
+                let ([i, j], top) = c.back_bore.unwrap_or_default();
+                let (ox, oy) = (x + i, y + j);
+                after.extend([
+                    Step::Rapid([ox, oy, r]),
+                    Step::SpindleOff,
+                    Step::Rapid([ox, oy, bottom]),
+                    Step::Rapid([x, y, bottom]),
+                ]);
+                after.extend(restart);
+                after.extend([Step::Feed([x, y, top]), Step::Rapid([x, y, bottom]), Step::SpindleOff]);
+                after.extend([Step::Rapid([ox, oy, bottom]), Step::Rapid([ox, oy, clear]), Step::Rapid([x, y, clear])]);
+                after.extend(restart);
+            }
+            Cycle::BoreManualOut => {
+                after.push(Step::Feed([x, y, bottom])).ok();
+                after.extend(dwell);
+                after.extend([Step::SpindleOff, Step::Pause]);
+                after.extend(restart);
+            }
+        }
+
+        self.before = before;
+        self.pecks = c.pecks();
+        self.after = after;
+        self.index = 0;
+    }
+
+    /// Step `n` of the pecks of the current hole.
+    fn peck(&self, n: usize) -> Step {
+        let c = &self.cycle;
+        let [x, y] = c.hole_at(self.hole);
+        let per_peck = if c.cycle == Cycle::PeckDrill { 3 } else { 2 };
+        let depth = c.r - (n / per_peck + 1) as f32 * c.peck.unwrap_or_default();
+        match (c.cycle, n % per_peck) {
+            (_, 0) => Step::Feed([x, y, depth]),
+            // G83 clears the chips all the way out to R, G73 only breaks them.
+            (Cycle::PeckDrill, 1) => Step::Rapid([x, y, c.r]),
+            _ => Step::Rapid([x, y, depth + PECK_CLEARANCE]),
+        }
+    }
+
+    fn node(&self, step: Step) -> AstNode {
+        let c = &self.cycle;
+        let [_, _, _, a, b, cc, e] = c.start.map(Some);
+        match step {
+            Step::Rapid([x, y, z]) => AstNode::RapidMove { x: Some(x), y: Some(y), z: Some(z), a, b, c: cc, e },
+            Step::Feed([x, y, z]) => {
+                AstNode::LinearMove { x: Some(x), y: Some(y), z: Some(z), a, b, c: cc, e, feedrate: c.feedrate, spindle_speed: None }
+            }
+            Step::Tap(z) => AstNode::RigidTap { z, pitch: c.pitch.unwrap_or_default() },
+            Step::Dwell(seconds) => AstNode::Dwell(seconds),
+            Step::SpindleOff => AstNode::SpindleOff,
+            Step::SpindleOn => AstNode::SpindleOn { clockwise: c.spindle == Spindle::Clockwise, speed: None },
+            Step::Pause => AstNode::ProgramPause,
+        }
+    }
+}
+
+impl Iterator for CycleMoves {
+    type Item = AstNode;
+
+    fn next(&mut self) -> Option<AstNode> {
+        while self.hole < self.cycle.repeats {
+            let per_peck = if self.cycle.cycle == Cycle::PeckDrill { 3 } else { 2 };
+            let pecking = self.pecks as usize * per_peck;
+            let n = self.index;
+            let step = if n < self.before.len() {
+                Some(self.before[n])
+            } else if n - self.before.len() < pecking {
+                Some(self.peck(n - self.before.len()))
+            } else {
+                self.after.get(n - self.before.len() - pecking).copied()
+            };
+            match step {
+                Some(step) => {
+                    self.index += 1;
+                    return Some(self.node(step));
+                }
+                None => {
+                    self.hole += 1;
+                    if self.hole < self.cycle.repeats {
+                        self.plan_hole();
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{parse_line, Code, SyntaxError, SyntaxErrorKind};
+    use crate::coordinates::{CoordinateResolver, OffsetTables, ResolveError};
+    use crate::dialect::{CncRouterDialect, MachineDialect};
+    use crate::lexer::lexer;
+    use crate::modal::ModalState;
+    use core::fmt::Write;
+    use heapless::String;
+
+    struct Machine {
+        state: ModalState,
+        resolver: CoordinateResolver,
+    }
+
+    /// `value` with trailing zeros trimmed: `4.8`, `-1.246`, `10`.
+    fn number(out: &mut String<2048>, value: f32) {
+        let mut text = String::<16>::new();
+        write!(text, "{:.3}", value).unwrap();
+        let trimmed = text.trim_end_matches('0').trim_end_matches('.');
+        out.push_str(if trimmed == "-0" { "0" } else { trimmed }).unwrap();
+    }
+
+    impl Machine {
+        fn new() -> Self {
+            Self { state: ModalState::default(), resolver: CoordinateResolver::new(OffsetTables::default()) }
+        }
+
+        /// Runs `line` through the router dialect and renders what it expands to, a move per
+        /// line, in the style of the LinuxCNC manual's cycle walkthroughs.
+        fn run(&mut self, line: &str) -> Result<String<2048>, ResolveError> {
+            let tokens = lexer(line.as_bytes()).unwrap();
+            let mut out = String::new();
+            for node in parse_line(&tokens, &mut self.state).unwrap() {
+                let node = self.resolver.resolve(node, &self.state)?;
+                for node in CncRouterDialect.expand(node, &self.state).ok().unwrap() {
+                    let xyz = match node {
+                        AstNode::RapidMove { x, y, z, .. } => Some(("G0", [x, y, z])),
+                        AstNode::LinearMove { x, y, z, .. } => Some(("G1", [x, y, z])),
+                        _ => None,
+                    };
+                    if let Some((code, axes)) = xyz {
+                        out.push_str(code).unwrap();
+                        for (word, value) in [" X", " Y", " Z"].into_iter().zip(axes) {
+                            out.push_str(word).unwrap();
+                            number(&mut out, value.unwrap());
+                        }
+                    } else {
+                        match node {
+                            AstNode::RigidTap { z, pitch } => {
+                                out.push_str("G33.1 Z").unwrap();
+                                number(&mut out, z);
+                                out.push_str(" K").unwrap();
+                                number(&mut out, pitch);
+                            }
+                            AstNode::Dwell(seconds) => {
+                                out.push_str("G4 P").unwrap();
+                                number(&mut out, seconds);
+                            }
+                            AstNode::SpindleOn { clockwise: true, .. } => out.push_str("M3").unwrap(),
+                            AstNode::SpindleOn { clockwise: false, .. } => out.push_str("M4").unwrap(),
+                            AstNode::SpindleOff => out.push_str("M5").unwrap(),
+                            AstNode::ProgramPause => out.push_str("M0").unwrap(),
+                            _ => continue,
+                        }
+                    }
+                    out.push('\n').unwrap();
+                }
+            }
+            Ok(out)
+        }
+    }
+
+    fn golden(m: &mut Machine, line: &str, expected: &[&str]) {
+        let actual = m.run(line).unwrap();
+        let actual: heapless::Vec<&str, 64> = actual.lines().collect();
+        assert_eq!(actual, expected, "{}", line);
+    }
+
+    #[test]
+    fn drill_from_the_linuxcnc_manual() {
+        // G81 example 1: absolute, from (1, 2, 3).
+        let mut m = Machine::new();
+        m.run("G0 X1 Y2 Z3").unwrap();
+        golden(&mut m, "G90 G81 G98 X4 Y5 Z1.5 R2.8", &["G0 X4 Y5 Z3", "G0 X4 Y5 Z2.8", "G1 X4 Y5 Z1.5", "G0 X4 Y5 Z3"]);
+
+        // G81 example 2: incremental with three repeats, from (1, 2, 3).
+        let mut m = Machine::new();
+        m.run("G0 X1 Y2 Z3").unwrap();
+        golden(
+            &mut m,
+            "G91 G81 G98 X4 Y5 Z-0.6 R1.8 L3",
+            &[
+                "G0 X1 Y2 Z4.8",
+                "G0 X5 Y7 Z4.8",
+                "G1 X5 Y7 Z4.2",
+                "G0 X5 Y7 Z4.8",
+                "G0 X9 Y12 Z4.8",
+                "G1 X9 Y12 Z4.2",
+                "G0 X9 Y12 Z4.8",
+                "G0 X13 Y17 Z4.8",
+                "G1 X13 Y17 Z4.2",
+                "G0 X13 Y17 Z4.8",
+            ],
+        );
+        assert_eq!(m.resolver.position()[..3], [13.0, 17.0, 4.8]);
+    }
+
+    #[test]
+    fn cycles_repeat_until_g80() {
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z10").unwrap();
+        golden(&mut m, "G99 G81 X10 Y10 Z-5 R2 F100", &["G0 X10 Y10 Z10", "G0 X10 Y10 Z2", "G1 X10 Y10 Z-5", "G0 X10 Y10 Z2"]);
+        golden(&mut m, "X20", &["G0 X20 Y10 Z2", "G1 X20 Y10 Z-5", "G0 X20 Y10 Z2"]);
+        golden(&mut m, "G98 Y20 Z-6", &["G0 X20 Y20 Z2", "G1 X20 Y20 Z-6", "G0 X20 Y20 Z2"]);
+        golden(&mut m, "G80", &[]);
+        golden(&mut m, "X30", &[]);
+        assert_eq!(m.resolver.position()[..3], [20.0, 20.0, 2.0]);
+
+        // G80 forgets Z and R.
+        let tokens = lexer(b"G81 X50").unwrap();
+        let error = parse_line(&tokens, &mut m.state).unwrap_err();
+        assert_eq!(error, SyntaxError { code: Some(Code::G(81, 0)), kind: SyntaxErrorKind::MissingWord('Z') });
+        let tokens = lexer(b"G81 X50 Z5 R2").unwrap();
+        let node = parse_line(&tokens, &mut m.state).unwrap().pop().unwrap();
+        assert_eq!(m.resolver.resolve(node, &m.state), Err(ResolveError::CycleBottomAboveRetract));
+    }
+
+    #[test]
+    fn peck_drilling() {
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z10").unwrap();
+        golden(
+            &mut m,
+            "G99 G83 Z-5 R1 Q2.5",
+            &[
+                "G0 X0 Y0 Z10",
+                "G0 X0 Y0 Z1",
+                "G1 X0 Y0 Z-1.5",
+                "G0 X0 Y0 Z1",
+                "G0 X0 Y0 Z-1.246",
+                "G1 X0 Y0 Z-4",
+                "G0 X0 Y0 Z1",
+                "G0 X0 Y0 Z-3.746",
+                "G1 X0 Y0 Z-5",
+                "G0 X0 Y0 Z1",
+            ],
+        );
+
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z10").unwrap();
+        golden(
+            &mut m,
+            "G98 G73 Z-5 R1 Q2.5",
+            &[
+                "G0 X0 Y0 Z10",
+                "G0 X0 Y0 Z1",
+                "G1 X0 Y0 Z-1.5",
+                "G0 X0 Y0 Z-1.246",
+                "G1 X0 Y0 Z-4",
+                "G0 X0 Y0 Z-3.746",
+                "G1 X0 Y0 Z-5",
+                "G0 X0 Y0 Z10",
+            ],
+        );
+
+        // A deep hole with fine pecks streams without a buffer.
+        let node = parse_line(&lexer(b"G83 Z-100 R1 Q0.125").unwrap(), &mut m.state).unwrap().pop().unwrap();
+        let node = m.resolver.resolve(node, &m.state).unwrap();
+        assert_eq!(CncRouterDialect.expand(node, &m.state).ok().unwrap().count(), 2 + 3 * 807 + 2);
+        let error = parse_line(&lexer(b"G83 Q0").unwrap(), &mut m.state).unwrap_err();
+        assert_eq!(error.kind, SyntaxErrorKind::InvalidValue('Q', 0.0));
+    }
+
+    #[test]
+    fn tapping_follows_the_spindle() {
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z5").unwrap();
+        let error = parse_line(&lexer(b"G84 X10 Z-10 R2 F750").unwrap(), &mut m.state).unwrap_err();
+        assert_eq!(error.kind, SyntaxErrorKind::TapWithoutClockwiseSpindle);
+
+        m.run("S500 M3").unwrap();
+        golden(&mut m, "G98 G84 X10 Y0 Z-10 R2 F750", &["G0 X10 Y0 Z5", "G0 X10 Y0 Z2", "G33.1 Z-10 K1.5", "G0 X10 Y0 Z5"]);
+        golden(&mut m, "G95 G20 G99 X0 F0.05", &["G0 X10 Y0 Z50.8", "G0 X0 Y0 Z50.8", "G33.1 Z-254 K1.27"]);
+    }
+
+    #[test]
+    fn boring() {
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z5").unwrap();
+        m.run("M3 S800").unwrap();
+        golden(&mut m, "G99 G85 Z-8 R2 F60", &["G0 X0 Y0 Z5", "G0 X0 Y0 Z2", "G1 X0 Y0 Z-8", "G1 X0 Y0 Z2"]);
+        golden(&mut m, "G98 G89 P1", &["G0 X0 Y0 Z2", "G1 X0 Y0 Z-8", "G4 P1", "G1 X0 Y0 Z2"]);
+
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z5").unwrap();
+        m.run("M4 S800").unwrap();
+        golden(
+            &mut m,
+            "G98 G86 Z-8 R2 P0.5 F60",
+            &["G0 X0 Y0 Z5", "G0 X0 Y0 Z2", "G1 X0 Y0 Z-8", "G4 P0.5", "M5", "G0 X0 Y0 Z5", "M4"],
+        );
+        golden(&mut m, "G88 P1", &["G0 X0 Y0 Z5", "G0 X0 Y0 Z2", "G1 X0 Y0 Z-8", "G4 P1", "M5", "M0", "M4"]);
+    }
+
+    #[test]
+    fn back_boring() {
+        let mut m = Machine::new();
+        m.run("G0 X0 Y0 Z5").unwrap();
+        m.run("M3 S800").unwrap();
+        golden(
+            &mut m,
+            "G98 G87 X10 Y0 Z-20 R2 I3 J0 K-12 F50",
+            &[
+                "G0 X10 Y0 Z5",
+                "G0 X10 Y0 Z2",
+                "G0 X13 Y0 Z2",
+                "M5",
+                "G0 X13 Y0 Z-20",
+                "G0 X10 Y0 Z-20",
+                "M3",
+                "G1 X10 Y0 Z-12",
+                "G0 X10 Y0 Z-20",
+                "M5",
+                "G0 X13 Y0 Z-20",
+                "G0 X13 Y0 Z5",
+                "G0 X10 Y0 Z5",
+                "M3",
+            ],
+        );
+    }
+}
@@ -1,53 +1,975 @@
+use core::fmt;
+use heapless::Vec;
+use tools::laser::dynamic_power::DynamicLaserPower;
+
 use crate::ast::AstNode;
-use crate::modal::ModalState;
+use crate::coordinates::{modal_feedrate, Position, AXES};
+use crate::cycles::CycleMoves;
+use crate::modal::{CompensationSide, ModalState, Spindle, ToolDimension};
+use crate::wire::{guides, left_normal, GuideHeights, Guides, Offsets};
+
+/// A node makes at most one command of its own, and a wire EDM move held back for its
+/// mitre, or a laser pierce, may come out ahead of it.
+pub const MAX_COMMANDS: usize = 2;
+pub const MAX_CONDITIONS: usize = 16;
+
+pub type Commands = Vec<MachineCommand, MAX_COMMANDS>;
 
+/// What a dialect makes of one primitive node. Targets are machine coordinates in
+/// millimetres and degrees, as `crate::coordinates` resolves them; feedrates are millimetres
+/// per minute, or moves per minute in G93.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MachineCommand {
-    LinearMove,
-    RapidMove,
-    Dwell,
-    ToolChange,
-    SpindleControl,
-    // ... and so on
+    LinearMove {
+        target: Position,
+        feedrate: f32,
+    },
+    RapidMove {
+        target: Position,
+    },
+    /// Pushes `length` millimetres of filament out, or pulls it back when negative, without
+    /// moving the axes or the E position.
+    Extrude {
+        length: f32,
+        feedrate: f32,
+    },
+    /// Seconds.
+    Dwell(f32),
+    /// Finds the endstops of the named axes and zeroes them.
+    Home {
+        x: bool,
+        y: bool,
+        z: bool,
+    },
+    ToolChange(u16),
+    SpindleControl {
+        spindle: Spindle,
+        speed: f32,
+    },
+    /// Spindle-synchronised feed in and out of a tapped hole.
+    RigidTap {
+        z: f32,
+        pitch: f32,
+    },
+    Pause,
+    SetTemperature {
+        heater: Heater,
+        celsius: f32,
+        wait: HeaterWait,
+    },
+    /// `speed` from 0 (off) to 1 (full).
+    SetFan {
+        fan: u8,
+        speed: f32,
+    },
+    /// Wire EDM: the lower guide to `xy` and the upper guide to `uv`, in step. `feedrate` is
+    /// `None` for a rapid.
+    GuideMove {
+        xy: [f32; 2],
+        uv: [f32; 2],
+        feedrate: Option<f32>,
+    },
+    SetCondition(Condition),
+    ThreadWire,
+    CutWire,
+    /// Wire EDM sparks, flushing and wire feed, on or off.
+    Machining(bool),
+    /// A feed move with the laser firing.
+    LaserMove {
+        target: Position,
+        feedrate: f32,
+        power: LaserPower,
+    },
+    /// Fires the laser at `power`, 0 to 1, for `duration_ms` without moving.
+    Pierce {
+        power: f32,
+        duration_ms: u32,
+    },
+    /// Switches air assist, then waits `delay_ms`.
+    AirAssist {
+        on: bool,
+        delay_ms: u32,
+    },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heater {
+    /// The hotend of an extruder, numbered from `T0`.
+    Hotend(u8),
+    Bed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaterWait {
+    No,           // M104, M140
+    UntilHeated,  // M109 S, M190 S: carries on at once if already hotter
+    UntilReached, // M109 R, M190 R: waits for cooling too
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ParseError {
     UnsupportedCommand,
     InvalidParameters,
+    /// A word the command cannot do without, like `S` on M104.
+    MissingParameter(char),
+    /// A feed move before any `F`.
+    MissingFeedrate,
+    /// A tool or extruder the machine does not have.
+    InvalidTool(f32),
+    InvalidTemperature(f32),
+    InvalidFan(f32),
+    /// A fan speed outside 0–255.
+    InvalidFanSpeed(f32),
+    InvalidDwell(f32),
+    /// A corner the wire cannot be offset or tapered round: the contour doubles back.
+    SharpCorner,
+    /// M80 before M60.
+    WireNotThreaded,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnsupportedCommand => write!(f, "command not supported on this machine"),
+            ParseError::InvalidParameters => write!(f, "invalid parameters"),
+            ParseError::MissingParameter(word) => write!(f, "missing {} word", word),
+            ParseError::MissingFeedrate => write!(f, "feed move without a feedrate"),
+            ParseError::InvalidTool(tool) => write!(f, "no tool {}", tool),
+            ParseError::InvalidTemperature(celsius) => write!(f, "temperature {} out of range", celsius),
+            ParseError::InvalidFan(fan) => write!(f, "no fan {}", fan),
+            ParseError::InvalidFanSpeed(speed) => write!(f, "fan speed {} outside 0 to 255", speed),
+            ParseError::InvalidDwell(time) => write!(f, "negative dwell {}", time),
+            ParseError::SharpCorner => write!(f, "contour doubles back under wire offset or taper"),
+            ParseError::WireNotThreaded => write!(f, "machining with the wire not threaded"),
+        }
+    }
+}
+
+/// The nodes one resolved node stands for, each to be passed to `interpret`.
+// Without an allocator the cycle's move plan lives inline; there is one expansion at a time.
+#[allow(clippy::large_enum_variant)]
+pub enum Expansion {
+    Node(Option<AstNode>),
+    Cycle(CycleMoves),
+}
+
+impl Iterator for Expansion {
+    type Item = AstNode;
+
+    fn next(&mut self) -> Option<AstNode> {
+        match self {
+            Expansion::Node(node) => node.take(),
+            Expansion::Cycle(moves) => moves.next(),
+        }
+    }
 }
 
 pub trait MachineDialect {
-    fn interpret(&self, node: &AstNode, state: &mut ModalState) -> Result<MachineCommand, ParseError>;
+    /// Splits a node that stands for several moves, such as a canned cycle, into the
+    /// primitive nodes `interpret` takes. By default every node stands for itself.
+    fn expand(&self, node: AstNode, _state: &ModalState) -> Result<Expansion, ParseError> {
+        Ok(Expansion::Node(Some(node)))
+    }
+
+    /// The commands for a primitive node; none for nodes that only change state. G2/G3
+    /// arrive as G1 chords, see `crate::arc::ArcLinearizer`.
+    fn interpret(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Commands, ParseError>;
+
+    /// True for dialects that apply G41/G42 themselves, as wire EDM does together with its
+    /// taper. The stream then leaves out `crate::compensation`.
+    fn compensates_cutter(&self) -> bool {
+        false
+    }
 }
 
-pub struct FdmPrinterDialect;
+/// Nodes the parser and resolver have already acted on in full.
+fn is_settled(node: &AstNode) -> bool {
+    matches!(
+        node,
+        AstNode::SetModalState(_)
+            | AstNode::Comment(_)
+            | AstNode::SetWorkOffset { .. }
+            | AstNode::SetAxisOffset { .. }
+            | AstNode::ClearAxisOffset
+    )
+}
 
-impl MachineDialect for FdmPrinterDialect {
-    fn interpret(&self, node: &AstNode, state: &mut ModalState) -> Result<MachineCommand, ParseError> {
-        match node {
-            AstNode::LinearMove { .. } => Ok(MachineCommand::LinearMove),
-            _ => Err(ParseError::UnsupportedCommand),
+/// Where a resolved move ends; the resolver sets every axis.
+fn target(axes: [Option<f32>; AXES]) -> Position {
+    axes.map(|value| value.unwrap_or(0.0))
+}
+
+/// The move's own `F`, or else the modal one.
+fn feedrate(feedrate: Option<f32>, state: &ModalState) -> Result<f32, ParseError> {
+    feedrate.or_else(|| modal_feedrate(state)).ok_or(ParseError::MissingFeedrate)
+}
+
+/// `value` as an index below `count`, if it is one.
+fn index(value: f32, count: u8) -> Option<u8> {
+    (value >= 0.0 && value < f32::from(count) && value == (value as u8) as f32).then_some(value as u8)
+}
+
+fn push(out: &mut Commands, command: MachineCommand) {
+    // MAX_COMMANDS covers the most one node can produce.
+    let _ = out.push(command);
+}
+
+/// Limits and firmware retraction for `FdmPrinterDialect`. The defaults are Marlin's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrinterSettings {
+    /// Extruders, numbered from `T0`.
+    pub extruders: u8,
+    /// Part cooling fans, numbered from `P0` on M106/M107.
+    pub fans: u8,
+    /// The hottest M104/M109 and M140/M190 may ask for, in °C.
+    pub max_hotend_celsius: f32,
+    pub max_bed_celsius: f32,
+    /// G10 pulls `retract_length` millimetres of filament back at `retract_feedrate`; G11
+    /// pushes it out again at `recover_feedrate`. Both in mm/min.
+    pub retract_length: f32,
+    pub retract_feedrate: f32,
+    pub recover_feedrate: f32,
+}
+
+impl Default for PrinterSettings {
+    fn default() -> Self {
+        Self {
+            extruders: 1,
+            fans: 1,
+            max_hotend_celsius: 260.0,
+            max_bed_celsius: 140.0,
+            retract_length: 3.0,
+            retract_feedrate: 2700.0,
+            recover_feedrate: 480.0,
+        }
+    }
+}
+
+/// Marlin-flavoured G-code for filament printers. `G4 P` is in milliseconds, a `T` word
+/// on its own switches extruders and M0 pauses.
+pub struct FdmPrinterDialect {
+    settings: PrinterSettings,
+    retracted: bool,
+}
+
+impl FdmPrinterDialect {
+    pub fn new(settings: PrinterSettings) -> Self {
+        Self { settings, retracted: false }
+    }
+
+    pub fn settings(&self) -> &PrinterSettings {
+        &self.settings
+    }
+
+    /// The extruder `T` names on M104/M109, or the active one.
+    fn extruder(&self, t: Option<f32>, state: &ModalState) -> Result<u8, ParseError> {
+        let t = t.unwrap_or(f32::from(state.tool));
+        index(t, self.settings.extruders).ok_or(ParseError::InvalidTool(t))
+    }
+
+    fn temperature(&self, code: u16, s: Option<f32>, r: Option<f32>, t: Option<f32>, state: &ModalState) -> Result<MachineCommand, ParseError> {
+        let (celsius, wait) = match (code, s, r) {
+            (104 | 140, Some(s), _) => (s, HeaterWait::No),
+            (_, Some(s), _) => (s, HeaterWait::UntilHeated),
+            (109 | 190, None, Some(r)) => (r, HeaterWait::UntilReached),
+            _ => return Err(ParseError::MissingParameter('S')),
+        };
+        let (heater, max) = match code {
+            104 | 109 => (Heater::Hotend(self.extruder(t, state)?), self.settings.max_hotend_celsius),
+            _ => (Heater::Bed, self.settings.max_bed_celsius),
+        };
+        if !(0.0..=max).contains(&celsius) {
+            return Err(ParseError::InvalidTemperature(celsius));
         }
+        Ok(MachineCommand::SetTemperature { heater, celsius, wait })
+    }
+
+    /// M106 `S` runs from 0 to 255 and defaults to full; M107 turns the fan off.
+    fn fan(&self, code: u16, s: Option<f32>, p: Option<f32>) -> Result<MachineCommand, ParseError> {
+        let p = p.unwrap_or(0.0);
+        let fan = index(p, self.settings.fans).ok_or(ParseError::InvalidFan(p))?;
+        let speed = if code == 107 { 0.0 } else { s.unwrap_or(255.0) };
+        if !(0.0..=255.0).contains(&speed) {
+            return Err(ParseError::InvalidFanSpeed(speed));
+        }
+        Ok(MachineCommand::SetFan { fan, speed: speed / 255.0 })
+    }
+
+    fn tool_change(&self, tool: u16, state: &mut ModalState) -> Result<MachineCommand, ParseError> {
+        if tool >= u16::from(self.settings.extruders) {
+            return Err(ParseError::InvalidTool(f32::from(tool)));
+        }
+        state.tool = tool;
+        Ok(MachineCommand::ToolChange(tool))
+    }
+}
+
+impl Default for FdmPrinterDialect {
+    fn default() -> Self {
+        Self::new(PrinterSettings::default())
+    }
+}
+
+impl MachineDialect for FdmPrinterDialect {
+    fn interpret(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Commands, ParseError> {
+        Ok(self.command(node, state)?.into_iter().collect())
+    }
+}
+
+impl FdmPrinterDialect {
+    fn command(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Option<MachineCommand>, ParseError> {
+        let command = match *node {
+            AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: f, .. } => {
+                MachineCommand::LinearMove { target: target([x, y, z, a, b, c, e]), feedrate: feedrate(f, state)? }
+            }
+            AstNode::RapidMove { x, y, z, a, b, c, e } => MachineCommand::RapidMove { target: target([x, y, z, a, b, c, e]) },
+            AstNode::Dwell(milliseconds) if milliseconds < 0.0 => return Err(ParseError::InvalidDwell(milliseconds)),
+            AstNode::Dwell(milliseconds) => MachineCommand::Dwell(milliseconds / 1000.0),
+            AstNode::Home { x, y, z } => MachineCommand::Home { x, y, z },
+            // Retracting twice pulls back no further, and G11 only recovers a retraction.
+            AstNode::Retract if self.retracted => return Ok(None),
+            AstNode::Unretract if !self.retracted => return Ok(None),
+            AstNode::Retract => {
+                self.retracted = true;
+                MachineCommand::Extrude { length: -self.settings.retract_length, feedrate: self.settings.retract_feedrate }
+            }
+            AstNode::Unretract => {
+                self.retracted = false;
+                MachineCommand::Extrude { length: self.settings.retract_length, feedrate: self.settings.recover_feedrate }
+            }
+            AstNode::SelectTool(tool) | AstNode::ToolChange(tool) => self.tool_change(tool, state)?,
+            AstNode::Auxiliary { code: code @ (104 | 109 | 140 | 190), s, r, t, .. } => self.temperature(code, s, r, t, state)?,
+            AstNode::Auxiliary { code: code @ (106 | 107), s, p, .. } => self.fan(code, s, p)?,
+            AstNode::ProgramPause => MachineCommand::Pause,
+            _ if is_settled(node) => return Ok(None),
+            _ => return Err(ParseError::UnsupportedCommand),
+        };
+        Ok(Some(command))
     }
 }
 
 pub struct CncRouterDialect;
 
 impl MachineDialect for CncRouterDialect {
-    fn interpret(&self, node: &AstNode, state: &mut ModalState) -> Result<MachineCommand, ParseError> {
+    fn expand(&self, node: AstNode, _state: &ModalState) -> Result<Expansion, ParseError> {
         match node {
-            AstNode::LinearMove { .. } => Ok(MachineCommand::LinearMove),
-            _ => Err(ParseError::UnsupportedCommand),
+            AstNode::DrillingCycle(cycle) => Ok(Expansion::Cycle(cycle.moves())),
+            node => Ok(Expansion::Node(Some(node))),
         }
     }
+
+    fn interpret(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Commands, ParseError> {
+        Ok(self.command(node, state)?.into_iter().collect())
+    }
+}
+
+impl CncRouterDialect {
+    fn command(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Option<MachineCommand>, ParseError> {
+        let command = match *node {
+            AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: f, .. } => {
+                MachineCommand::LinearMove { target: target([x, y, z, a, b, c, e]), feedrate: feedrate(f, state)? }
+            }
+            AstNode::RapidMove { x, y, z, a, b, c, e } => MachineCommand::RapidMove { target: target([x, y, z, a, b, c, e]) },
+            AstNode::Dwell(seconds) if seconds < 0.0 => return Err(ParseError::InvalidDwell(seconds)),
+            AstNode::Dwell(seconds) => MachineCommand::Dwell(seconds),
+            AstNode::SpindleOn { clockwise, speed } => MachineCommand::SpindleControl {
+                spindle: if clockwise { Spindle::Clockwise } else { Spindle::CounterClockwise },
+                speed: speed.or(state.spindle_speed).unwrap_or(0.0),
+            },
+            AstNode::SpindleOff => MachineCommand::SpindleControl { spindle: Spindle::Off, speed: 0.0 },
+            // A new speed for a running spindle.
+            AstNode::SpindleControl(_) if state.spindle == Spindle::Off => return Ok(None),
+            AstNode::SpindleControl(speed) => MachineCommand::SpindleControl { spindle: state.spindle, speed },
+            AstNode::RigidTap { z, pitch } => MachineCommand::RigidTap { z, pitch },
+            AstNode::ToolChange(tool) => MachineCommand::ToolChange(tool),
+            AstNode::ProgramPause => MachineCommand::Pause,
+            // The tool is fetched on M6.
+            AstNode::SelectTool(_) => return Ok(None),
+            _ if is_settled(node) => return Ok(None),
+            _ => return Err(ParseError::UnsupportedCommand),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// How hard a cutting move fires the laser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LaserPower {
+    /// M3: the same power however fast the head is moving, as a fraction of full power.
+    Constant(f32),
+    /// M4: power in proportion to the head's speed, so corners and accelerations burn no
+    /// deeper than the straights.
+    Dynamic(DynamicLaserPower),
+}
+
+impl LaserPower {
+    /// The power, from 0 to 1, for the head moving at `feedrate` mm/min.
+    pub fn at(&self, feedrate: f32) -> f32 {
+        match self {
+            LaserPower::Constant(power) => *power,
+            LaserPower::Dynamic(power) => power.calculate_pwm(feedrate).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LaserSettings {
+    /// The `S` value for full power, GRBL's `$30`. Larger values are held to it.
+    pub max_power: f32,
+    /// How long M8 waits for the air to come up to pressure, in ms.
+    pub air_delay_ms: u32,
+    /// How long each cut made with air assist on dwells at its power before the head
+    /// moves, to pierce the sheet, in ms.
+    pub pierce_delay_ms: u32,
+}
+
+impl Default for LaserSettings {
+    fn default() -> Self {
+        Self { max_power: 1000.0, air_delay_ms: 0, pierce_delay_ms: 0 }
+    }
+}
+
+/// GRBL-flavoured laser G-code. The beam only fires during feed moves, each carrying its
+/// own power, so a change of `S` or of mode takes effect with the move it is programmed
+/// on or the next one, however far ahead the lines are read. `G4 P` is in seconds.
+pub struct LaserDialect {
+    settings: LaserSettings,
+    /// `Some(true)` for M4, `Some(false)` for M3; `None` after M5.
+    dynamic: Option<bool>,
+    /// The last `S`, as written.
+    power: f32,
+    air_assist: bool,
+    /// Whether the last move was a cut, so that the next one needs no pierce.
+    cutting: bool,
+}
+
+impl LaserDialect {
+    pub fn new(settings: LaserSettings) -> Self {
+        Self { settings, dynamic: None, power: 0.0, air_assist: false, cutting: false }
+    }
+
+    pub fn settings(&self) -> &LaserSettings {
+        &self.settings
+    }
 }
 
-pub struct WireEdmDialect;
+impl Default for LaserDialect {
+    fn default() -> Self {
+        Self::new(LaserSettings::default())
+    }
+}
+
+impl MachineDialect for LaserDialect {
+    fn interpret(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Commands, ParseError> {
+        let mut out = Commands::new();
+        let command = match *node {
+            AstNode::LinearMove { x, y, z, a, b, c, e, feedrate: f, spindle_speed } => {
+                let target = target([x, y, z, a, b, c, e]);
+                let feedrate = feedrate(f, state)?;
+                self.power = spindle_speed.unwrap_or(self.power);
+                let power = (self.power / self.settings.max_power).clamp(0.0, 1.0);
+                let Some(dynamic) = self.dynamic.filter(|_| power > 0.0) else {
+                    self.cutting = false;
+                    push(&mut out, MachineCommand::LinearMove { target, feedrate });
+                    return Ok(out);
+                };
+                if self.air_assist && !self.cutting && self.settings.pierce_delay_ms > 0 {
+                    push(&mut out, MachineCommand::Pierce { power, duration_ms: self.settings.pierce_delay_ms });
+                }
+                self.cutting = true;
+                let power = match dynamic {
+                    true => LaserPower::Dynamic(DynamicLaserPower::new(power, feedrate)),
+                    false => LaserPower::Constant(power),
+                };
+                MachineCommand::LaserMove { target, feedrate, power }
+            }
+            AstNode::RapidMove { x, y, z, a, b, c, e } => {
+                self.cutting = false;
+                MachineCommand::RapidMove { target: target([x, y, z, a, b, c, e]) }
+            }
+            AstNode::SpindleOn { clockwise, speed } => {
+                self.dynamic = Some(!clockwise);
+                self.power = speed.unwrap_or(self.power);
+                return Ok(out);
+            }
+            AstNode::SpindleOff => {
+                self.dynamic = None;
+                return Ok(out);
+            }
+            AstNode::SpindleControl(power) => {
+                self.power = power;
+                return Ok(out);
+            }
+            AstNode::Auxiliary { code: code @ (8 | 9), .. } => {
+                self.air_assist = code == 8;
+                let delay_ms = if self.air_assist { self.settings.air_delay_ms } else { 0 };
+                MachineCommand::AirAssist { on: self.air_assist, delay_ms }
+            }
+            AstNode::Dwell(seconds) if seconds < 0.0 => return Err(ParseError::InvalidDwell(seconds)),
+            AstNode::Dwell(seconds) => MachineCommand::Dwell(seconds),
+            AstNode::ProgramPause => MachineCommand::Pause,
+            _ if is_settled(node) => return Ok(out),
+            _ => return Err(ParseError::UnsupportedCommand),
+        };
+        push(&mut out, command);
+        Ok(out)
+    }
+}
+
+/// A machining condition: the generator and wire settings for one cut.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    /// Between wire and work, per side, in mm.
+    pub spark_gap: f32,
+    /// Pulse on and off times in µs.
+    pub on_time: f32,
+    pub off_time: f32,
+    /// Peak current in A and servo reference voltage in V.
+    pub current: f32,
+    pub voltage: f32,
+    /// Wire feed in m/min and tension in N.
+    pub wire_speed: f32,
+    pub wire_tension: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WireSettings {
+    pub wire_diameter: f32,
+    pub guides: GuideHeights,
+    /// Numbered from `T0`.
+    pub conditions: Vec<Condition, MAX_CONDITIONS>,
+}
+
+/// A move made under wire offset or taper, held until the next move shows how its end is
+/// mitred.
+struct Held {
+    to: [f32; 2],
+    normal: [f32; 2],
+    offsets: Offsets,
+    feedrate: Option<f32>,
+    /// The first move after the offsets changed; it ends square to the move after it.
+    lead_in: bool,
+}
+
+fn signed(side: CompensationSide, value: f32) -> f32 {
+    match side {
+        CompensationSide::Left => value,
+        CompensationSide::Right => -value,
+    }
+}
+
+/// Four-axis wire EDM. Moves come out as `GuideMove`s, with G41/G42 and G51/G52 applied as
+/// `crate::wire` describes; the offset and taper build up over the first move after they
+/// change and die away over the first after G40/G50. A `T` word selects a machining
+/// condition, M60 threads the wire and M50 cuts it, and M80 and M81 switch machining on
+/// and off. `G4 P` is in seconds.
+pub struct WireEdmDialect {
+    settings: WireSettings,
+    /// The contour point the last move ended at.
+    position: [f32; 2],
+    /// The offsets the last move was made with.
+    offsets: Offsets,
+    held: Option<Held>,
+    threaded: bool,
+}
+
+impl WireEdmDialect {
+    pub fn new(settings: WireSettings) -> Self {
+        Self { settings, position: [0.0; 2], offsets: Offsets::default(), held: None, threaded: false }
+    }
+
+    pub fn settings(&self) -> &WireSettings {
+        &self.settings
+    }
+
+    fn condition(&self, number: u16) -> Result<&Condition, ParseError> {
+        self.settings.conditions.get(usize::from(number)).ok_or(ParseError::InvalidTool(f32::from(number)))
+    }
+
+    /// G41/G42 offset by the wire radius and the spark gap of the active condition, or of
+    /// condition `D`; G41.1/G42.1 take `D` as the kerf width.
+    fn offsets(&self, state: &ModalState) -> Result<Offsets, ParseError> {
+        let kerf = match state.cutter_compensation {
+            Some((side, ToolDimension::Given(width))) => signed(side, width / 2.0),
+            Some((side, ToolDimension::Tool(number))) => {
+                signed(side, self.settings.wire_diameter / 2.0 + self.condition(number)?.spark_gap)
+            }
+            None => 0.0,
+        };
+        let taper = state.taper.map_or(0.0, |(side, angle)| signed(side, angle));
+        Ok(Offsets::new(kerf, taper, &self.settings.guides))
+    }
+
+    /// Emits the held move, mitred into a move with left normal `after`, or square.
+    fn release(&mut self, after: Option<[f32; 2]>, out: &mut Commands) -> Result<(), ParseError> {
+        let Some(held) = self.held.take() else { return Ok(()) };
+        let (before, after) = match after {
+            Some(after) if held.lead_in => (after, after),
+            Some(after) => (held.normal, after),
+            None => (held.normal, held.normal),
+        };
+        let Guides { lower, upper } = guides(held.to, before, after, held.offsets).ok_or(ParseError::SharpCorner)?;
+        push(out, MachineCommand::GuideMove { xy: lower, uv: upper, feedrate: held.feedrate });
+        Ok(())
+    }
+
+    fn move_to(&mut self, to: [f32; 2], feedrate: Option<f32>, offsets: Offsets, out: &mut Commands) -> Result<(), ParseError> {
+        // The guides only move in XY and UV.
+        let Some(normal) = left_normal(self.position, to) else { return Ok(()) };
+        self.release(Some(normal), out)?;
+        if offsets.is_zero() {
+            let Guides { lower, upper } = Guides::upright(to);
+            push(out, MachineCommand::GuideMove { xy: lower, uv: upper, feedrate });
+        } else {
+            self.held = Some(Held { to, normal, offsets, feedrate, lead_in: offsets != self.offsets });
+        }
+        self.position = to;
+        self.offsets = offsets;
+        Ok(())
+    }
+}
 
 impl MachineDialect for WireEdmDialect {
-    fn interpret(&self, node: &AstNode, state: &mut ModalState) -> Result<MachineCommand, ParseError> {
-        match node {
-            AstNode::LinearMove { .. } => Ok(MachineCommand::LinearMove),
-            _ => Err(ParseError::UnsupportedCommand),
+    fn interpret(&mut self, node: &AstNode, state: &mut ModalState) -> Result<Commands, ParseError> {
+        let offsets = self.offsets(state)?;
+        let mut to = None;
+        let command = match *node {
+            AstNode::LinearMove { x, y, feedrate: f, .. } => {
+                to = Some(([x.unwrap_or(0.0), y.unwrap_or(0.0)], Some(feedrate(f, state)?)));
+                None
+            }
+            AstNode::RapidMove { x, y, .. } => {
+                to = Some(([x.unwrap_or(0.0), y.unwrap_or(0.0)], None));
+                None
+            }
+            AstNode::SelectTool(number) => {
+                let condition = *self.condition(number)?;
+                state.tool = number;
+                Some(MachineCommand::SetCondition(condition))
+            }
+            AstNode::Auxiliary { code: 60, .. } => {
+                self.threaded = true;
+                Some(MachineCommand::ThreadWire)
+            }
+            AstNode::Auxiliary { code: 50, .. } => {
+                self.threaded = false;
+                Some(MachineCommand::CutWire)
+            }
+            AstNode::Auxiliary { code: 80, .. } if !self.threaded => return Err(ParseError::WireNotThreaded),
+            AstNode::Auxiliary { code: code @ (80 | 81), .. } => Some(MachineCommand::Machining(code == 80)),
+            AstNode::Dwell(seconds) if seconds < 0.0 => return Err(ParseError::InvalidDwell(seconds)),
+            AstNode::Dwell(seconds) => Some(MachineCommand::Dwell(seconds)),
+            AstNode::ProgramPause => Some(MachineCommand::Pause),
+            _ if is_settled(node) => None,
+            _ => return Err(ParseError::UnsupportedCommand),
+        };
+
+        let mut out = Commands::new();
+        // A new offset or taper, or anything else for the machine to do, ends the contour
+        // square.
+        if command.is_some() || self.held.as_ref().is_some_and(|held| held.offsets != offsets) {
+            self.release(None, &mut out)?;
+        }
+        if let Some((to, feedrate)) = to {
+            self.move_to(to, feedrate, offsets, &mut out)?;
+        }
+        if let Some(command) = command {
+            push(&mut out, command);
+        }
+        Ok(out)
+    }
+
+    fn compensates_cutter(&self) -> bool {
+        true
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arc::ArcLinearizer;
+    use crate::ast::parse_line;
+    use crate::coordinates::{CoordinateResolver, OffsetTables};
+    use crate::lexer::lexer;
+    use approx::assert_relative_eq;
+    use libm::{hypotf, tanf};
+
+    /// Room for the chords of the arcs in these tests.
+    type Output = Vec<MachineCommand, 64>;
+
+    struct Machine<D> {
+        state: ModalState,
+        resolver: CoordinateResolver,
+        arcs: ArcLinearizer,
+        dialect: D,
+    }
+
+    impl<D: MachineDialect> Machine<D> {
+        fn new(dialect: D) -> Self {
+            let resolver = CoordinateResolver::new(OffsetTables::default());
+            Self { state: ModalState::default(), resolver, arcs: ArcLinearizer::default(), dialect }
+        }
+
+        fn commands(&mut self, line: &str) -> Result<Output, ParseError> {
+            let tokens = lexer(line.as_bytes()).unwrap();
+            let mut out = Output::new();
+            for node in parse_line(&tokens, &mut self.state).unwrap() {
+                let node = self.resolver.resolve(node, &self.state).unwrap();
+                for node in self.arcs.linearize(node, &self.state).unwrap() {
+                    for command in self.dialect.interpret(&node, &mut self.state)? {
+                        out.push(command).unwrap();
+                    }
+                }
+            }
+            Ok(out)
+        }
+
+        /// For lines that make one command at most.
+        fn run(&mut self, line: &str) -> Result<Option<MachineCommand>, ParseError> {
+            let commands = self.commands(line)?;
+            assert!(commands.len() <= 1);
+            Ok(commands.first().copied())
+        }
+    }
+
+    fn heater(heater: Heater, celsius: f32, wait: HeaterWait) -> Option<MachineCommand> {
+        Some(MachineCommand::SetTemperature { heater, celsius, wait })
+    }
+
+    #[test]
+    fn moves_and_extrusion_modes() {
+        let mut p = Machine::new(FdmPrinterDialect::default());
+        assert_eq!(p.run("G1 X10"), Err(ParseError::MissingFeedrate));
+        assert_eq!(
+            p.run("G1 X10 Y5 E1 F1800"),
+            Ok(Some(MachineCommand::LinearMove { target: [10.0, 5.0, 0.0, 0.0, 0.0, 0.0, 1.0], feedrate: 1800.0 }))
+        );
+        // M83 makes E relative and leaves X and Y alone; the feedrate carries over.
+        assert_eq!(p.run("M83"), Ok(None));
+        assert_eq!(
+            p.run("G1 X20 E0.5"),
+            Ok(Some(MachineCommand::LinearMove { target: [20.0, 5.0, 0.0, 0.0, 0.0, 0.0, 1.5], feedrate: 1800.0 }))
+        );
+        p.run("M82").unwrap();
+        // Targets are machine positions, so E keeps counting through G92.
+        assert_eq!(p.run("G92 E0"), Ok(None));
+        assert_eq!(
+            p.run("G1 E2"),
+            Ok(Some(MachineCommand::LinearMove { target: [20.0, 5.0, 0.0, 0.0, 0.0, 0.0, 3.5], feedrate: 1800.0 }))
+        );
+        // G91 takes E with it, as in Marlin.
+        p.run("G91").unwrap();
+        assert!(matches!(p.run("G0 Z1 E-1"), Ok(Some(MachineCommand::RapidMove { target: [20.0, 5.0, 1.0, _, _, _, e] })) if e == 2.5));
+        assert_eq!(p.run("G4 P250"), Ok(Some(MachineCommand::Dwell(0.25))));
+        assert_eq!(p.run("G4 P-1"), Err(ParseError::InvalidDwell(-1.0)));
+    }
+
+    #[test]
+    fn temperatures_and_fans() {
+        let mut p = Machine::new(FdmPrinterDialect::default());
+        assert_eq!(p.run("M104 S210"), Ok(heater(Heater::Hotend(0), 210.0, HeaterWait::No)));
+        assert_eq!(p.run("M109 S215"), Ok(heater(Heater::Hotend(0), 215.0, HeaterWait::UntilHeated)));
+        assert_eq!(p.run("M109 R180"), Ok(heater(Heater::Hotend(0), 180.0, HeaterWait::UntilReached)));
+        assert_eq!(p.run("M140 S60"), Ok(heater(Heater::Bed, 60.0, HeaterWait::No)));
+        assert_eq!(p.run("M190 S60"), Ok(heater(Heater::Bed, 60.0, HeaterWait::UntilHeated)));
+        assert_eq!(p.run("M104 S0"), Ok(heater(Heater::Hotend(0), 0.0, HeaterWait::No)));
+        assert_eq!(p.run("M104"), Err(ParseError::MissingParameter('S')));
+        assert_eq!(p.run("M104 R200"), Err(ParseError::MissingParameter('S')));
+        assert_eq!(p.run("M104 S400"), Err(ParseError::InvalidTemperature(400.0)));
+        assert_eq!(p.run("M190 S150"), Err(ParseError::InvalidTemperature(150.0)));
+        assert_eq!(p.run("M104 T1 S200"), Err(ParseError::InvalidTool(1.0)));
+        // A T word on M104 names the hotend; it does not select a tool.
+        assert_eq!(p.state.next_tool, None);
+
+        assert_eq!(p.run("M106"), Ok(Some(MachineCommand::SetFan { fan: 0, speed: 1.0 })));
+        assert_eq!(p.run("M106 S127.5"), Ok(Some(MachineCommand::SetFan { fan: 0, speed: 0.5 })));
+        assert_eq!(p.run("M107"), Ok(Some(MachineCommand::SetFan { fan: 0, speed: 0.0 })));
+        assert_eq!(p.run("M106 S300"), Err(ParseError::InvalidFanSpeed(300.0)));
+        assert_eq!(p.run("M106 P1 S255"), Err(ParseError::InvalidFan(1.0)));
+        assert_eq!(p.run("M84"), Err(ParseError::UnsupportedCommand));
+
+        // An M-code beside a move runs before it, unless it stops the program.
+        let commands = p.commands("G1 X5 F1200 M106 S255").unwrap();
+        assert_eq!(commands[0], MachineCommand::SetFan { fan: 0, speed: 1.0 });
+        assert!(matches!(commands[1..], [MachineCommand::LinearMove { .. }]));
+        let commands = p.commands("G1 X10 M0").unwrap();
+        assert!(matches!(commands[..], [MachineCommand::LinearMove { .. }, MachineCommand::Pause]));
+    }
+
+    #[test]
+    fn retraction_homing_and_extruders() {
+        let mut p = Machine::new(FdmPrinterDialect::new(PrinterSettings { extruders: 2, ..PrinterSettings::default() }));
+        assert_eq!(p.run("G10"), Ok(Some(MachineCommand::Extrude { length: -3.0, feedrate: 2700.0 })));
+        assert_eq!(p.run("G10"), Ok(None));
+        assert_eq!(p.run("G11"), Ok(Some(MachineCommand::Extrude { length: 3.0, feedrate: 480.0 })));
+        assert_eq!(p.run("G11"), Ok(None));
+
+        p.run("G1 X5 Y6 Z7 F600").unwrap();
+        assert_eq!(p.run("G28 X0 Y0"), Ok(Some(MachineCommand::Home { x: true, y: true, z: false })));
+        assert_eq!(p.resolver.position()[..3], [0.0, 0.0, 7.0]);
+        assert_eq!(p.run("G28"), Ok(Some(MachineCommand::Home { x: true, y: true, z: true })));
+
+        assert_eq!(p.run("T1"), Ok(Some(MachineCommand::ToolChange(1))));
+        assert_eq!(p.state.tool, 1);
+        assert_eq!(p.run("M104 S200"), Ok(heater(Heater::Hotend(1), 200.0, HeaterWait::No)));
+        assert_eq!(p.run("M104 T0 S190"), Ok(heater(Heater::Hotend(0), 190.0, HeaterWait::No)));
+        assert_eq!(p.run("T2"), Err(ParseError::InvalidTool(2.0)));
+        assert_eq!(p.state.tool, 1);
+    }
+
+    fn condition(spark_gap: f32) -> Condition {
+        Condition { spark_gap, on_time: 1.0, off_time: 10.0, current: 8.0, voltage: 50.0, wire_speed: 8.0, wire_tension: 12.0 }
+    }
+
+    /// A 0.2 mm wire with the lower guide on the programme plane and the upper 50 mm above.
+    fn wire_edm() -> Machine<WireEdmDialect> {
+        let mut conditions = Vec::new();
+        conditions.extend_from_slice(&[condition(0.02), condition(0.05)]).unwrap();
+        let guides = GuideHeights { lower: 0.0, upper: 50.0, programme: 0.0 };
+        Machine::new(WireEdmDialect::new(WireSettings { wire_diameter: 0.2, guides, conditions }))
+    }
+
+    /// Checks that `commands` are guide moves to the given XY and UV positions.
+    fn assert_guides(commands: Result<Output, ParseError>, expected: &[([f32; 2], [f32; 2])]) {
+        let commands = commands.unwrap();
+        assert_eq!(commands.len(), expected.len(), "{:?}", commands);
+        for (command, (xy, uv)) in commands.iter().zip(expected) {
+            let MachineCommand::GuideMove { xy: lower, uv: upper, .. } = command else { panic!("{:?}", command) };
+            for (actual, expected) in lower.iter().chain(upper).zip(xy.iter().chain(uv)) {
+                assert_relative_eq!(actual, expected, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn tapered_square() {
+        let mut m = wire_edm();
+        // How far the upper guide leans in at 5°.
+        let t = tanf(5.0f32.to_radians()) * 50.0;
+        assert_eq!(m.run("T0"), Ok(Some(MachineCommand::SetCondition(condition(0.02)))));
+        assert_eq!(m.run("M60"), Ok(Some(MachineCommand::ThreadWire)));
+        assert_eq!(m.run("M80"), Ok(Some(MachineCommand::Machining(true))));
+        assert_eq!(m.run("G0 X10 Y-5"), Ok(Some(MachineCommand::GuideMove { xy: [10.0, -5.0], uv: [10.0, -5.0], feedrate: None })));
+
+        // Counter-clockwise with the wire leaning left, into the square: a block narrower at
+        // the top. The lead-in tilts the wire; each side then comes out once the next one
+        // shows how its corner is mitred.
+        assert_guides(m.commands("G51 A5"), &[]);
+        assert_guides(m.commands("G1 X10 Y0 F2"), &[]);
+        assert!(matches!(m.run("G1 X20"), Ok(Some(MachineCommand::GuideMove { feedrate: Some(f), .. })) if f == 2.0));
+        assert_guides(m.commands("G1 Y20"), &[([20.0, 0.0], [20.0 - t, t])]);
+        assert_guides(m.commands("G1 X0"), &[([20.0, 20.0], [20.0 - t, 20.0 - t])]);
+        assert_guides(m.commands("G1 Y0"), &[([0.0, 20.0], [t, 20.0 - t])]);
+        assert_guides(m.commands("G1 X10"), &[([0.0, 0.0], [t, t])]);
+        // G50 ends the contour square and the lead-out brings the wire upright.
+        assert_guides(m.commands("G50"), &[([10.0, 0.0], [10.0, t])]);
+        assert_guides(m.commands("G1 Y-5"), &[([10.0, -5.0], [10.0, -5.0])]);
+        assert_eq!(m.run("M81"), Ok(Some(MachineCommand::Machining(false))));
+        assert_eq!(m.run("M50"), Ok(Some(MachineCommand::CutWire)));
+    }
+
+    #[test]
+    fn wire_offset_with_taper() {
+        let mut m = wire_edm();
+        let t = tanf(5.0f32.to_radians()) * 50.0;
+        // Condition 1: 0.1 mm wire radius and 0.05 mm gap, wider across the leaning wire.
+        let k = 0.15 / libm::cosf(5.0f32.to_radians());
+        m.run("T1").unwrap();
+        m.run("G0 X10 Y-5").unwrap();
+        // G42 puts the wire outside the square, on the lower guide as on the upper.
+        assert_guides(m.commands("G42 G51 A5"), &[]);
+        assert_guides(m.commands("G1 X10 Y0 F2"), &[]);
+        assert_guides(m.commands("G1 X20"), &[([10.0, -k], [10.0, t - k])]);
+        assert_guides(m.commands("G1 Y20"), &[([20.0 + k, -k], [20.0 - t + k, t - k])]);
+        assert_guides(m.commands("G40 G50"), &[([20.0 + k, 20.0], [20.0 - t + k, 20.0])]);
+        assert_guides(m.commands("G1 X25"), &[([25.0, 20.0], [25.0, 20.0])]);
+    }
+
+    #[test]
+    fn wire_edm_vocabulary() {
+        let mut m = wire_edm();
+        let t = tanf(5.0f32.to_radians()) * 50.0;
+        assert_eq!(m.run("M80"), Err(ParseError::WireNotThreaded));
+        assert_eq!(m.run("T2"), Err(ParseError::InvalidTool(2.0)));
+        assert_eq!(m.run("G1 X5"), Err(ParseError::MissingFeedrate));
+        assert_eq!(m.run("G4 P1.5"), Ok(Some(MachineCommand::Dwell(1.5))));
+        assert!(parse_line(&lexer(b"G52").unwrap(), &mut ModalState::default()).is_err());
+
+        // A condition change mid-contour ends the side before it square.
+        m.run("G51 A5").unwrap();
+        m.run("G1 X10 F1").unwrap();
+        m.run("G1 X20").unwrap();
+        let commands = m.commands("T1").unwrap();
+        assert_guides(Ok(commands.iter().take(1).copied().collect()), &[([20.0, 0.0], [20.0, t])]);
+        assert_eq!(commands[1], MachineCommand::SetCondition(condition(0.05)));
+        // Doubling back leaves the mitre nowhere to go.
+        m.run("G1 X30").unwrap();
+        assert_eq!(m.run("G1 X25"), Err(ParseError::SharpCorner));
+        // An offset from a condition the table does not have.
+        assert_eq!(m.run("G41 D5"), Err(ParseError::InvalidTool(5.0)));
+    }
+
+    fn laser(power: LaserPower) -> impl Fn(&MachineCommand) -> bool {
+        move |command| matches!(command, MachineCommand::LaserMove { power: p, .. } if *p == power)
+    }
+
+    #[test]
+    fn laser_power_follows_the_moves() {
+        let mut m = Machine::new(LaserDialect::default());
+        // M3 arms the laser; nothing fires until the next feed move.
+        assert_eq!(m.run("M3 S500"), Ok(None));
+        assert!(m.run("G1 X10 F600").unwrap().as_ref().is_some_and(laser(LaserPower::Constant(0.5))));
+        // S on a move changes the power from that move on, and is held to the range.
+        assert!(m.run("G1 X20 S2000").unwrap().as_ref().is_some_and(laser(LaserPower::Constant(1.0))));
+        assert_eq!(m.run("S250"), Ok(None));
+        assert!(m.run("G1 X30").unwrap().as_ref().is_some_and(laser(LaserPower::Constant(0.25))));
+
+        // M4 scales the power with the head's speed: half power at half the feedrate.
+        m.run("M4").unwrap();
+        let Some(MachineCommand::LaserMove { power, .. }) = m.run("G1 X40 S1000").unwrap() else { panic!() };
+        assert_eq!(power, LaserPower::Dynamic(DynamicLaserPower::new(1.0, 600.0)));
+        assert_eq!(power.at(300.0), 0.5);
+        assert_eq!(power.at(600.0), 1.0);
+
+        // Rapids, S0 and M5 leave the beam off.
+        assert_eq!(m.run("G0 X0"), Ok(Some(MachineCommand::RapidMove { target: [0.0; AXES] })));
+        assert!(matches!(m.run("G1 X5 S0"), Ok(Some(MachineCommand::LinearMove { .. }))));
+        m.run("M5 S1000").unwrap();
+        assert!(matches!(m.run("G1 X10"), Ok(Some(MachineCommand::LinearMove { .. }))));
+        // M3 on the line of a move arms the laser for that move.
+        assert!(m.run("G1 X20 S1000 M3").unwrap().as_ref().is_some_and(laser(LaserPower::Constant(1.0))));
+    }
+
+    #[test]
+    fn air_assist_and_pierce() {
+        let settings = LaserSettings { air_delay_ms: 500, pierce_delay_ms: 150, ..LaserSettings::default() };
+        let mut m = Machine::new(LaserDialect::new(settings));
+        assert_eq!(m.run("M8"), Ok(Some(MachineCommand::AirAssist { on: true, delay_ms: 500 })));
+        m.run("M3 S800").unwrap();
+        m.run("G0 X5").unwrap();
+        // The first cut after a rapid pierces at its own power; the cut carries on without.
+        let commands = m.commands("G1 X10 F300").unwrap();
+        assert_eq!(commands[0], MachineCommand::Pierce { power: 0.8, duration_ms: 150 });
+        assert!(laser(LaserPower::Constant(0.8))(&commands[1]));
+        assert_eq!(m.commands("G1 Y10").unwrap().len(), 1);
+        m.run("G0 X20").unwrap();
+        assert_eq!(m.commands("G1 X30").unwrap().len(), 2);
+
+        assert_eq!(m.run("M9"), Ok(Some(MachineCommand::AirAssist { on: false, delay_ms: 0 })));
+        m.run("G0 X0").unwrap();
+        assert_eq!(m.commands("G1 X10").unwrap().len(), 1);
+
+        // Arcs are cut as chords, each ending on the circle.
+        let chords = m.commands("G2 X0 Y10 I-5").unwrap();
+        assert!(chords.len() > 4, "{:?}", chords);
+        for chord in &chords {
+            let MachineCommand::LaserMove { target, feedrate, power } = chord else { panic!("{:?}", chord) };
+            assert_relative_eq!(hypotf(target[0] - 5.0, target[1] - 10.0), 5.0, epsilon = 1e-4);
+            assert!(target[1] <= 10.0 + 1e-4, "clockwise from X10 runs below the centre");
+            assert_eq!((*feedrate, *power), (300.0, LaserPower::Constant(0.8)));
         }
+        let Some(MachineCommand::LaserMove { target, .. }) = chords.last() else { panic!() };
+        assert_eq!(target[..2], [0.0, 10.0]);
     }
 }
@@ -0,0 +1,680 @@
+//! RS274NGC parameters and expressions: `#1`, `#<_name>`, `[#1 * 2 + SIN[30]]`.
+//!
+//! [`substitute`] evaluates every expression and parameter on a line and writes the results
+//! back as plain numbers, so the line can go on to `crate::lexer::lexer`. Parameter settings
+//! (`#1 = [#2 * 2]`) are returned rather than applied: RS274NGC makes every setting on a line
+//! take effect once the whole line has been read, so `#1=2 X#1` moves to the old `#1`.
+//!
+//! Parameters `#1` to `#5399` read as zero until set. Named parameters belong to the
+//! subroutine call that set them unless the name starts with `_`, and reading one nobody set
+//! is an error. Angles are in degrees. Brackets and parameter references nest at most
+//! [`MAX_DEPTH`] deep, which bounds the evaluator's stack.
+
+use core::fmt::{self, Write};
+use heapless::{FnvIndexMap, String, Vec};
+use libm::{acosf, asinf, atan2f, ceilf, cosf, expf, fabsf, floorf, logf, powf, roundf, sinf, sqrtf, tanf};
+
+/// Longest line, before or after substitution.
+pub const MAX_LINE: usize = 256;
+/// Highest numbered parameter.
+pub const MAX_PARAMETER: u16 = 5399;
+/// `#1` to `#30`, which hold subroutine arguments.
+pub const ARGUMENTS: usize = 30;
+pub const MAX_NAME_LEN: usize = 32;
+/// Numbered parameters above `#30` that can hold a value at once.
+pub const MAX_NUMBERED: usize = 128;
+pub const MAX_GLOBALS: usize = 64;
+/// Named parameters local to one subroutine call.
+pub const MAX_LOCALS: usize = 16;
+pub const MAX_DEPTH: usize = 16;
+/// Parameter settings on one line.
+pub const MAX_SETTINGS: usize = 16;
+
+/// `EQ` and `NE` treat values this close as equal, as LinuxCNC does.
+const EQUAL_TOLERANCE: f32 = 1e-4;
+/// Operator precedence levels, from `**` (1) to `AND`/`OR`/`XOR` (5).
+const LEVELS: u8 = 5;
+const FUNCTIONS: [&str; 14] = [
+    "ABS", "ACOS", "ASIN", "ATAN", "COS", "EXISTS", "EXP", "FIX", "FUP", "LN", "ROUND", "SIN", "SQRT", "TAN",
+];
+
+pub type Line = Vec<u8, MAX_LINE>;
+pub type Name = String<MAX_NAME_LEN>;
+pub type Locals = FnvIndexMap<Name, f32, MAX_LOCALS>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parameter {
+    Numbered(u16),
+    /// Lowercase, without spaces.
+    Named(Name),
+}
+
+impl Parameter {
+    /// `None` unless `value` is a whole number from 1 to [`MAX_PARAMETER`].
+    pub fn numbered(value: f32) -> Option<Self> {
+        let rounded = roundf(value);
+        if fabsf(value - rounded) > 1e-4 || !(1.0..=f32::from(MAX_PARAMETER)).contains(&rounded) {
+            return None;
+        }
+        Some(Self::Numbered(rounded as u16))
+    }
+
+    pub fn is_global(&self) -> bool {
+        match self {
+            Self::Numbered(_) => true,
+            Self::Named(name) => name.starts_with('_'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Parameters {
+    pub(crate) arguments: [f32; ARGUMENTS],
+    numbered: FnvIndexMap<u16, f32, MAX_NUMBERED>,
+    globals: FnvIndexMap<Name, f32, MAX_GLOBALS>,
+    pub(crate) locals: Locals,
+}
+
+impl Parameters {
+    /// `None` for a named parameter nobody set.
+    pub fn get(&self, parameter: &Parameter) -> Option<f32> {
+        match parameter {
+            Parameter::Numbered(number @ 1..=30) => Some(self.arguments[usize::from(*number) - 1]),
+            Parameter::Numbered(number) => Some(self.numbered.get(number).copied().unwrap_or(0.0)),
+            Parameter::Named(name) if parameter.is_global() => self.globals.get(name).copied(),
+            Parameter::Named(name) => self.locals.get(name).copied(),
+        }
+    }
+
+    /// Returns false when the table for the parameter's kind is full.
+    pub fn set(&mut self, parameter: Parameter, value: f32) -> bool {
+        let global = parameter.is_global();
+        match parameter {
+            Parameter::Numbered(number @ 1..=30) => {
+                self.arguments[usize::from(number) - 1] = value;
+                true
+            }
+            Parameter::Numbered(number) => self.numbered.insert(number, value).is_ok(),
+            Parameter::Named(name) if global => self.globals.insert(name, value).is_ok(),
+            Parameter::Named(name) => self.locals.insert(name, value).is_ok(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExpressionErrorKind {
+    UnexpectedCharacter(char),
+    /// The line ended where a value should be.
+    MissingValue,
+    /// `[` without its `]`.
+    UnclosedBracket,
+    /// `#<` without its `>`.
+    UnclosedName,
+    NameTooLong,
+    /// Letters that are neither a function nor an operator.
+    UnknownWord,
+    /// A named parameter read before anything set it.
+    UndefinedParameter,
+    /// A parameter number that is not a whole number from 1 to 5399.
+    InvalidParameter(f32),
+    DivisionByZero,
+    /// The square root of a negative number, `LN[0]`, `ASIN[2]` and the like.
+    OutOfDomain,
+    /// A result too large to write back on the line.
+    OutOfRange,
+    TooDeep,
+    TooManySettings,
+    TooManyValues,
+    LineTooLong,
+}
+
+/// What went wrong, and where: `column` counts bytes from 1, as in `crate::lexer::LexError`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExpressionError {
+    pub column: usize,
+    pub kind: ExpressionErrorKind,
+}
+
+impl fmt::Display for ExpressionErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c.escape_default()),
+            Self::MissingValue => write!(f, "missing value"),
+            Self::UnclosedBracket => write!(f, "expression is missing its closing bracket"),
+            Self::UnclosedName => write!(f, "parameter name is missing its closing '>'"),
+            Self::NameTooLong => write!(f, "parameter name is longer than {} characters", MAX_NAME_LEN),
+            Self::UnknownWord => write!(f, "unknown function or operator"),
+            Self::UndefinedParameter => write!(f, "named parameter is not set"),
+            Self::InvalidParameter(number) => write!(f, "#{} is not a parameter from 1 to {}", number, MAX_PARAMETER),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::OutOfDomain => write!(f, "argument out of the function's domain"),
+            Self::OutOfRange => write!(f, "result out of range"),
+            Self::TooDeep => write!(f, "more than {} nested brackets or parameter references", MAX_DEPTH),
+            Self::TooManySettings => write!(f, "more than {} parameter settings on one line", MAX_SETTINGS),
+            Self::TooManyValues => write!(f, "more than {} values", ARGUMENTS),
+            Self::LineTooLong => write!(f, "line is longer than {} characters", MAX_LINE),
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Power,
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    And,
+    Or,
+    Xor,
+}
+
+impl Operator {
+    fn apply(self, a: f32, b: f32) -> Result<f32, ExpressionErrorKind> {
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+        let result = match self {
+            Self::Power => powf(a, b),
+            Self::Multiply => a * b,
+            Self::Divide | Self::Modulo if b == 0.0 => return Err(ExpressionErrorKind::DivisionByZero),
+            Self::Divide => a / b,
+            // The result takes the divisor's sign, as in LinuxCNC.
+            Self::Modulo => a - b * floorf(a / b),
+            Self::Add => a + b,
+            Self::Subtract => a - b,
+            Self::Equal => truth(fabsf(a - b) < EQUAL_TOLERANCE),
+            Self::NotEqual => truth(fabsf(a - b) >= EQUAL_TOLERANCE),
+            Self::Greater => truth(a > b),
+            Self::GreaterOrEqual => truth(a >= b),
+            Self::Less => truth(a < b),
+            Self::LessOrEqual => truth(a <= b),
+            Self::And => truth(a != 0.0 && b != 0.0),
+            Self::Or => truth(a != 0.0 || b != 0.0),
+            Self::Xor => truth((a != 0.0) != (b != 0.0)),
+        };
+        if result.is_nan() {
+            Err(ExpressionErrorKind::OutOfDomain)
+        } else if result.is_infinite() {
+            Err(ExpressionErrorKind::OutOfRange)
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+struct Parser<'a> {
+    line: &'a [u8],
+    pos: usize,
+    parameters: &'a Parameters,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_spaces(&mut self) {
+        while matches!(self.line.get(self.pos), Some(b' ' | b'\t' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    /// The next byte that is not a space.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.line.get(self.pos).copied()
+    }
+
+    fn error(&self, kind: ExpressionErrorKind) -> ExpressionError {
+        ExpressionError { column: self.pos + 1, kind }
+    }
+
+    fn unexpected(&self) -> ExpressionError {
+        match self.line.get(self.pos) {
+            Some(&c) => self.error(ExpressionErrorKind::UnexpectedCharacter(c as char)),
+            None => self.error(ExpressionErrorKind::MissingValue),
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), ExpressionError> {
+        if self.peek() != Some(byte) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Takes `word`, in any case, if it comes next.
+    fn word(&mut self, word: &str) -> bool {
+        self.skip_spaces();
+        let rest = &self.line[self.pos..];
+        if rest.len() >= word.len() && rest[..word.len()].eq_ignore_ascii_case(word.as_bytes()) {
+            self.pos += word.len();
+            return true;
+        }
+        false
+    }
+
+    /// Goes one level deeper at a bracket, sign or `#` in `column`.
+    fn enter(&mut self, column: usize) -> Result<(), ExpressionError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ExpressionError { column, kind: ExpressionErrorKind::TooDeep });
+        }
+        Ok(())
+    }
+
+    /// The contents of a `[...]`, with the opening bracket already taken.
+    fn bracketed(&mut self) -> Result<f32, ExpressionError> {
+        let open = self.pos;
+        self.enter(open)?;
+        let value = self.binary(LEVELS)?;
+        if self.peek() != Some(b']') {
+            return match self.line.get(self.pos) {
+                Some(_) => Err(self.unexpected()),
+                None => Err(ExpressionError { column: open, kind: ExpressionErrorKind::UnclosedBracket }),
+            };
+        }
+        self.pos += 1;
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn binary(&mut self, level: u8) -> Result<f32, ExpressionError> {
+        if level == 0 {
+            return self.value();
+        }
+        let mut left = self.binary(level - 1)?;
+        while let Some((operator, column)) = self.operator(level) {
+            let right = self.binary(level - 1)?;
+            left = operator.apply(left, right).map_err(|kind| ExpressionError { column, kind })?;
+        }
+        Ok(left)
+    }
+
+    /// Takes the next operator if it binds at `level`.
+    fn operator(&mut self, level: u8) -> Option<(Operator, usize)> {
+        self.skip_spaces();
+        let column = self.pos + 1;
+        let line = self.line;
+        let rest = &line[self.pos..];
+        let operator = match level {
+            1 if rest.starts_with(b"**") => {
+                self.pos += 2;
+                Operator::Power
+            }
+            2 if rest.starts_with(b"*") && !rest.starts_with(b"**") => {
+                self.pos += 1;
+                Operator::Multiply
+            }
+            2 if rest.starts_with(b"/") => {
+                self.pos += 1;
+                Operator::Divide
+            }
+            2 if self.word("MOD") => Operator::Modulo,
+            3 if rest.starts_with(b"+") => {
+                self.pos += 1;
+                Operator::Add
+            }
+            3 if rest.starts_with(b"-") => {
+                self.pos += 1;
+                Operator::Subtract
+            }
+            4 if self.word("EQ") => Operator::Equal,
+            4 if self.word("NE") => Operator::NotEqual,
+            4 if self.word("GT") => Operator::Greater,
+            4 if self.word("GE") => Operator::GreaterOrEqual,
+            4 if self.word("LT") => Operator::Less,
+            4 if self.word("LE") => Operator::LessOrEqual,
+            5 if self.word("AND") => Operator::And,
+            5 if self.word("OR") => Operator::Or,
+            5 if self.word("XOR") => Operator::Xor,
+            _ => return None,
+        };
+        Some((operator, column))
+    }
+
+    /// A number, bracketed expression, parameter or function, with any signs before it.
+    fn value(&mut self) -> Result<f32, ExpressionError> {
+        match self.peek() {
+            Some(b'[') => {
+                self.pos += 1;
+                self.bracketed()
+            }
+            Some(b'#') => {
+                let start = self.pos;
+                self.pos += 1;
+                let parameter = self.parameter()?;
+                self.parameters
+                    .get(&parameter)
+                    .ok_or(ExpressionError { column: start + 1, kind: ExpressionErrorKind::UndefinedParameter })
+            }
+            Some(sign @ (b'+' | b'-')) => {
+                self.pos += 1;
+                self.enter(self.pos)?;
+                let value = self.value()?;
+                self.depth -= 1;
+                Ok(if sign == b'-' { -value } else { value })
+            }
+            Some(b'0'..=b'9' | b'.') => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.function(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Digits with at most one decimal point, spaces allowed between them.
+    fn number(&mut self) -> Result<f32, ExpressionError> {
+        let start = self.pos;
+        let mut text: String<32> = String::new();
+        let mut seen_dot = false;
+        // Just past the last digit, before any spaces.
+        let mut end = self.pos;
+        while let Some(c) = self.peek() {
+            match c {
+                b'0'..=b'9' => {}
+                b'.' if !seen_dot => seen_dot = true,
+                _ => break,
+            }
+            text.push(c as char).map_err(|_| ExpressionError { column: start + 1, kind: ExpressionErrorKind::OutOfRange })?;
+            self.pos += 1;
+            end = self.pos;
+        }
+        self.pos = end;
+        text.parse()
+            .map_err(|_| ExpressionError { column: start + 1, kind: ExpressionErrorKind::UnexpectedCharacter('.') })
+    }
+
+    /// What follows a `#`: `<name>`, a number, or any value giving a number.
+    fn parameter(&mut self) -> Result<Parameter, ExpressionError> {
+        let start = self.pos;
+        if self.peek() == Some(b'<') {
+            self.pos += 1;
+            let mut name = Name::new();
+            loop {
+                match self.line.get(self.pos) {
+                    Some(b'>') => break,
+                    Some(b' ' | b'\t') => {}
+                    Some(&c) => name
+                        .push(c.to_ascii_lowercase() as char)
+                        .map_err(|_| self.error(ExpressionErrorKind::NameTooLong))?,
+                    None => return Err(ExpressionError { column: start + 1, kind: ExpressionErrorKind::UnclosedName }),
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            return Ok(Parameter::Named(name));
+        }
+        self.enter(start)?;
+        let number = self.value()?;
+        self.depth -= 1;
+        Parameter::numbered(number)
+            .ok_or(ExpressionError { column: start + 1, kind: ExpressionErrorKind::InvalidParameter(number) })
+    }
+
+    fn function(&mut self) -> Result<f32, ExpressionError> {
+        let start = self.pos;
+        let unknown = ExpressionError { column: start + 1, kind: ExpressionErrorKind::UnknownWord };
+        let mut name: String<8> = String::new();
+        while let Some(c) = self.line.get(self.pos).filter(|c| c.is_ascii_alphabetic()) {
+            name.push(c.to_ascii_uppercase() as char).map_err(|_| unknown)?;
+            self.pos += 1;
+        }
+        if !FUNCTIONS.contains(&name.as_str()) {
+            return Err(unknown);
+        }
+        self.expect(b'[')?;
+        if name == "EXISTS" {
+            self.expect(b'#')?;
+            let parameter = self.parameter()?;
+            self.expect(b']')?;
+            return Ok(if self.parameters.get(&parameter).is_some() { 1.0 } else { 0.0 });
+        }
+        let argument_column = self.pos + 1;
+        let argument = self.bracketed()?;
+        let domain = |valid: bool, value: f32| {
+            if valid {
+                Ok(value)
+            } else {
+                Err(ExpressionError { column: argument_column, kind: ExpressionErrorKind::OutOfDomain })
+            }
+        };
+        match name.as_str() {
+            "ABS" => Ok(fabsf(argument)),
+            "ACOS" => domain((-1.0..=1.0).contains(&argument), acosf(argument).to_degrees()),
+            "ASIN" => domain((-1.0..=1.0).contains(&argument), asinf(argument).to_degrees()),
+            "ATAN" => {
+                // `ATAN[y]/[x]`, the angle of the point (x, y).
+                self.expect(b'/')?;
+                self.expect(b'[')?;
+                let x = self.bracketed()?;
+                Ok(atan2f(argument, x).to_degrees())
+            }
+            "COS" => Ok(cosf(argument.to_radians())),
+            "EXP" => Ok(expf(argument)),
+            "FIX" => Ok(floorf(argument)),
+            "FUP" => Ok(ceilf(argument)),
+            "LN" => domain(argument > 0.0, logf(argument)),
+            "ROUND" => Ok(roundf(argument)),
+            "SIN" => Ok(sinf(argument.to_radians())),
+            "SQRT" => domain(argument >= 0.0, sqrtf(argument)),
+            _ => Ok(tanf(argument.to_radians())),
+        }
+    }
+
+    /// Whether a value starts here, rather than a number for the lexer.
+    fn value_ahead(&self) -> bool {
+        let mut pos = self.pos;
+        let skip = |pos: &mut usize| {
+            while matches!(self.line.get(*pos), Some(b' ' | b'\t')) {
+                *pos += 1;
+            }
+        };
+        skip(&mut pos);
+        while matches!(self.line.get(pos), Some(b'+' | b'-')) {
+            pos += 1;
+            skip(&mut pos);
+        }
+        match self.line.get(pos) {
+            Some(b'#' | b'[') => true,
+            Some(c) if c.is_ascii_alphabetic() => {
+                let end = pos + self.line[pos..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+                let mut bracket = end;
+                skip(&mut bracket);
+                self.line.get(bracket) == Some(&b'[')
+                    && FUNCTIONS.iter().any(|function| function.as_bytes().eq_ignore_ascii_case(&self.line[pos..end]))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A line with its expressions evaluated, and the parameter settings it makes.
+#[derive(Debug, Default)]
+pub struct Substituted {
+    pub line: Line,
+    pub settings: Vec<(Parameter, f32), MAX_SETTINGS>,
+}
+
+/// Evaluates the expressions and parameter references on `line`, leaving comments as they
+/// are. A word's value may be a number, `[...]`, `#...` or a function like `SIN[30]`.
+pub fn substitute(line: &[u8], parameters: &Parameters) -> Result<Substituted, ExpressionError> {
+    let mut parser = Parser { line, pos: 0, parameters, depth: 0 };
+    let mut substituted = Substituted::default();
+    let too_long = |column: usize| ExpressionError { column: column + 1, kind: ExpressionErrorKind::LineTooLong };
+    // Just after a word letter, where its value goes.
+    let mut value_next = false;
+    while let Some(&c) = line.get(parser.pos) {
+        let start = parser.pos;
+        if c == b'(' || c == b';' {
+            let end = match c {
+                b'(' => line[start..].iter().position(|&b| b == b')').map_or(line.len(), |close| start + close + 1),
+                _ => line.len(),
+            };
+            substituted.line.extend_from_slice(&line[start..end]).map_err(|_| too_long(start))?;
+            parser.pos = end;
+            value_next = false;
+        } else if c == b'#' && !value_next {
+            parser.pos += 1;
+            let parameter = parser.parameter()?;
+            parser.expect(b'=')?;
+            let value = parser.value()?;
+            substituted
+                .settings
+                .push((parameter, value))
+                .map_err(|_| ExpressionError { column: start + 1, kind: ExpressionErrorKind::TooManySettings })?;
+        } else if value_next && !matches!(c, b' ' | b'\t') && parser.value_ahead() {
+            let value = parser.value()?;
+            write_number(&mut substituted.line, value).map_err(|kind| ExpressionError { column: start + 1, kind })?;
+            value_next = false;
+        } else {
+            substituted.line.push(c).map_err(|_| too_long(start))?;
+            value_next = c.is_ascii_alphabetic() || (value_next && matches!(c, b' ' | b'\t'));
+            parser.pos += 1;
+        }
+    }
+    Ok(substituted)
+}
+
+/// Evaluates the values from `start` to the end of `line` or a comment, like the
+/// arguments of `O100 call [1.5] [#2]`.
+pub fn values(line: &[u8], start: usize, parameters: &Parameters) -> Result<Vec<f32, ARGUMENTS>, ExpressionError> {
+    let mut parser = Parser { line, pos: start, parameters, depth: 0 };
+    let mut values = Vec::new();
+    while !matches!(parser.peek(), None | Some(b'(' | b';')) {
+        let column = parser.pos + 1;
+        let value = parser.value()?;
+        values
+            .push(value)
+            .map_err(|_| ExpressionError { column, kind: ExpressionErrorKind::TooManyValues })?;
+    }
+    Ok(values)
+}
+
+/// Evaluates a single value, such as `[1 + 2]` or `#<_x>`, that makes up all of `text`.
+pub fn evaluate(text: &[u8], parameters: &Parameters) -> Result<f32, ExpressionError> {
+    let mut parser = Parser { line: text, pos: 0, parameters, depth: 0 };
+    let value = parser.value()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+    Ok(value)
+}
+
+/// Writes `value` to six decimal places without trailing zeros.
+fn write_number(line: &mut Line, value: f32) -> Result<(), ExpressionErrorKind> {
+    let mut text: String<32> = String::new();
+    write!(text, "{:.6}", value).map_err(|_| ExpressionErrorKind::OutOfRange)?;
+    let trimmed = text.trim_end_matches('0').trim_end_matches('.');
+    let trimmed = if trimmed == "-0" { "0" } else { trimmed };
+    line.extend_from_slice(trimmed.as_bytes()).map_err(|_| ExpressionErrorKind::LineTooLong)
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn eval(text: &str) -> f32 {
+        evaluate(text.as_bytes(), &Parameters::default()).unwrap()
+    }
+
+    fn error(text: &str, parameters: &Parameters) -> ExpressionError {
+        evaluate(text.as_bytes(), parameters).unwrap_err()
+    }
+
+    fn line(text: &str, parameters: &Parameters) -> Substituted {
+        substitute(text.as_bytes(), parameters).unwrap()
+    }
+
+    fn named(name: &str) -> Parameter {
+        Parameter::Named(Name::from(name))
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        assert_eq!(eval("[1 + 2 * 3]"), 7.0);
+        assert_eq!(eval("[[1 + 2] * 3]"), 9.0);
+        assert_eq!(eval("[2 ** 3 ** 2]"), 64.0);
+        assert_eq!(eval("[10 - 4 - 3]"), 3.0);
+        assert_eq!(eval("[-2 * -3]"), 6.0);
+        assert_eq!(eval("[7 MOD 3]"), 1.0);
+        assert_eq!(eval("[-7 mod 3]"), 2.0);
+        assert_eq!(eval("[1 + 1 EQ 2]"), 1.0);
+        assert_eq!(eval("[3 GT 2 AND 2 GE 3]"), 0.0);
+        assert_eq!(eval("[1 LT 2 XOR 2 LE 1]"), 1.0);
+        assert_eq!(eval("[0.1 + 0.2 NE 0.3]"), 0.0);
+    }
+
+    #[test]
+    fn functions_work_in_degrees() {
+        assert_relative_eq!(eval("SIN[30]"), 0.5, epsilon = 1e-6);
+        assert_relative_eq!(eval("[cos[60] * 2]"), 1.0, epsilon = 1e-6);
+        assert_relative_eq!(eval("TAN[45]"), 1.0, epsilon = 1e-6);
+        assert_relative_eq!(eval("ATAN[1]/[-1]"), 135.0, epsilon = 1e-4);
+        assert_relative_eq!(eval("ACOS[0]"), 90.0, epsilon = 1e-4);
+        assert_relative_eq!(eval("[LN[EXP[2]]]"), 2.0, epsilon = 1e-5);
+        assert_eq!(eval("[SQRT[16] + ABS[-1]]"), 5.0);
+        assert_eq!(eval("[FIX[-1.5] + FUP[1.2] + ROUND[2.5]]"), 3.0);
+    }
+
+    #[test]
+    fn parameters() {
+        let mut parameters = Parameters::default();
+        assert!(parameters.set(Parameter::Numbered(1), 4.0));
+        assert!(parameters.set(Parameter::Numbered(4), 100.0));
+        assert!(parameters.set(named("_depth"), -2.5));
+        assert_eq!(evaluate(b"#1", &parameters), Ok(4.0));
+        assert_eq!(evaluate(b"##1", &parameters), Ok(100.0));
+        assert_eq!(evaluate(b"#[#1 * 5]", &parameters), Ok(0.0));
+        assert_eq!(evaluate(b"[#< _Depth > * 2]", &parameters), Ok(-5.0));
+        assert_eq!(evaluate(b"[EXISTS[#<_depth>] + EXISTS[#<width>]]", &parameters), Ok(1.0));
+        assert_eq!(error("[#<width> + 1]", &parameters), ExpressionError { column: 2, kind: ExpressionErrorKind::UndefinedParameter });
+        assert_eq!(error("#[#1 - 4]", &parameters), ExpressionError { column: 2, kind: ExpressionErrorKind::InvalidParameter(0.0) });
+    }
+
+    #[test]
+    fn substitution_writes_plain_numbers() {
+        let mut parameters = Parameters::default();
+        parameters.set(Parameter::Numbered(1), 2.0);
+        parameters.set(named("_feed"), 1200.0);
+        let substituted = line("G1 X[#1 * 1.5] Y-#1 Z SIN[30] F#<_feed> (keep #1 [here])", &parameters);
+        assert_eq!(substituted.line.as_slice(), b"G1 X3 Y-2 Z 0.5 F1200 (keep #1 [here])");
+        assert!(substituted.settings.is_empty());
+
+        // Settings take effect after the line: X uses the old #1.
+        let substituted = line("#1 = [#1 + 1] #<step> = 0.25 G0 X#1", &parameters);
+        assert_eq!(substituted.line.as_slice(), b"  G0 X2");
+        assert_eq!(substituted.settings.as_slice(), [(Parameter::Numbered(1), 3.0), (named("step"), 0.25)]);
+
+        // Lines without expressions pass through untouched, checksums and all.
+        assert_eq!(line("N3 G1 X10.5 Y-2 E1e-3*47", &parameters).line.as_slice(), b"N3 G1 X10.5 Y-2 E1e-3*47");
+    }
+
+    #[test]
+    fn positioned_errors() {
+        let parameters = Parameters::default();
+        assert_eq!(error("[1 / [2 - 2]]", &parameters), ExpressionError { column: 4, kind: ExpressionErrorKind::DivisionByZero });
+        assert_eq!(error("SQRT[-1]", &parameters), ExpressionError { column: 6, kind: ExpressionErrorKind::OutOfDomain });
+        assert_eq!(error("[1 + 2", &parameters), ExpressionError { column: 1, kind: ExpressionErrorKind::UnclosedBracket });
+        assert_eq!(error("[1 + FOO[2]]", &parameters), ExpressionError { column: 6, kind: ExpressionErrorKind::UnknownWord });
+        assert_eq!(error("[1 +]", &parameters), ExpressionError { column: 5, kind: ExpressionErrorKind::UnexpectedCharacter(']') });
+        assert_eq!(
+            error("[[[[[[[[[[[[[[[[[1]]]]]]]]]]]]]]]]]", &parameters),
+            ExpressionError { column: 17, kind: ExpressionErrorKind::TooDeep }
+        );
+        assert_eq!(
+            substitute(b"G1 X[1 +]", &parameters).unwrap_err(),
+            ExpressionError { column: 9, kind: ExpressionErrorKind::UnexpectedCharacter(']') }
+        );
+    }
+}
@@ -1,113 +1,358 @@
-use nom::{
-    branch::alt,
-    bytes::complete::{is_not, tag},
-    character::complete::{alpha1, char, digit1, multispace0, space0},
-    combinator::{map, map_res, opt},
-    multi::many0,
-    sequence::{delimited, preceded, tuple},
-    IResult,
-};
-use heapless::Vec;
+//! Splits one line of G-code into words.
+//!
+//! Accepts what CAM post-processors and slicers actually write: lowercase letters, spaces
+//! anywhere outside comments (`G 1 X 1 0` is `G1 X10`), numbers like `.5`, `15.`, `+1` and
+//! `1e-3`, `(...)` and `;` comments, `N` line numbers, `O` program numbers, `%` tape marks,
+//! a leading `/` (block delete, treated as off) and RepRap `*NN` checksums.
+
+use core::fmt;
+use heapless::{String, Vec};
+use libm::fabsf;
+
+pub const MAX_TOKENS: usize = 64;
+/// Longest number text, signs and exponent included.
+const MAX_NUMBER_LEN: usize = 32;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Token<'a> {
     G(u16),
+    /// `G<code>.<sub>`, e.g. G90.1 or G59.3. `G1.0` lexes as `G(1)`.
+    GSub(u16, u8),
     M(u16),
+    /// `X`, `Y`, `Z`, `A`, `B`, `C`, `E`, `U`, `V` or `W`.
     Axis(char, f32),
+    /// `I`, `J` or `K`: arc centre offset along X, Y or Z.
+    ArcOffset(char, f32),
+    /// `R`: arc radius.
+    Radius(f32),
     Feedrate(f32),
     SpindleSpeed(f32),
     P(f32),
+    /// Any other word: `T`, `H`, `D`, `Q` or `L`.
+    Word(char, f32),
     Comment(&'a str),
     LineNumber(u32),
+    ProgramNumber(u32),
 }
 
-fn parse_u16(input: &[u8]) -> IResult<&[u8], u16> {
-    map_res(digit1, |s: &[u8]| core::str::from_utf8(s).unwrap().parse::<u16>())(input)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    /// A word letter with no number after it.
+    MissingValue(char),
+    InvalidNumber(char),
+    UnterminatedComment,
+    InvalidUtf8,
+    TooManyTokens,
+    /// `*` without a checksum, or anything but whitespace after it.
+    MalformedChecksum,
+    ChecksumMismatch { given: u8, computed: u8 },
 }
 
-fn parse_f32(input: &[u8]) -> IResult<&[u8], f32> {
-    map_res(
-        tuple((opt(char('-')), digit1, opt(preceded(char('.'), digit1)))),
-        |(sign, int_part, frac_part)| {
-            let mut s = core::str::from_utf8(int_part).unwrap().to_string();
-            if let Some(frac) = frac_part {
-                s.push('.');
-                s.push_str(core::str::from_utf8(frac).unwrap());
-            }
-            if sign.is_some() {
-                s.insert(0, '-');
-            }
-            s.parse::<f32>()
-        },
-    )(input)
+/// What went wrong, and where: `column` counts bytes from 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LexError {
+    pub column: usize,
+    pub kind: LexErrorKind,
 }
 
-fn parse_u32(input: &[u8]) -> IResult<&[u8], u32> {
-    map_res(digit1, |s: &[u8]| core::str::from_utf8(s).unwrap().parse::<u32>())(input)
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c.escape_default()),
+            LexErrorKind::MissingValue(word) => write!(f, "{} word has no value", word),
+            LexErrorKind::InvalidNumber(word) => write!(f, "invalid number for {} word", word),
+            LexErrorKind::UnterminatedComment => write!(f, "comment is missing its closing parenthesis"),
+            LexErrorKind::InvalidUtf8 => write!(f, "comment is not valid UTF-8"),
+            LexErrorKind::TooManyTokens => write!(f, "more than {} words on one line", MAX_TOKENS),
+            LexErrorKind::MalformedChecksum => write!(f, "checksum must be a number from 0 to 255 ending the line"),
+            LexErrorKind::ChecksumMismatch { given, computed } => {
+                write!(f, "checksum {} does not match the line's {}", given, computed)
+            }
+        }
+    }
 }
 
-fn g_code(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('G'), parse_u16), Token::G)(input)
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.kind)
+    }
 }
 
-fn m_code(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('M'), parse_u16), Token::M)(input)
+struct Cursor<'a> {
+    line: &'a [u8],
+    pos: usize,
 }
 
-fn axis(input: &[u8]) -> IResult<&[u8], Token> {
-    map(
-        tuple((
-            alt((
-                char('X'), char('Y'), char('Z'), char('A'), char('B'), char('C'), char('E'),
-            )),
-            parse_f32,
-        )),
-        |(axis, value)| Token::Axis(axis, value),
-    )(input)
-}
+impl<'a> Cursor<'a> {
+    fn skip_spaces(&mut self) {
+        while matches!(self.line.get(self.pos), Some(b' ' | b'\t' | b'\r')) {
+            self.pos += 1;
+        }
+    }
 
-fn feedrate(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('F'), parse_f32), Token::Feedrate)(input)
-}
+    /// The next byte that is not a space.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.line.get(self.pos).copied()
+    }
 
-fn spindle_speed(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('S'), parse_f32), Token::SpindleSpeed)(input)
-}
+    fn error(&self, kind: LexErrorKind) -> LexError {
+        LexError { column: self.pos + 1, kind }
+    }
 
-fn p_value(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('P'), parse_f32), Token::P)(input)
-}
+    fn text(&self, start: usize, end: usize) -> Result<&'a str, LexError> {
+        core::str::from_utf8(&self.line[start..end]).map_err(|_| LexError { column: start + 1, kind: LexErrorKind::InvalidUtf8 })
+    }
 
-fn comment(input: &[u8]) -> IResult<&[u8], Token> {
-    map(
-        alt((
-            delimited(char('('), is_not(")"), char(')')),
-            preceded(char(';'), is_not("\r\n")),
-        )),
-        |s: &[u8]| Token::Comment(core::str::from_utf8(s).unwrap()),
-    )(input)
-}
+    /// The number after `word`, with spaces removed. `exponent` allows a `1e-3` exponent.
+    fn number(&mut self, word: char, exponent: bool) -> Result<String<MAX_NUMBER_LEN>, LexError> {
+        let start = self.pos;
+        let invalid = LexError { column: start + 1, kind: LexErrorKind::InvalidNumber(word) };
+        let mut text = String::new();
+        let mut push = |c: u8| text.push(c as char).map_err(|_| invalid);
+        let mut signed = false;
+        if let Some(sign @ (b'+' | b'-')) = self.peek() {
+            push(sign)?;
+            self.pos += 1;
+            signed = true;
+        }
+        let mut digits = 0;
+        let mut seen_dot = false;
+        // Just past the last character taken, before any spaces.
+        let mut end = self.pos;
+        while let Some(c) = self.peek() {
+            match c {
+                b'0'..=b'9' => digits += 1,
+                b'.' if !seen_dot => seen_dot = true,
+                _ => break,
+            }
+            push(c)?;
+            self.pos += 1;
+            end = self.pos;
+        }
+        if digits == 0 {
+            let kind = if signed || seen_dot { LexErrorKind::InvalidNumber(word) } else { LexErrorKind::MissingValue(word) };
+            return Err(LexError { column: start + 1, kind });
+        }
+        // Only a lowercase `e` right after the digits and followed by a sign starts an
+        // exponent, so that `X10 E-2` and compact slicer output like `X10E2.5` or `x10e2`
+        // keep their `E` words. `x10e-2` is still X0.1.
+        if exponent && self.line.get(end) == Some(&b'e') && matches!(self.line.get(end + 1), Some(b'+' | b'-')) {
+            let mut exponent_end = end + 2;
+            let digits_start = exponent_end;
+            while matches!(self.line.get(exponent_end), Some(b'0'..=b'9')) {
+                exponent_end += 1;
+            }
+            if exponent_end > digits_start {
+                for &c in &self.line[end..exponent_end] {
+                    push(c)?;
+                }
+                self.pos = exponent_end;
+            }
+        }
+        Ok(text)
+    }
+
+    fn float(&mut self, word: char) -> Result<f32, LexError> {
+        self.decimal(word, true)
+    }
 
-fn line_number(input: &[u8]) -> IResult<&[u8], Token> {
-    map(preceded(char('N'), parse_u32), Token::LineNumber)(input)
+    fn decimal(&mut self, word: char, exponent: bool) -> Result<f32, LexError> {
+        let start = self.pos;
+        let text = self.number(word, exponent)?;
+        text.parse()
+            .map_err(|_| LexError { column: start + 1, kind: LexErrorKind::InvalidNumber(word) })
+    }
+
+    fn integer(&mut self, word: char) -> Result<u32, LexError> {
+        let start = self.pos;
+        let text = self.number(word, false)?;
+        text.parse()
+            .map_err(|_| LexError { column: start + 1, kind: LexErrorKind::InvalidNumber(word) })
+    }
+
+    /// `G` and `M` numbers: whole, or with one decimal digit like `G59.3`.
+    fn code(&mut self, word: char) -> Result<(u16, u8), LexError> {
+        let start = self.pos;
+        let invalid = LexError { column: start + 1, kind: LexErrorKind::InvalidNumber(word) };
+        let value = self.decimal(word, false)?;
+        let tenths = value * 10.0;
+        let rounded = (tenths + 0.5) as u32;
+        if !(0.0..65536.0).contains(&value) || fabsf(tenths - rounded as f32) > 1e-3 {
+            return Err(invalid);
+        }
+        Ok(((rounded / 10) as u16, (rounded % 10) as u8))
+    }
+
+    /// Checks a `*NN` checksum: the XOR of every byte before the `*`.
+    fn checksum(&mut self) -> Result<(), LexError> {
+        let star = self.pos;
+        let computed = self.line[..star].iter().fold(0u8, |sum, b| sum ^ b);
+        self.pos += 1;
+        let digits_start = self.pos;
+        while matches!(self.line.get(self.pos), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        let given = core::str::from_utf8(&self.line[digits_start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse::<u8>().ok());
+        let rest_is_blank = self.peek().is_none();
+        let column = star + 1;
+        match given {
+            Some(given) if rest_is_blank && given == computed => Ok(()),
+            Some(given) if rest_is_blank => Err(LexError { column, kind: LexErrorKind::ChecksumMismatch { given, computed } }),
+            _ => Err(LexError { column, kind: LexErrorKind::MalformedChecksum }),
+        }
+    }
 }
 
-fn token(input: &[u8]) -> IResult<&[u8], Token> {
-    preceded(
-        space0,
-        alt((
-            g_code,
-            m_code,
-            axis,
-            feedrate,
-            spindle_speed,
-            p_value,
-            comment,
-            line_number,
-        )),
-    )(input)
+/// Lexes one line, without its line ending.
+pub fn lexer(line: &[u8]) -> Result<Vec<Token<'_>, MAX_TOKENS>, LexError> {
+    let mut cursor = Cursor { line, pos: 0 };
+    let mut tokens = Vec::new();
+
+    if cursor.peek() == Some(b'/') {
+        cursor.pos += 1;
+    }
+    if cursor.peek() == Some(b'%') {
+        cursor.pos += 1;
+        if cursor.peek().is_none() {
+            return Ok(tokens);
+        }
+        return Err(cursor.error(LexErrorKind::UnexpectedCharacter('%')));
+    }
+
+    while let Some(c) = cursor.peek() {
+        let start = cursor.pos;
+        let token = match c {
+            b'(' => {
+                let close = line[start..]
+                    .iter()
+                    .position(|&b| b == b')')
+                    .ok_or_else(|| cursor.error(LexErrorKind::UnterminatedComment))?;
+                cursor.pos = start + close + 1;
+                Token::Comment(cursor.text(start + 1, start + close)?)
+            }
+            b';' => {
+                let end = line.len() - line.iter().rev().take_while(|&&b| b == b'\r').count();
+                cursor.pos = line.len();
+                Token::Comment(cursor.text(start + 1, end.max(start + 1))?)
+            }
+            b'*' => {
+                cursor.checksum()?;
+                break;
+            }
+            c if c.is_ascii_alphabetic() => {
+                let word = c.to_ascii_uppercase() as char;
+                cursor.pos += 1;
+                match word {
+                    'G' => match cursor.code(word)? {
+                        (code, 0) => Token::G(code),
+                        (code, sub) => Token::GSub(code, sub),
+                    },
+                    'M' => match cursor.code(word)? {
+                        (code, 0) => Token::M(code),
+                        _ => return Err(LexError { column: start + 2, kind: LexErrorKind::InvalidNumber(word) }),
+                    },
+                    'N' => Token::LineNumber(cursor.integer(word)?),
+                    'O' => Token::ProgramNumber(cursor.integer(word)?),
+                    'X' | 'Y' | 'Z' | 'A' | 'B' | 'C' | 'E' | 'U' | 'V' | 'W' => Token::Axis(word, cursor.float(word)?),
+                    'I' | 'J' | 'K' => Token::ArcOffset(word, cursor.float(word)?),
+                    'R' => Token::Radius(cursor.float(word)?),
+                    'F' => Token::Feedrate(cursor.float(word)?),
+                    'S' => Token::SpindleSpeed(cursor.float(word)?),
+                    'P' => Token::P(cursor.float(word)?),
+                    'T' | 'H' | 'D' | 'Q' | 'L' => Token::Word(word, cursor.float(word)?),
+                    _ => return Err(LexError { column: start + 1, kind: LexErrorKind::UnexpectedCharacter(word) }),
+                }
+            }
+            other => return Err(cursor.error(LexErrorKind::UnexpectedCharacter(other as char))),
+        };
+        tokens
+            .push(token)
+            .map_err(|_| LexError { column: start + 1, kind: LexErrorKind::TooManyTokens })?;
+    }
+    Ok(tokens)
 }
 
-pub fn lexer(input: &[u8]) -> IResult<&[u8], Vec<Token, 64>> {
-    preceded(multispace0, many0(token))(input)
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(line: &str) -> Vec<Token<'_>, MAX_TOKENS> {
+        lexer(line.as_bytes()).unwrap()
+    }
+
+    fn error(line: &str) -> LexError {
+        lexer(line.as_bytes()).unwrap_err()
+    }
+
+    #[test]
+    fn number_forms() {
+        assert_eq!(
+            lex("G1 X.5 Y-.25 Z+1 E15. F1e+3 S2.5e+2"),
+            [
+                Token::G(1),
+                Token::Axis('X', 0.5),
+                Token::Axis('Y', -0.25),
+                Token::Axis('Z', 1.0),
+                Token::Axis('E', 15.0),
+                Token::Feedrate(1000.0),
+                Token::SpindleSpeed(250.0),
+            ]
+        );
+        assert_eq!(lex("G1 X10E2.5"), [Token::G(1), Token::Axis('X', 10.0), Token::Axis('E', 2.5)]);
+        assert_eq!(lex("G1 X1e-3"), [Token::G(1), Token::Axis('X', 0.001)]);
+        // Without a sign a lowercase `e` is the E word, and codes never take an exponent.
+        assert_eq!(lex("g1x10e2"), [Token::G(1), Token::Axis('X', 10.0), Token::Axis('E', 2.0)]);
+        assert_eq!(lex("G1 F1e3"), [Token::G(1), Token::Feedrate(1.0), Token::Axis('E', 3.0)]);
+        assert_eq!(lex("g1e-2"), [Token::G(1), Token::Axis('E', -2.0)]);
+        assert_eq!(lex("G1 X10 E-2"), [Token::G(1), Token::Axis('X', 10.0), Token::Axis('E', -2.0)]);
+    }
+
+    #[test]
+    fn case_spacing_and_codes() {
+        assert_eq!(lex("g 1 x 1 0 . 5 y-2"), [Token::G(1), Token::Axis('X', 10.5), Token::Axis('Y', -2.0)]);
+        assert_eq!(lex("G90.1 G59.3 G01 G1.0 M03"), [Token::GSub(90, 1), Token::GSub(59, 3), Token::G(1), Token::G(1), Token::M(3)]);
+        assert_eq!(lex("N120 T2 M6"), [Token::LineNumber(120), Token::Word('T', 2.0), Token::M(6)]);
+        assert_eq!(lex("O1001(ADAPTIVE)"), [Token::ProgramNumber(1001), Token::Comment("ADAPTIVE")]);
+        assert_eq!(lex("%"), []);
+        assert_eq!(lex("/G0 Z5"), [Token::G(0), Token::Axis('Z', 5.0)]);
+        assert_eq!(lex("  \r"), []);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            lex("G0 (rapid) X1 ; to the corner\r"),
+            [Token::G(0), Token::Comment("rapid"), Token::Axis('X', 1.0), Token::Comment(" to the corner")]
+        );
+        assert_eq!(lex("(T1 D=6. (FLAT)"), [Token::Comment("T1 D=6. (FLAT")]);
+        assert_eq!(lex(";"), [Token::Comment("")]);
+    }
+
+    #[test]
+    fn checksums() {
+        // Marlin's example: the XOR of "N3 T0" is 57.
+        assert_eq!(lex("N3 T0*57"), [Token::LineNumber(3), Token::Word('T', 0.0)]);
+        assert_eq!(lex("N3 T0*57 \r"), [Token::LineNumber(3), Token::Word('T', 0.0)]);
+        assert_eq!(error("N3 T0*58"), LexError { column: 6, kind: LexErrorKind::ChecksumMismatch { given: 58, computed: 57 } });
+        assert_eq!(error("N3 T0*"), LexError { column: 6, kind: LexErrorKind::MalformedChecksum });
+        assert_eq!(error("N3 T0*57 X1"), LexError { column: 6, kind: LexErrorKind::MalformedChecksum });
+    }
+
+    #[test]
+    fn positioned_errors() {
+        assert_eq!(error("G1 X"), LexError { column: 5, kind: LexErrorKind::MissingValue('X') });
+        assert_eq!(error("G1 X- Y2"), LexError { column: 5, kind: LexErrorKind::InvalidNumber('X') });
+        assert_eq!(error("G1 X1.2.3"), LexError { column: 8, kind: LexErrorKind::UnexpectedCharacter('.') });
+        assert_eq!(error("G1 (oops X1"), LexError { column: 4, kind: LexErrorKind::UnterminatedComment });
+        assert_eq!(error("G1 Ö"), LexError { column: 4, kind: LexErrorKind::UnexpectedCharacter('\u{c3}') });
+        assert_eq!(error("G1 $1"), LexError { column: 4, kind: LexErrorKind::UnexpectedCharacter('$') });
+        assert_eq!(error("M3.5"), LexError { column: 2, kind: LexErrorKind::InvalidNumber('M') });
+        assert_eq!(error("G-1"), LexError { column: 2, kind: LexErrorKind::InvalidNumber('G') });
+        assert_eq!(error("G1 % X1"), LexError { column: 4, kind: LexErrorKind::UnexpectedCharacter('%') });
+    }
 }
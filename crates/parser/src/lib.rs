@@ -1,7 +1,16 @@
 #![no_std]
 
+pub mod expression;
+pub mod program;
 pub mod lexer;
 pub mod ast;
+pub mod arc;
+pub mod compensation;
+pub mod coordinates;
+pub mod cycles;
 pub mod modal;
+pub mod tool_table;
+pub mod wire;
 pub mod dialect;
+pub mod lines;
 pub mod stream;
@@ -0,0 +1,100 @@
+//! Reassembles lines from a byte stream.
+//!
+//! A UART read can end anywhere, so bytes are gathered until a newline ends the line. A
+//! line longer than [`MAX_LINE`], or one the UART reported an error on while it was
+//! arriving, is not passed on in part: the rest of it is read up to its newline and the
+//! whole line comes out as an error, so that every line sent gets exactly one answer.
+
+use core::fmt;
+
+use crate::expression::{Line, MAX_LINE};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LineError {
+    TooLong,
+    /// The UART reported an error while the line was arriving; some of it may be missing.
+    Garbled,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong => write!(f, "line is longer than {} characters", MAX_LINE),
+            Self::Garbled => write!(f, "line was garbled in transmission"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LineAssembler {
+    line: Line,
+    error: Option<LineError>,
+    /// The last call completed a line, which is cleared when the next one starts.
+    complete: bool,
+}
+
+impl LineAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes bytes up to and including the first newline in `bytes`. Returns how many were
+    /// taken, and the line if one was completed, without its newline. Bytes after the
+    /// newline are left for the next call.
+    pub fn feed(&mut self, bytes: &[u8]) -> (usize, Option<Result<&[u8], LineError>>) {
+        if self.complete {
+            self.line.clear();
+            self.complete = false;
+        }
+        let (part, taken, complete) = match bytes.iter().position(|&b| b == b'\n') {
+            Some(end) => (&bytes[..end], end + 1, true),
+            None => (bytes, bytes.len(), false),
+        };
+        if self.error.is_none() && self.line.extend_from_slice(part).is_err() {
+            self.error = Some(LineError::TooLong);
+        }
+        if !complete {
+            return (taken, None);
+        }
+        self.complete = true;
+        let line = match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(&self.line[..]),
+        };
+        (taken, Some(line))
+    }
+
+    /// Marks the line now arriving as garbled.
+    pub fn garble(&mut self) {
+        self.error = Some(LineError::Garbled);
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_across_reads() {
+        let mut lines = LineAssembler::new();
+        assert_eq!(lines.feed(b"G1 X1"), (5, None));
+        assert_eq!(lines.feed(b"0 Y2\nG0"), (5, Some(Ok(&b"G1 X10 Y2"[..]))));
+        assert_eq!(lines.feed(b"G0"), (2, None));
+        assert_eq!(lines.feed(b"\n\n"), (1, Some(Ok(&b"G0"[..]))));
+        assert_eq!(lines.feed(b"\n"), (1, Some(Ok(&b""[..]))));
+
+        // An overlong line is read to its end and answered once.
+        let long = [b'X'; MAX_LINE + 1];
+        assert_eq!(lines.feed(&long), (MAX_LINE + 1, None));
+        assert_eq!(lines.feed(b"1\nM2\n"), (2, Some(Err(LineError::TooLong))));
+        assert_eq!(lines.feed(b"M2\n"), (3, Some(Ok(&b"M2"[..]))));
+
+        lines.feed(b"G1 X");
+        lines.garble();
+        assert_eq!(lines.feed(b"5\n"), (2, Some(Err(LineError::Garbled))));
+        lines.garble();
+        assert_eq!(lines.feed(b"G1 X5\n"), (6, Some(Err(LineError::Garbled))));
+        assert_eq!(lines.feed(b"G1 X5\n"), (6, Some(Ok(&b"G1 X5"[..]))));
+    }
+}
@@ -13,8 +13,69 @@ pub enum Units {
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DistanceMode {
-    Absolute, // G90
-    Relative, // G91
+    Absolute, // G90, or G90.1 for arc centres
+    Relative, // G91, or G91.1 for arc centres
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Rapid,                // G0
+    Linear,               // G1
+    ClockwiseArc,         // G2
+    CounterClockwiseArc,  // G3
+    Cycle(Cycle),         // G73, G81–G89
+}
+
+/// Canned drilling cycles; see `crate::cycles`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cycle {
+    ChipBreakDrill,   // G73
+    Drill,            // G81
+    DwellDrill,       // G82
+    PeckDrill,        // G83
+    Tap,              // G84
+    Bore,             // G85
+    BoreStopRapidOut, // G86
+    BackBore,         // G87
+    BoreManualOut,    // G88
+    BoreDwell,        // G89
+}
+
+/// Where a canned cycle retracts to between holes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetractMode {
+    InitialLevel, // G98
+    RPlane,       // G99
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spindle {
+    Clockwise,        // M3
+    CounterClockwise, // M4
+    Off,              // M5
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompensationSide {
+    Left,  // G41
+    Right, // G42
+}
+
+/// A tool's diameter or length: from its entry in the tool table, or given on the line in
+/// millimetres (G41.1, G42.1, G43.1).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToolDimension {
+    Tool(u16),
+    Given(f32),
+}
+
+/// Canned cycle words that carry over to the following holes until G80.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CycleParameters {
+    pub z: Option<f32>,
+    pub r: Option<f32>,
+    pub q: Option<f32>,
+    pub p: Option<f32>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -32,6 +93,33 @@ pub enum CoordinateSystem {
     G57,
     G58,
     G59,
+    G59_1,
+    G59_2,
+    G59_3,
+}
+
+impl CoordinateSystem {
+    pub const ALL: [CoordinateSystem; 9] = [
+        CoordinateSystem::G54,
+        CoordinateSystem::G55,
+        CoordinateSystem::G56,
+        CoordinateSystem::G57,
+        CoordinateSystem::G58,
+        CoordinateSystem::G59,
+        CoordinateSystem::G59_1,
+        CoordinateSystem::G59_2,
+        CoordinateSystem::G59_3,
+    ];
+
+    /// Position in the offset tables: 0 for G54 up to 8 for G59.3. G10 numbers them from
+    /// `P1`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -39,8 +127,31 @@ pub struct ModalState {
     pub plane: Plane,
     pub units: Units,
     pub distance_mode: DistanceMode,
+    /// How `E` is given: M82/M83, and G90/G91 as well, as in Marlin.
+    pub extrusion_mode: DistanceMode,
     pub feedrate_mode: FeedrateMode,
     pub coordinate_system: CoordinateSystem,
+    /// How `I`/`J`/`K` arc centres are given.
+    pub arc_distance_mode: DistanceMode,
+    /// The motion a line with only axis words repeats; `None` until the first G0–G3 and
+    /// after G80.
+    pub motion: Option<Motion>,
+    pub retract_mode: RetractMode,
+    pub cycle: CycleParameters,
+    pub spindle: Spindle,
+    /// The last `F` and `S` words, as written.
+    pub feedrate: Option<f32>,
+    pub spindle_speed: Option<f32>,
+    /// G41/G42 and the diameter to offset by; `None` after G40.
+    pub cutter_compensation: Option<(CompensationSide, ToolDimension)>,
+    /// G43/G43.1; `None` after G49.
+    pub tool_length_offset: Option<ToolDimension>,
+    /// Wire EDM taper: the wire leans this many degrees to the left (G51) or right (G52)
+    /// of the direction of travel; `None` after G50.
+    pub taper: Option<(CompensationSide, f32)>,
+    /// The tool in the spindle, and the one `T` selected for the next M6.
+    pub tool: u16,
+    pub next_tool: Option<u16>,
 }
 
 impl Default for ModalState {
@@ -49,8 +160,21 @@ impl Default for ModalState {
             plane: Plane::XY,
             units: Units::Millimeters,
             distance_mode: DistanceMode::Absolute,
+            extrusion_mode: DistanceMode::Absolute,
             feedrate_mode: FeedrateMode::UnitsPerMinute,
             coordinate_system: CoordinateSystem::G54,
+            arc_distance_mode: DistanceMode::Relative,
+            motion: None,
+            retract_mode: RetractMode::InitialLevel,
+            cycle: CycleParameters::default(),
+            spindle: Spindle::Off,
+            feedrate: None,
+            spindle_speed: None,
+            cutter_compensation: None,
+            tool_length_offset: None,
+            taper: None,
+            tool: 0,
+            next_tool: None,
         }
     }
 }
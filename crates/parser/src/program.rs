@@ -0,0 +1,677 @@
+//! O-word control flow: subroutines, `if` blocks and `while`/`do` loops.
+//!
+//! Lines go in through [`Program::push`] and come out of [`Program::next_line`] with their
+//! control flow carried out and their expressions evaluated (see `crate::expression`), ready
+//! for the lexer. Loops and calls run lines again, so lines are kept in a fixed-size store:
+//! subroutine definitions for as long as the program runs, everything else only until no
+//! block or call is open. When a block's closing line has not arrived yet, `next_line`
+//! returns `None` until it has been pushed.
+//!
+//! Blocks are matched by their O number, as in LinuxCNC:
+//!
+//! ```text
+//! O100 sub            O101 while [#1 LT 3]      O102 do
+//!   G0 X#1              #1 = [#1 + 1]             ...
+//! O100 endsub         O101 endwhile             O102 while [#2 GT 0]
+//! O100 call [1.5]
+//! ```
+//!
+//! State lives in fixed-size tables rather than on the call stack: calls nest at most
+//! [`MAX_CALL_DEPTH`] deep, blocks at most [`MAX_BLOCKS`], and a loop that runs more than
+//! [`MAX_ITERATIONS`] times is stopped.
+
+use core::fmt;
+use heapless::Vec;
+
+use crate::expression::{
+    substitute, values, ExpressionError, Line, Locals, Name, Parameter, Parameters, ARGUMENTS, MAX_LINE,
+};
+
+/// Bytes of program text the store can hold.
+pub const STORE_BYTES: usize = 4096;
+pub const MAX_LINES: usize = 256;
+pub const MAX_SUBROUTINES: usize = 16;
+pub const MAX_CALL_DEPTH: usize = 8;
+pub const MAX_BLOCKS: usize = 16;
+pub const MAX_ITERATIONS: u32 = 10_000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProgramError {
+    Expression(ExpressionError),
+    LineTooLong,
+    /// The store is full: an open block or a subroutine is too long to keep.
+    ProgramTooLong,
+    TooManySubroutines,
+    TooManyParameters,
+    UnknownSubroutine(u32),
+    /// `sub` inside a subroutine.
+    NestedSubroutine(u32),
+    CallTooDeep(u32),
+    TooManyBlocks(u32),
+    TooManyIterations(u32),
+    /// `if`, `elseif` or `while` without a condition.
+    MissingCondition(u32),
+    /// A line closing or jumping out of a block that is not open.
+    Unmatched(u32),
+    ReturnOutsideSubroutine(u32),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expression(error) => write!(f, "{}", error),
+            Self::LineTooLong => write!(f, "line is longer than {} characters", MAX_LINE),
+            Self::ProgramTooLong => write!(f, "program does not fit in {} bytes", STORE_BYTES),
+            Self::TooManySubroutines => write!(f, "more than {} subroutines", MAX_SUBROUTINES),
+            Self::TooManyParameters => write!(f, "too many parameters set"),
+            Self::UnknownSubroutine(number) => write!(f, "O{}: no such subroutine", number),
+            Self::NestedSubroutine(number) => write!(f, "O{}: subroutine defined inside a subroutine", number),
+            Self::CallTooDeep(number) => write!(f, "O{}: calls nested more than {} deep", number, MAX_CALL_DEPTH),
+            Self::TooManyBlocks(number) => write!(f, "O{}: blocks nested more than {} deep", number, MAX_BLOCKS),
+            Self::TooManyIterations(number) => write!(f, "O{}: loop ran more than {} times", number, MAX_ITERATIONS),
+            Self::MissingCondition(number) => write!(f, "O{}: missing condition", number),
+            Self::Unmatched(number) => write!(f, "O{}: does not match the open block", number),
+            Self::ReturnOutsideSubroutine(number) => write!(f, "O{}: return outside a subroutine", number),
+        }
+    }
+}
+
+impl From<ExpressionError> for ProgramError {
+    fn from(error: ExpressionError) -> Self {
+        Self::Expression(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keyword {
+    Sub,
+    EndSub,
+    Return,
+    Call,
+    If,
+    ElseIf,
+    Else,
+    EndIf,
+    While,
+    EndWhile,
+    Do,
+    Break,
+    Continue,
+}
+
+const KEYWORDS: [(&str, Keyword); 13] = [
+    ("sub", Keyword::Sub),
+    ("endsub", Keyword::EndSub),
+    ("return", Keyword::Return),
+    ("call", Keyword::Call),
+    ("if", Keyword::If),
+    ("elseif", Keyword::ElseIf),
+    ("else", Keyword::Else),
+    ("endif", Keyword::EndIf),
+    ("while", Keyword::While),
+    ("endwhile", Keyword::EndWhile),
+    ("do", Keyword::Do),
+    ("break", Keyword::Break),
+    ("continue", Keyword::Continue),
+];
+
+/// A control line: its O number, keyword and where the rest of the line starts.
+type Control = (u32, Keyword, usize);
+
+/// Reads `O<number> <keyword>`. Other lines, including program numbers like `O1001`,
+/// are `None`.
+fn control(line: &[u8]) -> Option<Control> {
+    let mut pos = line.iter().position(|&c| !matches!(c, b' ' | b'\t'))?;
+    if !line[pos].eq_ignore_ascii_case(&b'o') {
+        return None;
+    }
+    pos += 1;
+    let mut number: u32 = 0;
+    let mut digits = 0;
+    while let Some(&c) = line.get(pos) {
+        match c {
+            b'0'..=b'9' => {
+                number = number.checked_mul(10)?.checked_add(u32::from(c - b'0'))?;
+                digits += 1;
+            }
+            b' ' | b'\t' => {}
+            _ => break,
+        }
+        pos += 1;
+    }
+    let end = pos + line[pos..].iter().take_while(|c| c.is_ascii_alphabetic()).count();
+    let word = &line[pos..end];
+    let (_, keyword) = KEYWORDS.iter().find(|(name, _)| name.as_bytes().eq_ignore_ascii_case(word))?;
+    (digits > 0).then_some((number, *keyword, end))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Block {
+    If(u32),
+    /// `start` is the line of the `while`.
+    While { number: u32, start: usize, iterations: u32 },
+    /// `start` is the line of the `do`.
+    Do { number: u32, start: usize, iterations: u32 },
+}
+
+impl Block {
+    fn number(&self) -> u32 {
+        match *self {
+            Self::If(number) | Self::While { number, .. } | Self::Do { number, .. } => number,
+        }
+    }
+}
+
+/// Lines being passed over without running them.
+#[derive(Debug, Clone, Copy)]
+enum Skip {
+    /// A subroutine's body, up to its `endsub`.
+    Definition { number: u32, first: usize },
+    /// An `if` branch, up to the next branch or the `endif`; once a branch has run, up to
+    /// the `endif`.
+    Branch { number: u32, taken: bool },
+    /// The rest of a loop, up to its `endwhile` or the `while` closing a `do`.
+    Out { number: u32, do_while: bool },
+    /// The rest of a `do` loop's body, up to the `while` that tests it.
+    Condition(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Subroutine {
+    number: u32,
+    /// The `sub` line.
+    first: usize,
+    /// The `endsub` line.
+    last: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    number: u32,
+    return_to: usize,
+    /// Blocks open in the caller.
+    blocks: usize,
+    /// The caller's `#1` to `#30` and named locals.
+    arguments: [f32; ARGUMENTS],
+    locals: Locals,
+}
+
+pub struct Program {
+    parameters: Parameters,
+    store: Vec<u8, STORE_BYTES>,
+    /// Start and end of each stored line in `store`.
+    lines: Vec<(u16, u16), MAX_LINES>,
+    /// The next line to run.
+    pc: usize,
+    subroutines: Vec<Subroutine, MAX_SUBROUTINES>,
+    blocks: Vec<Block, MAX_BLOCKS>,
+    frames: Vec<Frame, MAX_CALL_DEPTH>,
+    skip: Option<Skip>,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self {
+            parameters: Parameters::default(),
+            store: Vec::new(),
+            lines: Vec::new(),
+            pc: 0,
+            subroutines: Vec::new(),
+            blocks: Vec::new(),
+            frames: Vec::new(),
+            skip: None,
+        }
+    }
+
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    pub fn parameters_mut(&mut self) -> &mut Parameters {
+        &mut self.parameters
+    }
+
+    /// Queues one line, without its line ending.
+    pub fn push(&mut self, line: &[u8]) -> Result<(), ProgramError> {
+        if line.len() > MAX_LINE {
+            return Err(ProgramError::LineTooLong);
+        }
+        if self.idle() {
+            self.compact();
+        }
+        let start = self.store.len();
+        self.store.extend_from_slice(line).map_err(|_| ProgramError::ProgramTooLong)?;
+        if self.lines.push((start as u16, self.store.len() as u16)).is_err() {
+            self.store.truncate(start);
+            return Err(ProgramError::ProgramTooLong);
+        }
+        Ok(())
+    }
+
+    /// The next line to run, or `None` until more lines are pushed. An error abandons the
+    /// running program: open blocks and calls are dropped, while parameters and subroutine
+    /// definitions are kept.
+    pub fn next_line(&mut self) -> Option<Result<Line, ProgramError>> {
+        while self.pc < self.lines.len() {
+            match self.step() {
+                Ok(Some(line)) => return Some(Ok(line)),
+                Ok(None) => {}
+                Err(error) => {
+                    self.abandon();
+                    return Some(Err(error));
+                }
+            }
+        }
+        None
+    }
+
+    fn idle(&self) -> bool {
+        self.pc == self.lines.len() && self.skip.is_none() && self.blocks.is_empty() && self.frames.is_empty()
+    }
+
+    fn abandon(&mut self) {
+        while let Some(frame) = self.frames.pop() {
+            self.parameters.arguments = frame.arguments;
+            self.parameters.locals = frame.locals;
+        }
+        self.blocks.clear();
+        self.skip = None;
+        self.pc = self.lines.len();
+    }
+
+    /// Drops every stored line outside a subroutine definition.
+    fn compact(&mut self) {
+        let (mut lines, mut bytes) = (0, 0);
+        for subroutine in self.subroutines.iter_mut() {
+            let first = lines;
+            for index in subroutine.first..=subroutine.last {
+                let (start, end) = self.lines[index];
+                let (start, end) = (usize::from(start), usize::from(end));
+                self.store.copy_within(start..end, bytes);
+                self.lines[lines] = (bytes as u16, (bytes + end - start) as u16);
+                bytes += end - start;
+                lines += 1;
+            }
+            subroutine.first = first;
+            subroutine.last = lines - 1;
+        }
+        self.store.truncate(bytes);
+        self.lines.truncate(lines);
+        self.pc = lines;
+    }
+
+    fn step(&mut self) -> Result<Option<Line>, ProgramError> {
+        let (start, end) = self.lines[self.pc];
+        let mut line = Line::new();
+        // Stored lines are no longer than `MAX_LINE`.
+        let _ = line.extend_from_slice(&self.store[usize::from(start)..usize::from(end)]);
+        let control = control(&line);
+
+        if let Some(skip) = self.skip {
+            self.pc += 1;
+            if let Some(control) = control {
+                self.skip_over(skip, control, &line)?;
+            }
+            return Ok(None);
+        }
+        if let Some(control) = control {
+            self.execute(control, &line)?;
+            return Ok(None);
+        }
+
+        self.pc += 1;
+        let substituted = substitute(&line, &self.parameters)?;
+        for (parameter, value) in substituted.settings {
+            if !self.parameters.set(parameter, value) {
+                return Err(ProgramError::TooManyParameters);
+            }
+        }
+        let blank = substituted.line.iter().all(|c| matches!(c, b' ' | b'\t' | b'\r'));
+        Ok((!blank).then_some(substituted.line))
+    }
+
+    fn execute(&mut self, (number, keyword, rest): Control, line: &[u8]) -> Result<(), ProgramError> {
+        let next = self.pc + 1;
+        match keyword {
+            Keyword::Sub => {
+                if !self.frames.is_empty() {
+                    return Err(ProgramError::NestedSubroutine(number));
+                }
+                self.skip = Some(Skip::Definition { number, first: self.pc });
+                self.pc = next;
+            }
+            Keyword::Call => {
+                let arguments = values(line, rest, &self.parameters)?;
+                let subroutine = self
+                    .subroutines
+                    .iter()
+                    .find(|subroutine| subroutine.number == number)
+                    .ok_or(ProgramError::UnknownSubroutine(number))?;
+                let first = subroutine.first;
+                if self.frames.is_full() {
+                    return Err(ProgramError::CallTooDeep(number));
+                }
+                let frame = Frame {
+                    number,
+                    return_to: next,
+                    blocks: self.blocks.len(),
+                    arguments: self.parameters.arguments,
+                    locals: core::mem::take(&mut self.parameters.locals),
+                };
+                let _ = self.frames.push(frame);
+                self.parameters.arguments = [0.0; ARGUMENTS];
+                self.parameters.arguments[..arguments.len()].copy_from_slice(&arguments);
+                self.pc = first + 1;
+            }
+            Keyword::EndSub | Keyword::Return => {
+                let value = values(line, rest, &self.parameters)?.first().copied();
+                let frame = match self.frames.last() {
+                    Some(frame) if frame.number == number => self.frames.pop().unwrap(),
+                    Some(_) => return Err(ProgramError::Unmatched(number)),
+                    None => return Err(ProgramError::ReturnOutsideSubroutine(number)),
+                };
+                self.blocks.truncate(frame.blocks);
+                self.parameters.arguments = frame.arguments;
+                self.parameters.locals = frame.locals;
+                if let Some(value) = value {
+                    if !self.parameters.set(Parameter::Named(Name::from("_value")), value) {
+                        return Err(ProgramError::TooManyParameters);
+                    }
+                }
+                self.pc = frame.return_to;
+            }
+            Keyword::If => {
+                let condition = self.condition(number, line, rest)?;
+                self.open(Block::If(number))?;
+                if !condition {
+                    self.skip = Some(Skip::Branch { number, taken: false });
+                }
+                self.pc = next;
+            }
+            Keyword::ElseIf | Keyword::Else => {
+                self.innermost(number, |block| matches!(block, Block::If(_)))?;
+                self.skip = Some(Skip::Branch { number, taken: true });
+                self.pc = next;
+            }
+            Keyword::EndIf => {
+                self.innermost(number, |block| matches!(block, Block::If(_)))?;
+                self.blocks.pop();
+                self.pc = next;
+            }
+            Keyword::While => match self.blocks.last().copied().filter(|block| block.number() == number && self.blocks.len() > self.base()) {
+                // The end of a `do` loop.
+                Some(Block::Do { start, .. }) => {
+                    if self.condition(number, line, rest)? {
+                        self.count(number)?;
+                        self.pc = start + 1;
+                    } else {
+                        self.blocks.pop();
+                        self.pc = next;
+                    }
+                }
+                // Back at the top of a `while` loop.
+                Some(Block::While { start, .. }) if start == self.pc => {
+                    if self.condition(number, line, rest)? {
+                        self.count(number)?;
+                    } else {
+                        self.blocks.pop();
+                        self.skip = Some(Skip::Out { number, do_while: false });
+                    }
+                    self.pc = next;
+                }
+                _ => {
+                    if self.condition(number, line, rest)? {
+                        self.open(Block::While { number, start: self.pc, iterations: 0 })?;
+                    } else {
+                        self.skip = Some(Skip::Out { number, do_while: false });
+                    }
+                    self.pc = next;
+                }
+            },
+            Keyword::EndWhile => match self.innermost(number, |block| matches!(block, Block::While { .. }))? {
+                Block::While { start, .. } => self.pc = start,
+                _ => unreachable!(),
+            },
+            Keyword::Do => {
+                self.open(Block::Do { number, start: self.pc, iterations: 0 })?;
+                self.pc = next;
+            }
+            Keyword::Break => {
+                let block = self.unwind(number)?;
+                self.blocks.pop();
+                self.skip = Some(Skip::Out { number, do_while: matches!(block, Block::Do { .. }) });
+                self.pc = next;
+            }
+            Keyword::Continue => match self.unwind(number)? {
+                Block::While { start, .. } => self.pc = start,
+                _ => {
+                    self.skip = Some(Skip::Condition(number));
+                    self.pc = next;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Handles a control line met while skipping; `pc` has already moved past it.
+    fn skip_over(&mut self, skip: Skip, (number, keyword, rest): Control, line: &[u8]) -> Result<(), ProgramError> {
+        match (skip, keyword) {
+            (Skip::Definition { number: n, first }, Keyword::EndSub) if n == number => {
+                self.skip = None;
+                self.define(Subroutine { number, first, last: self.pc - 1 })?;
+            }
+            (Skip::Branch { number: n, taken: false }, Keyword::ElseIf) if n == number => {
+                let taken = self.condition(number, line, rest)?;
+                self.skip = if taken { None } else { Some(skip) };
+            }
+            (Skip::Branch { number: n, taken: false }, Keyword::Else) if n == number => self.skip = None,
+            (Skip::Branch { number: n, .. }, Keyword::EndIf) if n == number => {
+                self.skip = None;
+                self.innermost(number, |block| matches!(block, Block::If(_)))?;
+                self.blocks.pop();
+            }
+            (Skip::Out { number: n, do_while: false }, Keyword::EndWhile)
+            | (Skip::Out { number: n, do_while: true }, Keyword::While)
+                if n == number =>
+            {
+                self.skip = None
+            }
+            (Skip::Condition(n), Keyword::While) if n == number => {
+                self.skip = None;
+                self.pc -= 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, subroutine: Subroutine) -> Result<(), ProgramError> {
+        self.subroutines.retain(|defined| defined.number != subroutine.number);
+        self.subroutines.push(subroutine).map_err(|_| ProgramError::TooManySubroutines)
+    }
+
+    fn condition(&self, number: u32, line: &[u8], rest: usize) -> Result<bool, ProgramError> {
+        let condition = values(line, rest, &self.parameters)?;
+        let value = condition.first().ok_or(ProgramError::MissingCondition(number))?;
+        Ok(*value != 0.0)
+    }
+
+    fn open(&mut self, block: Block) -> Result<(), ProgramError> {
+        self.blocks.push(block).map_err(|_| ProgramError::TooManyBlocks(block.number()))
+    }
+
+    /// Blocks below this belong to the callers of the running subroutine.
+    fn base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.blocks)
+    }
+
+    /// The innermost open block, if it has `number` and the right kind.
+    fn innermost(&self, number: u32, kind: impl Fn(&Block) -> bool) -> Result<Block, ProgramError> {
+        self.blocks[self.base()..]
+            .last()
+            .copied()
+            .filter(|block| block.number() == number && kind(block))
+            .ok_or(ProgramError::Unmatched(number))
+    }
+
+    /// Closes the blocks inside loop `number`, for `break` and `continue`.
+    fn unwind(&mut self, number: u32) -> Result<Block, ProgramError> {
+        let base = self.base();
+        let index = self.blocks[base..]
+            .iter()
+            .rposition(|block| block.number() == number && !matches!(block, Block::If(_)))
+            .ok_or(ProgramError::Unmatched(number))?;
+        self.blocks.truncate(base + index + 1);
+        Ok(self.blocks[base + index])
+    }
+
+    /// Counts another pass of the innermost loop.
+    fn count(&mut self, number: u32) -> Result<(), ProgramError> {
+        if let Some(Block::While { iterations, .. } | Block::Do { iterations, .. }) = self.blocks.last_mut() {
+            *iterations += 1;
+            if *iterations > MAX_ITERATIONS {
+                return Err(ProgramError::TooManyIterations(number));
+            }
+        }
+        Ok(())
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::ExpressionErrorKind;
+    use heapless::String;
+
+    /// Pushes `program` a line at a time, collecting whatever is ready after each.
+    fn run(program: &mut Program, text: &str) -> Result<String<1024>, ProgramError> {
+        let mut output = String::new();
+        for line in text.lines() {
+            program.push(line.trim().as_bytes())?;
+            while let Some(line) = program.next_line() {
+                output.push_str(core::str::from_utf8(&line?).unwrap()).unwrap();
+                output.push('\n').unwrap();
+            }
+        }
+        Ok(output)
+    }
+
+    fn output(text: &str) -> String<1024> {
+        run(&mut Program::new(), text).unwrap()
+    }
+
+    #[test]
+    fn if_elseif_else() {
+        let program = "#1 = 2
+            O10 if [#1 EQ 1]
+              (one)
+            O10 elseif [#1 EQ 2]
+              (two)
+              O11 if [1]
+                (nested)
+              O11 endif
+            O10 else
+              (other)
+            O10 endif
+            G0 X#1";
+        assert_eq!(output(program).as_str(), "(two)\n(nested)\nG0 X2\n");
+    }
+
+    #[test]
+    fn while_and_do_loops() {
+        let program = "#1 = 0
+            O20 while [#1 LT 3]
+              G1 X[#1 * 10]
+              #1 = [#1 + 1]
+            O20 endwhile
+            O21 do
+              #1 = [#1 - 1]
+              O22 if [#1 EQ 1]
+                O21 continue
+              O22 endif
+              G1 Y#1
+            O21 while [#1 GT 0]
+            O23 while [1]
+              O23 break
+              (never)
+            O23 endwhile
+            M2";
+        assert_eq!(output(program).as_str(), "G1 X0\nG1 X10\nG1 X20\nG1 Y2\nG1 Y0\nM2\n");
+    }
+
+    #[test]
+    fn subroutines() {
+        let mut program = Program::new();
+        let defined = run(
+            &mut program,
+            "O100 sub
+               (hole at #1, #2)
+               #<depth> = [#3 * 2]
+               G0 X#1 Y#2
+               G1 Z-#<depth>
+               O101 if [#1 GT 5]
+                 O100 return [#1]
+               O101 endif
+               G0 Z1
+             O100 endsub
+             #<depth> = 7",
+        )
+        .unwrap();
+        assert!(defined.is_empty());
+
+        // Lines outside definitions are dropped between calls; the subroutine stays.
+        assert!(program.lines.len() > 9);
+        let called = run(&mut program, "O100 call [1] [2] [0.5]\nO100 call [6] [2] [1]\nG0 X#<depth> Y#<_value>").unwrap();
+        assert_eq!(
+            called.as_str(),
+            "(hole at #1, #2)\nG0 X1 Y2\nG1 Z-1\nG0 Z1\n\
+             (hole at #1, #2)\nG0 X6 Y2\nG1 Z-2\n\
+             G0 X7 Y6\n"
+        );
+        // The subroutine's ten lines and the last line pushed.
+        assert_eq!(program.lines.len(), 11);
+        assert_eq!(program.parameters().get(&Parameter::Numbered(1)), Some(0.0));
+    }
+
+    #[test]
+    fn blocks_wait_for_their_closing_line() {
+        let mut program = Program::new();
+        program.push(b"O1 while [#1 LT 2]").unwrap();
+        program.push(b"G0 X#1").unwrap();
+        assert_eq!(program.next_line(), Some(Ok(Line::from_slice(b"G0 X0").unwrap())));
+        program.push(b"#1 = [#1 + 1]").unwrap();
+        assert_eq!(program.next_line(), None);
+        program.push(b"O1 endwhile").unwrap();
+        assert_eq!(program.next_line(), Some(Ok(Line::from_slice(b"G0 X1").unwrap())));
+        assert_eq!(program.next_line(), None);
+        assert!(program.idle());
+    }
+
+    #[test]
+    fn limits_and_errors() {
+        let mut program = Program::new();
+        assert_eq!(run(&mut program, "O1 while [1]\nO1 endwhile"), Err(ProgramError::TooManyIterations(1)));
+        assert!(program.idle());
+
+        let recursive = "O2 sub\nO2 call\nO2 endsub\nO2 call";
+        assert_eq!(run(&mut Program::new(), recursive), Err(ProgramError::CallTooDeep(2)));
+        assert_eq!(run(&mut Program::new(), "O3 call"), Err(ProgramError::UnknownSubroutine(3)));
+        assert_eq!(run(&mut Program::new(), "O4 if\nO4 endif"), Err(ProgramError::MissingCondition(4)));
+        assert_eq!(run(&mut Program::new(), "O5 endwhile"), Err(ProgramError::Unmatched(5)));
+        assert_eq!(run(&mut Program::new(), "O6 return"), Err(ProgramError::ReturnOutsideSubroutine(6)));
+        assert_eq!(
+            run(&mut Program::new(), "O7 if [1 / 0]"),
+            Err(ProgramError::Expression(ExpressionError { column: 10, kind: ExpressionErrorKind::DivisionByZero }))
+        );
+
+        // Program numbers are not control lines.
+        assert_eq!(output("O1001 (ADAPTIVE)").as_str(), "O1001 (ADAPTIVE)\n");
+    }
+}
@@ -1,63 +1,438 @@
+//! Runs G-code arriving over a UART.
+//!
+//! Commands go into a look-ahead channel that the motion planner drains. Each line sent is
+//! answered with `ok` once its commands are all in the channel, or with `error:N reason`,
+//! `N` counting lines received from 1. A sender that waits for the answer before sending
+//! more is held off while the channel is full, since nothing more is read from the UART
+//! until the line's commands fit.
+
+use core::fmt::{self, Write};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Sender;
+use embassy_time::{Duration, Timer};
 use hal::uart::Uart;
-use heapless::VecDeque;
-use crate::lexer::{lexer, Token};
-use crate::ast::{parse_line, AstNode};
-use crate::dialect::{MachineDialect, MachineCommand};
+use heapless::String;
+use crate::lexer::{lexer, LexError};
+use crate::arc::{ArcError, ArcLinearizer};
+use crate::ast::{parse_line, AstNode, SyntaxError};
+use crate::compensation::{Compensated, CompensationError, CutterCompensator};
+use crate::coordinates::{CoordinateResolver, OffsetTables, ResolveError};
+use crate::dialect::{MachineDialect, MachineCommand, ParseError};
+use crate::lines::{LineAssembler, LineError};
 use crate::modal::ModalState;
+use crate::program::{Program, ProgramError};
+
+/// Longest answer sent back; a longer reason is cut short.
+const MAX_RESPONSE: usize = 128;
+
+/// Default size of the look-ahead channel, in commands.
+pub const LOOKAHEAD: usize = 1024;
+
+/// Pause after a failed UART read, so a UART that keeps failing does not starve other tasks.
+const READ_ERROR_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Why a line was rejected.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StreamError {
+    Line(LineError),
+    Program(ProgramError),
+    Lex(LexError),
+    Parse(SyntaxError),
+    Resolve(ResolveError),
+    Compensation(CompensationError),
+    Arc(ArcError),
+    Dialect(ParseError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Line(error) => write!(f, "{}", error),
+            Self::Program(error) => write!(f, "{}", error),
+            Self::Lex(error) => write!(f, "{}", error),
+            Self::Parse(error) => write!(f, "{}", error),
+            Self::Resolve(error) => write!(f, "{}", error),
+            Self::Compensation(error) => write!(f, "{}", error),
+            Self::Arc(error) => write!(f, "{}", error),
+            Self::Dialect(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<LineError> for StreamError {
+    fn from(error: LineError) -> Self {
+        Self::Line(error)
+    }
+}
 
-pub struct GcodeStreamer<UART, DIALECT>
+impl From<ProgramError> for StreamError {
+    fn from(error: ProgramError) -> Self {
+        Self::Program(error)
+    }
+}
+
+impl From<LexError> for StreamError {
+    fn from(error: LexError) -> Self {
+        Self::Lex(error)
+    }
+}
+
+impl From<SyntaxError> for StreamError {
+    fn from(error: SyntaxError) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<ResolveError> for StreamError {
+    fn from(error: ResolveError) -> Self {
+        Self::Resolve(error)
+    }
+}
+
+impl From<CompensationError> for StreamError {
+    fn from(error: CompensationError) -> Self {
+        Self::Compensation(error)
+    }
+}
+
+impl From<ArcError> for StreamError {
+    fn from(error: ArcError) -> Self {
+        Self::Arc(error)
+    }
+}
+
+impl From<ParseError> for StreamError {
+    fn from(error: ParseError) -> Self {
+        Self::Dialect(error)
+    }
+}
+
+pub struct GcodeStreamer<'a, UART, DIALECT, M, const N: usize = LOOKAHEAD>
 where
     UART: Uart,
     DIALECT: MachineDialect,
+    M: RawMutex,
 {
     uart: UART,
     dialect: DIALECT,
-    lookahead_buffer: VecDeque<MachineCommand, 1024>,
+    commands: Sender<'a, M, MachineCommand, N>,
     modal_state: ModalState,
+    program: Program,
+    coordinates: CoordinateResolver,
+    compensation: CutterCompensator,
+    arcs: ArcLinearizer,
+    /// Lines received so far.
+    received: u32,
 }
 
-impl<UART, DIALECT> GcodeStreamer<UART, DIALECT>
+impl<'a, UART, DIALECT, M, const N: usize> GcodeStreamer<'a, UART, DIALECT, M, N>
 where
     UART: Uart,
     DIALECT: MachineDialect,
+    M: RawMutex,
 {
-    pub fn new(uart: UART, dialect: DIALECT) -> Self {
+    /// `commands` is the sending end of the look-ahead channel; the planner receives from
+    /// the same `embassy_sync::channel::Channel`.
+    pub fn new(uart: UART, dialect: DIALECT, commands: Sender<'a, M, MachineCommand, N>) -> Self {
+        Self::with_offsets(uart, dialect, commands, OffsetTables::default())
+    }
+
+    /// Starts from offset tables saved by an earlier run.
+    pub fn with_offsets(
+        uart: UART,
+        dialect: DIALECT,
+        commands: Sender<'a, M, MachineCommand, N>,
+        offsets: OffsetTables,
+    ) -> Self {
         Self {
             uart,
             dialect,
-            lookahead_buffer: VecDeque::new(),
+            commands,
             modal_state: ModalState::default(),
+            program: Program::new(),
+            coordinates: CoordinateResolver::new(offsets),
+            compensation: CutterCompensator::new(),
+            arcs: ArcLinearizer::default(),
+            received: 0,
         }
     }
 
+    /// For homing, and for saving the offset tables when they change.
+    pub fn coordinates(&mut self) -> &mut CoordinateResolver {
+        &mut self.coordinates
+    }
+
+    /// For setting the chord tolerance arcs are cut into.
+    pub fn arcs(&mut self) -> &mut ArcLinearizer {
+        &mut self.arcs
+    }
+
+    /// For presetting and reading parameters.
+    pub fn program(&mut self) -> &mut Program {
+        &mut self.program
+    }
+
     pub async fn run(&mut self) {
         let mut buffer = [0u8; 256];
+        let mut lines = LineAssembler::new();
         loop {
-            if self.lookahead_buffer.is_full() {
-                embassy_time::Timer::after(embassy_time::Duration::from_millis(10)).await;
-                continue;
-            }
-
-            let bytes_read = self.uart.read(&mut buffer).await.unwrap();
-            if bytes_read > 0 {
-                let mut input = &buffer[..bytes_read];
-                while !input.is_empty() {
-                    match lexer(input) {
-                        Ok((remaining, tokens)) => {
-                            if let Ok(Some(ast_node)) = parse_line(&tokens, &mut self.modal_state) {
-                                if let Ok(machine_command) = self.dialect.interpret(&ast_node, &mut self.modal_state) {
-                                    self.lookahead_buffer.push_back(machine_command).unwrap();
-                                }
-                            }
-                            input = remaining;
-                        }
-                        Err(_) => {
-                            // Handle lexer error
-                            break;
+            let bytes_read = match self.uart.read(&mut buffer).await {
+                Ok(bytes_read) => bytes_read,
+                Err(_) => {
+                    // Bytes may have been lost: the line they belong to is answered with
+                    // an error when it ends.
+                    lines.garble();
+                    Timer::after(READ_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            let mut pending = &buffer[..bytes_read];
+            while !pending.is_empty() {
+                let (taken, line) = lines.feed(pending);
+                pending = &pending[taken..];
+                if let Some(line) = line {
+                    self.received += 1;
+                    let result = match line {
+                        Ok(line) => self.run_line(line).await,
+                        Err(error) => Err(error.into()),
+                    };
+                    self.respond(result).await;
+                }
+            }
+        }
+    }
+
+    /// Runs one received line, and any lines of the program it lets run. The first error
+    /// is the line's answer; the lines after it still run.
+    async fn run_line(&mut self, line: &[u8]) -> Result<(), StreamError> {
+        self.program.push(line)?;
+        let mut result = Ok(());
+        // A loop can produce any number of lines.
+        while let Some(line) = self.program.next_line() {
+            let executed = match line {
+                Ok(line) => self.execute(&line).await,
+                Err(error) => Err(error.into()),
+            };
+            result = result.and(executed);
+        }
+        result
+    }
+
+    /// Runs one line from the program through the rest of the pipeline, waiting for room
+    /// in the look-ahead channel for each command.
+    async fn execute(&mut self, line: &[u8]) -> Result<(), StreamError> {
+        let tokens = lexer(line)?;
+        for ast_node in parse_line(&tokens, &mut self.modal_state)? {
+            let ast_node = self.coordinates.resolve(ast_node, &self.modal_state)?;
+            if let AstNode::Home { .. } = ast_node {
+                self.compensation.set_position(self.coordinates.position());
+                self.arcs.set_position(self.coordinates.position());
+            }
+            let compensated = if self.dialect.compensates_cutter() {
+                let mut nodes = Compensated::new();
+                let _ = nodes.push(ast_node);
+                nodes
+            } else {
+                self.compensation.push(ast_node, &self.modal_state, self.coordinates.tools())?
+            };
+            for ast_node in compensated {
+                for ast_node in self.dialect.expand(ast_node, &self.modal_state)? {
+                    for ast_node in self.arcs.linearize(ast_node, &self.modal_state)? {
+                        for machine_command in self.dialect.interpret(&ast_node, &mut self.modal_state)? {
+                            self.commands.send(machine_command).await;
                         }
                     }
                 }
             }
         }
+        Ok(())
+    }
+
+    async fn respond(&mut self, result: Result<(), StreamError>) {
+        let mut response: String<MAX_RESPONSE> = String::new();
+        match result {
+            Ok(()) => {
+                let _ = response.push_str("ok");
+            }
+            Err(error) => {
+                let _ = write!(response, "error:{} {}", self.received, error);
+            }
+        }
+        // There is no one to tell if the answer cannot be sent.
+        let _ = self.uart.write(response.as_bytes()).await;
+        let _ = self.uart.write(b"\n").await;
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::{CncRouterDialect, FdmPrinterDialect};
+    use crate::tool_table::Tool;
+    use embassy_futures::block_on;
+    use embassy_futures::select::{select, Either};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::channel::Channel;
+
+    /// Sends `program`, or `G0 X1` to `G0 X<lines>` without one, fails one read just before
+    /// line `fail_before`, and keeps the answers.
+    struct ScriptedUart {
+        program: &'static [&'static str],
+        lines: u32,
+        sent: u32,
+        fail_before: u32,
+        answer: String<MAX_RESPONSE>,
+        oks: u32,
+        errors: heapless::Vec<String<MAX_RESPONSE>, 4>,
+    }
+
+    impl ScriptedUart {
+        fn new(lines: u32, fail_before: u32) -> Self {
+            Self { program: &[], lines, sent: 0, fail_before, answer: String::new(), oks: 0, errors: heapless::Vec::new() }
+        }
+
+        fn with_program(program: &'static [&'static str]) -> Self {
+            Self { program, ..Self::new(program.len() as u32, 0) }
+        }
+    }
+
+    impl Uart for ScriptedUart {
+        type Error = ();
+
+        async fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+            for &byte in bytes {
+                if byte != b'\n' {
+                    self.answer.push(byte as char).unwrap();
+                } else if self.answer == "ok" {
+                    self.oks += 1;
+                    self.answer.clear();
+                } else {
+                    self.errors.push(self.answer.clone()).unwrap();
+                    self.answer.clear();
+                }
+            }
+            Ok(())
+        }
+
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ()> {
+            if self.sent == self.lines {
+                return core::future::pending().await;
+            }
+            if self.sent + 1 == self.fail_before {
+                self.fail_before = 0;
+                return Err(());
+            }
+            let mut line: String<32> = String::new();
+            match self.program.get(self.sent as usize) {
+                Some(text) => writeln!(line, "{}", text).unwrap(),
+                None => writeln!(line, "G0 X{}", self.sent + 1).unwrap(),
+            }
+            self.sent += 1;
+            buffer[..line.len()].copy_from_slice(line.as_bytes());
+            Ok(line.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streams_more_lines_than_the_lookahead_holds() {
+        const LINES: u32 = 40;
+        let channel: Channel<NoopRawMutex, MachineCommand, 8> = Channel::new();
+        let mut streamer = GcodeStreamer::new(ScriptedUart::new(LINES, 20), FdmPrinterDialect::default(), channel.sender());
+
+        // The garbled line 20 makes no command.
+        let planner = async {
+            let mut xs: heapless::Vec<f32, 64> = heapless::Vec::new();
+            while xs.len() < LINES as usize - 1 {
+                if xs.is_empty() {
+                    assert!(channel.is_full(), "the streamer runs ahead until the channel is full");
+                }
+                match channel.receive().await {
+                    MachineCommand::RapidMove { target } => xs.push(target[0]).unwrap(),
+                    other => panic!("unexpected command {:?}", other),
+                }
+            }
+            xs
+        };
+        let xs = match block_on(select(streamer.run(), planner)) {
+            Either::First(()) => unreachable!("the streamer runs forever"),
+            Either::Second(xs) => xs,
+        };
+
+        assert!(xs.iter().copied().eq((1..=LINES).filter(|&n| n != 20).map(|n| n as f32)));
+        assert_eq!(streamer.uart.oks, LINES - 1);
+        assert_eq!(streamer.uart.errors, ["error:20 line was garbled in transmission"]);
+    }
+
+    #[test]
+    fn cuts_round_the_outside_of_a_square() {
+        // The outside profile of a 20 x 10 rectangle with a 4 mm cutter.
+        const PROGRAM: &[&str] = &[
+            "G0 X-10 Y5",
+            "G41 D1 G1 X0 Y5 F100",
+            "G1 Z-1",
+            "G1 X0 Y10",
+            "G1 X20 Y10",
+            "G1 X20 Y0",
+            "G1 X0 Y0",
+            "G1 X0 Y5",
+            "G40 G1 X-10 Y5",
+        ];
+        let channel: Channel<NoopRawMutex, MachineCommand, 8> = Channel::new();
+        let mut streamer = GcodeStreamer::new(ScriptedUart::with_program(PROGRAM), CncRouterDialect, channel.sender());
+        streamer.coordinates().tools_mut().set(1, Tool { diameter: 4.0, length: 0.0 });
+
+        let planner = async {
+            let mut path: heapless::Vec<[f32; 2], 256> = heapless::Vec::new();
+            loop {
+                let (MachineCommand::RapidMove { target } | MachineCommand::LinearMove { target, .. }) = channel.receive().await else {
+                    panic!("only moves expected");
+                };
+                path.push([target[0], target[1]]).unwrap();
+                if target[..2] == [-10.0, 5.0] && path.len() > 1 {
+                    return path;
+                }
+            }
+        };
+        let path = match block_on(select(streamer.run(), planner)) {
+            Either::First(()) => unreachable!("the streamer runs forever"),
+            Either::Second(path) => path,
+        };
+
+        assert_eq!(streamer.uart.oks, PROGRAM.len() as u32);
+        assert!(streamer.uart.errors.is_empty(), "{:?}", streamer.uart.errors);
+        // Each corner is rounded with chords, and no move cuts closer to the part than the
+        // tool radius, less the chord tolerance.
+        assert!(path.len() > PROGRAM.len() + 8, "{:?}", path);
+        let clearance = |[x, y]: [f32; 2]| libm::hypotf((-x).max(x - 20.0).max(0.0), (-y).max(y - 10.0).max(0.0));
+        for pair in path.windows(2) {
+            let middle = [(pair[0][0] + pair[1][0]) / 2.0, (pair[0][1] + pair[1][1]) / 2.0];
+            assert!(clearance(middle) >= 2.0 - 0.011, "{:?} cuts into the part", pair);
+        }
+    }
+
+    #[test]
+    fn names_the_word_a_rejected_line_is_missing() {
+        const PROGRAM: &[&str] = &["G0 X1", "G4", "G10 L2 P12 X0", "G0 X2"];
+        let channel: Channel<NoopRawMutex, MachineCommand, 8> = Channel::new();
+        let mut streamer = GcodeStreamer::new(ScriptedUart::with_program(PROGRAM), CncRouterDialect, channel.sender());
+
+        let planner = async {
+            loop {
+                if let MachineCommand::RapidMove { target: [2.0, ..] } = channel.receive().await {
+                    return;
+                }
+            }
+        };
+        if let Either::First(()) = block_on(select(streamer.run(), planner)) {
+            unreachable!("the streamer runs forever");
+        }
+
+        assert_eq!(streamer.uart.oks, 2);
+        assert_eq!(streamer.uart.errors, ["error:2 G4: P word is missing", "error:3 G10: P12 is out of range"]);
     }
 }
@@ -0,0 +1,54 @@
+//! Tool geometry for cutter radius compensation and tool length offsets.
+
+use serde::{Deserialize, Serialize};
+
+use crate::modal::ToolDimension;
+
+/// Tools are numbered `T0` to `T31`; `T0` is the empty spindle.
+pub const MAX_TOOLS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Tool {
+    /// Millimetres.
+    pub diameter: f32,
+    /// Millimetres, measured like the work offsets: a longer tool has a larger length.
+    pub length: f32,
+}
+
+/// Tools by number. Entries nobody set describe a tool of no size. Persist it like
+/// `crate::coordinates::OffsetTables`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ToolTable {
+    pub tools: [Tool; MAX_TOOLS],
+}
+
+impl ToolTable {
+    pub fn get(&self, number: u16) -> Tool {
+        self.tools.get(usize::from(number)).copied().unwrap_or_default()
+    }
+
+    /// Returns false for a number past the end of the table.
+    pub fn set(&mut self, number: u16, tool: Tool) -> bool {
+        match self.tools.get_mut(usize::from(number)) {
+            Some(entry) => {
+                *entry = tool;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn diameter(&self, dimension: ToolDimension) -> f32 {
+        match dimension {
+            ToolDimension::Tool(number) => self.get(number).diameter,
+            ToolDimension::Given(diameter) => diameter,
+        }
+    }
+
+    pub fn length(&self, dimension: ToolDimension) -> f32 {
+        match dimension {
+            ToolDimension::Tool(number) => self.get(number).length,
+            ToolDimension::Given(length) => length,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Wire EDM guide positions.
+//!
+//! A wire machine cuts with a straight wire strung between a lower guide, moved by X and
+//! Y, and an upper guide, moved by U and V. The part is programmed as a contour on one
+//! plane, the programme plane. The wire passes through the contour offset to one side by
+//! the wire radius and spark gap (G41/G42), measured square to the wire, and leans by the
+//! taper angle to one side of the direction of travel (G51/G52).
+//!
+//! Along a straight move both come to an offset from the contour that grows linearly with
+//! height, so on each guide plane the guide follows the contour offset by its own distance.
+//! Where two moves meet the offset paths are mitred: the wire swings through the sharp
+//! corner of a tapered block rather than rolling round it.
+
+use libm::{cosf, hypotf, tanf};
+
+/// Heights of the guides and of the programme plane, in millimetres in one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuideHeights {
+    pub lower: f32,
+    pub upper: f32,
+    pub programme: f32,
+}
+
+/// How far the wire sits to the left of the contour on each guide plane, in millimetres;
+/// negative to the right.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Offsets {
+    pub lower: f32,
+    pub upper: f32,
+}
+
+impl Offsets {
+    /// `kerf` is the wire radius plus spark gap and `taper` the lean in degrees, both
+    /// positive to the left of the direction of travel.
+    pub fn new(kerf: f32, taper: f32, heights: &GuideHeights) -> Self {
+        let taper = taper.to_radians();
+        // The kerf is square to the wire, so wider across a leaning one.
+        let kerf = kerf / cosf(taper);
+        let lean = tanf(taper);
+        Self {
+            lower: kerf + lean * (heights.lower - heights.programme),
+            upper: kerf + lean * (heights.upper - heights.programme),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.lower == 0.0 && self.upper == 0.0
+    }
+}
+
+/// Where the lower (XY) and upper (UV) guides go for one contour point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Guides {
+    pub lower: [f32; 2],
+    pub upper: [f32; 2],
+}
+
+impl Guides {
+    /// The wire upright through `point`.
+    pub fn upright(point: [f32; 2]) -> Self {
+        Self { lower: point, upper: point }
+    }
+}
+
+/// The unit normal to the left of travel from `from` to `to`, or `None` for a move without
+/// XY motion.
+pub fn left_normal(from: [f32; 2], to: [f32; 2]) -> Option<[f32; 2]> {
+    let [dx, dy] = [to[0] - from[0], to[1] - from[1]];
+    let length = hypotf(dx, dy);
+    (length > 1e-6).then(|| [-dy / length, dx / length])
+}
+
+/// The guides at contour point `point`, where the move with left normal `before` meets the
+/// one with left normal `after`. Pass the same normal twice for a square end. `None` where
+/// the contour doubles back on itself and the mitre has no end.
+pub fn guides(point: [f32; 2], before: [f32; 2], after: [f32; 2], offsets: Offsets) -> Option<Guides> {
+    // Lines `d` out along each normal cross at d (before + after) / (1 + before · after).
+    let dot = 1.0 + before[0] * after[0] + before[1] * after[1];
+    if dot < 1e-3 {
+        return None;
+    }
+    let mitre = [(before[0] + after[0]) / dot, (before[1] + after[1]) / dot];
+    let at = |distance: f32| [point[0] + mitre[0] * distance, point[1] + mitre[1] * distance];
+    Some(Guides { lower: at(offsets.lower), upper: at(offsets.upper) })
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn offsets_and_mitres() {
+        let heights = GuideHeights { lower: -10.0, upper: 40.0, programme: 0.0 };
+        let offsets = Offsets::new(0.0, 45.0, &heights);
+        assert_relative_eq!(offsets.lower, -10.0, epsilon = 1e-4);
+        assert_relative_eq!(offsets.upper, 40.0, epsilon = 1e-4);
+        // Square to a wire leaning at 60° a 0.1 mm kerf is 0.2 mm across.
+        let offsets = Offsets::new(0.1, 60.0, &GuideHeights { lower: 0.0, upper: 0.0, programme: 0.0 });
+        assert_relative_eq!(offsets.lower, 0.2, epsilon = 1e-5);
+
+        let east = left_normal([0.0, 0.0], [5.0, 0.0]).unwrap();
+        let north = left_normal([5.0, 0.0], [5.0, 5.0]).unwrap();
+        assert_eq!(east, [0.0, 1.0]);
+        assert_eq!(left_normal([1.0, 1.0], [1.0, 1.0]), None);
+        let corner = guides([5.0, 0.0], east, north, Offsets { lower: -1.0, upper: 2.0 }).unwrap();
+        assert_eq!(corner, Guides { lower: [6.0, -1.0], upper: [3.0, 2.0] });
+        let square = guides([5.0, 0.0], east, east, Offsets { lower: -1.0, upper: 2.0 }).unwrap();
+        assert_eq!(square, Guides { lower: [5.0, -1.0], upper: [5.0, 2.0] });
+        assert_eq!(guides([5.0, 0.0], east, [0.0, -1.0], Offsets { lower: 0.0, upper: 1.0 }), None);
+    }
+}
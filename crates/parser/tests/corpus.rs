@@ -0,0 +1,112 @@
+//! Lexes and parses sample programs from common CAM post-processors and a RepRap host,
+//! line by line.
+
+use parser::ast::{parse_line, AstNode};
+use parser::lexer::{lexer, Token};
+use parser::modal::ModalState;
+
+#[derive(Debug, Default)]
+struct Summary {
+    lines: usize,
+    comments: usize,
+    line_numbers: usize,
+    arcs: usize,
+    spindle_starts: usize,
+    moves: Vec<AstNode>,
+}
+
+fn run(name: &str, program: &str) -> Summary {
+    let mut state = ModalState::default();
+    let mut summary = Summary::default();
+    for (index, line) in program.lines().enumerate() {
+        let tokens = lexer(line.as_bytes()).unwrap_or_else(|e| panic!("{}:{}: {}", name, index + 1, e));
+        summary.lines += 1;
+        for token in &tokens {
+            match token {
+                Token::Comment(_) => summary.comments += 1,
+                Token::LineNumber(_) => summary.line_numbers += 1,
+                _ => {}
+            }
+        }
+        let nodes = parse_line(&tokens, &mut state)
+            .unwrap_or_else(|e| panic!("{}:{}: cannot parse {:?}: {}", name, index + 1, line, e));
+        for node in nodes {
+            match node {
+                AstNode::ArcMove { .. } => {
+                    summary.arcs += 1;
+                    summary.moves.push(node);
+                }
+                AstNode::LinearMove { .. } | AstNode::RapidMove { .. } => summary.moves.push(node),
+                AstNode::SpindleOn { .. } => summary.spindle_starts += 1,
+                _ => {}
+            }
+        }
+    }
+    summary
+}
+
+#[test]
+fn fusion_360_fanuc_post() {
+    let summary = run("fusion360_fanuc.nc", include_str!("corpus/fusion360_fanuc.nc"));
+    assert_eq!(summary.line_numbers, 30);
+    assert_eq!(summary.arcs, 5);
+    assert!(summary.moves.contains(&AstNode::ArcMove {
+        clockwise: true,
+        plane: parser::modal::Plane::XY,
+        x: Some(2.75),
+        y: Some(-9.25),
+        z: None,
+        a: None,
+        b: None,
+        c: None,
+        e: None,
+        i: None,
+        j: None,
+        k: None,
+        r: Some(0.75),
+        turns: None,
+        feedrate: None,
+        spindle_speed: None,
+    }));
+}
+
+#[test]
+fn freecad_linuxcnc_post() {
+    let summary = run("freecad_linuxcnc.ngc", include_str!("corpus/freecad_linuxcnc.ngc"));
+    assert_eq!(summary.comments, 13);
+    assert_eq!(summary.arcs, 4);
+}
+
+#[test]
+fn mastercam_fanuc_post() {
+    let summary = run("mastercam_fanuc.nc", include_str!("corpus/mastercam_fanuc.nc"));
+    assert_eq!(summary.line_numbers, 29);
+    assert_eq!(summary.arcs, 2);
+    // Both tools start the spindle on the line of their first rapid.
+    assert_eq!(summary.spindle_starts, 2);
+}
+
+#[test]
+fn reprap_host_with_checksums_and_hand_edits() {
+    let summary = run("reprap_host.gcode", include_str!("corpus/reprap_host.gcode"));
+    assert_eq!(summary.lines, 9);
+    assert_eq!(
+        summary.moves.last(),
+        Some(&AstNode::RapidMove { x: None, y: None, z: Some(5.0), a: None, b: None, c: None, e: None })
+    );
+    assert!(summary.moves.contains(&AstNode::LinearMove {
+        x: Some(10.5),
+        y: Some(-2.0),
+        z: None,
+        a: None,
+        b: None,
+        c: None,
+        e: None,
+        feedrate: None,
+        spindle_speed: None,
+    }));
+
+    // One flipped bit anywhere in a line breaks its checksum.
+    let error = lexer(b"N3 G1 X10.5 Y21 E.4 F1500*47").unwrap_err();
+    assert_eq!(error.to_string(), "column 26: checksum 47 does not match the line's 46");
+}
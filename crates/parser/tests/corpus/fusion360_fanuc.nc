@@ -0,0 +1,37 @@
+%
+O1001 (POCKET)
+(Using high feed G1 F5000. instead of G0.)
+(T1  D=6. CR=0. - ZMIN=-5. - FLAT END MILL)
+N10 G90 G94 G17 G49 G40 G80
+N15 G21
+N20 G28 G91 Z0.
+N25 G90
+
+(2D POCKET1)
+N30 T1 M6
+N35 S10000 M3
+N40 G54
+N45 M8
+N50 G0 X-3.5 Y10.
+N55 G43 Z15. H1
+N60 G0 Z5.
+N65 G1 Z1. F1000.
+N70 Z-4.6 F333.3
+N75 G3 X-2.75 Y9.25 I.75 J0.
+N80 G1 X2.75 F1000.
+N85 G2 X3.5 Y8.5 I0. J-.75
+N90 G1 Y-8.5
+N95 G2 X2.75 Y-9.25 R.75
+N100 G1 X-2.75
+N105 G2 X-3.5 Y-8.5 I0. J.75
+N110 G1 Y8.5
+N115 G3 X-4.25 Y9.25 I-.75 J0.
+N120 G0 Z15.
+N125 M9
+N130 M5
+N135 G28 G91 Z0.
+N140 G90
+N145 G28 G91 X0. Y0.
+N150 G90
+N155 M30
+%
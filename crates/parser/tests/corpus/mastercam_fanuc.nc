@@ -0,0 +1,38 @@
+%
+O0000(TEST PART)
+(DATE=DD-MM-YY - 14-05-24 TIME=HH:MM - 10:35)
+(MCX FILE - T:\PARTS\TEST PART.MCX)
+(NC FILE - T:\NC\TEST PART.NC)
+(MATERIAL - ALUMINUM MM - 2024)
+( T1 |  10. FLAT ENDMILL | H1 )
+( T2 |  6.8 DRILL | H2 )
+N100 G21
+N102 G0 G17 G40 G49 G80 G90
+N104 T1 M6
+N106 G0 G90 G54 X-5.5 Y-5.5 A0. S3500 M3
+N108 G43 H1 Z25.
+N110 Z10.
+N112 G1 Z-2. F250.
+N114 X44.5 F800.
+N116 G3 X50.5 Y.5 R6.
+N118 G1 Y44.5
+N120 G3 X44.5 Y50.5 I-6. J0.
+N122 G1 X5.5
+N124 G0 Z25.
+N126 M5
+N128 G91 G28 Z0.
+N130 A0.
+N132 M01
+N134 T2 M6
+N136 G0 G90 G54 X10. Y10. A0. S2200 M3
+N138 G43 H2 Z25.
+N140 G99 G83 Z-12. R2. Q2. F100.
+N142 X40.
+N144 Y40.
+N146 G98 X10.
+N148 G80
+N150 M5
+N152 G91 G28 Z0.
+N154 G28 X0. Y0. A0.
+N156 M30
+%
@@ -0,0 +1,9 @@
+M110 N0*35
+N1 G28*18
+N2 M104 S210*100
+N3 G1 X10.5 Y20 E.4 F1500*47
+N4 G1 E-2 F2400*54
+N5 M107*32
+g 1 x 1 0 . 5 y-2 (hand edit)
+g0 z+5
+; done
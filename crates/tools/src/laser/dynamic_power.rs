@@ -1,4 +1,5 @@
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DynamicLaserPower {
     commanded_power: f32,
     commanded_feedrate: f32,
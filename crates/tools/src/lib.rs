@@ -1,2 +1,3 @@
 // Placeholder for tools crate
+#![no_std]
 pub mod laser;
@@ -0,0 +1,257 @@
+//! HTTP, JSON-RPC and WebSocket access to a host-server.
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use reqwest::multipart::{Form, Part};
+use serde_json::{json, Value};
+use std::path::Path;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+pub struct Client {
+    http: reqwest::Client,
+    base: Url,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// `printer` selects a fleet printer; routes are then namespaced under `/printers/{id}`.
+    pub fn new(url: &str, api_key: Option<String>, printer: Option<&str>) -> Result<Self> {
+        let mut base = Url::parse(url).with_context(|| format!("Invalid server URL {}", url))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            bail!("Server URL must be http or https, got {}", url);
+        }
+        let mut path = base.path().trim_end_matches('/').to_string();
+        if let Some(printer) = printer {
+            path.push_str(&format!("/printers/{}", printer));
+        }
+        base.set_path(&format!("{}/", path));
+        Ok(Self { http: reqwest::Client::new(), base, api_key })
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        Ok(self.base.join(path.trim_start_matches('/'))?)
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.header(API_KEY_HEADER, key),
+            None => request,
+        }
+    }
+
+    /// Calls a JSON-RPC method over HTTP and returns its `result`.
+    pub async fn rpc(&self, method: &str, params: Value) -> Result<Value> {
+        let request = self
+            .http
+            .post(self.endpoint("api/rpc")?)
+            .json(&rpc_request(1, method, params));
+        let response = self.authorize(request).send().await?;
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .with_context(|| format!("{} returned a non-JSON reply ({})", method, status))?;
+        rpc_result(body).with_context(|| format!("{} failed", method))
+    }
+
+    /// Uploads a G-code file and returns its path in the `gcodes` root, as `start_print`
+    /// takes it.
+    pub async fn upload(&self, file: &Path) -> Result<String> {
+        let name = file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow!("{} has no file name", file.display()))?
+            .to_string();
+        let content = tokio::fs::read(file)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let form = Form::new().part("file", Part::bytes(content).file_name(name));
+        let request = self.http.post(self.endpoint("api/files/upload")?).multipart(form);
+        let response = self.authorize(request).send().await?;
+        let status = response.status();
+        let body: Value = response.json().await.with_context(|| format!("Upload returned a non-JSON reply ({})", status))?;
+        if let Some(message) = error_message(&body) {
+            bail!("Upload failed: {}", message);
+        }
+        body["path"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Upload reply has no path: {}", body))
+    }
+
+    /// Starts printing `filename`, a path in the `gcodes` root.
+    pub async fn start_print(&self, filename: &str) -> Result<Value> {
+        self.rpc("printer.print.start", json!({ "filename": filename })).await
+    }
+
+    /// Opens the event stream: telemetry frames and `notify_*` notifications.
+    pub async fn websocket(&self) -> Result<EventStream> {
+        let mut url = self.endpoint("websocket")?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme).map_err(|_| anyhow!("Cannot use {} for a WebSocket", url))?;
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(key) = &self.api_key {
+            request.headers_mut().insert(API_KEY_HEADER, HeaderValue::from_str(key)?);
+        }
+        let (socket, _) = connect_async(request)
+            .await
+            .with_context(|| format!("Failed to open {}", url))?;
+        Ok(EventStream { socket, next_id: 1 })
+    }
+}
+
+pub struct EventStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+}
+
+impl EventStream {
+    /// Sends a JSON-RPC request over the socket and returns its id. The reply arrives as a
+    /// frame like any other.
+    pub async fn send_rpc(&mut self, method: &str, params: Value) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.socket
+            .send(Message::Text(rpc_request(id, method, params).to_string()))
+            .await?;
+        Ok(id)
+    }
+
+    /// The next JSON frame, or `None` once the server closes the socket.
+    pub async fn next(&mut self) -> Result<Option<Value>> {
+        while let Some(message) = self.socket.next().await {
+            match message? {
+                Message::Text(text) => return Ok(Some(serde_json::from_str(&text)?)),
+                Message::Close(_) => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn rpc_request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn error_message(body: &Value) -> Option<String> {
+    let error = body.get("error")?;
+    Some(match error["message"].as_str() {
+        Some(message) => message.to_string(),
+        None => error.to_string(),
+    })
+}
+
+/// The `result` of a JSON-RPC reply, or its error as an `Err`.
+pub fn rpc_result(mut reply: Value) -> Result<Value> {
+    if let Some(message) = error_message(&reply) {
+        bail!(message);
+    }
+    match reply.get_mut("result") {
+        Some(result) => Ok(result.take()),
+        None => bail!("Reply has no result: {}", reply),
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Reads one HTTP request and returns its head and body.
+    async fn read_request(socket: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else { continue };
+            let head = String::from_utf8_lossy(&request[..end]).into_owned();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                .unwrap_or(0);
+            while request.len() < end + 4 + length {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            return (head, request.split_off(end + 4));
+        }
+    }
+
+    /// Answers like host-server: uploads land in the `gcodes` root and `printer.print.start`
+    /// only finds files there.
+    async fn fake_server(listener: TcpListener) {
+        let uploaded = Arc::new(Mutex::new(Vec::<String>::new()));
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let (head, body) = read_request(&mut socket).await;
+            let reply = if head.starts_with("POST /api/files/upload ") {
+                let body = String::from_utf8_lossy(&body);
+                let name = body.split("filename=\"").nth(1).and_then(|rest| rest.split('"').next()).unwrap();
+                uploaded.lock().unwrap().push(name.to_string());
+                json!({ "message": "File uploaded successfully", "path": name })
+            } else {
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(request["method"], "printer.print.start");
+                let filename = request["params"]["filename"].as_str().unwrap_or_default();
+                if uploaded.lock().unwrap().iter().any(|name| name == filename) {
+                    json!({ "id": request["id"], "result": "ok" })
+                } else {
+                    json!({ "id": request["id"], "error": { "code": 404, "message": format!("No file gcodes/{}", filename) } })
+                }
+            };
+            let reply = reply.to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                reply.len(),
+                reply
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn printer_routes_are_namespaced() {
+        let client = Client::new("http://pi.local:7125", None, Some("mk4-02")).unwrap();
+        assert_eq!(client.endpoint("api/rpc").unwrap().as_str(), "http://pi.local:7125/printers/mk4-02/api/rpc");
+        let client = Client::new("https://pi.local/klipper/", None, None).unwrap();
+        assert_eq!(client.endpoint("/websocket").unwrap().as_str(), "https://pi.local/klipper/websocket");
+        assert!(Client::new("ftp://pi.local", None, None).is_err());
+    }
+
+    #[test]
+    fn rpc_errors_become_err() {
+        assert_eq!(rpc_result(json!({ "id": 1, "result": "ok" })).unwrap(), json!("ok"));
+        let error = rpc_result(json!({ "error": { "code": 409, "message": "Already printing a.gcode" } }));
+        assert_eq!(error.unwrap_err().to_string(), "Already printing a.gcode");
+        assert!(rpc_result(json!({})).is_err());
+    }
+
+    #[tokio::test]
+    async fn uploads_start_by_the_path_the_server_returns() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(&format!("http://{}", listener.local_addr().unwrap()), None, None).unwrap();
+        tokio::spawn(fake_server(listener));
+        let dir = std::env::temp_dir().join(format!("rklipp_cli_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(dir.join("cube.gcode"), "G28\n").await.unwrap();
+
+        let path = client.upload(&dir.join("cube.gcode")).await.unwrap();
+        assert_eq!(path, "cube.gcode");
+        assert_eq!(client.start_print(&path).await.unwrap(), json!("ok"));
+        let error = client.start_print("/home/pi/gcodes/cube.gcode").await.unwrap_err();
+        assert!(format!("{:#}", error).contains("No file"), "{:#}", error);
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
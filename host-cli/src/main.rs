@@ -0,0 +1,175 @@
+//! `rklipp`: a command-line client for scripting against host-server.
+
+mod client;
+mod monitor;
+mod output;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::timeout;
+
+use client::{rpc_result, Client, EventStream};
+use output::Format;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// host-server base URL
+    #[arg(long, global = true, env = "RKLIPP_URL", default_value = "http://localhost:7125")]
+    url: String,
+    /// API key sent as `X-Api-Key`
+    #[arg(long, global = true, env = "RKLIPP_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    /// Fleet printer id; omit to use the server's default printer
+    #[arg(long, global = true, env = "RKLIPP_PRINTER")]
+    printer: Option<String>,
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    format: Format,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Upload a G-code file
+    Upload {
+        file: PathBuf,
+        /// Start printing the file once it is uploaded
+        #[arg(long)]
+        print: bool,
+    },
+    /// Start printing an uploaded file, by the path `upload` printed
+    Start { filename: String },
+    /// Pause the running job
+    Pause,
+    /// Resume a paused job
+    Resume,
+    /// Cancel the running job
+    Cancel,
+    /// Show job state and temperatures
+    Status {
+        /// Keep streaming temperatures and position until interrupted
+        #[arg(long, short)]
+        follow: bool,
+    },
+    /// Send G-code and print what the printer answers
+    Gcode {
+        /// Commands, sent as one script with one command per argument
+        #[arg(required = true)]
+        script: Vec<String>,
+        /// Stop waiting for answers after this many milliseconds without one
+        #[arg(long, default_value_t = 2000)]
+        wait_ms: u64,
+    },
+    /// List finished and running jobs
+    History {
+        #[arg(long, short, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Live dashboard with temperatures, job progress and a G-code console
+    Monitor,
+    /// Follow the WebSocket event stream as JSON lines
+    Events {
+        /// Leave out telemetry frames and print notifications only
+        #[arg(long)]
+        no_telemetry: bool,
+    },
+}
+
+/// `notify_*` notifications carry a method; telemetry frames do not.
+fn is_notification(frame: &Value) -> bool {
+    frame.get("method").is_some()
+}
+
+/// Sends `script` over the socket and collects `notify_gcode_response` lines until the
+/// printer has been quiet for `wait`.
+async fn run_gcode(events: &mut EventStream, script: &str, wait: Duration) -> Result<Vec<String>> {
+    let id = events.send_rpc("printer.gcode.script", json!({ "script": script })).await?;
+    let mut responses = Vec::new();
+    while let Ok(frame) = timeout(wait, events.next()).await {
+        let Some(frame) = frame? else { break };
+        if frame["id"] == json!(id) {
+            rpc_result(frame)?;
+        } else if frame["method"] == "notify_gcode_response" {
+            responses.extend(frame["params"].as_array().into_iter().flatten().filter_map(Value::as_str).map(str::to_string));
+        }
+    }
+    Ok(responses)
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let client = Client::new(&cli.url, cli.api_key, cli.printer.as_deref())?;
+    let format = cli.format;
+    match cli.command {
+        Commands::Upload { file, print } => {
+            let path = client.upload(&file).await?;
+            if print {
+                client.start_print(&path).await?;
+            }
+            output::print(format, &json!({ "path": path, "printing": print }), |_| {
+                if print {
+                    format!("Uploaded and started {}", path)
+                } else {
+                    format!("Uploaded {}", path)
+                }
+            });
+        }
+        Commands::Start { filename } => {
+            let result = client.start_print(&filename).await?;
+            output::print(format, &result, |_| format!("Started {}", filename));
+        }
+        Commands::Pause => {
+            let result = client.rpc("printer.print.pause", json!({})).await?;
+            output::print(format, &result, |_| "Paused".to_string());
+        }
+        Commands::Resume => {
+            let result = client.rpc("printer.print.resume", json!({})).await?;
+            output::print(format, &result, |_| "Resumed".to_string());
+        }
+        Commands::Cancel => {
+            let result = client.rpc("printer.print.cancel", json!({})).await?;
+            output::print(format, &result, |_| "Cancelled".to_string());
+        }
+        Commands::Status { follow } => {
+            let info = client.rpc("printer.info", json!({})).await?;
+            output::print(format, &info, output::status);
+            if follow {
+                let mut events = client.websocket().await?;
+                while let Some(frame) = events.next().await? {
+                    if !is_notification(&frame) {
+                        output::print(format, &frame, output::telemetry);
+                    }
+                }
+            }
+        }
+        Commands::Gcode { script, wait_ms } => {
+            let script = script.join("\n");
+            let mut events = client.websocket().await?;
+            let responses = run_gcode(&mut events, &script, Duration::from_millis(wait_ms)).await?;
+            output::print(format, &json!(responses), |_| responses.join("\n"));
+        }
+        Commands::History { limit } => {
+            let result = client.rpc("server.history.list", json!({ "limit": limit })).await?;
+            output::print(format, &result, output::history);
+        }
+        Commands::Monitor => monitor::run(client).await?,
+        Commands::Events { no_telemetry } => {
+            let mut events = client.websocket().await?;
+            while let Some(frame) = events.next().await? {
+                if !no_telemetry || is_notification(&frame) {
+                    println!("{}", frame);
+                }
+            }
+            bail!("The server closed the event stream");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    run(Cli::parse()).await
+}
@@ -0,0 +1,283 @@
+//! Monitor state: what the WebSocket last reported, the console and the input line.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde_json::Value;
+use std::collections::VecDeque;
+
+/// Temperature samples kept for the sparklines; wider terminals show more of them.
+const TEMP_HISTORY: usize = 512;
+/// Console lines kept for scrolling back.
+const CONSOLE_LINES: usize = 1000;
+/// Commands offered by Tab before anything has been typed into the history.
+const KNOWN_COMMANDS: &[&str] = &[
+    "G0", "G1", "G2", "G3", "G4", "G10", "G11", "G28", "G29", "G90", "G91", "G92", "M82", "M83", "M84", "M104",
+    "M105", "M106", "M107", "M109", "M112", "M114", "M115", "M117", "M140", "M190", "M204", "M220", "M221", "M400",
+    "M500", "M501", "M503",
+];
+
+/// What a key asks the monitor to do beyond editing its own state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send(String),
+    Pause,
+    Resume,
+    Cancel,
+    EmergencyStop,
+    Quit,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Heater {
+    pub temp: f64,
+    pub target: f64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Job {
+    pub state: String,
+    pub filename: Option<String>,
+    /// From 0.0 to 1.0.
+    pub progress: f64,
+    pub elapsed_secs: f64,
+}
+
+impl Job {
+    /// Remaining time extrapolated from progress so far, once there is enough to go on.
+    pub fn eta_secs(&self) -> Option<f64> {
+        if self.filename.is_none() || self.progress < 0.01 {
+            return None;
+        }
+        Some(self.elapsed_secs * (1.0 - self.progress) / self.progress)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct App {
+    pub connected: bool,
+    pub extruder: Heater,
+    pub bed: Heater,
+    pub extruder_history: VecDeque<u64>,
+    pub bed_history: VecDeque<u64>,
+    pub position: [f64; 3],
+    pub job: Job,
+    pub console: VecDeque<String>,
+    /// Lines scrolled back from the bottom of the console.
+    pub scroll: usize,
+    pub input: String,
+    /// Sent commands, oldest first, and the entry Up/Down is on.
+    history: Vec<String>,
+    history_index: Option<usize>,
+    /// Set by the first cancel key press; a second one cancels.
+    pub confirm_cancel: bool,
+}
+
+fn push_bounded<T>(buffer: &mut VecDeque<T>, value: T, limit: usize) {
+    if buffer.len() == limit {
+        buffer.pop_front();
+    }
+    buffer.push_back(value);
+}
+
+impl App {
+    pub fn log(&mut self, line: impl Into<String>) {
+        push_bounded(&mut self.console, line.into(), CONSOLE_LINES);
+        if self.scroll > 0 {
+            // Keep the lines being read in place while new ones arrive.
+            self.scroll = (self.scroll + 1).min(self.console.len());
+        }
+    }
+
+    /// Applies a WebSocket frame: telemetry, or a notification the console shows.
+    pub fn apply_frame(&mut self, frame: &Value) {
+        match frame["method"].as_str() {
+            Some("notify_gcode_response") => {
+                for line in frame["params"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                    self.log(line.to_string());
+                }
+            }
+            Some(_) => {}
+            None if frame.get("nozzle_temp").is_some() => self.apply_telemetry(frame),
+            None => {}
+        }
+    }
+
+    fn apply_telemetry(&mut self, frame: &Value) {
+        let number = |key: &str| frame[key].as_f64().unwrap_or_default();
+        self.extruder = Heater { temp: number("nozzle_temp"), target: number("nozzle_target") };
+        self.bed = Heater { temp: number("bed_temp"), target: number("bed_target") };
+        self.position = [number("x_pos"), number("y_pos"), number("z_pos")];
+        push_bounded(&mut self.extruder_history, self.extruder.temp.max(0.0).round() as u64, TEMP_HISTORY);
+        push_bounded(&mut self.bed_history, self.bed.temp.max(0.0).round() as u64, TEMP_HISTORY);
+    }
+
+    /// Applies a `printer.info` result.
+    pub fn apply_info(&mut self, info: &Value) {
+        let stats = &info["print_stats"];
+        self.job = Job {
+            state: stats["state"].as_str().unwrap_or("unknown").to_string(),
+            filename: stats["filename"].as_str().map(str::to_string),
+            progress: stats["progress"].as_f64().unwrap_or_default(),
+            elapsed_secs: stats["total_duration"].as_f64().unwrap_or_default(),
+        };
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        let cancel_pending = std::mem::take(&mut self.confirm_cancel);
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return match key.code {
+                KeyCode::Char('c') | KeyCode::Char('q') => Some(Action::Quit),
+                KeyCode::Char('p') => Some(Action::Pause),
+                KeyCode::Char('r') => Some(Action::Resume),
+                KeyCode::Char('e') => Some(Action::EmergencyStop),
+                KeyCode::Char('x') if cancel_pending => Some(Action::Cancel),
+                KeyCode::Char('x') => {
+                    self.confirm_cancel = true;
+                    None
+                }
+                _ => None,
+            };
+        }
+        match key.code {
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Tab => self.complete(),
+            KeyCode::Up => self.recall(true),
+            KeyCode::Down => self.recall(false),
+            KeyCode::PageUp => self.scroll = (self.scroll + 10).min(self.console.len()),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => return self.submit(),
+            _ => {}
+        }
+        None
+    }
+
+    fn submit(&mut self) -> Option<Action> {
+        let command = std::mem::take(&mut self.input).trim().to_string();
+        self.history_index = None;
+        self.scroll = 0;
+        if command.is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&command) {
+            self.history.push(command.clone());
+        }
+        self.log(format!("> {}", command));
+        Some(Action::Send(command))
+    }
+
+    /// Steps through sent commands; stepping past the newest clears the input.
+    fn recall(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) => Some(i + 1).filter(|&i| i < self.history.len()),
+        };
+        self.history_index = index;
+        self.input = index.map(|i| self.history[i].clone()).unwrap_or_default();
+    }
+
+    /// Completes the command word being typed from known commands and the history. With
+    /// several candidates it extends to their common prefix and lists them.
+    fn complete(&mut self) {
+        if self.input.contains(' ') {
+            return;
+        }
+        let typed = self.input.to_ascii_uppercase();
+        let mut candidates: Vec<String> = KNOWN_COMMANDS
+            .iter()
+            .map(|c| c.to_string())
+            .chain(self.history.iter().filter_map(|h| h.split_whitespace().next()).map(str::to_ascii_uppercase))
+            .filter(|c| c.starts_with(&typed))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        match candidates.as_slice() {
+            [] => {}
+            [only] => self.input = format!("{} ", only),
+            [first, rest @ ..] => {
+                let common = rest.iter().fold(first.len(), |len, c| {
+                    first.bytes().zip(c.bytes()).take(len).take_while(|(a, b)| a == b).count()
+                });
+                if common == typed.len() {
+                    self.log(candidates.join("  "));
+                }
+                self.input = first[..common].to_string();
+            }
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn type_line(app: &mut App, line: &str) -> Option<Action> {
+        line.chars().for_each(|c| {
+            app.handle_key(key(KeyCode::Char(c)));
+        });
+        app.handle_key(key(KeyCode::Enter))
+    }
+
+    #[test]
+    fn frames_update_readings_and_console() {
+        let mut app = App::default();
+        app.apply_frame(&json!({ "nozzle_temp": 209.6, "nozzle_target": 210.0, "bed_temp": 60.2, "x_pos": 12.5 }));
+        assert_eq!(app.extruder.target, 210.0);
+        assert_eq!(app.position[0], 12.5);
+        assert_eq!(app.extruder_history.back(), Some(&210));
+        app.apply_frame(&json!({ "jsonrpc": "2.0", "method": "notify_gcode_response", "params": ["X:12.50 Y:0.00 Z:0.00"] }));
+        assert_eq!(app.console.back().unwrap(), "X:12.50 Y:0.00 Z:0.00");
+
+        app.apply_info(&json!({ "print_stats": {
+            "filename": "./uploads/cube.gcode", "progress": 0.25, "state": "printing", "total_duration": 600.0
+        }}));
+        assert_eq!(app.job.eta_secs(), Some(1800.0));
+    }
+
+    #[test]
+    fn console_history_and_completion() {
+        let mut app = App::default();
+        assert_eq!(type_line(&mut app, "G28"), Some(Action::Send("G28".to_string())));
+        assert_eq!(type_line(&mut app, "M114"), Some(Action::Send("M114".to_string())));
+        app.handle_key(key(KeyCode::Up));
+        app.handle_key(key(KeyCode::Up));
+        assert_eq!(app.input, "G28");
+        app.handle_key(key(KeyCode::Down));
+        app.handle_key(key(KeyCode::Down));
+        assert_eq!(app.input, "");
+
+        "m10".chars().for_each(|c| {
+            app.handle_key(key(KeyCode::Char(c)));
+        });
+        app.handle_key(key(KeyCode::Tab));
+        assert_eq!(app.input, "M10", "several candidates share only what was typed");
+        assert_eq!(app.console.back().unwrap(), "M104  M105  M106  M107  M109");
+        app.handle_key(key(KeyCode::Char('9')));
+        app.handle_key(key(KeyCode::Tab));
+        assert_eq!(app.input, "M109 ");
+    }
+
+    #[test]
+    fn cancel_needs_confirmation() {
+        let mut app = App::default();
+        assert_eq!(app.handle_key(ctrl('x')), None);
+        assert_eq!(app.handle_key(ctrl('x')), Some(Action::Cancel));
+        assert_eq!(app.handle_key(ctrl('x')), None);
+        app.handle_key(key(KeyCode::Char('a')));
+        assert_eq!(app.handle_key(ctrl('x')), None, "any other key drops the pending cancel");
+        assert_eq!(app.handle_key(ctrl('e')), Some(Action::EmergencyStop));
+    }
+}
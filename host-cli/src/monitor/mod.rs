@@ -0,0 +1,131 @@
+//! `rklipp monitor`: a terminal dashboard over host-server's WebSocket, for SSH sessions on
+//! printers without a browser.
+
+mod app;
+mod ui;
+
+use anyhow::Result;
+use crossterm::event::{Event, EventStream as TerminalEvents, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use futures_util::StreamExt;
+use ratatui::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use crate::client::{rpc_result, Client, EventStream};
+use app::{Action, App};
+
+/// How often job progress is polled, and a dropped connection retried.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// RPCs waiting for their reply on the socket, by request id.
+type Pending = HashMap<u64, &'static str>;
+
+pub async fn run(client: Client) -> Result<()> {
+    // Fail before taking over the terminal when the server is unreachable.
+    let socket = client.websocket().await?;
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    let res = run_app(&mut terminal, &client, socket).await;
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    res
+}
+
+async fn next_frame(socket: &mut Option<EventStream>) -> Result<Option<Value>> {
+    match socket {
+        Some(socket) => socket.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn send(app: &mut App, socket: &mut Option<EventStream>, pending: &mut Pending, method: &'static str, params: Value) {
+    let Some(stream) = socket.as_mut() else {
+        app.log(format!("!! Not connected, {} not sent", method));
+        return;
+    };
+    match stream.send_rpc(method, params).await {
+        Ok(id) => {
+            pending.insert(id, method);
+        }
+        Err(e) => {
+            app.log(format!("!! {}: {}", method, e));
+            app.connected = false;
+            *socket = None;
+        }
+    }
+}
+
+fn apply_reply(app: &mut App, method: &str, reply: Value) {
+    match (method, rpc_result(reply)) {
+        ("printer.info", Ok(info)) => app.apply_info(&info),
+        ("printer.gcode.script", Ok(_)) => {}
+        (_, Ok(_)) => app.log(format!("{} ok", method)),
+        (_, Err(e)) => app.log(format!("!! {}: {}", method, e)),
+    }
+}
+
+async fn run_app<B: Backend>(terminal: &mut Terminal<B>, client: &Client, socket: EventStream) -> Result<()> {
+    let mut app = App::default();
+    app.connected = true;
+    let mut socket = Some(socket);
+    let mut pending = Pending::new();
+    let mut keys = TerminalEvents::new();
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        terminal.draw(|f| ui::draw(f, &app))?;
+
+        tokio::select! {
+            event = keys.next() => {
+                let Some(Event::Key(key)) = event.transpose()? else { continue };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                let (method, params) = match app.handle_key(key) {
+                    None => continue,
+                    Some(Action::Quit) => return Ok(()),
+                    Some(Action::Send(script)) => ("printer.gcode.script", json!({ "script": script })),
+                    Some(Action::Pause) => ("printer.print.pause", json!({})),
+                    Some(Action::Resume) => ("printer.print.resume", json!({})),
+                    Some(Action::Cancel) => ("printer.print.cancel", json!({})),
+                    Some(Action::EmergencyStop) => ("printer.emergency_stop", json!({})),
+                };
+                send(&mut app, &mut socket, &mut pending, method, params).await;
+            }
+            frame = next_frame(&mut socket) => match frame {
+                Ok(Some(frame)) => match frame["id"].as_u64().and_then(|id| pending.remove(&id)) {
+                    Some(method) => apply_reply(&mut app, method, frame),
+                    None => app.apply_frame(&frame),
+                },
+                Ok(None) | Err(_) => {
+                    app.log("!! Connection to host-server lost, reconnecting");
+                    app.connected = false;
+                    socket = None;
+                    pending.clear();
+                }
+            },
+            _ = poll.tick() => {
+                if socket.is_none() {
+                    if let Ok(stream) = client.websocket().await {
+                        app.log("Reconnected");
+                        app.connected = true;
+                        socket = Some(stream);
+                    }
+                }
+                if socket.is_some() {
+                    send(&mut app, &mut socket, &mut pending, "printer.info", json!({})).await;
+                }
+            }
+        }
+    }
+}
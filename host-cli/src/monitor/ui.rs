@@ -0,0 +1,105 @@
+//! Draws the monitor: temperatures, job and position, the console and the input line.
+
+use ratatui::prelude::*;
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph, Sparkline};
+use std::collections::VecDeque;
+
+use super::app::{App, Heater};
+use crate::output::duration;
+
+const HELP: &str =
+    "Enter send  Tab complete  ↑↓ history  PgUp/Dn scroll  ^P pause  ^R resume  ^X cancel  ^E e-stop  ^Q quit";
+
+pub fn draw(f: &mut Frame, app: &App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(10), Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)])
+        .split(f.size());
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+        .split(rows[0]);
+
+    draw_temperatures(f, app, top[0]);
+    draw_job(f, app, top[1]);
+    draw_console(f, app, rows[1]);
+
+    let input = Paragraph::new(app.input.as_str()).block(Block::default().borders(Borders::ALL).title("G-code"));
+    f.render_widget(input, rows[2]);
+    f.set_cursor(rows[2].x + 1 + app.input.chars().count() as u16, rows[2].y + 1);
+
+    let footer = if app.confirm_cancel {
+        Line::styled("Press ^X again to cancel the job", Style::default().fg(Color::Red).bold())
+    } else {
+        Line::styled(HELP, Style::default().fg(Color::DarkGray))
+    };
+    f.render_widget(Paragraph::new(footer), rows[3]);
+}
+
+fn heater_title(name: &str, heater: Heater) -> String {
+    if heater.target > 0.0 {
+        format!("{} {:.1}/{:.1} °C", name, heater.temp, heater.target)
+    } else {
+        format!("{} {:.1} °C", name, heater.temp)
+    }
+}
+
+fn draw_sparkline(f: &mut Frame, area: Rect, title: String, history: &VecDeque<u64>, target: f64, color: Color) {
+    let width = area.width.saturating_sub(2) as usize;
+    let recent: Vec<u64> = history.iter().skip(history.len().saturating_sub(width)).copied().collect();
+    // Scale to the target too, so heating up reads as climbing towards it.
+    let max = recent.iter().copied().max().unwrap_or_default().max(target.round() as u64).max(1);
+    let sparkline = Sparkline::default()
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .data(&recent)
+        .max(max)
+        .style(Style::default().fg(color));
+    f.render_widget(sparkline, area);
+}
+
+fn draw_temperatures(f: &mut Frame, app: &App, area: Rect) {
+    let halves = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+    let extruder = heater_title("Extruder", app.extruder);
+    draw_sparkline(f, halves[0], extruder, &app.extruder_history, app.extruder.target, Color::Red);
+    let bed = heater_title("Bed", app.bed);
+    draw_sparkline(f, halves[1], bed, &app.bed_history, app.bed.target, Color::Blue);
+}
+
+fn draw_job(f: &mut Frame, app: &App, area: Rect) {
+    let title = if app.connected { "Job" } else { "Job (disconnected)" };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(2), Constraint::Length(1), Constraint::Length(2), Constraint::Min(1)])
+        .split(inner);
+    let job = &app.job;
+    let file = job.filename.as_deref().unwrap_or("-");
+    f.render_widget(Paragraph::new(format!("{}\n{}", job.state, file)), rows[0]);
+
+    let gauge = Gauge::default()
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(job.progress.clamp(0.0, 1.0));
+    f.render_widget(gauge, rows[1]);
+
+    let eta = job.eta_secs().map_or_else(|| "-".to_string(), duration);
+    let elapsed = if job.filename.is_some() { duration(job.elapsed_secs) } else { "-".to_string() };
+    f.render_widget(Paragraph::new(format!("Elapsed {}\nETA     {}", elapsed, eta)), rows[2]);
+
+    let [x, y, z] = app.position;
+    f.render_widget(Paragraph::new(format!("X{:.2} Y{:.2} Z{:.2}", x, y, z)), rows[3]);
+}
+
+fn draw_console(f: &mut Frame, app: &App, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = app.console.len() - app.scroll.min(app.console.len());
+    let start = end.saturating_sub(height);
+    let lines: Vec<Line> = app.console.range(start..end).map(|l| Line::raw(l.as_str())).collect();
+    let title = if app.scroll > 0 { format!("Console (+{})", app.scroll) } else { "Console".to_string() };
+    f.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)), area);
+}
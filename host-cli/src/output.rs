@@ -0,0 +1,135 @@
+//! Human-readable renderings of host-server replies. `--format json` prints replies as-is.
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Human,
+    /// One compact JSON document per line.
+    Json,
+}
+
+fn temp(value: &Value) -> String {
+    match value.as_f64() {
+        Some(t) => format!("{:.1}", t),
+        None => "-".to_string(),
+    }
+}
+
+/// Heater reading with its target when there is one, e.g. `205.3/210.0 °C`.
+fn heater(temp_value: &Value, target: &Value) -> String {
+    match target.as_f64() {
+        Some(t) if t > 0.0 => format!("{}/{:.1} °C", temp(temp_value), t),
+        _ => format!("{} °C", temp(temp_value)),
+    }
+}
+
+/// `printer.info` as one line.
+pub fn status(info: &Value) -> String {
+    let stats = &info["print_stats"];
+    let state = stats["state"].as_str().unwrap_or("unknown");
+    let job = match stats["filename"].as_str() {
+        Some(file) => format!(" {} {:.1}%", file, stats["progress"].as_f64().unwrap_or(0.0) * 100.0),
+        None => String::new(),
+    };
+    format!(
+        "{}{} | extruder {} °C | bed {} °C",
+        state,
+        job,
+        temp(&info["extruder"]["temperature"]),
+        temp(&info["heater_bed"]["temperature"])
+    )
+}
+
+/// A telemetry frame from the WebSocket as one line.
+pub fn telemetry(frame: &Value) -> String {
+    format!(
+        "extruder {} | bed {} | X{:.2} Y{:.2} Z{:.2}",
+        heater(&frame["nozzle_temp"], &frame["nozzle_target"]),
+        heater(&frame["bed_temp"], &frame["bed_target"]),
+        frame["x_pos"].as_f64().unwrap_or_default(),
+        frame["y_pos"].as_f64().unwrap_or_default(),
+        frame["z_pos"].as_f64().unwrap_or_default()
+    )
+}
+
+/// `server.history.list` as a table, newest job first as the server returns them.
+pub fn history(result: &Value) -> String {
+    let jobs = result["jobs"].as_array().map(Vec::as_slice).unwrap_or_default();
+    if jobs.is_empty() {
+        return "No jobs in history".to_string();
+    }
+    let mut lines = vec![format!("{:<20} {:<10} {:>9} {:>10}  FILE", "STARTED", "STATUS", "DURATION", "FILAMENT")];
+    for job in jobs {
+        let summary = &job["telemetry_summary"];
+        let started = job["start_time"].as_str().unwrap_or("-");
+        let duration = match summary["print_duration"].as_f64() {
+            Some(secs) => duration(secs),
+            None => "-".to_string(),
+        };
+        let filament = match summary["total_filament_used"].as_f64() {
+            Some(mm) => format!("{:.2} m", mm / 1000.0),
+            None => "-".to_string(),
+        };
+        lines.push(format!(
+            "{:<20} {:<10} {:>9} {:>10}  {}",
+            started.get(..19).unwrap_or(started).replace('T', " "),
+            job["status"].as_str().unwrap_or("-"),
+            duration,
+            filament,
+            job["file_path"].as_str().unwrap_or("-")
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Seconds as `h:mm:ss`.
+pub fn duration(secs: f64) -> String {
+    let secs = secs.max(0.0).round() as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Prints a reply in the chosen format, using `human` to render it for people.
+pub fn print(format: Format, value: &Value, human: impl FnOnce(&Value) -> String) {
+    match format {
+        Format::Human => println!("{}", human(value)),
+        Format::Json => println!("{}", value),
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn status_and_telemetry_lines() {
+        let info = json!({
+            "heater_bed": { "temperature": 59.96 },
+            "extruder": { "temperature": 210.04 },
+            "print_stats": { "filename": "./uploads/cube.gcode", "progress": 0.425, "state": "printing" }
+        });
+        assert_eq!(status(&info), "printing ./uploads/cube.gcode 42.5% | extruder 210.0 °C | bed 60.0 °C");
+
+        let frame = json!({
+            "nozzle_temp": 180.0, "nozzle_target": 210.0, "bed_temp": 24.5, "bed_target": 0.0,
+            "x_pos": 10.0, "y_pos": 20.5, "z_pos": 0.2
+        });
+        assert_eq!(telemetry(&frame), "extruder 180.0/210.0 °C | bed 24.5 °C | X10.00 Y20.50 Z0.20");
+    }
+
+    #[test]
+    fn history_table() {
+        let result = json!({ "count": 1, "jobs": [{
+            "file_path": "./uploads/cube.gcode",
+            "start_time": "2024-05-01T12:00:00.123Z",
+            "status": "completed",
+            "telemetry_summary": { "print_duration": 3725.0, "total_filament_used": 1250.0 }
+        }]});
+        let table = history(&result);
+        assert!(table.lines().nth(1).unwrap().starts_with("2024-05-01 12:00:00  completed    1:02:05     1.25 m"));
+        assert_eq!(history(&json!({ "count": 0, "jobs": [] })), "No jobs in history");
+    }
+}
@@ -0,0 +1,143 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::AppState;
+use crate::auth::{AuthError, Identity, Role};
+
+#[derive(Debug, Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    password: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateApiKeyRequest {
+    name: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameQuery {
+    name: String,
+}
+
+async fn login(
+    body: web::Json<LoginRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AuthError> {
+    let (token, identity) = state.auth.login(&body.username, &body.password).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": {
+            "username": identity.username,
+            "role": identity.role,
+            "token": token,
+            "expires_in": state.auth.token_lifetime_secs(),
+        }
+    })))
+}
+
+async fn get_current_user(identity: web::ReqData<Identity>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "result": identity.into_inner() }))
+}
+
+async fn list_users(state: web::Data<AppState>) -> Result<HttpResponse, AuthError> {
+    let users: Vec<_> = state
+        .db
+        .get_users()
+        .await?
+        .into_iter()
+        .map(|user| json!({ "username": user.username, "role": user.role, "created": user.created }))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "result": { "users": users } })))
+}
+
+async fn create_user(
+    body: web::Json<CreateUserRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AuthError> {
+    if state.db.get_user(&body.username).await?.is_some() {
+        return Ok(HttpResponse::Conflict().json(json!({
+            "error": { "code": 409, "message": format!("User {} already exists", body.username) }
+        })));
+    }
+    state.auth.create_user(&body.username, &body.password, body.role).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "username": body.username, "role": body.role, "action": "user_created" }
+    })))
+}
+
+async fn delete_user(
+    query: web::Query<NameQuery>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AuthError> {
+    if query.name == identity.username {
+        return Ok(HttpResponse::BadRequest().json(json!({
+            "error": { "code": 400, "message": "Cannot delete the logged-in user" }
+        })));
+    }
+    match state.db.delete_user(&query.name).await? {
+        Some(user) => Ok(HttpResponse::Ok().json(json!({
+            "result": { "username": user.username, "action": "user_deleted" }
+        }))),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "error": { "code": 404, "message": format!("No user named {}", query.name) }
+        }))),
+    }
+}
+
+async fn list_api_keys(state: web::Data<AppState>) -> Result<HttpResponse, AuthError> {
+    let keys: Vec<_> = state
+        .db
+        .get_api_keys()
+        .await?
+        .into_iter()
+        .map(|key| json!({ "name": key.name, "role": key.role, "created": key.created }))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "result": { "api_keys": keys } })))
+}
+
+async fn create_api_key(
+    body: web::Json<CreateApiKeyRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AuthError> {
+    let key = state.auth.create_api_key(&body.name, body.role).await?;
+    // The plain key is only ever returned here.
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "name": body.name, "role": body.role, "api_key": key }
+    })))
+}
+
+async fn delete_api_key(
+    query: web::Query<NameQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, AuthError> {
+    match state.db.delete_api_key(&query.name).await? {
+        Some(key) => Ok(HttpResponse::Ok().json(json!({
+            "result": { "name": key.name, "action": "api_key_deleted" }
+        }))),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "error": { "code": 404, "message": format!("No API key named {}", query.name) }
+        }))),
+    }
+}
+
+/// Registers the `/access` routes. Role checks are applied by `auth::auth_middleware`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/access/login", web::post().to(login))
+        .route("/access/user", web::get().to(get_current_user))
+        .route("/access/user", web::post().to(create_user))
+        .route("/access/user", web::delete().to(delete_user))
+        .route("/access/users/list", web::get().to(list_users))
+        .route("/access/api_key", web::post().to(create_api_key))
+        .route("/access/api_key", web::delete().to(delete_api_key))
+        .route("/access/api_keys/list", web::get().to(list_api_keys));
+}
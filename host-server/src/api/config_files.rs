@@ -0,0 +1,144 @@
+use actix_web::{web, HttpResponse};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::AppState;
+use crate::auth::Identity;
+use crate::db::HostError;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct FileQuery {
+    filename: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct WriteRequest {
+    filename: String,
+    content: String,
+    /// Commit even if validation produced warnings. Without it, warnings are only reported.
+    #[serde(default)]
+    accept_warnings: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DiffQuery {
+    filename: String,
+    from: u32,
+    /// Defaults to the file currently on disk.
+    to: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct RollbackRequest {
+    filename: String,
+    version: u32,
+}
+
+/// Parses JSON-RPC `params` into the same request types the REST routes use. Missing
+/// params count as an empty object, so requests whose fields all have defaults need none.
+pub(super) fn params<T: DeserializeOwned>(params: &serde_json::Value) -> Result<T, HostError> {
+    let params = if params.is_null() { serde_json::json!({}) } else { params.clone() };
+    serde_json::from_value(params).map_err(|e| HostError::InvalidRequest(e.to_string()))
+}
+
+pub(super) async fn list(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let files = state.config_files.list().await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "files": files } })))
+}
+
+pub(super) async fn read(state: web::Data<AppState>, query: FileQuery) -> Result<HttpResponse, HostError> {
+    let content = state.config_files.read(&query.filename).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "filename": query.filename, "content": content }
+    })))
+}
+
+pub(super) async fn validate(state: web::Data<AppState>, body: WriteRequest) -> Result<HttpResponse, HostError> {
+    let report = state.config_files.validate(&body.filename, &body.content).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": report })))
+}
+
+pub(super) async fn write(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    body: WriteRequest,
+) -> Result<HttpResponse, HostError> {
+    let saved = state
+        .config_files
+        .save(&body.filename, &body.content, &identity.username, body.accept_warnings)
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": saved })))
+}
+
+pub(super) async fn versions(state: web::Data<AppState>, query: FileQuery) -> Result<HttpResponse, HostError> {
+    let versions = state.config_files.versions(&query.filename).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "filename": query.filename, "versions": versions }
+    })))
+}
+
+pub(super) async fn diff(state: web::Data<AppState>, query: DiffQuery) -> Result<HttpResponse, HostError> {
+    let diff = state.config_files.diff(&query.filename, query.from, query.to).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "filename": query.filename, "from": query.from, "to": query.to, "diff": diff }
+    })))
+}
+
+pub(super) async fn rollback(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    body: RollbackRequest,
+) -> Result<HttpResponse, HostError> {
+    let version = state
+        .config_files
+        .rollback(&body.filename, body.version, &identity.username)
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "filename": body.filename, "restored": body.version, "version": version }
+    })))
+}
+
+async fn read_route(query: web::Query<FileQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    read(state, query.into_inner()).await
+}
+
+async fn validate_route(body: web::Json<WriteRequest>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    validate(state, body.into_inner()).await
+}
+
+async fn write_route(
+    body: web::Json<WriteRequest>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    write(state, &identity, body.into_inner()).await
+}
+
+async fn versions_route(query: web::Query<FileQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    versions(state, query.into_inner()).await
+}
+
+async fn diff_route(query: web::Query<DiffQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    diff(state, query.into_inner()).await
+}
+
+async fn rollback_route(
+    body: web::Json<RollbackRequest>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    rollback(state, &identity, body.into_inner()).await
+}
+
+/// Registers the `/server/config` routes. Reading or writing file contents requires admin,
+/// see `auth::required_role`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/server/config/files/list", web::get().to(list))
+        .route("/server/config/file", web::get().to(read_route))
+        .route("/server/config/file", web::post().to(write_route))
+        .route("/server/config/validate", web::post().to(validate_route))
+        .route("/server/config/versions", web::get().to(versions_route))
+        .route("/server/config/diff", web::get().to(diff_route))
+        .route("/server/config/rollback", web::post().to(rollback_route));
+}
@@ -0,0 +1,298 @@
+//! Moonraker file-root endpoints (`server.files.*`) for the `gcodes` and `config` roots.
+
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::AppState;
+use crate::auth::{AuthError, Identity, Role};
+use crate::db::HostError;
+use crate::events::{self, HostEvent};
+use crate::files::{self, FileChange};
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ListQuery {
+    #[serde(default = "default_root")]
+    root: String,
+}
+
+fn default_root() -> String {
+    files::GCODES.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct PathQuery {
+    #[serde(default = "default_root")]
+    path: String,
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct TransferRequest {
+    source: String,
+    dest: String,
+}
+
+/// Config files can hold credentials such as API keys, so reading them needs an admin.
+/// Listing the `config` root stays open to viewers; names are not secret.
+fn check_read(identity: &Identity, root: &str) -> Result<(), AuthError> {
+    identity.require(if root == files::CONFIG { Role::Admin } else { Role::Viewer })
+}
+
+/// Changes to the `config` root bypass validation and versioning, so they need an admin.
+fn check_write(identity: &Identity, paths: &[&str]) -> Result<(), AuthError> {
+    let touches_config = paths
+        .iter()
+        .any(|p| files::split_path(p).is_ok_and(|(root, _)| root == files::CONFIG));
+    identity.require(if touches_config { Role::Admin } else { Role::Operator })
+}
+
+/// Moves or drops the G-code metadata of files that were moved or deleted in the `gcodes` root.
+async fn sync_gcode_metadata(state: &AppState, change: &FileChange) -> Result<(), HostError> {
+    let gcodes = state.files.root_dir(files::GCODES)?;
+    let full = |path: &str| gcodes.join(path).to_string_lossy().into_owned();
+    let (old, new) = match &change.source_item {
+        Some(source) if source.root == files::GCODES => {
+            (full(&source.path), (change.item.root == files::GCODES).then(|| full(&change.item.path)))
+        }
+        None if change.item.root == files::GCODES && change.action.starts_with("delete_") => {
+            (full(&change.item.path), None)
+        }
+        _ => return Ok(()),
+    };
+    for file in state.db.get_gcode_files().await? {
+        // The file itself, or anything below it when a directory changed.
+        let Some(rest) = file.path.strip_prefix(&old) else { continue };
+        if !rest.is_empty() && !rest.starts_with('/') {
+            continue;
+        }
+        match &new {
+            Some(new) => state.db.rename_gcode_file(&file.path, &format!("{}{}", new, rest)).await?,
+            None => state.db.delete_gcode_metadata(&file.path).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Applies the side effects of a file change and tells WebSocket clients about it.
+async fn publish_change(state: &AppState, change: FileChange) -> Result<HttpResponse, HostError> {
+    sync_gcode_metadata(state, &change).await?;
+    events::publish(&state.event_bus, HostEvent::file_list_changed(change.clone()));
+    Ok(HttpResponse::Ok().json(json!({ "result": change })))
+}
+
+pub(super) async fn list(state: web::Data<AppState>, query: ListQuery) -> Result<HttpResponse, HostError> {
+    let files = state.files.list(&query.root).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": files })))
+}
+
+pub(super) async fn get_directory(state: web::Data<AppState>, query: PathQuery) -> Result<HttpResponse, HostError> {
+    let listing = state.files.get_directory(&query.path).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": listing })))
+}
+
+pub(super) async fn post_directory(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    query: PathQuery,
+) -> Result<HttpResponse, HostError> {
+    if let Err(e) = check_write(identity, &[query.path.as_str()]) {
+        return Ok(e.error_response());
+    }
+    let change = state.files.post_directory(&query.path).await?;
+    publish_change(&state, change).await
+}
+
+pub(super) async fn delete_directory(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    query: PathQuery,
+) -> Result<HttpResponse, HostError> {
+    if let Err(e) = check_write(identity, &[query.path.as_str()]) {
+        return Ok(e.error_response());
+    }
+    let change = state.files.delete_directory(&query.path, query.force).await?;
+    publish_change(&state, change).await
+}
+
+pub(super) async fn move_item(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    body: TransferRequest,
+) -> Result<HttpResponse, HostError> {
+    if let Err(e) = check_write(identity, &[body.source.as_str(), body.dest.as_str()]) {
+        return Ok(e.error_response());
+    }
+    let change = state.files.move_item(&body.source, &body.dest).await?;
+    publish_change(&state, change).await
+}
+
+pub(super) async fn copy_item(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    body: TransferRequest,
+) -> Result<HttpResponse, HostError> {
+    // A copy out of the `config` root would let anyone who can read the destination see it.
+    let source_root = files::split_path(&body.source).map(|(root, _)| root).unwrap_or_default();
+    let allowed = check_read(identity, source_root).and_then(|()| check_write(identity, &[body.dest.as_str()]));
+    if let Err(e) = allowed {
+        return Ok(e.error_response());
+    }
+    let change = state.files.copy_item(&body.source, &body.dest).await?;
+    publish_change(&state, change).await
+}
+
+pub(super) async fn delete_file(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    path: &str,
+) -> Result<HttpResponse, HostError> {
+    if let Err(e) = check_write(identity, &[path]) {
+        return Ok(e.error_response());
+    }
+    let change = state.files.delete_file(path).await?;
+    publish_change(&state, change).await
+}
+
+async fn list_route(query: web::Query<ListQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    list(state, query.into_inner()).await
+}
+
+async fn get_directory_route(query: web::Query<PathQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    get_directory(state, query.into_inner()).await
+}
+
+async fn post_directory_route(
+    body: web::Json<PathQuery>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    post_directory(state, &identity, body.into_inner()).await
+}
+
+async fn delete_directory_route(
+    query: web::Query<PathQuery>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    delete_directory(state, &identity, query.into_inner()).await
+}
+
+async fn move_route(
+    body: web::Json<TransferRequest>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    move_item(state, &identity, body.into_inner()).await
+}
+
+async fn copy_route(
+    body: web::Json<TransferRequest>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    copy_item(state, &identity, body.into_inner()).await
+}
+
+pub(super) async fn download(
+    req: &HttpRequest,
+    state: web::Data<AppState>,
+    identity: &Identity,
+    path: &str,
+) -> Result<HttpResponse, HostError> {
+    let file = state.files.resolve(path).await?;
+    if let Err(e) = check_read(identity, &file.root) {
+        return Ok(e.error_response());
+    }
+    let file = NamedFile::open_async(&file.full)
+        .await
+        .map_err(|_| HostError::NotFound(format!("No file {}/{}", file.root, file.rel)))?;
+    Ok(file.into_response(req))
+}
+
+async fn download_route(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let (root, filename) = path.into_inner();
+    download(&req, state, &identity, &format!("{}/{}", root, filename)).await
+}
+
+async fn delete_file_route(
+    path: web::Path<(String, String)>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let (root, filename) = path.into_inner();
+    delete_file(state, &identity, &format!("{}/{}", root, filename)).await
+}
+
+/// Registers the `/server/files` routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/server/files/list", web::get().to(list_route))
+        .route("/server/files/directory", web::get().to(get_directory_route))
+        .route("/server/files/directory", web::post().to(post_directory_route))
+        .route("/server/files/directory", web::delete().to(delete_directory_route))
+        .route("/server/files/move", web::post().to(move_route))
+        .route("/server/files/copy", web::post().to(copy_route))
+        .route("/server/files/{root}/{filename:.*}", web::get().to(download_route))
+        .route("/server/files/{root}/{filename:.*}", web::delete().to(delete_file_route));
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use crate::auth::AuthSource;
+
+    fn identity(role: Role) -> Identity {
+        Identity { username: "someone".to_string(), role, source: AuthSource::Session }
+    }
+
+    #[tokio::test]
+    async fn test_config_root_downloads_need_admin() {
+        let printer = crate::api::tests::test_printer().await;
+        for (dir, name) in [("config", "printer.cfg"), ("gcodes", "cube.gcode")] {
+            tokio::fs::create_dir_all(printer.dir.join(dir)).await.unwrap();
+            tokio::fs::write(printer.dir.join(dir).join(name), "secret").await.unwrap();
+        }
+        let req = TestRequest::default().to_http_request();
+        let viewer = identity(Role::Viewer);
+        let operator = identity(Role::Operator);
+        let admin = identity(Role::Admin);
+
+        for path in ["config/printer.cfg", "./config/printer.cfg", "config//printer.cfg"] {
+            let response = download(&req, printer.state.clone(), &operator, path).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+        }
+        let response = download(&req, printer.state.clone(), &admin, "config/printer.cfg").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = download(&req, printer.state.clone(), &viewer, "gcodes/cube.gcode").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        printer.remove().await;
+    }
+
+    #[tokio::test]
+    async fn test_copies_out_of_the_config_root_need_admin() {
+        let printer = crate::api::tests::test_printer().await;
+        tokio::fs::create_dir_all(printer.dir.join("config")).await.unwrap();
+        tokio::fs::write(printer.dir.join("config/printer.cfg"), "secret").await.unwrap();
+        let transfer = || TransferRequest {
+            source: "config/printer.cfg".to_string(),
+            dest: "gcodes/x.cfg".to_string(),
+        };
+
+        let response = copy_item(printer.state.clone(), &identity(Role::Operator), transfer()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!printer.dir.join("gcodes/x.cfg").exists());
+        let response = copy_item(printer.state.clone(), &identity(Role::Admin), transfer()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        printer.remove().await;
+    }
+}
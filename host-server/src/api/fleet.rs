@@ -0,0 +1,59 @@
+//! Fleet-wide routes: the printer overview and the shared job queue. Per-printer routes are
+//! the regular API, mounted under `/printers/{id}` by `run_api_server`.
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::auth::Identity;
+use crate::db::HostError;
+use crate::fleet::Fleet;
+
+#[derive(Debug, Deserialize)]
+struct QueueRequest {
+    /// Relative to the `gcodes` root.
+    filename: String,
+    /// Tags the printer must have, e.g. `["petg", "nozzle_0.6"]`, or a printer id.
+    #[serde(default)]
+    requires: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobQuery {
+    job_id: String,
+}
+
+async fn get_overview(fleet: web::Data<Fleet>) -> Result<HttpResponse, HostError> {
+    let printers = fleet.overview().await;
+    let queued_jobs = fleet.queued_jobs().await.len();
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "printers": printers, "queued_jobs": queued_jobs }
+    })))
+}
+
+async fn get_queue(fleet: web::Data<Fleet>) -> Result<HttpResponse, HostError> {
+    Ok(HttpResponse::Ok().json(json!({ "result": { "queued_jobs": fleet.queued_jobs().await } })))
+}
+
+async fn enqueue_job(
+    body: web::Json<QueueRequest>,
+    identity: web::ReqData<Identity>,
+    fleet: web::Data<Fleet>,
+) -> Result<HttpResponse, HostError> {
+    let body = body.into_inner();
+    let job = fleet.enqueue(&body.filename, body.requires, &identity.username).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "queued_job": job } })))
+}
+
+async fn remove_job(query: web::Query<JobQuery>, fleet: web::Data<Fleet>) -> Result<HttpResponse, HostError> {
+    let job = fleet.remove(&query.job_id).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "removed": job } })))
+}
+
+/// Registers the `/server/fleet` routes. Requires `Fleet` app data.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/server/fleet/overview", web::get().to(get_overview))
+        .route("/server/fleet/queue", web::get().to(get_queue))
+        .route("/server/fleet/queue", web::post().to(enqueue_job))
+        .route("/server/fleet/queue", web::delete().to(remove_job));
+}
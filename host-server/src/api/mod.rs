@@ -1,20 +1,45 @@
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
+pub mod access;
+pub mod config_files;
+pub mod files;
+pub mod fleet;
+pub mod multipart;
+pub mod octoprint;
+pub mod power;
+pub mod spoolman;
+pub mod web_ui;
+
+use actix_web::http::{header::CONTENT_TYPE, StatusCode};
+use actix_web::{middleware::from_fn, web, App, HttpRequest, HttpServer, Responder, HttpResponse, ResponseError};
 use actix_cors::Cors;
 use actix_ws::{Message, ProtocolError};
 use futures_util::{StreamExt, SinkExt};
-use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::sync::{broadcast, mpsc, watch, RwLock};
 use std::{sync::Arc, time::Duration};
 use anyhow::Result;
 use serde_json::json;
 use uuid::Uuid;
 use tokio::fs;
-use tokio_util::codec::{BytesCodec, FramedRead};
+use tokio_util::codec::{FramedRead, LinesCodec};
 use mime::Mime;
 use log::{info, error};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::path::Path;
 
+use crate::auth::{auth_middleware, rpc_method_role, Authenticator, Identity};
+use crate::config::HostConfig;
+use crate::config_files::ConfigManager;
 use crate::db::{Database, HostError};
-use crate::db::models::{GCodeFile, GCodeMetadata, PrintHistory, PrintStatus};
-use crate::bridge::HostToMcu; // Assuming this will be defined in bridge module
+use crate::db::models::{GCodeFile, GCodeMetadata, PrintStatus};
+use crate::bridge::{Command, FaultCode, HostToMcu}; // Assuming this will be defined in bridge module
+use crate::events::{self, HostEvent};
+use crate::files::{FileChange, FileManager, ResolvedPath, GCODES};
+use crate::fleet::Fleet;
+use crate::history::{HistoryTotals, JobRecorder};
+use crate::metrics::HostMetrics;
+use crate::power::{PowerDeviceInfo, PowerManager};
+use crate::spools::SpoolManager;
+use crate::timeseries::TimeSeriesStore;
 
 // Placeholder for machine state
 #[derive(Debug, Default)]
@@ -23,25 +48,70 @@ pub struct MachineState {
     pub bed_temp: f32,
     pub current_print_file: Option<String>,
     pub print_progress: f32,
+    pub print_start_time: Option<DateTime<Utc>>,
     // Add other relevant machine state fields
 }
 
+/// Requested state of the running job, watched by the streaming task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobControl {
+    Run,
+    Pause,
+    Cancel,
+}
+
+/// Everything the API needs to drive one printer. In fleet mode there is one per printer,
+/// sharing the database, auth and the `gcodes` root.
 pub struct AppState {
+    pub printer_id: String,
     pub db: Arc<Database>,
     pub telemetry_broadcaster: broadcast::Sender<serde_json::Value>,
+    pub event_bus: broadcast::Sender<HostEvent>,
     pub mcu_cmd_sender: mpsc::Sender<HostToMcu>,
+    /// Bypasses the flow-controlled G-code queue; for emergency stops.
+    pub mcu_priority_sender: mpsc::Sender<HostToMcu>,
     pub machine_state: Arc<RwLock<MachineState>>,
+    pub telemetry_store: Arc<RwLock<TimeSeriesStore>>,
+    pub auth: Arc<Authenticator>,
+    pub job_control: watch::Sender<JobControl>,
+    pub metrics: Arc<HostMetrics>,
+    pub config_files: Arc<ConfigManager>,
+    pub files: Arc<FileManager>,
+    pub spools: Arc<SpoolManager>,
+    /// Shared by every printer; see `PowerDeviceConfig::printer`.
+    pub power: Arc<PowerManager>,
+}
+
+impl ResponseError for HostError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            HostError::Conflict(_) => StatusCode::CONFLICT,
+            HostError::NotFound(_) => StatusCode::NOT_FOUND,
+            HostError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": { "code": self.status_code().as_u16(), "message": self.to_string() }
+        }))
+    }
 }
 
 async fn websocket_route(
     req: actix_web::HttpRequest,
     stream: web::Payload,
+    identity: web::ReqData<Identity>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, ProtocolError> {
     let (response, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
 
     let mut rx = state.telemetry_broadcaster.subscribe();
-    let mcu_cmd_sender = state.mcu_cmd_sender.clone();
+    let mut events_rx = state.event_bus.subscribe();
+    let identity = identity.into_inner();
+    info!("WebSocket session opened for {} ({:?}).", identity.username, identity.role);
+    state.metrics.websocket_clients.inc();
 
     actix_web::rt::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(10)); // Ping interval
@@ -57,8 +127,10 @@ async fn websocket_route(
                         }
                         Some(Ok(Message::Text(text))) => {
                             info!("Received WebSocket message: {}", text);
-                            // Handle incoming messages from client if needed
-                            // e.g., JSON-RPC commands over WebSocket
+                            let reply = rpc_over_websocket(&text, &identity, state.clone()).await;
+                            if session.text(reply).await.is_err() {
+                                break;
+                            }
                         }
                         Some(Ok(Message::Close(_))) => {
                             break;
@@ -80,6 +152,30 @@ async fn websocket_route(
                         Err(_) => break, // Broadcaster closed
                     }
                 }
+                event = events_rx.recv() => {
+                    match event {
+                        Ok(HostEvent::FileListChanged { change, .. }) => {
+                            if session.text(file_list_notification(&change).to_string()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(HostEvent::GCodeResponse { message, .. }) => {
+                            if session.text(gcode_response_notification(&message).to_string()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(HostEvent::PowerChanged { device, .. }) => {
+                            if session.text(power_notification(&device).to_string()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            error!("WebSocket client missed {} host events.", n);
+                        }
+                        Err(_) => break,
+                    }
+                }
                 _ = interval.tick() => {
                     if session.ping(b"").await.is_err() {
                         break;
@@ -87,14 +183,51 @@ async fn websocket_route(
                 }
             }
         }
+        state.metrics.websocket_clients.dec();
         info!("WebSocket session ended.");
     });
 
     Ok(response)
 }
 
+/// Moonraker's `notify_filelist_changed` notification.
+fn file_list_notification(change: &FileChange) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notify_filelist_changed",
+        "params": [change],
+    })
+}
+
+/// Moonraker's `notify_gcode_response` notification.
+fn gcode_response_notification(message: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notify_gcode_response",
+        "params": [message],
+    })
+}
+
+/// Moonraker's `notify_power_changed` notification.
+fn power_notification(device: &PowerDeviceInfo) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notify_power_changed",
+        "params": [device],
+    })
+}
+
 async fn get_printer_info(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
     let machine_state = state.machine_state.read().await;
+    let paused = *state.job_control.borrow() == JobControl::Pause;
+    let print_state = match (&machine_state.current_print_file, paused) {
+        (None, _) => "standby",
+        (Some(_), true) => "paused",
+        (Some(_), false) => "printing",
+    };
+    let total_duration = machine_state
+        .print_start_time
+        .map_or(0.0, |start| (Utc::now() - start).num_milliseconds() as f64 / 1000.0);
     Ok(HttpResponse::Ok().json(json!({
         "result": {
             "heater_bed": { "temperature": machine_state.bed_temp },
@@ -102,7 +235,8 @@ async fn get_printer_info(state: web::Data<AppState>) -> Result<HttpResponse, Ho
             "print_stats": {
                 "filename": machine_state.current_print_file,
                 "progress": machine_state.print_progress,
-                "state": "printing" // Placeholder
+                "state": print_state,
+                "total_duration": total_duration,
             },
             // Add more printer info from machine_state
         }
@@ -120,16 +254,198 @@ async fn get_server_info() -> Result<HttpResponse, HostError> {
     })))
 }
 
-async fn handle_rpc(
-    body: web::Json<serde_json::Value>,
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    limit: Option<u32>,
+    uid: Option<String>,
+}
+
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+
+async fn get_history_list(state: web::Data<AppState>, limit: Option<u32>) -> Result<HttpResponse, HostError> {
+    let jobs = state
+        .db
+        .get_print_history(limit.unwrap_or(DEFAULT_HISTORY_LIMIT))
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": {
+            "count": jobs.len(),
+            "jobs": jobs,
+        }
+    })))
+}
+
+async fn get_history_job(state: web::Data<AppState>, uid: Option<String>) -> Result<HttpResponse, HostError> {
+    let uid = uid.ok_or_else(|| HostError::InvalidRequest("Missing job uid".to_string()))?;
+    let job = state
+        .db
+        .get_print_job(&uid)
+        .await?
+        .ok_or_else(|| HostError::NotFound(format!("No print job with uid {}", uid)))?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "job": job } })))
+}
+
+async fn get_history_totals(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let jobs = state.db.get_all_print_history().await?;
+    let totals = HistoryTotals::from_jobs(&jobs);
+    Ok(HttpResponse::Ok().json(json!({ "result": totals })))
+}
+
+async fn history_list_route(
+    query: web::Query<HistoryQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    get_history_list(state, query.limit).await
+}
+
+async fn history_job_route(
+    query: web::Query<HistoryQuery>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, HostError> {
-    let method = body["method"].as_str().unwrap_or_default();
-    let id = body["id"].clone();
+    get_history_job(state, query.into_inner().uid).await
+}
+
+#[derive(Debug, Deserialize)]
+struct TelemetryQuery {
+    metric: String,
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+const DEFAULT_TELEMETRY_WINDOW_MS: i64 = 3_600_000;
+const TEMPERATURE_STORE_SECONDS: usize = 1200;
+
+async fn get_telemetry_metrics(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let store = state.telemetry_store.read().await;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "metrics": store.metrics() } })))
+}
+
+async fn query_telemetry(state: web::Data<AppState>, query: TelemetryQuery) -> Result<HttpResponse, HostError> {
+    let end = query.end.unwrap_or_else(|| Utc::now().timestamp_millis());
+    let start = query.start.unwrap_or(end - DEFAULT_TELEMETRY_WINDOW_MS);
+
+    let store = state.telemetry_store.read().await;
+    match store.query(&query.metric, start, end) {
+        Some(points) => Ok(HttpResponse::Ok().json(json!({
+            "result": {
+                "metric": query.metric,
+                "start": start,
+                "end": end,
+                "points": points,
+            }
+        }))),
+        None => Ok(HttpResponse::NotFound().json(json!({
+            "error": { "code": 404, "message": format!("Unknown metric: {}", query.metric) }
+        }))),
+    }
+}
+
+/// Moonraker `server.temperature_store`: the last 20 minutes of each heater at 1 s resolution.
+async fn get_temperature_store(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let now = Utc::now().timestamp_millis();
+    let store = state.telemetry_store.read().await;
+    let heater = |metric: &str| {
+        json!({ "temperatures": store.resample_seconds(metric, now, TEMPERATURE_STORE_SECONDS) })
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "result": {
+            "extruder": heater("nozzle_temp"),
+            "heater_bed": heater("bed_temp"),
+        }
+    })))
+}
+
+async fn telemetry_query_route(
+    query: web::Query<TelemetryQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    query_telemetry(state, query.into_inner()).await
+}
+
+/// Runs one JSON-RPC call on behalf of `identity`. Shared by `/api/rpc` and the WebSocket.
+async fn dispatch_rpc(
+    request: &serde_json::Value,
+    identity: &Identity,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let method = request["method"].as_str().unwrap_or_default();
+    let id = request["id"].clone();
+    let params = &request["params"];
+
+    if let Err(e) = identity.require(rpc_method_role(method)) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "id": id,
+            "error": { "code": 403, "message": e.to_string() }
+        })));
+    }
 
     match method {
         "printer.info" => get_printer_info(state).await,
         "server.info" => get_server_info().await,
+        "server.history.list" => {
+            let limit = params["limit"].as_u64().map(|l| l as u32);
+            get_history_list(state, limit).await
+        }
+        "server.history.get_job" => {
+            let uid = params["uid"].as_str().map(str::to_string);
+            get_history_job(state, uid).await
+        }
+        "server.history.totals" => get_history_totals(state).await,
+        "server.telemetry.list" => get_telemetry_metrics(state).await,
+        "server.telemetry.query" => {
+            let query = TelemetryQuery {
+                metric: params["metric"].as_str().unwrap_or_default().to_string(),
+                start: params["start"].as_i64(),
+                end: params["end"].as_i64(),
+            };
+            query_telemetry(state, query).await
+        }
+        "server.temperature_store" => get_temperature_store(state).await,
+        "server.config.files.list" => config_files::list(state).await,
+        "server.config.file.get" => config_files::read(state, config_files::params(params)?).await,
+        "server.config.file.validate" => config_files::validate(state, config_files::params(params)?).await,
+        "server.config.file.write" => config_files::write(state, identity, config_files::params(params)?).await,
+        "server.config.versions" => config_files::versions(state, config_files::params(params)?).await,
+        "server.config.diff" => config_files::diff(state, config_files::params(params)?).await,
+        "server.config.rollback" => config_files::rollback(state, identity, config_files::params(params)?).await,
+        "server.files.list" => files::list(state, config_files::params(params)?).await,
+        "server.files.get_directory" => files::get_directory(state, config_files::params(params)?).await,
+        "server.files.post_directory" => files::post_directory(state, identity, config_files::params(params)?).await,
+        "server.files.delete_directory" => files::delete_directory(state, identity, config_files::params(params)?).await,
+        "server.files.move" => files::move_item(state, identity, config_files::params(params)?).await,
+        "server.files.copy" => files::copy_item(state, identity, config_files::params(params)?).await,
+        "server.files.delete_file" => {
+            let path = params["path"]
+                .as_str()
+                .ok_or_else(|| HostError::InvalidRequest("Missing path".to_string()))?;
+            files::delete_file(state, identity, path).await
+        }
+        "server.spoolman.get_spool_id" => spoolman::get_spool_id(state, config_files::params(params)?).await,
+        "server.spoolman.post_spool_id" => spoolman::post_spool_id(state, config_files::params(params)?).await,
+        "server.spoolman.status" => spoolman::status(state).await,
+        "server.spoolman.proxy" => spoolman::proxy(state, identity, config_files::params(params)?).await,
+        "machine.device_power.devices" => power::devices(state).await,
+        "machine.device_power.get_device" => power::get_device(state, config_files::params(params)?).await,
+        "machine.device_power.post_device" => power::post_device(state, config_files::params(params)?).await,
+        "machine.device_power.status" => power::status(state, power::param_device_names(params)?).await,
+        "machine.device_power.on" => power::switch(state, power::param_device_names(params)?, true).await,
+        "machine.device_power.off" => power::switch(state, power::param_device_names(params)?, false).await,
+        "printer.emergency_stop" => emergency_stop(state, identity).await,
+        "printer.gcode.script" => {
+            let script = params["script"].as_str().unwrap_or_default();
+            run_gcode_script(state, script).await
+        }
+        "printer.print.start" => {
+            let filename = params["filename"]
+                .as_str()
+                .ok_or_else(|| HostError::InvalidRequest("Missing filename".to_string()))?;
+            let file = resolve_gcode_file(&state, filename).await?;
+            start_job(&state, file.full.to_string_lossy().into_owned()).await?;
+            Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
+        }
+        "printer.print.pause" => control_job(state, JobControl::Pause).await,
+        "printer.print.resume" => control_job(state, JobControl::Run).await,
+        "printer.print.cancel" => control_job(state, JobControl::Cancel).await,
         // Add more RPC methods as needed
         _ => Ok(HttpResponse::BadRequest().json(json!({
             "id": id,
@@ -141,108 +457,262 @@ async fn handle_rpc(
     }
 }
 
-async fn get_files(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
-    let files = state.db.get_gcode_files().await?;
-    Ok(HttpResponse::Ok().json(json!({ "files": files })))
+async fn emergency_stop(state: web::Data<AppState>, identity: &Identity) -> Result<HttpResponse, HostError> {
+    state
+        .mcu_priority_sender
+        .send(HostToMcu::Command(Command::EmergencyStop))
+        .await
+        .map_err(|e| HostError::Other(format!("Failed to send emergency stop: {}", e)))?;
+    events::publish(
+        &state.event_bus,
+        HostEvent::fault(FaultCode::EmergencyStop, format!("Requested by {}", identity.username)),
+    );
+    Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
 }
 
-async fn upload_file(
-    mut payload: web::Payload,
+/// Sends each non-empty line of `script` to the MCU in order.
+async fn run_gcode_script(state: web::Data<AppState>, script: &str) -> Result<HttpResponse, HostError> {
+    for line in script.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        state
+            .mcu_cmd_sender
+            .send(HostToMcu::GCode(line.to_string()))
+            .await
+            .map_err(|e| HostError::Other(format!("Failed to send G-code: {}", e)))?;
+    }
+    Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
+}
+
+async fn control_job(state: web::Data<AppState>, control: JobControl) -> Result<HttpResponse, HostError> {
+    if state.machine_state.read().await.current_print_file.is_none() {
+        return Err(HostError::Conflict("No print in progress".to_string()));
+    }
+    state.job_control.send_replace(control);
+    Ok(HttpResponse::Ok().json(json!({ "result": "ok" })))
+}
+
+async fn handle_rpc(
+    body: web::Json<serde_json::Value>,
+    identity: web::ReqData<Identity>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, HostError> {
-    let upload_dir = "./uploads"; // Define your upload directory
-    fs::create_dir_all(upload_dir).await.map_err(|e| HostError::Other(e.to_string()))?;
-
-    let mut filename = String::new();
-    let mut file_content = Vec::new();
-    let mut boundary = String::new();
-
-    // This is a simplified multipart parser. A real implementation would use a dedicated crate.
-    while let Some(item) = payload.next().await {
-        let mut field = item.map_err(|e| HostError::Other(e.to_string()))?;
-        if boundary.is_empty() {
-            // Attempt to extract boundary from Content-Type header if available
-            // For simplicity, we'll assume a basic boundary for now or expect it in the first chunk
-            // A proper solution would parse the Content-Type header from the request.
-            // For now, let's just assume the first line is the boundary.
-            let s = String::from_utf8_lossy(&field);
-            if let Some(line) = s.lines().next() {
-                if line.starts_with("--") {
-                    boundary = line.to_string();
-                }
-            }
-        }
+    dispatch_rpc(&body, &identity, state).await
+}
 
-        // Simplified logic to extract filename and content
-        // In a real app, use `actix-multipart` or similar
-        let s = String::from_utf8_lossy(&field);
-        if s.contains("filename=\"") {
-            if let Some(start) = s.find("filename=\"") {
-                let rest = &s[start + "filename=\"".len()..];
-                if let Some(end) = rest.find("\"") {
-                    filename = rest[..end].to_string();
-                }
-            }
-        }
-        file_content.extend_from_slice(&field);
-    }
+/// JSON-RPC reply for a request that could not be parsed.
+pub(crate) fn rpc_parse_error(e: &serde_json::Error) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": null,
+        "error": { "code": -32700, "message": format!("Parse error: {}", e) }
+    })
+}
 
-    if filename.is_empty() {
-        return Err(HostError::Other("No filename found in multipart data".to_string()));
+/// Answers a JSON-RPC text frame with the same body the REST endpoint would return.
+async fn rpc_over_websocket(text: &str, identity: &Identity, state: web::Data<AppState>) -> String {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(request) => rpc_reply(&request, identity, state).await.to_string(),
+        Err(e) => rpc_parse_error(&e).to_string(),
     }
+}
 
-    let file_path = format!("{}/{}", upload_dir, filename);
-    fs::write(&file_path, &file_content).await.map_err(|e| HostError::Other(e.to_string()))?;
+/// Runs `request` and wraps the REST response body as a JSON-RPC reply.
+/// Used by transports without HTTP responses (WebSocket, MQTT).
+pub(crate) async fn rpc_reply(
+    request: &serde_json::Value,
+    identity: &Identity,
+    state: web::Data<AppState>,
+) -> serde_json::Value {
+    let response = match dispatch_rpc(request, identity, state).await {
+        Ok(response) => response,
+        Err(e) => e.error_response(),
+    };
+    // Handler bodies are always complete, and awaiting a boxed body would make this
+    // future non-Send, which MQTT needs it to be.
+    let body = actix_web::body::MessageBody::try_into_bytes(response.into_body()).unwrap_or_default();
+    let mut reply: serde_json::Value = serde_json::from_slice(&body).unwrap_or_else(|_| json!({}));
+    reply["jsonrpc"] = json!("2.0");
+    reply["id"] = request["id"].clone();
+    reply
+}
+
+/// Prometheus scrape endpoint. Scrapers authenticate like any other client, typically via
+/// a trusted-clients CIDR or an `apikey` query parameter in the scrape config.
+async fn get_metrics(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let sender = &state.mcu_cmd_sender;
+    state
+        .metrics
+        .set_gcode_queue(sender.max_capacity() - sender.capacity(), sender.max_capacity());
+    let body = state.metrics.render().map_err(|e| HostError::Other(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
+
+async fn get_files(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let files = state.db.get_gcode_files().await?;
+    Ok(HttpResponse::Ok().json(json!({ "files": files })))
+}
+
+const MAX_UPLOAD_SIZE: usize = 512 * 1024 * 1024;
+
+/// Stores an uploaded G-code file in `folder` of the `gcodes` root and records it in the
+/// database, replacing any earlier upload with the same name unless it is being printed.
+pub(crate) async fn store_gcode_file(
+    state: &AppState,
+    folder: &str,
+    filename: &str,
+    content: &[u8],
+) -> Result<ResolvedPath, HostError> {
+    // Only keep the final path component so an upload can never escape the upload directory.
+    let name = Path::new(filename)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| HostError::Other(format!("Invalid filename: {}", filename)))?;
+
+    let target = state.files.resolve(&format!("{}/{}/{}", GCODES, folder, name)).await?;
+    let file_path = target.full.to_string_lossy().into_owned();
+    if state.machine_state.read().await.current_print_file.as_deref() == Some(file_path.as_str()) {
+        return Err(HostError::Conflict(format!("{} is being printed", target.rel)));
+    }
+    if let Some(parent) = target.full.parent() {
+        fs::create_dir_all(parent).await.map_err(|e| HostError::Other(e.to_string()))?;
+    }
+    let replaced = fs::try_exists(&target.full).await.unwrap_or(false);
+    fs::write(&target.full, content).await.map_err(|e| HostError::Other(e.to_string()))?;
 
     let metadata = GCodeMetadata::default(); // Placeholder for actual parsing
     let gcode_file = GCodeFile {
         id: None,
         path: file_path.clone(),
-        size: file_content.len() as u64,
+        size: content.len() as u64,
         upload_date: Utc::now(),
         metadata,
     };
 
+    state.db.delete_gcode_metadata(&file_path).await?;
     state.db.save_gcode_metadata(gcode_file).await?;
 
-    Ok(HttpResponse::Ok().json(json!({ "message": "File uploaded successfully", "path": file_path })))
+    let change = FileChange {
+        action: if replaced { "modify_file" } else { "create_file" }.to_string(),
+        item: state.files.item(&target).await?,
+        source_item: None,
+    };
+    events::publish(&state.event_bus, HostEvent::file_list_changed(change));
+    Ok(target)
 }
 
-async fn start_print(
-    path: web::Path<String>,
+async fn upload_file(
+    req: HttpRequest,
+    body: web::Bytes,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, HostError> {
-    let file_path = path.into_inner();
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let boundary = multipart::boundary(content_type)
+        .ok_or_else(|| HostError::Other("Expected a multipart/form-data upload".to_string()))?;
+
+    let parts = multipart::parse(&body, &boundary)?;
+    let (filename, content) = parts
+        .iter()
+        .find_map(|p| p.filename.as_deref().map(|name| (name, p.data)))
+        .ok_or_else(|| HostError::Other("No filename found in multipart data".to_string()))?;
+
+    // The path in the `gcodes` root, as `printer.print.start` takes it.
+    let file_path = store_gcode_file(&state, "", filename, content).await?.rel;
+
+    Ok(HttpResponse::Ok().json(json!({ "message": "File uploaded successfully", "path": file_path })))
+}
+
+/// Resolves `name` in the `gcodes` root for printing, refusing paths that leave the root.
+async fn resolve_gcode_file(state: &AppState, name: &str) -> Result<ResolvedPath, HostError> {
+    let file = state.files.resolve(&format!("{}/{}", GCODES, name)).await?;
+    if !fs::metadata(&file.full).await.is_ok_and(|m| m.is_file()) {
+        return Err(HostError::NotFound(format!("No file {}/{}", GCODES, file.rel)));
+    }
+    Ok(file)
+}
+
+/// Lines streamed between charging extruded filament to the active spools.
+const SPOOL_UPDATE_LINES: u32 = 500;
+
+/// Starts streaming `file_path` to the MCU. Shared by every route that can start a print.
+/// Returns a warning when the active spool looks too short for the job; it starts anyway.
+pub(crate) async fn start_job(state: &web::Data<AppState>, file_path: String) -> Result<Option<String>, HostError> {
     info!("Attempting to start print for file: {}", file_path);
+    let warning = state.spools.job_warning(&file_path).await?;
 
     let file = fs::File::open(&file_path).await.map_err(|e| HostError::Other(format!("Failed to open file: {}", e)))?;
-    let reader = FramedRead::new(file, BytesCodec::new());
+    let mut lines = FramedRead::new(file, LinesCodec::new());
 
-    let mut lines = reader.map(|r| r.map(|b| String::from_utf8_lossy(&b).to_string()));
+    // Claim the printer before spawning so two concurrent requests cannot both start a job.
+    let mut state_guard = state.machine_state.write().await;
+    if let Some(current) = &state_guard.current_print_file {
+        return Err(HostError::Conflict(format!("Already printing {}", current)));
+    }
+    state_guard.current_print_file = Some(file_path.clone());
+    state_guard.print_progress = 0.0;
+    state_guard.print_start_time = Some(Utc::now());
+    drop(state_guard);
+    state.job_control.send_replace(JobControl::Run);
+    let mut control = state.job_control.subscribe();
 
     // Simulate sending G-code lines to MCU
-    let mut mcu_cmd_sender = state.mcu_cmd_sender.clone();
+    let mcu_cmd_sender = state.mcu_cmd_sender.clone();
     let machine_state = state.machine_state.clone();
+    let db = state.db.clone();
+    let event_bus = state.event_bus.clone();
+    let printer_id = state.printer_id.clone();
+    let spools = state.spools.clone();
+    let mut recorder = JobRecorder::start(file_path.clone(), &state.telemetry_broadcaster);
+    events::publish(&event_bus, HostEvent::job(&file_path, PrintStatus::InProgress));
 
     tokio::spawn(async move {
         let mut line_num = 0;
-        while let Some(line) = lines.next().await {
+        let mut status = PrintStatus::Completed;
+        'stream: while let Some(line) = lines.next().await {
+            // Hold here while paused; a cancel ends the job at the next line boundary.
+            loop {
+                let requested = *control.borrow_and_update();
+                match requested {
+                    JobControl::Run => break,
+                    JobControl::Cancel => {
+                        info!("Print cancelled.");
+                        status = PrintStatus::Cancelled;
+                        break 'stream;
+                    }
+                    JobControl::Pause => {
+                        if control.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
             match line {
                 Ok(gcode_line) => {
                     info!("Sending G-code line {}: {}", line_num, gcode_line.trim());
                     // In a real scenario, you'd parse and send specific commands
                     if let Err(e) = mcu_cmd_sender.send(HostToMcu::GCode(gcode_line.trim().to_string())).await {
                         error!("Failed to send G-code to MCU: {}", e);
+                        status = PrintStatus::Failed;
                         break;
                     }
+                    recorder.observe_gcode(&gcode_line);
                     line_num += 1;
+                    if line_num % SPOOL_UPDATE_LINES == 0 {
+                        spools.record_extrusion(recorder.take_extruded()).await;
+                    }
                     // Update print progress (simplified)
                     let mut state_guard = machine_state.write().await;
                     state_guard.print_progress = (line_num as f32 / 1000.0).min(1.0); // Assuming 1000 lines for simplicity
-                    tokio::time::sleep(Duration::from_millis(50)).await; // Simulate print speed
+                    drop(state_guard);
                 }
                 Err(e) => {
                     error!("Error reading G-code file: {}", e);
+                    status = PrintStatus::Failed;
                     break;
                 }
             }
@@ -251,63 +721,204 @@ async fn start_print(
         let mut state_guard = machine_state.write().await;
         state_guard.print_progress = 1.0;
         state_guard.current_print_file = None;
+        state_guard.print_start_time = None;
+        drop(state_guard);
 
-        // Save print history
-        let db = state.db.clone(); // Clone Arc for async block
-        let history = PrintHistory {
-            id: None,
-            start_time: Utc::now(), // Should be actual start time
-            end_time: Some(Utc::now()),
-            status: PrintStatus::Completed,
-            telemetry_summary: Default::default(),
-        };
+        spools.record_extrusion(recorder.take_extruded()).await;
+
+        // Save print history with the telemetry collected while the job ran
+        let mut history = recorder.finish(status).await;
+        history.printer = Some(printer_id);
+        events::publish(&event_bus, HostEvent::job(&history.file_path, status));
         if let Err(e) = db.save_print_history(history).await {
             error!("Failed to save print history: {:?}", e);
         }
     });
 
-    let mut state_guard = state.machine_state.write().await;
-    state_guard.current_print_file = Some(file_path.clone());
-    state_guard.print_progress = 0.0;
+    Ok(warning)
+}
 
-    Ok(HttpResponse::Ok().json(json!({ "message": format!("Print started for {}", file_path) })))
+async fn start_print(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let file = resolve_gcode_file(&state, &path.into_inner()).await?;
+    let warning = start_job(&state, file.full.to_string_lossy().into_owned()).await?;
+    Ok(HttpResponse::Ok().json(json!({ "message": format!("Print started for {}", file.rel), "warning": warning })))
 }
 
 
-pub async fn run_api_server(
-    db: Arc<Database>,
-    telemetry_broadcaster: broadcast::Sender<serde_json::Value>,
-    mcu_cmd_sender: mpsc::Sender<HostToMcu>,
-    machine_state: Arc<RwLock<MachineState>>,
-) -> Result<()> {
-    info!("Starting Actix-Web server on 0.0.0.0:7125");
+/// The routes that act on one printer. Mounted once at the root for the first printer and
+/// again under `/printers/{id}` for every printer, each with that printer's `AppState`.
+fn printer_routes(cfg: &mut web::ServiceConfig) {
+    cfg.configure(config_files::configure)
+        .configure(files::configure)
+        .configure(octoprint::configure)
+        .configure(spoolman::configure)
+        .configure(power::configure)
+        .service(web::resource("/websocket").to(websocket_route))
+        .route("/api/rpc", web::post().to(handle_rpc))
+        .route("/api/files", web::get().to(get_files))
+        .route("/api/files/upload", web::post().to(upload_file))
+        .route("/api/print/start/{file_path}", web::post().to(start_print))
+        .route("/server/history/list", web::get().to(history_list_route))
+        .route("/server/history/job", web::get().to(history_job_route))
+        .route("/server/history/totals", web::get().to(get_history_totals))
+        .route("/server/telemetry/list", web::get().to(get_telemetry_metrics))
+        .route("/server/telemetry/query", web::get().to(telemetry_query_route))
+        .route("/server/temperature_store", web::get().to(get_temperature_store))
+        .route("/metrics", web::get().to(get_metrics));
+}
 
-    let app_state = web::Data::new(AppState {
-        db,
-        telemetry_broadcaster,
-        mcu_cmd_sender,
-        machine_state,
-    });
+pub async fn run_api_server(fleet: web::Data<Fleet>, config: Arc<HostConfig>) -> Result<()> {
+    info!("Starting Actix-Web server on {}", config.server.bind_address);
 
+    let cors_domains = config.server.cors_domains.clone();
+    let web_root = web::Data::new(web_ui::WebRoot(config.server.web_root.clone()));
+    if let Some(dir) = &config.server.web_root {
+        info!("Serving the web UI from {}", dir.display());
+    }
     HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin() // For development, allow any origin
+        let mut cors = Cors::default()
             .allow_any_method()
             .allow_any_header()
             .max_age(3600);
+        for domain in &cors_domains {
+            cors = if domain == "*" {
+                cors.allow_any_origin()
+            } else {
+                cors.allowed_origin(domain)
+            };
+        }
 
-        App::new()
-            .wrap(cors)
-            .app_data(app_state.clone())
-            .service(web::resource("/websocket").to(websocket_route))
-            .route("/api/rpc", web::post().to(handle_rpc))
-            .route("/api/files", web::get().to(get_files))
-            .route("/api/files/upload", web::post().to(upload_file))
-            .route("/api/print/start/{file_path}", web::post().to(start_print))
+        let mut app = App::new()
+            .wrap(from_fn(auth_middleware))
+            .wrap(cors) // Outermost, so CORS preflights never need credentials
+            .app_data(fleet.default_printer().state.clone())
+            .app_data(fleet.clone())
+            .app_data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
+            .configure(access::configure)
+            .configure(fleet::configure);
+        for printer in fleet.printers() {
+            app = app.service(
+                web::scope(&format!("/printers/{}", printer.id))
+                    .app_data(printer.state.clone())
+                    .configure(printer_routes),
+            );
+        }
+        app.configure(printer_routes)
+            .app_data(web_root.clone())
+            .configure(web_ui::configure)
     })
-    .bind("0.0.0.0:7125")?
+    .bind(config.server.bind_address.as_str())?
     .run()
     .await?;
 
     Ok(())
 }
+
+// --- Tests ---
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::config::AuthConfig;
+
+    /// A printer backed by a fresh database and file roots under the temp directory.
+    pub(crate) struct TestPrinter {
+        pub state: web::Data<AppState>,
+        pub dir: PathBuf,
+    }
+
+    impl TestPrinter {
+        pub async fn remove(self) {
+            fs::remove_dir_all(&self.dir).await.unwrap();
+        }
+    }
+
+    pub(crate) async fn test_printer() -> TestPrinter {
+        let dir = std::env::temp_dir().join(format!("r_klipp_api_{}", Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let db = Arc::new(Database::new(&dir.join("r_klipp.db").to_string_lossy()).await.unwrap());
+        db.init_schema().await.unwrap();
+
+        let (telemetry_broadcaster, _) = broadcast::channel(16);
+        let (event_bus, _) = broadcast::channel(16);
+        let (mcu_cmd_sender, _) = mpsc::channel(16);
+        let (job_control, _) = watch::channel(JobControl::Run);
+        let state = web::Data::new(AppState {
+            printer_id: "default".to_string(),
+            db: db.clone(),
+            telemetry_broadcaster,
+            event_bus,
+            mcu_priority_sender: mcu_cmd_sender.clone(),
+            mcu_cmd_sender,
+            machine_state: Arc::new(RwLock::new(MachineState::default())),
+            telemetry_store: Arc::new(RwLock::new(TimeSeriesStore::default())),
+            auth: Arc::new(Authenticator::new(db.clone(), &AuthConfig::default()).unwrap()),
+            job_control,
+            metrics: Arc::new(HostMetrics::new().unwrap()),
+            config_files: Arc::new(ConfigManager::new("default", dir.join("config"), db.clone())),
+            files: Arc::new(FileManager::new(dir.join("gcodes"), dir.join("config"))),
+            spools: Arc::new(SpoolManager::new("default", db)),
+            power: Arc::new(PowerManager::new(&[], &[])),
+        });
+        TestPrinter { state, dir }
+    }
+
+    #[tokio::test]
+    async fn test_history_job_lookup_errors() {
+        let printer = test_printer().await;
+        let missing = get_history_job(printer.state.clone(), None).await.unwrap_err();
+        assert_eq!(missing.status_code(), StatusCode::BAD_REQUEST);
+        let unknown = get_history_job(printer.state.clone(), Some("0042".to_string())).await.unwrap_err();
+        assert_eq!(unknown.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(unknown.error_response().status(), StatusCode::NOT_FOUND);
+        printer.remove().await;
+    }
+
+    #[tokio::test]
+    async fn test_uploads_answer_with_the_name_print_start_takes() {
+        let printer = test_printer().await;
+        let req = actix_web::test::TestRequest::post()
+            .uri("/api/files/upload")
+            .insert_header((CONTENT_TYPE, "multipart/form-data; boundary=b"))
+            .to_http_request();
+        let body = "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"cube.gcode\"\r\n\r\nG28\r\n--b--\r\n";
+        let response = upload_file(req, web::Bytes::from(body), printer.state.clone()).await.unwrap();
+        let body = actix_web::body::MessageBody::try_into_bytes(response.into_body()).unwrap();
+        let reply: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["path"], "cube.gcode");
+        assert!(resolve_gcode_file(&printer.state, "cube.gcode").await.is_ok());
+        printer.remove().await;
+    }
+
+    #[tokio::test]
+    async fn test_print_start_stays_in_gcodes_root() {
+        let printer = test_printer().await;
+        let config = printer.dir.join("config");
+        fs::create_dir_all(&config).await.unwrap();
+        fs::write(config.join("printer.cfg"), "[printer]\n").await.unwrap();
+        let identity = Identity {
+            username: "admin".to_string(),
+            role: crate::auth::Role::Admin,
+            source: crate::auth::AuthSource::Session,
+        };
+
+        let outside = config.join("printer.cfg").to_string_lossy().into_owned();
+        for (filename, status) in [
+            ("../config/printer.cfg", StatusCode::BAD_REQUEST),
+            ("sub/../../config/printer.cfg", StatusCode::BAD_REQUEST),
+            (outside.as_str(), StatusCode::NOT_FOUND),
+        ] {
+            let request = json!({ "method": "printer.print.start", "params": { "filename": filename } });
+            let error = dispatch_rpc(&request, &identity, printer.state.clone()).await.unwrap_err();
+            assert_eq!(error.status_code(), status, "{}", filename);
+        }
+        let missing = json!({ "method": "printer.print.start", "params": {} });
+        let error = dispatch_rpc(&missing, &identity, printer.state.clone()).await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert!(printer.state.machine_state.read().await.current_print_file.is_none());
+        printer.remove().await;
+    }
+}
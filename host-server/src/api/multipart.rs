@@ -0,0 +1,130 @@
+use crate::db::HostError;
+
+/// One `multipart/form-data` field.
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: &'a [u8],
+}
+
+impl Part<'_> {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(self.data).trim().to_string()
+    }
+}
+
+/// Extracts the boundary from a `Content-Type: multipart/form-data; boundary=...` header.
+/// Qt-based clients (Cura) quote the boundary.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|pos| pos + from)
+}
+
+/// Reads `name="..."` style parameters out of a `Content-Disposition` header.
+fn disposition_param(header: &str, param: &str) -> Option<String> {
+    header.split(';').find_map(|part| {
+        let (key, value) = part.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(param)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Splits a complete `multipart/form-data` body into its fields.
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<Part<'a>>, HostError> {
+    let malformed = |reason: &str| HostError::Other(format!("Malformed multipart body: {}", reason));
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next_delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut pos = find(body, &delimiter, 0).ok_or_else(|| malformed("missing boundary"))? + delimiter.len();
+    let mut parts = Vec::new();
+
+    loop {
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts); // Closing delimiter
+        }
+        pos += body[pos..]
+            .starts_with(b"\r\n")
+            .then_some(2)
+            .ok_or_else(|| malformed("expected CRLF after boundary"))?;
+
+        let headers_end = find(body, b"\r\n\r\n", pos).ok_or_else(|| malformed("unterminated part headers"))?;
+        let part_end = find(body, &next_delimiter, headers_end).ok_or_else(|| malformed("missing closing boundary"))?;
+        let headers = String::from_utf8_lossy(&body[pos..headers_end]);
+
+        let disposition = headers
+            .split("\r\n")
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim().eq_ignore_ascii_case("Content-Disposition").then_some(value)
+            })
+            .ok_or_else(|| malformed("part without Content-Disposition"))?;
+
+        parts.push(Part {
+            name: disposition_param(disposition, "name").unwrap_or_default(),
+            filename: disposition_param(disposition, "filename"),
+            data: &body[headers_end + 4..part_end],
+        });
+        pos = part_end + next_delimiter.len();
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary_plain_and_quoted() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----abc123").as_deref(),
+            Some("----abc123")
+        );
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"boundary_.oOo._xyz\"").as_deref(),
+            Some("boundary_.oOo._xyz")
+        );
+        assert_eq!(boundary("application/json"), None);
+    }
+
+    #[test]
+    fn test_parse_fields_and_file() {
+        let body = b"--XX\r\n\
+Content-Disposition: form-data; name=\"print\"\r\n\r\n\
+true\r\n\
+--XX\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.gcode\"\r\n\
+Content-Type: application/octet-stream\r\n\r\n\
+G28\r\nG1 X10\r\n\
+--XX--\r\n";
+        let parts = parse(body, "XX").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "print");
+        assert_eq!(parts[0].text(), "true");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.gcode"));
+        assert_eq!(parts[1].data, b"G28\r\nG1 X10");
+    }
+
+    #[test]
+    fn test_parse_rejects_truncated_body() {
+        let body = b"--XX\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\nG28";
+        assert!(parse(body, "XX").is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! The subset of the OctoPrint REST API used by slicer "upload to printer" integrations
+//! (PrusaSlicer, Cura's OctoPrint plugin, OrcaSlicer). Authenticates with `X-Api-Key`.
+
+use actix_web::http::header::{CONTENT_TYPE, LOCATION};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::path::Path;
+
+use crate::api::multipart::{self, Part};
+use crate::api::{start_job, store_gcode_file, AppState, MachineState};
+use crate::db::models::GCodeFile;
+use crate::db::HostError;
+
+/// Version reported to clients. Slicers check that `text` starts with "OctoPrint".
+const OCTOPRINT_VERSION: &str = "1.9.3";
+const OCTOPRINT_API_VERSION: &str = "0.1";
+
+/// The fields of a `POST /api/files/local` upload that we act on.
+#[derive(Debug, PartialEq)]
+pub struct UploadRequest<'a> {
+    pub filename: String,
+    pub data: &'a [u8],
+    /// Start printing the file once stored.
+    pub print: bool,
+    /// Folder in the `gcodes` root to store the file in, `/`-separated; empty for the root.
+    pub path: String,
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "true" | "yes" | "1")
+}
+
+impl<'a> UploadRequest<'a> {
+    pub fn from_parts(parts: &[Part<'a>]) -> Result<Self, HostError> {
+        let file = parts
+            .iter()
+            .find(|p| p.name == "file")
+            .ok_or_else(|| HostError::Other("No file included".to_string()))?;
+        let field = |name: &str| parts.iter().find(|p| p.name == name).map(Part::text);
+
+        Ok(Self {
+            filename: file
+                .filename
+                .clone()
+                .ok_or_else(|| HostError::Other("File part has no filename".to_string()))?,
+            data: file.data,
+            print: field("print").is_some_and(|v| is_true(&v)),
+            path: field("path").unwrap_or_default(),
+        })
+    }
+}
+
+// --- Response Bodies ---
+
+fn version_json() -> serde_json::Value {
+    json!({
+        "api": OCTOPRINT_API_VERSION,
+        "server": OCTOPRINT_VERSION,
+        "text": format!("OctoPrint {} (r_klipp host-server {})", OCTOPRINT_VERSION, env!("CARGO_PKG_VERSION")),
+    })
+}
+
+fn state_text(machine_state: &MachineState) -> &'static str {
+    if machine_state.current_print_file.is_some() {
+        "Printing"
+    } else {
+        "Operational"
+    }
+}
+
+fn printer_json(machine_state: &MachineState) -> serde_json::Value {
+    let printing = machine_state.current_print_file.is_some();
+    json!({
+        "temperature": {
+            "tool0": { "actual": machine_state.nozzle_temp, "target": null, "offset": 0 },
+            "bed": { "actual": machine_state.bed_temp, "target": null, "offset": 0 },
+        },
+        "sd": { "ready": false },
+        "state": {
+            "text": state_text(machine_state),
+            "flags": {
+                "operational": true,
+                "printing": printing,
+                "cancelling": false,
+                "pausing": false,
+                "resuming": false,
+                "finishing": false,
+                "closedOrError": false,
+                "error": false,
+                "paused": false,
+                "ready": !printing,
+                "sdReady": false,
+            }
+        }
+    })
+}
+
+fn job_json(machine_state: &MachineState, file: Option<&GCodeFile>, now: DateTime<Utc>) -> serde_json::Value {
+    let name = machine_state
+        .current_print_file
+        .as_deref()
+        .and_then(|p| Path::new(p).file_name())
+        .map(|n| n.to_string_lossy().into_owned());
+    let print_time = machine_state
+        .print_start_time
+        .map(|start| (now - start).num_seconds().max(0));
+    let estimated = file.and_then(|f| f.metadata.estimated_time);
+    let time_left = match (estimated, print_time) {
+        (Some(total), Some(elapsed)) => Some((total as i64 - elapsed).max(0)),
+        _ => None,
+    };
+
+    json!({
+        "job": {
+            "file": {
+                "name": name,
+                "path": name,
+                "display": name,
+                "origin": name.as_ref().map(|_| "local"),
+                "size": file.map(|f| f.size),
+                "date": file.map(|f| f.upload_date.timestamp()),
+            },
+            "estimatedPrintTime": estimated,
+            "filament": {
+                "tool0": { "length": file.and_then(|f| f.metadata.filament_length), "volume": null }
+            },
+            "user": null,
+        },
+        "progress": {
+            "completion": name.as_ref().map(|_| machine_state.print_progress * 100.0),
+            "filepos": null,
+            "printTime": print_time,
+            "printTimeLeft": time_left,
+            "printTimeLeftOrigin": time_left.map(|_| "estimate"),
+        },
+        "state": state_text(machine_state),
+    })
+}
+
+// --- Routes ---
+
+async fn get_version() -> HttpResponse {
+    HttpResponse::Ok().json(version_json())
+}
+
+async fn upload_local(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let Some(boundary) = multipart::boundary(content_type) else {
+        return Ok(HttpResponse::BadRequest().json(json!({ "error": "Expected multipart/form-data upload" })));
+    };
+    let parts = multipart::parse(&body, &boundary)?;
+    let upload = match UploadRequest::from_parts(&parts) {
+        Ok(upload) => upload,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(json!({ "error": e.to_string() }))),
+    };
+
+    let stored = store_gcode_file(&state, &upload.path, &upload.filename, upload.data).await?;
+    let name = Path::new(&stored.rel)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = stored.rel;
+
+    if upload.print {
+        start_job(&state, stored.full.to_string_lossy().into_owned()).await?;
+    }
+
+    let info = req.connection_info();
+    let resource = format!("{}://{}/api/files/local/{}", info.scheme(), info.host(), path);
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, resource.clone()))
+        .json(json!({
+            "done": true,
+            "effectivePrint": upload.print,
+            "effectiveSelect": upload.print,
+            "files": {
+                "local": {
+                    "name": name,
+                    "path": path,
+                    "display": name,
+                    "origin": "local",
+                    "refs": {
+                        "resource": resource,
+                        "download": format!("{}://{}/downloads/files/local/{}", info.scheme(), info.host(), path),
+                    }
+                }
+            }
+        })))
+}
+
+async fn get_job(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let machine_state = state.machine_state.read().await;
+    let file = match &machine_state.current_print_file {
+        Some(path) => state.db.get_gcode_file(path).await?,
+        None => None,
+    };
+    Ok(HttpResponse::Ok().json(job_json(&machine_state, file.as_ref(), Utc::now())))
+}
+
+async fn get_printer(state: web::Data<AppState>) -> HttpResponse {
+    let machine_state = state.machine_state.read().await;
+    HttpResponse::Ok().json(printer_json(&machine_state))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/version", web::get().to(get_version))
+        .route("/api/files/local", web::post().to(upload_local))
+        .route("/api/job", web::get().to(get_job))
+        .route("/api/printer", web::get().to(get_printer));
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+    use crate::db::models::GCodeMetadata;
+
+    /// Splits a recorded HTTP request into its Content-Type header and body.
+    fn recorded_upload(raw: &[u8]) -> (String, &[u8]) {
+        let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&raw[..split]);
+        assert!(head.starts_with("POST /api/files/local HTTP/1.1"));
+        assert!(head.lines().any(|l| l.starts_with("X-Api-Key: ")));
+
+        let content_type = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Type: "))
+            .unwrap()
+            .to_string();
+        let content_length: usize = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = &raw[split + 4..];
+        assert_eq!(body.len(), content_length);
+        (content_type, body)
+    }
+
+    fn parse_upload(raw: &[u8]) -> (String, bool, String, Vec<u8>) {
+        let (content_type, body) = recorded_upload(raw);
+        let boundary = multipart::boundary(&content_type).unwrap();
+        let parts = multipart::parse(body, &boundary).unwrap();
+        let upload = UploadRequest::from_parts(&parts).unwrap();
+        (upload.filename, upload.print, upload.path, upload.data.to_vec())
+    }
+
+    #[test]
+    fn test_prusaslicer_upload_and_print() {
+        let raw = include_bytes!("../../tests/fixtures/octoprint/prusaslicer_upload_and_print.http");
+        let (filename, print, path, data) = parse_upload(raw);
+        assert_eq!(filename, "3DBenchy_0.2mm_PLA_MK3S_42m.gcode");
+        assert!(print);
+        assert_eq!(path, "");
+        assert!(data.starts_with(b"; generated by PrusaSlicer 2.7.1"));
+        assert!(data.ends_with(b"; estimated printing time (normal mode) = 42m 13s\r\n"));
+    }
+
+    #[test]
+    fn test_cura_upload_with_quoted_boundary() {
+        let raw = include_bytes!("../../tests/fixtures/octoprint/cura_upload_select.http");
+        let (filename, print, _, data) = parse_upload(raw);
+        assert_eq!(filename, "CE3E3V2_calibration_cube.gcode");
+        assert!(!print);
+        assert!(data.starts_with(b";FLAVOR:Marlin\n"));
+        assert!(data.ends_with(b"E0.02903\n"));
+    }
+
+    #[test]
+    fn test_orcaslicer_upload_to_subfolder() {
+        let raw = include_bytes!("../../tests/fixtures/octoprint/orcaslicer_upload_subfolder.http");
+        let (filename, print, path, data) = parse_upload(raw);
+        assert_eq!(filename, "Voron_cube_PETG.gcode");
+        assert!(!print);
+        assert_eq!(path, "orca/projects");
+        assert!(data.ends_with(b"; EXECUTABLE_BLOCK_END\n"));
+    }
+
+    #[test]
+    fn test_upload_without_file_is_rejected() {
+        let parts = [Part { name: "print".to_string(), filename: None, data: b"true" }];
+        assert!(UploadRequest::from_parts(&parts).is_err());
+    }
+
+    #[test]
+    fn test_version_identifies_as_octoprint() {
+        let version = version_json();
+        assert!(version["text"].as_str().unwrap().starts_with("OctoPrint "));
+        assert_eq!(version["api"], OCTOPRINT_API_VERSION);
+    }
+
+    #[test]
+    fn test_printer_and_job_state() {
+        let idle = MachineState { nozzle_temp: 24.5, bed_temp: 23.0, ..Default::default() };
+        let printer = printer_json(&idle);
+        assert_eq!(printer["temperature"]["tool0"]["actual"], 24.5);
+        assert_eq!(printer["state"]["text"], "Operational");
+        assert_eq!(printer["state"]["flags"]["ready"], true);
+
+        let now = Utc::now();
+        let printing = MachineState {
+            current_print_file: Some("./uploads/cube.gcode".to_string()),
+            print_progress: 0.25,
+            print_start_time: Some(now - chrono::Duration::seconds(600)),
+            ..Default::default()
+        };
+        let file = GCodeFile {
+            id: None,
+            path: "./uploads/cube.gcode".to_string(),
+            size: 1024,
+            upload_date: now,
+            metadata: GCodeMetadata { estimated_time: Some(2400), ..Default::default() },
+        };
+        let job = job_json(&printing, Some(&file), now);
+        assert_eq!(job["state"], "Printing");
+        assert_eq!(job["job"]["file"]["name"], "cube.gcode");
+        assert_eq!(job["progress"]["completion"], 25.0);
+        assert_eq!(job["progress"]["printTime"], 600);
+        assert_eq!(job["progress"]["printTimeLeft"], 1800);
+        assert_eq!(printer_json(&printing)["state"]["flags"]["printing"], true);
+    }
+
+    // --- Handlers ---
+
+    fn upload_request(content_type: &str) -> HttpRequest {
+        actix_web::test::TestRequest::post()
+            .uri("/api/files/local")
+            .insert_header((CONTENT_TYPE, content_type))
+            .to_http_request()
+    }
+
+    /// A multipart upload of `data` as `filename` into folder `path`.
+    fn form(filename: &str, path: &str, data: &[u8]) -> (String, web::Bytes) {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--b\r\nContent-Disposition: form-data; name=\"path\"\r\n\r\n");
+        body.extend_from_slice(path.as_bytes());
+        body.extend_from_slice(b"\r\n--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"");
+        body.extend_from_slice(filename.as_bytes());
+        body.extend_from_slice(b"\"\r\n\r\n");
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--b--\r\n");
+        ("multipart/form-data; boundary=b".to_string(), body.into())
+    }
+
+    async fn json_body(response: HttpResponse) -> serde_json::Value {
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upload_job_and_printer_handlers() {
+        let printer = crate::api::tests::test_printer().await;
+        let state = printer.state.clone();
+
+        let raw = include_bytes!("../../tests/fixtures/octoprint/orcaslicer_upload_subfolder.http");
+        let (content_type, body) = recorded_upload(raw);
+        let response = upload_local(upload_request(&content_type), web::Bytes::copy_from_slice(body), state.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uploaded = json_body(response).await;
+        assert_eq!(uploaded["files"]["local"]["name"], "Voron_cube_PETG.gcode");
+        assert_eq!(uploaded["files"]["local"]["path"], "orca/projects/Voron_cube_PETG.gcode");
+        let stored = printer.dir.join("gcodes/orca/projects/Voron_cube_PETG.gcode");
+        assert!(stored.is_file());
+
+        let job = json_body(get_job(state.clone()).await.unwrap()).await;
+        assert_eq!(job["state"], "Operational");
+        assert_eq!(job["job"]["file"]["name"], serde_json::Value::Null);
+        let idle = json_body(get_printer(state.clone()).await).await;
+        assert_eq!(idle["state"]["flags"]["ready"], true);
+
+        // The file being printed cannot be replaced.
+        state.machine_state.write().await.current_print_file = Some(stored.to_string_lossy().into_owned());
+        let (content_type, body) = form("Voron_cube_PETG.gcode", "orca/projects", b"G28\n");
+        let error = upload_local(upload_request(&content_type), body, state.clone()).await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+        assert!(std::fs::read(&stored).unwrap().ends_with(b"; EXECUTABLE_BLOCK_END\n"));
+
+        let job = json_body(get_job(state.clone()).await.unwrap()).await;
+        assert_eq!(job["state"], "Printing");
+        assert_eq!(job["job"]["file"]["name"], "Voron_cube_PETG.gcode");
+        let printing = json_body(get_printer(state.clone()).await).await;
+        assert_eq!(printing["state"]["flags"]["printing"], true);
+
+        // Folders stay inside the gcodes root.
+        let (content_type, body) = form("cube.gcode", "../config", b"G28\n");
+        let error = upload_local(upload_request(&content_type), body, state.clone()).await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+        assert!(!printer.dir.join("config/cube.gcode").exists());
+        printer.remove().await;
+    }
+}
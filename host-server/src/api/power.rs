@@ -0,0 +1,125 @@
+//! Moonraker's `machine.device_power.*` endpoints.
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::api::AppState;
+use crate::db::HostError;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DeviceQuery {
+    device: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DeviceAction {
+    device: String,
+    /// `on`, `off` or `toggle`.
+    action: String,
+}
+
+/// Device names given as bare query keys (`?printer&lights`) or JSON-RPC param keys.
+fn device_names<'a>(keys: impl Iterator<Item = &'a str>) -> Result<Vec<String>, HostError> {
+    let names: Vec<String> = keys.filter(|k| !k.is_empty()).map(str::to_string).collect();
+    if names.is_empty() {
+        return Err(HostError::InvalidRequest("No power devices given".to_string()));
+    }
+    Ok(names)
+}
+
+pub(super) fn query_device_names(query: &str) -> Result<Vec<String>, HostError> {
+    device_names(query.split('&').map(|pair| pair.split('=').next().unwrap_or_default()))
+}
+
+pub(super) fn param_device_names(params: &Value) -> Result<Vec<String>, HostError> {
+    device_names(params.as_object().into_iter().flat_map(|p| p.keys().map(String::as_str)))
+}
+
+pub(super) async fn devices(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let devices = state.power.devices().await;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "devices": devices } })))
+}
+
+pub(super) async fn get_device(state: web::Data<AppState>, query: DeviceQuery) -> Result<HttpResponse, HostError> {
+    let status = state.power.status(&query.device).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { query.device: status } })))
+}
+
+pub(super) async fn post_device(state: web::Data<AppState>, body: DeviceAction) -> Result<HttpResponse, HostError> {
+    let status = match body.action.as_str() {
+        "on" => state.power.set(&body.device, true).await?,
+        "off" => state.power.set(&body.device, false).await?,
+        "toggle" => state.power.toggle(&body.device).await?,
+        other => return Err(HostError::InvalidRequest(format!("Unknown power action {}", other))),
+    };
+    Ok(HttpResponse::Ok().json(json!({ "result": { body.device: status } })))
+}
+
+pub(super) async fn status(state: web::Data<AppState>, names: Vec<String>) -> Result<HttpResponse, HostError> {
+    let mut result = Map::new();
+    for name in names {
+        let status = state.power.status(&name).await?;
+        result.insert(name, json!(status));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "result": result })))
+}
+
+/// Switches every device in `names`. A device that fails reports `error` and the others
+/// are still switched, as in Moonraker.
+pub(super) async fn switch(state: web::Data<AppState>, names: Vec<String>, on: bool) -> Result<HttpResponse, HostError> {
+    let mut result = Map::new();
+    for name in names {
+        let status = match state.power.set(&name, on).await {
+            Ok(status) => status,
+            Err(HostError::NotFound(message)) => return Err(HostError::NotFound(message)),
+            Err(_) => "error",
+        };
+        result.insert(name, json!(status));
+    }
+    Ok(HttpResponse::Ok().json(json!({ "result": result })))
+}
+
+async fn get_device_route(query: web::Query<DeviceQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    get_device(state, query.into_inner()).await
+}
+
+async fn post_device_route(query: web::Query<DeviceAction>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    post_device(state, query.into_inner()).await
+}
+
+async fn status_route(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    status(state, query_device_names(req.query_string())?).await
+}
+
+async fn on_route(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    switch(state, query_device_names(req.query_string())?, true).await
+}
+
+async fn off_route(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    switch(state, query_device_names(req.query_string())?, false).await
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/machine/device_power/devices", web::get().to(devices))
+        .route("/machine/device_power/device", web::get().to(get_device_route))
+        .route("/machine/device_power/device", web::post().to(post_device_route))
+        .route("/machine/device_power/status", web::get().to(status_route))
+        .route("/machine/device_power/on", web::post().to(on_route))
+        .route("/machine/device_power/off", web::post().to(off_route));
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_names_come_from_bare_keys() {
+        assert_eq!(query_device_names("printer&lights").unwrap(), vec!["printer", "lights"]);
+        assert_eq!(query_device_names("printer=").unwrap(), vec!["printer"]);
+        assert!(query_device_names("").is_err());
+        assert_eq!(param_device_names(&json!({ "psu": null })).unwrap(), vec!["psu"]);
+        assert!(param_device_names(&Value::Null).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Spool inventory endpoints: a Spoolman-compatible REST subset under `/api/v1`, and
+//! Moonraker's `server.spoolman.*` methods for the spool loaded in each extruder.
+
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::api::config_files::params;
+use crate::api::AppState;
+use crate::auth::{Identity, Role};
+use crate::db::HostError;
+use crate::spools::DEFAULT_EXTRUDER;
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ExtruderQuery {
+    #[serde(default = "default_extruder")]
+    extruder: String,
+}
+
+fn default_extruder() -> String {
+    DEFAULT_EXTRUDER.to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct SpoolIdRequest {
+    spool_id: Option<u32>,
+    #[serde(default = "default_extruder")]
+    extruder: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ProxyRequest {
+    request_method: String,
+    /// Spoolman path, e.g. `/v1/spool/3`.
+    path: String,
+    #[serde(default)]
+    query: Option<String>,
+    #[serde(default)]
+    body: Option<Value>,
+    #[serde(default)]
+    use_v2_response: bool,
+}
+
+fn spool_id(segment: &str) -> Result<u32, HostError> {
+    segment
+        .parse()
+        .map_err(|_| HostError::InvalidRequest(format!("Invalid spool id {}", segment)))
+}
+
+/// Answers one Spoolman API request. `path` is relative to `/api/v1` (or the proxy's `/v1`).
+async fn spoolman_request(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    query: &str,
+    body: Value,
+) -> Result<Value, HostError> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method.as_str(), segments.as_slice()) {
+        ("GET", ["health"]) => Ok(json!({ "status": "healthy" })),
+        ("GET", ["info"]) => Ok(json!({ "version": env!("CARGO_PKG_VERSION"), "debug_mode": false })),
+        ("GET", ["spool"]) => {
+            let allow_archived = query.split('&').any(|pair| pair == "allow_archived=true");
+            let spools = state.spools.list().await?;
+            Ok(spools
+                .iter()
+                .filter(|spool| allow_archived || !spool.archived)
+                .map(|spool| spool.to_spoolman())
+                .collect())
+        }
+        ("POST", ["spool"]) => Ok(state.spools.create(params(&body)?).await?.to_spoolman()),
+        ("GET", ["spool", id]) => Ok(state.spools.get(spool_id(id)?).await?.to_spoolman()),
+        ("PATCH", ["spool", id]) => Ok(state.spools.update(spool_id(id)?, params(&body)?).await?.to_spoolman()),
+        ("DELETE", ["spool", id]) => Ok(state.spools.delete(spool_id(id)?).await?.to_spoolman()),
+        ("PUT", ["spool", id, "use"]) => Ok(state.spools.use_filament(spool_id(id)?, params(&body)?).await?.to_spoolman()),
+        _ => Err(HostError::NotFound(format!("No Spoolman endpoint {} {}", method, path))),
+    }
+}
+
+pub(super) async fn get_spool_id(state: web::Data<AppState>, query: ExtruderQuery) -> Result<HttpResponse, HostError> {
+    let spool_id = state.spools.active_spool(&query.extruder).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "spool_id": spool_id } })))
+}
+
+pub(super) async fn post_spool_id(state: web::Data<AppState>, body: SpoolIdRequest) -> Result<HttpResponse, HostError> {
+    state.spools.set_active_spool(&body.extruder, body.spool_id).await?;
+    Ok(HttpResponse::Ok().json(json!({ "result": { "spool_id": body.spool_id } })))
+}
+
+pub(super) async fn status(state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    let spool_id = state.spools.active_spool(DEFAULT_EXTRUDER).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "result": { "spoolman_connected": true, "pending_reports": [], "spool_id": spool_id }
+    })))
+}
+
+/// Moonraker's Spoolman proxy, answered from the local inventory.
+pub(super) async fn proxy(
+    state: web::Data<AppState>,
+    identity: &Identity,
+    request: ProxyRequest,
+) -> Result<HttpResponse, HostError> {
+    let method = Method::from_bytes(request.request_method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| HostError::InvalidRequest(format!("Invalid method {}", request.request_method)))?;
+    if method != Method::GET {
+        if let Err(e) = identity.require(Role::Operator) {
+            return Ok(e.error_response());
+        }
+    }
+    let path = request
+        .path
+        .strip_prefix("/v1/")
+        .ok_or_else(|| HostError::InvalidRequest(format!("Unsupported Spoolman path {}", request.path)))?;
+    let query = request.query.unwrap_or_default();
+    let result = spoolman_request(&state, &method, path, &query, request.body.unwrap_or(Value::Null)).await;
+
+    if !request.use_v2_response {
+        return Ok(HttpResponse::Ok().json(json!({ "result": result? })));
+    }
+    let result = match result {
+        Ok(response) => json!({ "response": response, "response_headers": null, "error": null }),
+        Err(e) => json!({
+            "response": null,
+            "response_headers": null,
+            "error": { "status_code": e.status_code().as_u16(), "message": e.to_string() }
+        }),
+    };
+    Ok(HttpResponse::Ok().json(json!({ "result": result })))
+}
+
+async fn spoolman_route(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body).map_err(|e| HostError::InvalidRequest(e.to_string()))?
+    };
+    let response = spoolman_request(&state, req.method(), &path, req.query_string(), body).await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+async fn get_spool_id_route(query: web::Query<ExtruderQuery>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    get_spool_id(state, query.into_inner()).await
+}
+
+async fn post_spool_id_route(body: web::Json<SpoolIdRequest>, state: web::Data<AppState>) -> Result<HttpResponse, HostError> {
+    post_spool_id(state, body.into_inner()).await
+}
+
+async fn proxy_route(
+    body: web::Json<ProxyRequest>,
+    identity: web::ReqData<Identity>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, HostError> {
+    proxy(state, &identity, body.into_inner()).await
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/v1/{path:.*}", web::route().to(spoolman_route))
+        .route("/server/spoolman/spool_id", web::get().to(get_spool_id_route))
+        .route("/server/spoolman/spool_id", web::post().to(post_spool_id_route))
+        .route("/server/spoolman/status", web::get().to(status))
+        .route("/server/spoolman/proxy", web::post().to(proxy_route));
+}
@@ -0,0 +1,120 @@
+//! Serves a built Mainsail or Fluidd bundle from `server.web_root`, so no separate web
+//! server is needed in front of host-server.
+
+use actix_files::NamedFile;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::path::{Path, PathBuf};
+
+use crate::db::HostError;
+use crate::files::ensure_inside;
+
+/// Path prefixes owned by the API. Everything else may belong to the web UI.
+const API_PREFIXES: &[&str] = &["/access", "/api", "/machine", "/metrics", "/printer", "/printers", "/server", "/websocket"];
+
+/// Bundlers emit content-hashed file names under `assets/`, so those never change.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Everything else is revalidated on each load, so a UI update shows up immediately.
+const REVALIDATE: &str = "no-cache";
+
+/// The configured web UI directory, registered as app data.
+pub struct WebRoot(pub Option<PathBuf>);
+
+pub fn is_api_path(path: &str) -> bool {
+    API_PREFIXES
+        .iter()
+        .any(|prefix| path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+}
+
+/// Finds the file to serve for `request_path`. Client-side routes (paths without a file
+/// extension) fall back to `index.html`; missing assets do not.
+pub async fn resolve_asset(web_root: &Path, request_path: &str) -> Option<PathBuf> {
+    let parts: Vec<&str> = request_path.split('/').filter(|p| !p.is_empty()).collect();
+    if parts.iter().any(|p| p.starts_with('.') || p.contains('\\')) {
+        return None;
+    }
+
+    let index = web_root.join("index.html");
+    let path = parts.iter().fold(web_root.to_path_buf(), |path, part| path.join(part));
+    let candidate = match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => path,
+        Ok(metadata) if metadata.is_dir() => path.join("index.html"),
+        _ if parts.last().is_none_or(|name| !name.contains('.')) => index,
+        _ => return None,
+    };
+    ensure_inside(web_root, &candidate).await.ok()?;
+    tokio::fs::metadata(&candidate).await.is_ok_and(|m| m.is_file()).then_some(candidate)
+}
+
+pub fn cache_control(web_root: &Path, file: &Path) -> &'static str {
+    let is_html = file.extension().is_some_and(|ext| ext == "html");
+    if !is_html && file.strip_prefix(web_root).is_ok_and(|rel| rel.starts_with("assets")) {
+        IMMUTABLE
+    } else {
+        REVALIDATE
+    }
+}
+
+async fn serve(req: HttpRequest, web_root: web::Data<WebRoot>) -> Result<HttpResponse, HostError> {
+    let not_found = || HostError::NotFound(format!("No route for {}", req.path()));
+    let Some(root) = &web_root.0 else { return Err(not_found()) };
+    if is_api_path(req.path()) || !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return Err(not_found());
+    }
+
+    let file = resolve_asset(root, req.path()).await.ok_or_else(not_found)?;
+    let mut response = NamedFile::open_async(&file)
+        .await
+        .map_err(|e| HostError::Other(e.to_string()))?
+        .into_response(&req);
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control(root, &file)));
+    Ok(response)
+}
+
+/// Serves the web UI for every request no other route matched. These skip authentication,
+/// see `auth_middleware`. Requires `WebRoot` app data.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.default_service(web::to(serve));
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_paths_are_never_served_from_the_web_root() {
+        for path in ["/server/files/list", "/websocket", "/api", "/metrics", "/access/login", "/printers/mk4/api/rpc"] {
+            assert!(is_api_path(path), "{}", path);
+        }
+        for path in ["/", "/index.html", "/assets/index-3f2a.js", "/apiary", "/settings/machine"] {
+            assert!(!is_api_path(path), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn client_routes_fall_back_to_index_but_missing_assets_do_not() {
+        let root = std::env::temp_dir().join(format!("r_klipp_web_{}", uuid::Uuid::new_v4().simple()));
+        tokio::fs::create_dir_all(root.join("assets")).await.unwrap();
+        tokio::fs::write(root.join("index.html"), "<html></html>").await.unwrap();
+        tokio::fs::write(root.join("assets/index-3f2a.js"), "").await.unwrap();
+        tokio::fs::write(root.join("config.json"), "{}").await.unwrap();
+
+        let index = root.join("index.html");
+        assert_eq!(resolve_asset(&root, "/").await, Some(index.clone()));
+        assert_eq!(resolve_asset(&root, "/settings/machine").await, Some(index.clone()));
+        assert_eq!(resolve_asset(&root, "/config.json").await, Some(root.join("config.json")));
+        assert_eq!(resolve_asset(&root, "/assets/missing.js").await, None);
+        assert_eq!(resolve_asset(&root, "/../secret").await, None);
+        assert_eq!(resolve_asset(&root, "/.env").await, None);
+
+        let asset = resolve_asset(&root, "/assets/index-3f2a.js").await.unwrap();
+        assert_eq!(cache_control(&root, &asset), IMMUTABLE);
+        assert_eq!(cache_control(&root, &index), REVALIDATE);
+        assert_eq!(cache_control(&root, &root.join("config.json")), REVALIDATE);
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
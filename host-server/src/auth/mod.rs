@@ -0,0 +1,536 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::Utc;
+use ipnet::IpNet;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::config::AuthConfig;
+use crate::db::models::{ApiKey, User};
+use crate::db::{Database, HostError};
+
+pub use crate::db::models::Role;
+
+// --- Identity and Errors ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthSource {
+    Session,
+    ApiKey,
+    TrustedClient,
+}
+
+/// Who is making a request. Inserted into the request extensions by `auth_middleware`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    pub username: String,
+    pub role: Role,
+    pub source: AuthSource,
+}
+
+impl Identity {
+    pub fn require(&self, role: Role) -> Result<(), AuthError> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden(role))
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("Authentication required")]
+    Unauthorized,
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error("Invalid or expired token")]
+    InvalidToken,
+    #[error("Tokens are only accepted in the query string on /websocket")]
+    TokenInQuery,
+    #[error("Invalid API key")]
+    InvalidApiKey,
+    #[error("The {0:?} role is required")]
+    Forbidden(Role),
+    #[error("Database error: {0}")]
+    Db(#[from] HostError),
+    #[error("Password hashing error: {0}")]
+    Hash(String),
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized
+            | AuthError::InvalidCredentials
+            | AuthError::InvalidToken
+            | AuthError::TokenInQuery
+            | AuthError::InvalidApiKey => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AuthError::Db(_) | AuthError::Hash(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(json!({
+            "error": {
+                "code": self.status_code().as_u16(),
+                "message": self.to_string(),
+            }
+        }))
+    }
+}
+
+// --- Credentials ---
+
+/// A session names only its user: the role is looked up on every request, so a deleted
+/// or demoted user loses access at once rather than when the token expires.
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+pub fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AuthError::Hash(e.to_string()))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false)
+}
+
+/// Generates a new random API key. Only its SHA-256 digest is ever stored.
+pub fn generate_api_key() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Parses the `trusted_clients` list. Bare addresses are accepted as single-host blocks.
+pub fn parse_trusted_clients(entries: &[String]) -> Result<Vec<IpNet>> {
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow!("Invalid trusted client CIDR: {}", entry))
+        })
+        .collect()
+}
+
+// --- Authenticator ---
+
+pub struct Authenticator {
+    db: Arc<Database>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    token_lifetime_secs: i64,
+    trusted_clients: Vec<IpNet>,
+    trusted_role: Role,
+}
+
+impl Authenticator {
+    pub fn new(db: Arc<Database>, config: &AuthConfig) -> Result<Self> {
+        let secret = match &config.jwt_secret {
+            Some(secret) => secret.clone(),
+            None => {
+                warn!("No auth.jwt_secret configured; sessions will not survive a restart.");
+                generate_api_key()
+            }
+        };
+        Ok(Self {
+            db,
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            decoding_key: DecodingKey::from_secret(secret.as_bytes()),
+            token_lifetime_secs: config.token_lifetime_secs as i64,
+            trusted_clients: parse_trusted_clients(&config.trusted_clients)?,
+            trusted_role: config.trusted_role,
+        })
+    }
+
+    pub fn token_lifetime_secs(&self) -> i64 {
+        self.token_lifetime_secs
+    }
+
+    pub fn issue_token(&self, username: &str) -> Result<String, AuthError> {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: username.to_string(),
+            iat: now,
+            exp: now + self.token_lifetime_secs,
+        };
+        encode(&Header::default(), &claims, &self.encoding_key).map_err(|_| AuthError::InvalidToken)
+    }
+
+    pub async fn verify_token(&self, token: &str) -> Result<Identity, AuthError> {
+        let claims = decode::<Claims>(token, &self.decoding_key, &Validation::default())
+            .map_err(|_| AuthError::InvalidToken)?
+            .claims;
+        // A token issued before the account was created belongs to an earlier user of the name.
+        let user = self
+            .db
+            .get_user(&claims.sub)
+            .await?
+            .filter(|user| claims.iat >= user.created.timestamp())
+            .ok_or(AuthError::InvalidToken)?;
+        Ok(Identity {
+            username: user.username,
+            role: user.role,
+            source: AuthSource::Session,
+        })
+    }
+
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted_clients.iter().any(|net| net.contains(&addr))
+    }
+
+    pub async fn verify_api_key(&self, key: &str) -> Result<Identity, AuthError> {
+        let api_key = self
+            .db
+            .get_api_key_by_hash(&hash_api_key(key))
+            .await?
+            .ok_or(AuthError::InvalidApiKey)?;
+        Ok(Identity {
+            username: api_key.name,
+            role: api_key.role,
+            source: AuthSource::ApiKey,
+        })
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> Result<(String, Identity), AuthError> {
+        let user = self
+            .db
+            .get_user(username)
+            .await?
+            .filter(|user| verify_password(password, &user.password_hash))
+            .ok_or(AuthError::InvalidCredentials)?;
+        let token = self.issue_token(&user.username)?;
+        Ok((
+            token,
+            Identity {
+                username: user.username,
+                role: user.role,
+                source: AuthSource::Session,
+            },
+        ))
+    }
+
+    /// Resolves the caller from, in order: a `Bearer` JWT, an `X-Api-Key` header,
+    /// a `token` query parameter on `/websocket` only (browsers cannot set headers on
+    /// WebSocket upgrades, and anywhere else it would end up in access logs), an
+    /// OctoPrint-style `apikey` query parameter, or the trusted-clients allow-list.
+    pub async fn authenticate(&self, req: &HttpRequest) -> Result<Identity, AuthError> {
+        let headers = req.headers();
+        if let Some(token) = headers
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+        {
+            return self.verify_token(token.trim()).await;
+        }
+        if let Some(key) = headers.get("X-Api-Key").and_then(|v| v.to_str().ok()) {
+            return self.verify_api_key(key.trim()).await;
+        }
+        let query = web::Query::<std::collections::HashMap<String, String>>::from_query(req.query_string())
+            .map(|q| q.into_inner())
+            .unwrap_or_default();
+        if let Some(token) = query.get("token") {
+            if strip_printer_prefix(req.path()) != "/websocket" {
+                return Err(AuthError::TokenInQuery);
+            }
+            return self.verify_token(token).await;
+        }
+        if let Some(key) = query.get("apikey") {
+            return self.verify_api_key(key).await;
+        }
+        if let Some(addr) = req.peer_addr().map(|a| a.ip()) {
+            if self.is_trusted(addr) {
+                return Ok(Identity {
+                    username: addr.to_string(),
+                    role: self.trusted_role,
+                    source: AuthSource::TrustedClient,
+                });
+            }
+        }
+        Err(AuthError::Unauthorized)
+    }
+
+    pub async fn create_user(&self, username: &str, password: &str, role: Role) -> Result<(), AuthError> {
+        let user = User {
+            username: username.to_string(),
+            password_hash: hash_password(password)?,
+            role,
+            created: Utc::now(),
+        };
+        self.db.save_user(user).await?;
+        Ok(())
+    }
+
+    /// Creates a named API key and returns the plain key. It cannot be recovered later.
+    pub async fn create_api_key(&self, name: &str, role: Role) -> Result<String, AuthError> {
+        let key = generate_api_key();
+        let api_key = ApiKey {
+            name: name.to_string(),
+            key_hash: hash_api_key(&key),
+            role,
+            created: Utc::now(),
+        };
+        self.db.save_api_key(api_key).await?;
+        Ok(key)
+    }
+
+    /// Creates the initial `admin` account from `RKLIPP_ADMIN_PASSWORD` when no users exist yet.
+    pub async fn bootstrap_admin(&self) -> Result<(), AuthError> {
+        if !self.db.get_users().await?.is_empty() {
+            return Ok(());
+        }
+        match std::env::var("RKLIPP_ADMIN_PASSWORD") {
+            Ok(password) if !password.is_empty() => {
+                self.create_user("admin", &password, Role::Admin).await?;
+                info!("Created initial admin user.");
+            }
+            _ => warn!("No users exist; set RKLIPP_ADMIN_PASSWORD to create an admin account."),
+        }
+        Ok(())
+    }
+}
+
+// --- Access Rules ---
+
+/// The route under a `/printers/{id}` prefix, so namespaced routes need the same role as
+/// the un-namespaced ones.
+fn strip_printer_prefix(path: &str) -> &str {
+    path.strip_prefix("/printers/")
+        .and_then(|rest| rest.find('/').map(|slash| &rest[slash..]))
+        .unwrap_or(path)
+}
+
+/// Minimum role for a REST route, or `None` for routes open to anyone.
+pub fn required_role(method: &Method, path: &str) -> Option<Role> {
+    match strip_printer_prefix(path) {
+        "/access/login" => None,
+        "/access/user" if method == Method::GET => Some(Role::Viewer),
+        p if p.starts_with("/access/") => Some(Role::Admin),
+        // Config files can hold credentials, so even reading them needs an admin. Downloads
+        // from the `config` file root are checked in `api::files`.
+        "/server/config/file" | "/server/config/diff" | "/server/config/rollback" => Some(Role::Admin),
+        // Each JSON-RPC call is checked against `rpc_method_role` once the method is known.
+        "/websocket" | "/api/rpc" => Some(Role::Viewer),
+        _ if method == Method::GET || method == Method::HEAD => Some(Role::Viewer),
+        _ => Some(Role::Operator),
+    }
+}
+
+/// Minimum role for a JSON-RPC method. Unknown methods require admin.
+pub fn rpc_method_role(method: &str) -> Role {
+    match method {
+        "printer.info" | "server.info" | "server.temperature_store" => Role::Viewer,
+        m if m.starts_with("server.history.") || m.starts_with("server.telemetry.") => Role::Viewer,
+        // Reading config file contents requires admin, like writing them.
+        "server.config.files.list" | "server.config.file.validate" | "server.config.versions" => Role::Viewer,
+        "server.files.list" | "server.files.get_directory" => Role::Viewer,
+        // Writes to the `config` root additionally require admin, see `api::files`.
+        m if m.starts_with("server.files.") => Role::Operator,
+        // Proxied Spoolman writes additionally require an operator, see `api::spoolman`.
+        "server.spoolman.get_spool_id" | "server.spoolman.status" | "server.spoolman.proxy" => Role::Viewer,
+        "server.spoolman.post_spool_id" => Role::Operator,
+        "machine.device_power.devices" | "machine.device_power.get_device" | "machine.device_power.status" => Role::Viewer,
+        m if m.starts_with("machine.device_power.") => Role::Operator,
+        m if m.starts_with("printer.") => Role::Operator,
+        _ => Role::Admin,
+    }
+}
+
+/// Authenticates every request and enforces `required_role` before it reaches a handler.
+pub async fn auth_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // The router matches on the percent-decoded path, so `/%73erver/config/file` reaches the
+    // same handler as `/server/config/file`. Check the role against what will actually run.
+    let path = req.match_info().as_str();
+    // Requests no route matches fall through to the web UI. The bundle is public; it logs in
+    // through the API like any other client.
+    if !req.resource_map().has_resource(path) {
+        return next.call(req).await;
+    }
+    let Some(role) = required_role(req.method(), path) else {
+        return next.call(req).await;
+    };
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .cloned()
+        .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing application state"))?;
+
+    let identity = state.auth.authenticate(req.request()).await?;
+    identity.require(role)?;
+    req.extensions_mut().insert(identity);
+    next.call(req).await
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not-a-hash"));
+    }
+
+    #[test]
+    fn test_api_keys_are_random_and_hashed() {
+        let a = generate_api_key();
+        let b = generate_api_key();
+        assert_ne!(a, b);
+        assert_eq!(a.len(), 64);
+        assert_eq!(hash_api_key(&a), hash_api_key(&a));
+        assert_ne!(hash_api_key(&a), a);
+    }
+
+    #[test]
+    fn test_trusted_clients() {
+        let nets = parse_trusted_clients(&["192.168.1.0/24".to_string(), "10.0.0.5".to_string()]).unwrap();
+        let contains = |ip: &str| nets.iter().any(|n| n.contains(&ip.parse::<IpAddr>().unwrap()));
+        assert!(contains("192.168.1.42"));
+        assert!(contains("10.0.0.5"));
+        assert!(!contains("10.0.0.6"));
+        assert!(!contains("192.168.2.1"));
+        assert!(parse_trusted_clients(&["not-a-cidr".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_role_ordering_and_rules() {
+        let viewer = Identity { username: "v".into(), role: Role::Viewer, source: AuthSource::Session };
+        assert!(viewer.require(Role::Viewer).is_ok());
+        assert!(matches!(viewer.require(Role::Operator), Err(AuthError::Forbidden(Role::Operator))));
+
+        assert_eq!(required_role(&Method::POST, "/access/login"), None);
+        assert_eq!(required_role(&Method::GET, "/api/files"), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::POST, "/api/files/upload"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::POST, "/access/user"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/access/users/list"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/assets/index-3f2a.js"), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::POST, "/assets/index-3f2a.js"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/server/files/gcodes/a.gcode"), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::DELETE, "/server/files/gcodes/a.gcode"), Some(Role::Operator));
+        assert_eq!(required_role(&Method::GET, "/printers/mk4/api/files"), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::POST, "/printers/mk4/server/config/file"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/server/config/file"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/printers/mk4/server/config/diff"), Some(Role::Admin));
+        assert_eq!(required_role(&Method::GET, "/server/config/versions"), Some(Role::Viewer));
+        assert_eq!(required_role(&Method::POST, "/server/fleet/queue"), Some(Role::Operator));
+
+        assert_eq!(rpc_method_role("server.history.list"), Role::Viewer);
+        assert_eq!(rpc_method_role("printer.gcode.script"), Role::Operator);
+        assert_eq!(rpc_method_role("server.files.get_directory"), Role::Viewer);
+        assert_eq!(rpc_method_role("server.files.move"), Role::Operator);
+        assert_eq!(rpc_method_role("server.spoolman.proxy"), Role::Viewer);
+        assert_eq!(rpc_method_role("server.config.file.get"), Role::Admin);
+        assert_eq!(rpc_method_role("server.config.diff"), Role::Admin);
+        assert_eq!(rpc_method_role("server.config.versions"), Role::Viewer);
+        assert_eq!(rpc_method_role("server.spoolman.post_spool_id"), Role::Operator);
+        assert_eq!(rpc_method_role("machine.reboot"), Role::Admin);
+        assert_eq!(rpc_method_role("machine.device_power.status"), Role::Viewer);
+        assert_eq!(rpc_method_role("machine.device_power.off"), Role::Operator);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_follow_the_user_record() {
+        let printer = crate::api::tests::test_printer().await;
+        let auth = &printer.state.auth;
+        auth.create_user("ada", "hunter2", Role::Admin).await.unwrap();
+        let (token, _) = auth.login("ada", "hunter2").await.unwrap();
+        assert_eq!(auth.verify_token(&token).await.unwrap().role, Role::Admin);
+
+        // Demoted: the same token now carries the new role.
+        let user = printer.state.db.delete_user("ada").await.unwrap().unwrap();
+        printer.state.db.save_user(User { role: Role::Viewer, ..user }).await.unwrap();
+        assert_eq!(auth.verify_token(&token).await.unwrap().role, Role::Viewer);
+
+        printer.state.db.delete_user("ada").await.unwrap();
+        assert!(matches!(auth.verify_token(&token).await, Err(AuthError::InvalidToken)));
+
+        // Query-string tokens only open the WebSocket.
+        auth.create_user("ada", "hunter2", Role::Admin).await.unwrap();
+        let (token, _) = auth.login("ada", "hunter2").await.unwrap();
+        let request = |uri: &str| actix_web::test::TestRequest::with_uri(uri).to_http_request();
+        let websocket = request(&format!("/printers/mk4/websocket?token={}", token));
+        assert_eq!(auth.authenticate(&websocket).await.unwrap().username, "ada");
+        let api = request(&format!("/server/info?token={}", token));
+        assert!(matches!(auth.authenticate(&api).await, Err(AuthError::TokenInQuery)));
+        printer.remove().await;
+    }
+
+    #[tokio::test]
+    async fn test_middleware_checks_the_routed_path() {
+        use actix_web::middleware::from_fn;
+        use actix_web::test::{init_service, try_call_service, TestRequest};
+
+        let printer = crate::api::tests::test_printer().await;
+        let viewer_key = printer.state.auth.create_api_key("dashboard", Role::Viewer).await.unwrap();
+        let app = init_service(
+            actix_web::App::new()
+                .wrap(from_fn(auth_middleware))
+                .app_data(printer.state.clone())
+                .route("/server/config/file", web::get().to(HttpResponse::Ok))
+                .route("/access/users/list", web::get().to(HttpResponse::Ok))
+                .route("/server/info", web::get().to(HttpResponse::Ok))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let status = |request: TestRequest| {
+            let response = try_call_service(&app, request.to_request());
+            async move {
+                match response.await {
+                    Ok(response) => response.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                }
+            }
+        };
+        let viewer = |uri: &str| TestRequest::get().uri(uri).insert_header(("X-Api-Key", viewer_key.as_str()));
+
+        let anonymous = TestRequest::get().uri("/%73erver/config/file?filename=printer.cfg");
+        assert_eq!(status(anonymous).await, StatusCode::UNAUTHORIZED);
+        let anonymous = TestRequest::get().uri("/%61ccess/users/list");
+        assert_eq!(status(anonymous).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(viewer("/server/config/fil%65")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(viewer("/%73erver/info")).await, StatusCode::OK);
+
+        // Only requests that fall through to the web UI skip authentication.
+        assert_eq!(status(TestRequest::get().uri("/assets/index-3f2a.js")).await, StatusCode::OK);
+        assert_eq!(status(TestRequest::get().uri("/server/info")).await, StatusCode::UNAUTHORIZED);
+        printer.remove().await;
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Result};
+use postcard::to_allocvec_cobs;
+use std::collections::{BTreeMap, VecDeque};
+use uuid::Uuid;
+
+use super::{HostFrame, HostToMcu};
+
+/// A COBS-encoded frame ready for the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub seq: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Credit-based flow control and delivery tracking for frames sent to the MCU.
+///
+/// Credits are the free slots the MCU last advertised through `buffer_fill_percent`, minus
+/// the frames sent since that report, and never more than the unacknowledged frames the
+/// buffer could hold. Every frame keeps its sequence number until a `Response` for it
+/// arrives; after a reconnect the unacknowledged frames are resent with their original
+/// numbers, so the MCU can discard any it had already executed. Frames carry a session id
+/// picked at random for each `FlowControl`, so the MCU tells a restarted host numbering from
+/// 0 again apart from a resend.
+#[derive(Debug)]
+pub struct FlowControl {
+    session: u32,
+    capacity: u32,
+    free_slots: u32,
+    sent_since_report: u32,
+    next_seq: u32,
+    in_flight: BTreeMap<u32, Vec<u8>>,
+    resend: VecDeque<u32>,
+}
+
+impl FlowControl {
+    /// `capacity` is the size of the MCU command buffer, in frames.
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            session: Uuid::new_v4().as_u128() as u32,
+            capacity,
+            free_slots: capacity,
+            sent_since_report: 0,
+            next_seq: 0,
+            in_flight: BTreeMap::new(),
+            resend: VecDeque::new(),
+        }
+    }
+
+    /// Frames that may be sent now.
+    pub fn credits(&self) -> u32 {
+        let on_wire = (self.in_flight.len() - self.resend.len()) as u32;
+        self.free_slots
+            .saturating_sub(self.sent_since_report)
+            .min(self.capacity.saturating_sub(on_wire))
+    }
+
+    /// Applies a buffer report from the MCU.
+    pub fn update_buffer(&mut self, fill_percent: u8) {
+        let free_percent = 100 - fill_percent.min(100) as u32;
+        self.free_slots = self.capacity * free_percent / 100;
+        self.sent_since_report = 0;
+    }
+
+    /// Assigns the next sequence number to `msg` and tracks it until acknowledged.
+    pub fn register(&mut self, msg: HostToMcu) -> Result<Frame> {
+        let seq = self.next_seq;
+        let bytes = to_allocvec_cobs(&HostFrame { session: self.session, seq, msg })
+            .map_err(|e| anyhow!("Postcard serialize error: {:?}", e))?;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.in_flight.insert(seq, bytes.clone());
+        Ok(Frame { seq, bytes })
+    }
+
+    /// Takes the next unacknowledged frame that must be resent after a reconnect.
+    pub fn next_resend(&mut self) -> Option<Frame> {
+        while let Some(seq) = self.resend.pop_front() {
+            if let Some(bytes) = self.in_flight.get(&seq) {
+                return Some(Frame { seq, bytes: bytes.clone() });
+            }
+        }
+        None
+    }
+
+    /// Spends one credit for a frame just written.
+    pub fn consume_credit(&mut self) {
+        self.sent_since_report += 1;
+    }
+
+    /// Marks `seq` as delivered. Returns false for unknown or repeated acknowledgements.
+    pub fn acknowledge(&mut self, seq: u32) -> bool {
+        if self.in_flight.remove(&seq).is_none() {
+            return false;
+        }
+        self.resend.retain(|&s| s != seq);
+        true
+    }
+
+    /// Queues every unacknowledged frame for resending, oldest first. Called on each connect.
+    pub fn requeue_unacked(&mut self) {
+        self.resend = self.in_flight.keys().copied().collect();
+    }
+
+    pub fn unacked(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cobs::decode_in_place;
+    use postcard::from_bytes;
+
+    fn decode(frame: &Frame) -> HostFrame {
+        let mut bytes = frame.bytes.clone();
+        let len = decode_in_place(&mut bytes).unwrap();
+        from_bytes(&bytes[..len]).unwrap()
+    }
+
+    fn gcode(line: &str) -> HostToMcu {
+        HostToMcu::GCode(line.to_string())
+    }
+
+    #[test]
+    fn credits_follow_the_advertised_buffer_space() {
+        let mut flow = FlowControl::new(10);
+        assert_eq!(flow.credits(), 10);
+
+        flow.update_buffer(70);
+        assert_eq!(flow.credits(), 3);
+        for i in 0..3 {
+            flow.register(gcode(&format!("G1 X{}", i))).unwrap();
+            flow.consume_credit();
+        }
+        assert_eq!(flow.credits(), 0);
+
+        // A fresh report resets the spent credits, but the unacknowledged frames still cap the window.
+        flow.update_buffer(0);
+        assert_eq!(flow.credits(), 7);
+        assert!(flow.acknowledge(0));
+        assert_eq!(flow.credits(), 8);
+
+        flow.update_buffer(150); // Clamped to 100 %
+        assert_eq!(flow.credits(), 0);
+    }
+
+    #[test]
+    fn frames_carry_increasing_sequence_numbers() {
+        let mut flow = FlowControl::new(4);
+        let a = flow.register(gcode("G28")).unwrap();
+        let b = flow.register(gcode("G1 Z5")).unwrap();
+        assert_eq!((a.seq, b.seq), (0, 1));
+        assert_eq!(*a.bytes.last().unwrap(), 0, "frames end with the COBS delimiter");
+        assert_eq!(decode(&b), HostFrame { session: flow.session, seq: 1, msg: gcode("G1 Z5") });
+    }
+
+    #[test]
+    fn each_flow_control_numbers_its_frames_in_a_session_of_its_own() {
+        let mut first = FlowControl::new(4);
+        let mut second = FlowControl::new(4);
+        let a = decode(&first.register(gcode("G28")).unwrap());
+        let b = decode(&second.register(gcode("G28")).unwrap());
+        assert_eq!((a.seq, b.seq), (0, 0));
+        assert_ne!(a.session, b.session);
+    }
+
+    #[test]
+    fn acknowledgements_are_matched_once() {
+        let mut flow = FlowControl::new(4);
+        flow.register(gcode("G28")).unwrap();
+        assert!(flow.acknowledge(0));
+        assert!(!flow.acknowledge(0));
+        assert!(!flow.acknowledge(42));
+        assert_eq!(flow.unacked(), 0);
+    }
+
+    #[test]
+    fn unacknowledged_frames_are_resent_in_order_with_their_original_numbers() {
+        let mut flow = FlowControl::new(8);
+        let frames: Vec<_> = ["G1 X1", "G1 X2", "G1 X3", "G1 X4"]
+            .into_iter()
+            .map(|line| flow.register(gcode(line)).unwrap())
+            .collect();
+        flow.acknowledge(1);
+
+        flow.requeue_unacked();
+        // Resends are not yet on the wire, so they do not hold the window.
+        assert_eq!(flow.credits(), 8);
+        // An acknowledgement that arrives late still cancels the resend.
+        flow.acknowledge(2);
+
+        let resent: Vec<_> = std::iter::from_fn(|| flow.next_resend()).collect();
+        assert_eq!(resent, vec![frames[0].clone(), frames[3].clone()]);
+        assert_eq!(flow.next_resend(), None);
+
+        // New frames continue the sequence instead of reusing numbers.
+        assert_eq!(flow.register(gcode("G1 X5")).unwrap().seq, 4);
+    }
+}
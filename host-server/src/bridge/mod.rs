@@ -1,24 +1,33 @@
+pub mod flow;
+
 use anyhow::{anyhow, Result};
-use async_trait::async_trait;
-use cobs::{decode_in_place, encode_in_place};
+use cobs::decode_in_place;
 use log::{error, info, warn};
-use postcard::{from_bytes, to_vec_cobs};
+use postcard::from_bytes;
 use serde::{Deserialize, Serialize};
 use std::io::ErrorKind;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::{broadcast, mpsc, RwLock};
-use tokio::time::sleep;
-use tokio_serial::{ClearBuffer, SerialPortBuilderExt, SerialStream};
+use tokio::sync::{broadcast, mpsc, Mutex, Notify, RwLock};
+use tokio::time::{sleep, timeout_at, Instant};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
 
 use crate::api::MachineState; // Assuming MachineState is defined in api module
+use crate::config::SerialConfig;
+use crate::events::{self, HostEvent};
+use crate::metrics::{DecodeStage, HostMetrics};
+use flow::{FlowControl, Frame};
 
 // --- Postcard Message Definitions ---
+/// Must stay variant-for-variant compatible with `r_klipp_api::McuToHost`.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum McuToHost {
     Telemetry(Telemetry),
-    Response(Response),
+    /// Acknowledges the `HostFrame` with sequence number `seq`.
+    Response { seq: u32, response: Response },
     Error(String),
+    Fault(FaultCode),
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -27,16 +36,43 @@ pub enum HostToMcu {
     Command(Command),
 }
 
+/// Wire envelope for every `HostToMcu` message, matching `r_klipp_api::HostFrame`. The MCU
+/// answers each frame with a `McuToHost::Response` carrying the same `seq`, and must ignore
+/// (but re-acknowledge) a `seq` it has already executed in the same `session`, since frames
+/// are resent after a reconnect. `session` and `seq` come first so the MCU can acknowledge a
+/// message it cannot decode.
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct HostFrame {
+    pub session: u32,
+    pub seq: u32,
+    pub msg: HostToMcu,
+}
+
+/// Mirrors `r_klipp_api::Telemetry`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Telemetry {
     pub nozzle_temp: f32,
     pub bed_temp: f32,
+    pub nozzle_target: f32,
+    pub bed_target: f32,
+    /// Heater PWM duty cycle from 0.0 to 1.0.
+    pub nozzle_pwm: f32,
+    pub bed_pwm: f32,
     pub x_pos: f32,
     pub y_pos: f32,
     pub z_pos: f32,
+    pub link_health: LinkHealth,
     // Add more telemetry fields as needed
 }
 
+/// Link quality as reported by the MCU, mirroring `r_klipp_api::LinkHealth`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LinkHealth {
+    pub rtt_us: u32,
+    pub buffer_fill_percent: u8,
+    pub dropped_packets: u16,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Response {
     Ok,
@@ -49,9 +85,21 @@ pub enum Command {
     SetBedTemp(f32),
     Home(Axis),
     Move { x: Option<f32>, y: Option<f32>, z: Option<f32> },
+    EmergencyStop,
     // Add more commands
 }
 
+/// Postcard encodes the variant index, so this must list the variants of
+/// `r_klipp_api::FaultCode` in the same order; new codes go at the end.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum FaultCode {
+    Unknown,
+    ThermalRunaway,
+    BufferStarvation,
+    GcodeError,
+    EmergencyStop,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Axis {
     X,
@@ -62,38 +110,78 @@ pub enum Axis {
 
 // --- Serial Bridge Implementation ---
 pub struct SerialBridge {
-    port_path: String,
-    baud_rate: u32,
+    config: SerialConfig,
     telemetry_broadcaster: broadcast::Sender<serde_json::Value>,
-    mcu_cmd_receiver: mpsc::Receiver<HostToMcu>,
+    event_bus: broadcast::Sender<HostEvent>,
+    mcu_cmd_receiver: Mutex<mpsc::Receiver<HostToMcu>>,
+    // Commands that must not wait behind queued G-code, such as an emergency stop.
+    priority_sender: mpsc::Sender<HostToMcu>,
+    priority_receiver: Mutex<mpsc::Receiver<HostToMcu>>,
     machine_state: Arc<RwLock<MachineState>>,
+    metrics: Arc<HostMetrics>,
+    flow: std::sync::Mutex<FlowControl>,
+    // Wakes the writer when acknowledgements or buffer reports free up credits.
+    credits_changed: Notify,
 }
 
+const PRIORITY_QUEUE_DEPTH: usize = 16;
+
 impl SerialBridge {
     pub fn new(
-        port_path: String,
-        baud_rate: u32,
+        config: SerialConfig,
         telemetry_broadcaster: broadcast::Sender<serde_json::Value>,
+        event_bus: broadcast::Sender<HostEvent>,
         mcu_cmd_receiver: mpsc::Receiver<HostToMcu>,
         machine_state: Arc<RwLock<MachineState>>,
+        metrics: Arc<HostMetrics>,
     ) -> Self {
+        let (priority_sender, priority_receiver) = mpsc::channel(PRIORITY_QUEUE_DEPTH);
+        let flow = FlowControl::new(config.mcu_buffer_frames);
         Self {
-            port_path,
-            baud_rate,
+            config,
             telemetry_broadcaster,
-            mcu_cmd_receiver,
+            event_bus,
+            mcu_cmd_receiver: Mutex::new(mcu_cmd_receiver),
+            priority_sender,
+            priority_receiver: Mutex::new(priority_receiver),
             machine_state,
+            metrics,
+            flow: std::sync::Mutex::new(flow),
+            credits_changed: Notify::new(),
         }
     }
 
-    pub async fn run(mut self) -> Result<()> {
+    /// Sender for commands that bypass flow control and jump the G-code queue.
+    pub fn priority_sender(&self) -> mpsc::Sender<HostToMcu> {
+        self.priority_sender.clone()
+    }
+
+    /// Frames sent but not yet acknowledged by the MCU.
+    pub fn unacked(&self) -> usize {
+        self.flow().unacked()
+    }
+
+    fn flow(&self) -> std::sync::MutexGuard<'_, FlowControl> {
+        self.flow.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub async fn run(self) -> Result<()> {
         info!("Starting SerialBridge task.");
         loop {
             match self.connect().await {
-                Ok(mut port) => {
-                    info!("Connected to serial port: {}", self.port_path);
+                Ok(port) => {
+                    info!("Connected to serial port: {}", self.config.port);
                     let (mut reader, mut writer) = tokio::io::split(port);
 
+                    let pending = {
+                        let mut flow = self.flow();
+                        flow.requeue_unacked();
+                        flow.unacked()
+                    };
+                    if pending > 0 {
+                        info!("Resending {} unacknowledged frames.", pending);
+                    }
+
                     let read_loop = self.read_loop(&mut reader);
                     let write_loop = self.write_loop(&mut writer);
 
@@ -115,18 +203,17 @@ impl SerialBridge {
     }
 
     async fn connect(&self) -> Result<SerialStream> {
-        tokio_serial::new(&self.port_path, self.baud_rate)
+        tokio_serial::new(&self.config.port, self.config.baud_rate)
             .open_native_async()
             .map_err(|e| anyhow!("Failed to open serial port: {}", e))
     }
 
     async fn read_loop(&self, reader: &mut (impl AsyncReadExt + Unpin)) -> Result<()> {
-        let mut buf = vec![0u8; 256]; // Max postcard message size
-        let mut cobs_buf = vec![0u8; 256]; // Buffer for COBS decoding
+        let mut cobs_buf = vec![0u8; 256]; // Buffer for COBS decoding, sized for the largest frame
         let mut read_pos = 0;
 
         loop {
-            let bytes_read = match reader.read(&mut buf[read_pos..]).await {
+            let bytes_read = match reader.read(&mut cobs_buf[read_pos..]).await {
                 Ok(0) => {
                     warn!("Serial port closed unexpectedly (read).");
                     return Err(anyhow!("Serial port closed"));
@@ -143,16 +230,23 @@ impl SerialBridge {
 
             // Process received bytes for COBS frames
             while let Some(frame_end) = cobs_buf[..read_pos].iter().position(|&b| b == 0x00) {
-                // Found a COBS frame (ends with 0x00)
+                // Found a COBS frame (ends with 0x00). Empty frames are just repeated delimiters.
                 let frame_data = &mut cobs_buf[..frame_end];
-                let decoded_len = decode_in_place(frame_data).map_err(|e| anyhow!("COBS decode error: {:?}", e))?;
-
-                match from_bytes::<McuToHost>(&frame_data[..decoded_len]) {
-                    Ok(mcu_msg) => {
-                        self.handle_mcu_message(mcu_msg).await?;
-                    }
-                    Err(e) => {
-                        error!("Postcard deserialize error: {:?}", e);
+                if !frame_data.is_empty() {
+                    match decode_in_place(frame_data) {
+                        Ok(decoded_len) => match from_bytes::<McuToHost>(&frame_data[..decoded_len]) {
+                            Ok(mcu_msg) => {
+                                self.handle_mcu_message(mcu_msg).await?;
+                            }
+                            Err(e) => {
+                                error!("Postcard deserialize error: {:?}", e);
+                                self.metrics.decode_error(DecodeStage::Postcard);
+                            }
+                        },
+                        Err(e) => {
+                            error!("COBS decode error: {:?}", e);
+                            self.metrics.decode_error(DecodeStage::Cobs);
+                        }
                     }
                 }
 
@@ -164,27 +258,67 @@ impl SerialBridge {
 
             if read_pos >= cobs_buf.len() {
                 warn!("COBS buffer overflow. Dropping data.");
+                self.metrics.decode_error(DecodeStage::Overflow);
                 read_pos = 0; // Reset buffer
             }
         }
     }
 
-    async fn write_loop(&mut self, writer: &mut (impl AsyncWriteExt + Unpin)) -> Result<()> {
+    /// Writes frames to the MCU. Priority commands go out immediately; G-code waits for
+    /// credits, and unacknowledged frames from a previous connection are resent first.
+    async fn write_loop(&self, writer: &mut (impl AsyncWriteExt + Unpin)) -> Result<()> {
+        let mut receiver = self.mcu_cmd_receiver.lock().await;
+        let mut priority = self.priority_receiver.lock().await;
         loop {
-            let cmd = self.mcu_cmd_receiver.recv().await.ok_or_else(|| anyhow!("MCU command channel closed"))?;
-            info!("Sending command to MCU: {:?}", cmd);
-
-            let mut buf = [0u8; 256];
-            let used = to_vec_cobs(&cmd, &mut buf).map_err(|e| anyhow!("Postcard serialize error: {:?}", e))?;
-
-            match writer.write_all(used).await {
-                Ok(_) => {
-                    // Successfully sent
+            let frame = tokio::select! {
+                biased;
+                Some(cmd) = priority.recv() => {
+                    info!("Sending priority command to MCU: {:?}", cmd);
+                    self.flow().register(cmd)?
                 }
-                Err(e) => {
-                    error!("Serial write error: {}", e);
-                    return Err(anyhow!("Serial write error: {}", e));
+                frame = self.next_frame(&mut receiver) => {
+                    let frame = frame?;
+                    self.flow().consume_credit();
+                    frame
                 }
+            };
+
+            if let Err(e) = writer.write_all(&frame.bytes).await {
+                error!("Serial write error: {}", e);
+                return Err(anyhow!("Serial write error: {}", e));
+            }
+        }
+    }
+
+    /// Waits for a credit, then takes the next frame to resend or the next queued command.
+    async fn next_frame(&self, receiver: &mut mpsc::Receiver<HostToMcu>) -> Result<Frame> {
+        self.wait_for_credit().await;
+        if let Some(frame) = self.flow().next_resend() {
+            return Ok(frame);
+        }
+        let cmd = receiver.recv().await.ok_or_else(|| anyhow!("MCU command channel closed"))?;
+        info!("Sending command to MCU: {:?}", cmd);
+        self.flow().register(cmd)
+    }
+
+    /// Waits until a frame may be sent. Warns once if that takes longer than
+    /// `credit_stall_warning_ms`, which usually means the MCU is not acknowledging frames.
+    async fn wait_for_credit(&self) {
+        let stall = Duration::from_millis(self.config.credit_stall_warning_ms);
+        let deadline = Instant::now() + stall;
+        let mut warned = false;
+        while self.flow().credits() == 0 {
+            if warned {
+                self.credits_changed.notified().await;
+            } else if timeout_at(deadline, self.credits_changed.notified()).await.is_err() {
+                warned = true;
+                let message = format!(
+                    "No MCU buffer credits for {:?} with {} frames unacknowledged; is the MCU acknowledging frames?",
+                    stall,
+                    self.unacked()
+                );
+                warn!("{}", message);
+                events::publish(&self.event_bus, HostEvent::mcu_error(message));
             }
         }
     }
@@ -192,6 +326,9 @@ impl SerialBridge {
     async fn handle_mcu_message(&self, msg: McuToHost) -> Result<()> {
         match msg {
             McuToHost::Telemetry(telemetry) => {
+                self.metrics.observe_telemetry(&telemetry);
+                self.flow().update_buffer(telemetry.link_health.buffer_fill_percent);
+                self.credits_changed.notify_one();
                 let mut state = self.machine_state.write().await;
                 state.nozzle_temp = telemetry.nozzle_temp;
                 state.bed_temp = telemetry.bed_temp;
@@ -204,13 +341,24 @@ impl SerialBridge {
                     error!("Failed to send telemetry to broadcaster: {}", e);
                 }
             }
-            McuToHost::Response(response) => {
-                info!("Received MCU response: {:?}", response);
-                // Handle specific responses, e.g., command acknowledgments
+            McuToHost::Response { seq, response } => {
+                if self.flow().acknowledge(seq) {
+                    self.credits_changed.notify_one();
+                } else {
+                    warn!("Ignoring acknowledgement for unknown frame {}", seq);
+                }
+                if let Response::Value(value) = response {
+                    info!("MCU response to frame {}: {}", seq, value);
+                    events::publish(&self.event_bus, HostEvent::gcode_response(value));
+                }
             }
             McuToHost::Error(e) => {
                 error!("Received MCU error: {}", e);
-                // Log or propagate error
+                events::publish(&self.event_bus, HostEvent::mcu_error(e));
+            }
+            McuToHost::Fault(code) => {
+                error!("MCU reported fault: {:?}", code);
+                events::publish(&self.event_bus, HostEvent::fault(code, "Reported by MCU"));
             }
         }
         Ok(())
@@ -221,6 +369,7 @@ impl SerialBridge {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use postcard::to_slice_cobs;
     use tokio::io::DuplexStream;
     use tokio::time::timeout;
 
@@ -229,23 +378,37 @@ mod tests {
         tokio::io::duplex(256) // Max buffer size
     }
 
+    fn mock_serial_config() -> SerialConfig {
+        SerialConfig {
+            port: "/dev/ttyUSB_mock".to_string(),
+            baud_rate: 115200,
+            mcu_buffer_frames: 4,
+            credit_stall_warning_ms: 200,
+        }
+    }
+
     #[tokio::test]
     async fn test_mcu_to_host_telemetry_serialization() -> Result<()> {
         let telemetry = Telemetry {
             nozzle_temp: 200.0,
             bed_temp: 60.0,
+            nozzle_target: 200.0,
+            bed_target: 60.0,
+            nozzle_pwm: 0.35,
+            bed_pwm: 0.6,
             x_pos: 10.0,
             y_pos: 20.0,
             z_pos: 5.0,
+            link_health: LinkHealth::default(),
         };
         let mcu_msg = McuToHost::Telemetry(telemetry);
 
         let mut buf = [0u8; 256];
-        let encoded = to_vec_cobs(&mcu_msg, &mut buf)?;
+        let encoded = to_slice_cobs(&mcu_msg, &mut buf)?;
 
         let mut decoded_buf = [0u8; 256];
         decoded_buf[..encoded.len()].copy_from_slice(encoded);
-        let decoded_len = decode_in_place(&mut decoded_buf[..encoded.len()])?;
+        let decoded_len = decode_in_place(&mut decoded_buf[..encoded.len()]).map_err(|_| anyhow!("COBS decode error"))?;
 
         let decoded_msg: McuToHost = from_bytes(&decoded_buf[..decoded_len])?;
 
@@ -259,11 +422,11 @@ mod tests {
         let host_msg = HostToMcu::GCode(gcode);
 
         let mut buf = [0u8; 256];
-        let encoded = to_vec_cobs(&host_msg, &mut buf)?;
+        let encoded = to_slice_cobs(&host_msg, &mut buf)?;
 
         let mut decoded_buf = [0u8; 256];
         decoded_buf[..encoded.len()].copy_from_slice(encoded);
-        let decoded_len = decode_in_place(&mut decoded_buf[..encoded.len()])?;
+        let decoded_len = decode_in_place(&mut decoded_buf[..encoded.len()]).map_err(|_| anyhow!("COBS decode error"))?;
 
         let decoded_msg: HostToMcu = from_bytes(&decoded_buf[..decoded_len])?;
 
@@ -274,16 +437,18 @@ mod tests {
     #[tokio::test]
     async fn test_serial_bridge_read_telemetry() -> Result<()> {
         let (mut host_side_writer, mut mcu_side_reader) = create_mock_serial();
-        let (tx, _rx) = broadcast::channel(10);
+        let (tx, mut rx) = broadcast::channel(10);
+        let (event_tx, _event_rx) = broadcast::channel(10);
         let (_mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(10);
         let machine_state = Arc::new(RwLock::new(MachineState::default()));
 
         let bridge = SerialBridge::new(
-            "/dev/ttyUSB_mock".to_string(),
-            115200,
+            mock_serial_config(),
             tx.clone(),
+            event_tx,
             mcu_cmd_rx,
             machine_state.clone(),
+            Arc::new(HostMetrics::new()?),
         );
 
         let read_task = tokio::spawn(async move {
@@ -294,13 +459,18 @@ mod tests {
         let telemetry = Telemetry {
             nozzle_temp: 200.0,
             bed_temp: 60.0,
+            nozzle_target: 200.0,
+            bed_target: 60.0,
+            nozzle_pwm: 0.35,
+            bed_pwm: 0.6,
             x_pos: 10.0,
             y_pos: 20.0,
             z_pos: 5.0,
+            link_health: LinkHealth::default(),
         };
         let mcu_msg = McuToHost::Telemetry(telemetry.clone());
         let mut encoded_buf = [0u8; 256];
-        let encoded_msg = to_vec_cobs(&mcu_msg, &mut encoded_buf)?;
+        let encoded_msg = to_slice_cobs(&mcu_msg, &mut encoded_buf)?;
 
         host_side_writer.write_all(encoded_msg).await?;
         host_side_writer.write_all(&[0x00]).await?; // COBS frame delimiter
@@ -313,7 +483,7 @@ mod tests {
         assert_eq!(state.bed_temp, telemetry.bed_temp);
 
         // Ensure telemetry was broadcasted
-        let received_telemetry = timeout(Duration::from_millis(100), tx.subscribe().recv()).await??;
+        let received_telemetry = timeout(Duration::from_millis(100), rx.recv()).await??;
         assert_eq!(received_telemetry["nozzle_temp"], telemetry.nozzle_temp);
 
         // Stop the read task
@@ -325,15 +495,17 @@ mod tests {
     async fn test_serial_bridge_write_gcode() -> Result<()> {
         let (mut host_side_reader, mut mcu_side_writer) = create_mock_serial();
         let (tx, _rx) = broadcast::channel(10);
+        let (event_tx, _event_rx) = broadcast::channel(10);
         let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(10);
         let machine_state = Arc::new(RwLock::new(MachineState::default()));
 
-        let mut bridge = SerialBridge::new(
-            "/dev/ttyUSB_mock".to_string(),
-            115200,
+        let bridge = SerialBridge::new(
+            mock_serial_config(),
             tx.clone(),
+            event_tx,
             mcu_cmd_rx,
             machine_state.clone(),
+            Arc::new(HostMetrics::new()?),
         );
 
         let write_task = tokio::spawn(async move {
@@ -357,10 +529,223 @@ mod tests {
 
         // COBS decode and deserialize
         let mut decoded_buf = received_bytes.clone();
-        let decoded_len = decode_in_place(&mut decoded_buf)?;
-        let decoded_msg: HostToMcu = from_bytes(&decoded_buf[..decoded_len])?;
+        let decoded_len = decode_in_place(&mut decoded_buf).map_err(|_| anyhow!("COBS decode error"))?;
+        let decoded_msg: HostFrame = from_bytes(&decoded_buf[..decoded_len])?;
+
+        assert_eq!((decoded_msg.seq, decoded_msg.msg), (0, HostToMcu::GCode(gcode_cmd)));
+        Ok(())
+    }
+
+    /// Reads COBS frames from the MCU side of a mock port until `count` have arrived.
+    async fn read_frames(reader: &mut DuplexStream, count: usize) -> Vec<HostFrame> {
+        let mut frames = Vec::new();
+        let mut pending = Vec::new();
+        while frames.len() < count {
+            let mut chunk = [0u8; 64];
+            let n = timeout(Duration::from_millis(500), reader.read(&mut chunk))
+                .await
+                .expect("timed out waiting for frames")
+                .unwrap();
+            pending.extend_from_slice(&chunk[..n]);
+            while let Some(end) = pending.iter().position(|&b| b == 0) {
+                let mut frame: Vec<u8> = pending.drain(..=end).collect();
+                let len = decode_in_place(&mut frame[..end]).unwrap();
+                frames.push(from_bytes(&frame[..len]).unwrap());
+            }
+        }
+        frames
+    }
+
+    async fn assert_no_frames(reader: &mut DuplexStream) {
+        let mut chunk = [0u8; 64];
+        assert!(
+            timeout(Duration::from_millis(100), reader.read(&mut chunk)).await.is_err(),
+            "bridge sent a frame without credit"
+        );
+    }
+
+    fn link_report(buffer_fill_percent: u8) -> McuToHost {
+        McuToHost::Telemetry(Telemetry {
+            nozzle_temp: 0.0,
+            bed_temp: 0.0,
+            nozzle_target: 0.0,
+            bed_target: 0.0,
+            nozzle_pwm: 0.0,
+            bed_pwm: 0.0,
+            x_pos: 0.0,
+            y_pos: 0.0,
+            z_pos: 0.0,
+            link_health: LinkHealth { rtt_us: 800, buffer_fill_percent, dropped_packets: 0 },
+        })
+    }
+
+    fn ok(seq: u32) -> McuToHost {
+        McuToHost::Response { seq, response: Response::Ok }
+    }
+
+    #[tokio::test]
+    async fn test_flow_control_resends_unacked_frames_after_reconnect() -> Result<()> {
+        let (tx, _rx) = broadcast::channel(10);
+        let (event_tx, _event_rx) = broadcast::channel(10);
+        let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(10);
+        let machine_state = Arc::new(RwLock::new(MachineState::default()));
+        let bridge = Arc::new(SerialBridge::new(
+            mock_serial_config(),
+            tx,
+            event_tx,
+            mcu_cmd_rx,
+            machine_state,
+            Arc::new(HostMetrics::new()?),
+        ));
+        for i in 0..6 {
+            mcu_cmd_tx.send(HostToMcu::GCode(format!("G1 X{}", i))).await?;
+        }
 
-        assert_eq!(decoded_msg, HostToMcu::GCode(gcode_cmd));
+        // The buffer holds 4 frames and reports itself half full: only 2 may be sent.
+        bridge.handle_mcu_message(link_report(50)).await?;
+        let (mut mcu_side, mut host_side) = create_mock_serial();
+        let writer = bridge.clone();
+        let write_task = tokio::spawn(async move { writer.write_loop(&mut host_side).await });
+        let frames = read_frames(&mut mcu_side, 2).await;
+        assert_eq!(frames.iter().map(|f| f.seq).collect::<Vec<_>>(), vec![0, 1]);
+        let session = frames[0].session;
+        assert_no_frames(&mut mcu_side).await;
+
+        // Frame 0 is acknowledged and the buffer drains: two more fit before the window of 4 closes.
+        bridge.handle_mcu_message(ok(0)).await?;
+        bridge.handle_mcu_message(link_report(0)).await?;
+        let frames = read_frames(&mut mcu_side, 3).await;
+        assert_eq!(frames.iter().map(|f| f.seq).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_no_frames(&mut mcu_side).await;
+
+        // The link drops before frames 1-4 are acknowledged; the ack for 3 still arrives.
+        write_task.abort();
+        drop(mcu_side);
+        bridge.handle_mcu_message(ok(3)).await?;
+
+        // On reconnect the unacknowledged frames go out again, unchanged, before new ones.
+        bridge.flow().requeue_unacked();
+        bridge.handle_mcu_message(link_report(0)).await?;
+        let (mut mcu_side, mut host_side) = create_mock_serial();
+        let writer = bridge.clone();
+        let write_task = tokio::spawn(async move { writer.write_loop(&mut host_side).await });
+        let frames = read_frames(&mut mcu_side, 4).await;
+        let seqs: Vec<_> = frames.iter().map(|f| f.seq).collect();
+        assert_eq!(seqs, vec![1, 2, 4, 5]);
+        assert!(frames.iter().all(|f| f.session == session), "resends stay in the session");
+        assert_eq!(frames[0].msg, HostToMcu::GCode("G1 X1".to_string()));
+        assert_eq!(frames[3].msg, HostToMcu::GCode("G1 X5".to_string()));
+
+        write_task.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_priority_commands_bypass_credits() -> Result<()> {
+        let (tx, _rx) = broadcast::channel(10);
+        let (event_tx, _event_rx) = broadcast::channel(10);
+        let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(10);
+        let machine_state = Arc::new(RwLock::new(MachineState::default()));
+        let bridge = Arc::new(SerialBridge::new(
+            mock_serial_config(),
+            tx,
+            event_tx,
+            mcu_cmd_rx,
+            machine_state,
+            Arc::new(HostMetrics::new()?),
+        ));
+        bridge.handle_mcu_message(link_report(100)).await?;
+        mcu_cmd_tx.send(HostToMcu::GCode("G1 X1".to_string())).await?;
+        bridge.priority_sender().send(HostToMcu::Command(Command::EmergencyStop)).await?;
+
+        let (mut mcu_side, mut host_side) = create_mock_serial();
+        let writer = bridge.clone();
+        let write_task = tokio::spawn(async move { writer.write_loop(&mut host_side).await });
+        let frames = read_frames(&mut mcu_side, 1).await;
+        assert_eq!(frames[0].msg, HostToMcu::Command(Command::EmergencyStop));
+        assert_no_frames(&mut mcu_side).await;
+
+        write_task.abort();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fault_codes_match_mcu_encoding() -> Result<()> {
+        use r_klipp_api::FaultCode as McuFaultCode;
+        let pairs = [
+            (FaultCode::Unknown, McuFaultCode::Unknown),
+            (FaultCode::ThermalRunaway, McuFaultCode::ThermalRunaway),
+            (FaultCode::BufferStarvation, McuFaultCode::BufferStarvation),
+            (FaultCode::GcodeError, McuFaultCode::GcodeError),
+            (FaultCode::EmergencyStop, McuFaultCode::EmergencyStop),
+        ];
+        for (host, mcu) in pairs {
+            let bytes = postcard::to_allocvec(&host)?;
+            assert_eq!(postcard::from_bytes::<McuFaultCode>(&bytes)?, mcu);
+            let bytes = postcard::to_allocvec(&mcu)?;
+            assert_eq!(postcard::from_bytes::<FaultCode>(&bytes)?, host);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mcu_messages_match_mcu_encoding() -> Result<()> {
+        use r_klipp_api as api;
+        let api::McuToHost::Telemetry(telemetry) = postcard::from_bytes(&postcard::to_allocvec(&link_report(40))?)? else {
+            panic!("telemetry decoded as another message");
+        };
+        assert_eq!(telemetry.link_health.buffer_fill_percent, 40);
+        let pairs = [
+            (
+                McuToHost::Response { seq: 300, response: Response::Ok },
+                api::McuToHost::Response { seq: 300, response: api::Response::Ok },
+            ),
+            (
+                McuToHost::Response { seq: 7, response: Response::Value("X:1.00 Y:2.00".to_string()) },
+                api::McuToHost::Response { seq: 7, response: api::Response::Value("X:1.00 Y:2.00".try_into().unwrap()) },
+            ),
+            (McuToHost::Error("Unsupported message".to_string()), api::McuToHost::Error("Unsupported message".try_into().unwrap())),
+            (McuToHost::Fault(FaultCode::GcodeError), api::McuToHost::Fault(api::FaultCode::GcodeError)),
+        ];
+        for (host, mcu) in pairs {
+            assert_eq!(postcard::from_bytes::<McuToHost>(&postcard::to_allocvec(&mcu)?)?, host);
+            assert_eq!(postcard::from_bytes::<api::McuToHost>(&postcard::to_allocvec(&host)?)?, mcu);
+        }
+
+        let frame = HostFrame { session: 0xdead_beef, seq: 70_000, msg: HostToMcu::GCode("G28".to_string()) };
+        assert_eq!(api::frame_header(&postcard::to_allocvec(&frame)?), Some((0xdead_beef, 70_000)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credit_stall_raises_a_warning() -> Result<()> {
+        let (tx, _rx) = broadcast::channel(10);
+        let (event_tx, mut event_rx) = broadcast::channel(10);
+        let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(10);
+        let machine_state = Arc::new(RwLock::new(MachineState::default()));
+        let bridge = Arc::new(SerialBridge::new(
+            mock_serial_config(),
+            tx,
+            event_tx,
+            mcu_cmd_rx,
+            machine_state,
+            Arc::new(HostMetrics::new()?),
+        ));
+        bridge.handle_mcu_message(link_report(100)).await?;
+        mcu_cmd_tx.send(HostToMcu::GCode("G1 X1".to_string())).await?;
+
+        let (_mcu_side, mut host_side) = create_mock_serial();
+        let writer = bridge.clone();
+        let write_task = tokio::spawn(async move { writer.write_loop(&mut host_side).await });
+        // Buffer reports that keep the window closed do not reset the deadline.
+        bridge.handle_mcu_message(link_report(100)).await?;
+        let event = timeout(Duration::from_secs(2), event_rx.recv()).await??;
+        match event {
+            HostEvent::McuError { message, .. } => assert!(message.contains("No MCU buffer credits"), "{}", message),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        write_task.abort();
         Ok(())
     }
 }
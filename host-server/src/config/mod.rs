@@ -0,0 +1,191 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Default location of the host-server machine config.
+pub const DEFAULT_CONFIG_PATH: &str = "./data/host-server.toml";
+
+/// Id of the printer built from `[serial]` when no `[[printers]]` are configured.
+pub const DEFAULT_PRINTER_ID: &str = "default";
+
+/// Machine config for host-server, read once at start-up.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostConfig {
+    pub server: ServerConfig,
+    pub serial: SerialConfig,
+    pub auth: AuthConfig,
+    /// Webhook targets, one `[[notifications]]` table each.
+    pub notifications: Vec<crate::notifications::WebhookConfig>,
+    /// MQTT bridge; disabled unless an `[mqtt]` table is present.
+    pub mqtt: Option<crate::mqtt::MqttConfig>,
+    /// Fleet mode: one `[[printers]]` table per printer. When empty, a single printer is
+    /// built from `[serial]` and `server.config_dir`.
+    pub printers: Vec<PrinterConfig>,
+    /// Power devices, one `[[power]]` table each.
+    pub power: Vec<crate::power::PowerDeviceConfig>,
+}
+
+/// One printer of the fleet. The first one also answers the un-namespaced routes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrinterConfig {
+    /// Used in `/printers/{id}/...` routes; letters, digits, `-` and `_`.
+    pub id: String,
+    /// Display name; defaults to the id.
+    pub name: String,
+    pub serial: SerialConfig,
+    /// Holds this printer's `printer.cfg`; defaults to `<server.config_dir>/<id>`.
+    pub config_dir: Option<PathBuf>,
+    /// Capabilities queued jobs can require, e.g. `pla`, `nozzle_0.4`, `bed_300`.
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub data_dir: PathBuf,
+    /// Directory holding `printer.cfg` and the files it includes; the `config` file root.
+    pub config_dir: PathBuf,
+    /// Uploaded print files; the `gcodes` file root.
+    pub gcodes_dir: PathBuf,
+    /// Built Mainsail or Fluidd bundle to serve on `/`. No web UI is served when unset.
+    pub web_root: Option<PathBuf>,
+    /// Origins allowed to make cross-origin requests. `"*"` allows any origin.
+    pub cors_domains: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: "0.0.0.0:7125".to_string(),
+            data_dir: PathBuf::from("./data"),
+            config_dir: PathBuf::from("./data/config"),
+            gcodes_dir: PathBuf::from("./uploads"),
+            web_root: None,
+            cors_domains: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SerialConfig {
+    pub port: String,
+    pub baud_rate: u32,
+    /// Frames the MCU command buffer holds; the upper bound for unacknowledged frames.
+    pub mcu_buffer_frames: u32,
+    /// How long queued frames may wait for a buffer credit before the host warns that the
+    /// MCU has stopped acknowledging frames or reporting its buffer.
+    pub credit_stall_warning_ms: u64,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            port: "/dev/ttyUSB0".to_string(),
+            baud_rate: 115200,
+            mcu_buffer_frames: 32,
+            credit_stall_warning_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// HMAC secret used to sign JWT sessions. A random secret is generated when unset,
+    /// which logs everyone out on restart.
+    pub jwt_secret: Option<String>,
+    pub token_lifetime_secs: u64,
+    /// CIDR blocks (e.g. `192.168.1.0/24`) that may use the API without logging in.
+    pub trusted_clients: Vec<String>,
+    /// Role granted to trusted clients.
+    pub trusted_role: crate::auth::Role,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: None,
+            token_lifetime_secs: 12 * 60 * 60,
+            trusted_clients: Vec::new(),
+            trusted_role: crate::auth::Role::Operator,
+        }
+    }
+}
+
+impl HostConfig {
+    /// Loads the config from `path`, falling back to defaults when the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            info!("No config at {}, using defaults.", path.display());
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let config: Self = toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+        config.check_printers()?;
+        config.check_power_devices()?;
+        Ok(config)
+    }
+
+    /// The configured printers, or the single default printer outside fleet mode.
+    pub fn printers(&self) -> Vec<PrinterConfig> {
+        if self.printers.is_empty() {
+            return vec![PrinterConfig {
+                id: DEFAULT_PRINTER_ID.to_string(),
+                name: DEFAULT_PRINTER_ID.to_string(),
+                serial: self.serial.clone(),
+                config_dir: Some(self.server.config_dir.clone()),
+                tags: Vec::new(),
+            }];
+        }
+        self.printers
+            .iter()
+            .map(|printer| PrinterConfig {
+                name: if printer.name.is_empty() { printer.id.clone() } else { printer.name.clone() },
+                config_dir: Some(
+                    printer
+                        .config_dir
+                        .clone()
+                        .unwrap_or_else(|| self.server.config_dir.join(&printer.id)),
+                ),
+                ..printer.clone()
+            })
+            .collect()
+    }
+
+    fn check_printers(&self) -> Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for printer in &self.printers {
+            let valid = !printer.id.is_empty()
+                && printer.id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+            if !valid {
+                bail!("Invalid printer id {:?}: use letters, digits, '-' and '_'", printer.id);
+            }
+            if !seen.insert(printer.id.as_str()) {
+                bail!("Duplicate printer id {:?}", printer.id);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_power_devices(&self) -> Result<()> {
+        let printers = self.printers();
+        let mut seen = std::collections::HashSet::new();
+        for device in &self.power {
+            if device.name.is_empty() || !seen.insert(device.name.as_str()) {
+                bail!("Power devices need a unique name, got {:?}", device.name);
+            }
+            if let Some(printer) = &device.printer {
+                if !printers.iter().any(|p| &p.id == printer) {
+                    bail!("Power device {:?} belongs to unknown printer {:?}", device.name, printer);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+use chrono::{DateTime, Utc};
+use compat_layer::{migrate_config, MigrationError, MigrationReport, PrinterConfig};
+use log::info;
+use serde::Serialize;
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use crate::config::DEFAULT_PRINTER_ID;
+use crate::db::models::{ConfigVersion, MachineConfig};
+use crate::db::{Database, HostError};
+
+/// The root Klipper config. Other files are validated as if included from it.
+pub const MAIN_CONFIG: &str = "printer.cfg";
+
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Author of the version recorded for a file that existed before its first save.
+const ORIGINAL_AUTHOR: &str = "system";
+
+#[derive(Debug, Serialize)]
+pub struct ConfigFileInfo {
+    pub filename: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+/// Outcome of running a config through the compat-layer migrator.
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    /// False when the migrator rejected the config outright.
+    pub valid: bool,
+    pub error: Option<String>,
+    pub warnings: Vec<String>,
+    pub unsupported_sections: Vec<String>,
+}
+
+impl ValidationReport {
+    fn from_migration(result: Result<(PrinterConfig, MigrationReport), MigrationError>) -> (Self, Option<PrinterConfig>) {
+        match result {
+            Ok((config, report)) => (
+                Self {
+                    valid: true,
+                    error: None,
+                    warnings: report.warnings,
+                    unsupported_sections: report.unsupported_sections,
+                },
+                Some(config),
+            ),
+            Err(e) => (
+                Self {
+                    valid: false,
+                    error: Some(e.to_string()),
+                    ..Self::default()
+                },
+                None,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SaveResult {
+    /// False when validation failed, or when it produced warnings that were not accepted.
+    pub committed: bool,
+    pub version: Option<u32>,
+    pub report: ValidationReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    pub author: String,
+    pub note: Option<String>,
+    pub size: usize,
+}
+
+impl From<&ConfigVersion> for VersionInfo {
+    fn from(v: &ConfigVersion) -> Self {
+        Self {
+            version: v.version,
+            created: v.created,
+            author: v.author.clone(),
+            note: v.note.clone(),
+            size: v.content.len(),
+        }
+    }
+}
+
+/// Rejects anything but a plain file name, so requests cannot reach outside the config directory.
+pub fn check_filename(filename: &str) -> Result<(), HostError> {
+    let valid = !filename.is_empty()
+        && filename.len() <= MAX_FILENAME_LEN
+        && !filename.starts_with('.')
+        && filename
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(HostError::InvalidRequest(format!("Invalid config filename: {}", filename)))
+    }
+}
+
+/// Validates `content` as the new `filename`. Included files cannot be migrated on their own,
+/// so they are checked appended to the current main config, as Klipper would see them.
+pub fn validate(main_config: Option<&str>, filename: &str, content: &str) -> (ValidationReport, Option<PrinterConfig>) {
+    if filename == MAIN_CONFIG {
+        return ValidationReport::from_migration(migrate_config(content));
+    }
+    let combined = format!("{}\n{}", main_config.unwrap_or_default(), content);
+    ValidationReport::from_migration(migrate_config(&combined))
+}
+
+/// Unified diff of two versions of `filename`.
+pub fn unified_diff(filename: &str, from_label: &str, old: &str, to_label: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("{} ({})", filename, from_label), &format!("{} ({})", filename, to_label))
+        .to_string()
+}
+
+/// Reads, validates and writes the printer config files, keeping every saved revision.
+pub struct ConfigManager {
+    printer_id: String,
+    dir: PathBuf,
+    db: Arc<Database>,
+    // Serializes saves so two writers never claim the same version number.
+    save_lock: Mutex<()>,
+}
+
+impl ConfigManager {
+    pub fn new(printer_id: &str, dir: PathBuf, db: Arc<Database>) -> Self {
+        Self {
+            printer_id: printer_id.to_string(),
+            dir,
+            db,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    fn path(&self, filename: &str) -> Result<PathBuf, HostError> {
+        check_filename(filename)?;
+        Ok(self.dir.join(filename))
+    }
+
+    /// Name the versions of `filename` are stored under. Fleet printers share the database,
+    /// so their files are prefixed with the printer id; `/` never occurs in a filename.
+    fn record_name(&self, filename: &str) -> String {
+        if self.printer_id == DEFAULT_PRINTER_ID {
+            filename.to_string()
+        } else {
+            format!("{}/{}", self.printer_id, filename)
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<ConfigFileInfo>, HostError> {
+        fs::create_dir_all(&self.dir).await.map_err(io_error)?;
+        let mut entries = fs::read_dir(&self.dir).await.map_err(io_error)?;
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let metadata = entry.metadata().await.map_err(io_error)?;
+            let Some(filename) = entry.file_name().to_str().map(str::to_string) else { continue };
+            if !metadata.is_file() || check_filename(&filename).is_err() {
+                continue;
+            }
+            files.push(ConfigFileInfo {
+                filename,
+                size: metadata.len(),
+                modified: metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now()),
+            });
+        }
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(files)
+    }
+
+    pub async fn read(&self, filename: &str) -> Result<String, HostError> {
+        read_optional(&self.path(filename)?)
+            .await?
+            .ok_or_else(|| HostError::NotFound(format!("No config file {}", filename)))
+    }
+
+    pub async fn validate(&self, filename: &str, content: &str) -> Result<ValidationReport, HostError> {
+        let main = read_optional(&self.path(MAIN_CONFIG)?).await?;
+        Ok(validate(main.as_deref(), filename, content).0)
+    }
+
+    /// Validates and, unless it fails or has unaccepted warnings, writes `content` and
+    /// records it as the next version.
+    pub async fn save(
+        &self,
+        filename: &str,
+        content: &str,
+        author: &str,
+        accept_warnings: bool,
+    ) -> Result<SaveResult, HostError> {
+        let path = self.path(filename)?;
+        let _guard = self.save_lock.lock().await;
+
+        let main = read_optional(&self.path(MAIN_CONFIG)?).await?;
+        let (report, config) = validate(main.as_deref(), filename, content);
+        if !report.valid || (!report.warnings.is_empty() && !accept_warnings) {
+            return Ok(SaveResult { committed: false, version: None, report });
+        }
+
+        let version = self.commit(&path, filename, content, author, None).await?;
+        if let (MAIN_CONFIG, Some(config)) = (filename, config) {
+            self.store_machine_config(&config).await?;
+        }
+        Ok(SaveResult { committed: true, version: Some(version), report })
+    }
+
+    /// Restores `version` of `filename` and records it as a new version, so a rollback can
+    /// itself be undone. The old content is validated again, since the original version
+    /// never was and the main config may have changed since; its warnings are accepted.
+    pub async fn rollback(&self, filename: &str, version: u32, author: &str) -> Result<u32, HostError> {
+        let path = self.path(filename)?;
+        let _guard = self.save_lock.lock().await;
+
+        let old = self
+            .db
+            .get_config_version(&self.record_name(filename), version)
+            .await?
+            .ok_or_else(|| HostError::NotFound(format!("No version {} of {}", version, filename)))?;
+        let main = read_optional(&self.path(MAIN_CONFIG)?).await?;
+        let (report, config) = validate(main.as_deref(), filename, &old.content);
+        if !report.valid {
+            return Err(HostError::InvalidRequest(format!(
+                "Version {} of {} is not a valid config: {}",
+                version,
+                filename,
+                report.error.unwrap_or_default()
+            )));
+        }
+
+        let note = Some(format!("rollback to v{}", version));
+        let version = self.commit(&path, filename, &old.content, author, note).await?;
+        if let (MAIN_CONFIG, Some(config)) = (filename, config) {
+            self.store_machine_config(&config).await?;
+        }
+        Ok(version)
+    }
+
+    pub async fn versions(&self, filename: &str) -> Result<Vec<VersionInfo>, HostError> {
+        check_filename(filename)?;
+        let versions = self.db.get_config_versions(&self.record_name(filename)).await?;
+        Ok(versions.iter().map(VersionInfo::from).collect())
+    }
+
+    /// Diff from version `from` to version `to`, or to the file on disk when `to` is `None`.
+    pub async fn diff(&self, filename: &str, from: u32, to: Option<u32>) -> Result<String, HostError> {
+        let old = self.version_content(filename, from).await?;
+        let (to_label, new) = match to {
+            Some(to) => (format!("v{}", to), self.version_content(filename, to).await?),
+            None => ("current".to_string(), self.read(filename).await?),
+        };
+        Ok(unified_diff(filename, &format!("v{}", from), &old, &to_label, &new))
+    }
+
+    async fn version_content(&self, filename: &str, version: u32) -> Result<String, HostError> {
+        check_filename(filename)?;
+        self.db
+            .get_config_version(&self.record_name(filename), version)
+            .await?
+            .map(|v| v.content)
+            .ok_or_else(|| HostError::NotFound(format!("No version {} of {}", version, filename)))
+    }
+
+    async fn commit(
+        &self,
+        path: &Path,
+        filename: &str,
+        content: &str,
+        author: &str,
+        note: Option<String>,
+    ) -> Result<u32, HostError> {
+        let record = self.record_name(filename);
+        let mut latest = self.db.get_config_versions(&record).await?.first().map_or(0, |v| v.version);
+
+        // Keep the hand-edited file that predates the manager, so the first save can be undone.
+        if latest == 0 {
+            if let Some(original) = read_optional(path).await? {
+                latest = 1;
+                self.db
+                    .save_config_version(ConfigVersion {
+                        filename: record.clone(),
+                        version: latest,
+                        content: original,
+                        created: Utc::now(),
+                        author: ORIGINAL_AUTHOR.to_string(),
+                        note: Some("original".to_string()),
+                    })
+                    .await?;
+            }
+        }
+
+        fs::create_dir_all(&self.dir).await.map_err(io_error)?;
+        let tmp = self.dir.join(format!(".{}.tmp", filename)); // Hidden, so never listed
+        fs::write(&tmp, content).await.map_err(io_error)?;
+        fs::rename(&tmp, path).await.map_err(io_error)?;
+
+        let version = latest + 1;
+        self.db
+            .save_config_version(ConfigVersion {
+                filename: record,
+                version,
+                content: content.to_string(),
+                created: Utc::now(),
+                author: author.to_string(),
+                note,
+            })
+            .await?;
+        info!("Saved {} as version {} ({}).", filename, version, author);
+        Ok(version)
+    }
+
+    /// Keeps the typed view of `printer.cfg` in `machine_config` for other subsystems,
+    /// as `printer` (or `<id>/printer` for fleet printers).
+    async fn store_machine_config(&self, config: &PrinterConfig) -> Result<(), HostError> {
+        let config: HashMap<String, serde_json::Value> = match serde_json::to_value(config)? {
+            serde_json::Value::Object(map) => map.into_iter().collect(),
+            _ => HashMap::new(),
+        };
+        self.db
+            .save_machine_config(MachineConfig {
+                name: self.record_name("printer"),
+                config,
+            })
+            .await
+    }
+}
+
+async fn read_optional(path: &Path) -> Result<Option<String>, HostError> {
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io_error(e)),
+    }
+}
+
+fn io_error(e: std::io::Error) -> HostError {
+    HostError::Other(e.to_string())
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENDER3: &str = include_str!("../../../crates/compat-layer/tests/ender3.cfg");
+
+    #[test]
+    fn filenames_must_stay_inside_the_config_dir() {
+        assert!(check_filename("printer.cfg").is_ok());
+        assert!(check_filename("macros-v2_final.cfg").is_ok());
+        for bad in ["", "../printer.cfg", "sub/printer.cfg", ".hidden", "..", "a\\b.cfg", "print er.cfg"] {
+            assert!(check_filename(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn main_config_is_migrated_and_broken_configs_are_rejected() {
+        let (report, config) = validate(None, MAIN_CONFIG, ENDER3);
+        assert!(report.valid, "{:?}", report.error);
+        assert!(config.is_some());
+
+        let (report, config) = validate(None, MAIN_CONFIG, "[stepper_x]\nstep_pin: PA1\n");
+        assert!(!report.valid);
+        assert!(config.is_none());
+        assert!(report.error.unwrap().contains("printer"));
+    }
+
+    #[test]
+    fn included_files_are_checked_against_the_main_config() {
+        let include = "[stepper_a]\nstep_pin: PB1\ndir_pin: PB2\nenable_pin: PB3\nmicrosteps: 16\nrotation_distance: 8\nposition_endstop: 0\nposition_max: 100\n";
+        let (report, _) = validate(Some(ENDER3), "extra_axis.cfg", include);
+        assert!(report.valid, "{:?}", report.error);
+        assert!(report.warnings.iter().any(|w| w.contains("stepper_a")), "{:?}", report.warnings);
+
+        // Without a main config there is no [printer] section to merge into.
+        let (report, _) = validate(None, "extra_axis.cfg", include);
+        assert!(!report.valid);
+    }
+
+    #[test]
+    fn diff_shows_changed_lines_with_context() {
+        let old = "[printer]\nkinematics: cartesian\nmax_velocity: 300\nmax_accel: 3000\n";
+        let new = "[printer]\nkinematics: cartesian\nmax_velocity: 250\nmax_accel: 3000\n";
+        let diff = unified_diff("printer.cfg", "v1", old, "v2", new);
+        assert!(diff.starts_with("--- printer.cfg (v1)\n+++ printer.cfg (v2)\n"));
+        assert!(diff.contains("-max_velocity: 300\n"));
+        assert!(diff.contains("+max_velocity: 250\n"));
+        assert!(diff.contains(" max_accel: 3000\n"));
+        assert_eq!(unified_diff("printer.cfg", "v1", old, "v1", old), "");
+    }
+
+    #[tokio::test]
+    async fn rollback_revalidates_and_updates_the_machine_config() {
+        let dir = std::env::temp_dir().join(format!("r_klipp_config_{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(dir.join("config")).await.unwrap();
+        let db = Arc::new(Database::new(&dir.join("r_klipp.db").to_string_lossy()).await.unwrap());
+        db.init_schema().await.unwrap();
+        let manager = ConfigManager::new(DEFAULT_PRINTER_ID, dir.join("config"), db.clone());
+        let machine_config = || async {
+            let stored = db.get_machine_config("printer").await.unwrap().unwrap();
+            serde_json::to_value(stored.config).unwrap()
+        };
+
+        // A broken hand-edited file, then two valid saves.
+        let broken = "[stepper_x]\nstep_pin: PA1\n";
+        fs::write(dir.join("config").join(MAIN_CONFIG), broken).await.unwrap();
+        let slower = ENDER3.replace("max_velocity = 300", "max_velocity = 250");
+        assert!(manager.save(MAIN_CONFIG, ENDER3, "alice", true).await.unwrap().committed);
+        let ender3 = machine_config().await;
+        assert!(manager.save(MAIN_CONFIG, &slower, "alice", true).await.unwrap().committed);
+        assert_ne!(machine_config().await, ender3);
+
+        let versions = manager.versions(MAIN_CONFIG).await.unwrap();
+        let original = versions.iter().find(|v| v.version == 1).unwrap();
+        assert_eq!(original.author, ORIGINAL_AUTHOR);
+
+        let error = manager.rollback(MAIN_CONFIG, 1, "bob").await.unwrap_err();
+        assert!(matches!(error, HostError::InvalidRequest(_)), "{:?}", error);
+        assert_eq!(manager.read(MAIN_CONFIG).await.unwrap(), slower);
+
+        assert_eq!(manager.rollback(MAIN_CONFIG, 2, "bob").await.unwrap(), 4);
+        assert_eq!(manager.read(MAIN_CONFIG).await.unwrap(), ENDER3);
+        assert_eq!(machine_config().await, ender3);
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -7,7 +7,7 @@ use surrealdb::{
 };
 use thiserror::Error;
 
-use crate::db::models::{GCodeFile, MachineConfig, PrintHistory};
+use crate::db::models::{ActiveSpool, ApiKey, ConfigVersion, GCodeFile, MachineConfig, PrintHistory, Spool, User};
 
 #[derive(Error, Debug)]
 pub enum HostError {
@@ -15,6 +15,12 @@ pub enum HostError {
     DbError(#[from] surrealdb::Error),
     #[error("Serialization error: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
     #[error("Other error: {0}")]
     Other(String),
 }
@@ -67,6 +73,9 @@ impl Database {
         self.db
             .query("DEFINE TABLE print_history SCHEMAFULL;")
             .await?;
+        self.db
+            .query("DEFINE FIELD file_path ON TABLE print_history TYPE string;")
+            .await?;
         self.db
             .query("DEFINE FIELD start_time ON TABLE print_history TYPE datetime;")
             .await?;
@@ -79,6 +88,130 @@ impl Database {
         self.db
             .query("DEFINE FIELD telemetry_summary ON TABLE print_history TYPE object;")
             .await?;
+        self.db
+            .query("DEFINE FIELD printer ON TABLE print_history TYPE option<string>;")
+            .await?;
+
+        // User table, keyed by username
+        self.db
+            .query("DEFINE TABLE user SCHEMAFULL;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD username ON TABLE user TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD password_hash ON TABLE user TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD role ON TABLE user TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD created ON TABLE user TYPE datetime;")
+            .await?;
+
+        // ApiKey table, keyed by name
+        self.db
+            .query("DEFINE TABLE api_key SCHEMAFULL;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD name ON TABLE api_key TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD key_hash ON TABLE api_key TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD role ON TABLE api_key TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD created ON TABLE api_key TYPE datetime;")
+            .await?;
+        self.db
+            .query("DEFINE INDEX unique_key_hash ON TABLE api_key COLUMNS key_hash UNIQUE;")
+            .await?;
+
+        // ConfigVersion table, keyed by `<filename>@<version>`
+        self.db
+            .query("DEFINE TABLE config_version SCHEMAFULL;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD filename ON TABLE config_version TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD version ON TABLE config_version TYPE int;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD content ON TABLE config_version TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD created ON TABLE config_version TYPE datetime;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD author ON TABLE config_version TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD note ON TABLE config_version TYPE option<string>;")
+            .await?;
+
+        // Spool table, keyed by spool_id
+        self.db
+            .query("DEFINE TABLE spool SCHEMAFULL;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD spool_id ON TABLE spool TYPE int;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD registered ON TABLE spool TYPE datetime;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD first_used ON TABLE spool TYPE option<datetime>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD last_used ON TABLE spool TYPE option<datetime>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD name ON TABLE spool TYPE option<string>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD material ON TABLE spool TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD vendor ON TABLE spool TYPE option<string>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD color_hex ON TABLE spool TYPE option<string>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD diameter ON TABLE spool TYPE number;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD density ON TABLE spool TYPE number;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD initial_weight ON TABLE spool TYPE number;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD remaining_weight ON TABLE spool TYPE number;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD lot_nr ON TABLE spool TYPE option<string>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD comment ON TABLE spool TYPE option<string>;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD archived ON TABLE spool TYPE bool;")
+            .await?;
+
+        // ActiveSpool table, keyed by extruder (prefixed with the printer id in fleet mode)
+        self.db
+            .query("DEFINE TABLE active_spool SCHEMAFULL;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD extruder ON TABLE active_spool TYPE string;")
+            .await?;
+        self.db
+            .query("DEFINE FIELD spool_id ON TABLE active_spool TYPE int;")
+            .await?;
 
         Ok(())
     }
@@ -92,6 +225,33 @@ impl Database {
         Ok(())
     }
 
+    pub async fn get_gcode_file(&self, path: &str) -> Result<Option<GCodeFile>, HostError> {
+        let mut files: Vec<GCodeFile> = self
+            .db
+            .query("SELECT * FROM gcode_file WHERE path = $path LIMIT 1;")
+            .bind(("path", path.to_string()))
+            .await?
+            .take(0)?;
+        Ok(files.pop())
+    }
+
+    pub async fn delete_gcode_metadata(&self, path: &str) -> Result<(), HostError> {
+        self.db
+            .query("DELETE gcode_file WHERE path = $path;")
+            .bind(("path", path.to_string()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn rename_gcode_file(&self, old_path: &str, new_path: &str) -> Result<(), HostError> {
+        self.db
+            .query("UPDATE gcode_file SET path = $new WHERE path = $old;")
+            .bind(("old", old_path.to_string()))
+            .bind(("new", new_path.to_string()))
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_gcode_files(&self) -> Result<Vec<GCodeFile>, HostError> {
         let files: Vec<GCodeFile> = self.db.select("gcode_file").await?;
         Ok(files)
@@ -106,6 +266,19 @@ impl Database {
         Ok(history)
     }
 
+    pub async fn get_print_job(&self, id: &str) -> Result<Option<PrintHistory>, HostError> {
+        let job: Option<PrintHistory> = self
+            .db
+            .select(("print_history", id))
+            .await?;
+        Ok(job)
+    }
+
+    pub async fn get_all_print_history(&self) -> Result<Vec<PrintHistory>, HostError> {
+        let history: Vec<PrintHistory> = self.db.select("print_history").await?;
+        Ok(history)
+    }
+
     pub async fn save_print_history(&self, history: PrintHistory) -> Result<(), HostError> {
         let _created: PrintHistory = self
             .db
@@ -115,10 +288,11 @@ impl Database {
         Ok(())
     }
 
+    /// Saves `config` under its name, replacing any earlier config with the same name.
     pub async fn save_machine_config(&self, config: MachineConfig) -> Result<(), HostError> {
-        let _created: MachineConfig = self
+        let _saved: Option<MachineConfig> = self
             .db
-            .create("machine_config")
+            .update(("machine_config", config.name.as_str()))
             .content(config)
             .await?;
         Ok(())
@@ -131,4 +305,135 @@ impl Database {
             .await?;
         Ok(config)
     }
+
+    pub async fn save_user(&self, user: User) -> Result<(), HostError> {
+        let _created: Option<User> = self
+            .db
+            .create(("user", user.username.as_str()))
+            .content(user)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_user(&self, username: &str) -> Result<Option<User>, HostError> {
+        let user: Option<User> = self.db.select(("user", username)).await?;
+        Ok(user)
+    }
+
+    pub async fn get_users(&self) -> Result<Vec<User>, HostError> {
+        let users: Vec<User> = self.db.select("user").await?;
+        Ok(users)
+    }
+
+    pub async fn delete_user(&self, username: &str) -> Result<Option<User>, HostError> {
+        let deleted: Option<User> = self.db.delete(("user", username)).await?;
+        Ok(deleted)
+    }
+
+    pub async fn save_api_key(&self, key: ApiKey) -> Result<(), HostError> {
+        let _created: Option<ApiKey> = self
+            .db
+            .create(("api_key", key.name.as_str()))
+            .content(key)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>, HostError> {
+        let mut keys: Vec<ApiKey> = self
+            .db
+            .query("SELECT * FROM api_key WHERE key_hash = $key_hash LIMIT 1;")
+            .bind(("key_hash", key_hash.to_string()))
+            .await?
+            .take(0)?;
+        Ok(keys.pop())
+    }
+
+    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>, HostError> {
+        let keys: Vec<ApiKey> = self.db.select("api_key").await?;
+        Ok(keys)
+    }
+
+    pub async fn delete_api_key(&self, name: &str) -> Result<Option<ApiKey>, HostError> {
+        let deleted: Option<ApiKey> = self.db.delete(("api_key", name)).await?;
+        Ok(deleted)
+    }
+
+    pub async fn save_config_version(&self, version: ConfigVersion) -> Result<(), HostError> {
+        let id = format!("{}@{}", version.filename, version.version);
+        let _created: Option<ConfigVersion> = self
+            .db
+            .create(("config_version", id.as_str()))
+            .content(version)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_config_version(&self, filename: &str, version: u32) -> Result<Option<ConfigVersion>, HostError> {
+        let id = format!("{}@{}", filename, version);
+        let version: Option<ConfigVersion> = self
+            .db
+            .select(("config_version", id.as_str()))
+            .await?;
+        Ok(version)
+    }
+
+    /// All saved versions of `filename`, newest first.
+    pub async fn get_config_versions(&self, filename: &str) -> Result<Vec<ConfigVersion>, HostError> {
+        let versions: Vec<ConfigVersion> = self
+            .db
+            .query("SELECT * FROM config_version WHERE filename = $filename ORDER BY version DESC;")
+            .bind(("filename", filename.to_string()))
+            .await?
+            .take(0)?;
+        Ok(versions)
+    }
+
+    /// Saves `spool` under its id, replacing any earlier record.
+    pub async fn save_spool(&self, spool: Spool) -> Result<(), HostError> {
+        let _saved: Option<Spool> = self
+            .db
+            .update(("spool", spool.spool_id as i64))
+            .content(spool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_spool(&self, spool_id: u32) -> Result<Option<Spool>, HostError> {
+        let spool: Option<Spool> = self.db.select(("spool", spool_id as i64)).await?;
+        Ok(spool)
+    }
+
+    pub async fn get_spools(&self) -> Result<Vec<Spool>, HostError> {
+        let spools: Vec<Spool> = self
+            .db
+            .query("SELECT * FROM spool ORDER BY spool_id;")
+            .await?
+            .take(0)?;
+        Ok(spools)
+    }
+
+    pub async fn delete_spool(&self, spool_id: u32) -> Result<Option<Spool>, HostError> {
+        let deleted: Option<Spool> = self.db.delete(("spool", spool_id as i64)).await?;
+        Ok(deleted)
+    }
+
+    pub async fn save_active_spool(&self, key: &str, active: ActiveSpool) -> Result<(), HostError> {
+        let _saved: Option<ActiveSpool> = self
+            .db
+            .update(("active_spool", key))
+            .content(active)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_active_spool(&self, key: &str) -> Result<Option<ActiveSpool>, HostError> {
+        let active: Option<ActiveSpool> = self.db.select(("active_spool", key)).await?;
+        Ok(active)
+    }
+
+    pub async fn delete_active_spool(&self, key: &str) -> Result<(), HostError> {
+        let _deleted: Option<ActiveSpool> = self.db.delete(("active_spool", key)).await?;
+        Ok(())
+    }
 }
@@ -35,13 +35,17 @@ pub struct Thumbnail {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PrintHistory {
     pub id: Option<surrealdb::sql::Thing>, // SurrealDB ID
+    pub file_path: String,
+    /// Fleet printer that ran the job; `None` for jobs from before fleet mode.
+    #[serde(default)]
+    pub printer: Option<String>,
     pub start_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
     pub status: PrintStatus,
     pub telemetry_summary: PrintTelemetrySummary,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum PrintStatus {
     #[serde(rename = "in_progress")]
     InProgress,
@@ -53,10 +57,94 @@ pub enum PrintStatus {
     Cancelled,
 }
 
+impl PrintStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrintStatus::InProgress => "in_progress",
+            PrintStatus::Completed => "completed",
+            PrintStatus::Failed => "failed",
+            PrintStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct PrintTelemetrySummary {
     pub max_nozzle_temp: Option<f32>,
     pub max_bed_temp: Option<f32>,
-    pub total_filament_used: Option<f32>,
-    // Add other relevant summary statistics
+    pub avg_nozzle_temp: Option<f32>,
+    pub avg_bed_temp: Option<f32>,
+    pub total_filament_used: Option<f32>, // mm of filament pushed by the extruder
+    pub print_duration: Option<f64>,      // seconds from job start to job end
+    pub telemetry_samples: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    #[serde(rename = "viewer")]
+    Viewer,
+    #[serde(rename = "operator")]
+    Operator,
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    pub password_hash: String, // Argon2 PHC string
+    pub role: Role,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key_hash: String, // SHA-256 of the key, hex encoded
+    pub role: Role,
+    pub created: DateTime<Utc>,
+}
+
+/// One saved revision of a config file. Version numbers start at 1 per file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigVersion {
+    pub filename: String,
+    pub version: u32,
+    pub content: String,
+    pub created: DateTime<Utc>,
+    pub author: String,
+    /// Why the version exists when it was not a plain save, e.g. `rollback to v3`.
+    pub note: Option<String>,
+}
+
+/// A filament spool in the inventory. Weights are grams of filament, without the spool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spool {
+    /// Spoolman-style numeric id; also the record key.
+    pub spool_id: u32,
+    pub registered: DateTime<Utc>,
+    pub first_used: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    /// Product name, e.g. `Galaxy Black`.
+    pub name: Option<String>,
+    pub material: String,
+    pub vendor: Option<String>,
+    /// `RRGGBB`, without a leading `#`.
+    pub color_hex: Option<String>,
+    /// mm
+    pub diameter: f32,
+    /// g/cm³
+    pub density: f32,
+    pub initial_weight: f32,
+    pub remaining_weight: f32,
+    pub lot_nr: Option<String>,
+    pub comment: Option<String>,
+    pub archived: bool,
+}
+
+/// The spool loaded in one extruder of one printer, keyed like config versions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveSpool {
+    pub extruder: String,
+    pub spool_id: u32,
 }
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::bridge::FaultCode;
+use crate::db::models::PrintStatus;
+use crate::files::FileChange;
+use crate::power::PowerDeviceInfo;
+
+/// Host-level events that other subsystems (notifications, MQTT, WebSocket) react to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HostEvent {
+    JobStateChanged {
+        file_path: String,
+        status: PrintStatus,
+        timestamp: DateTime<Utc>,
+    },
+    McuError {
+        message: String,
+        timestamp: DateTime<Utc>,
+    },
+    Fault {
+        code: FaultCode,
+        message: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// A file or directory in one of the file roots was created, changed, moved or removed.
+    FileListChanged {
+        change: FileChange,
+        timestamp: DateTime<Utc>,
+    },
+    /// Text the MCU returned for a G-code command, e.g. the answer to `M114`.
+    GCodeResponse {
+        message: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// A power device was switched on or off.
+    PowerChanged {
+        device: PowerDeviceInfo,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl HostEvent {
+    pub fn job(file_path: &str, status: PrintStatus) -> Self {
+        HostEvent::JobStateChanged {
+            file_path: file_path.to_string(),
+            status,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn mcu_error(message: impl Into<String>) -> Self {
+        HostEvent::McuError {
+            message: message.into(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn fault(code: FaultCode, message: impl Into<String>) -> Self {
+        HostEvent::Fault {
+            code,
+            message: message.into(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn file_list_changed(change: FileChange) -> Self {
+        HostEvent::FileListChanged {
+            change,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn gcode_response(message: impl Into<String>) -> Self {
+        HostEvent::GCodeResponse {
+            message: message.into(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn power_changed(device: PowerDeviceInfo) -> Self {
+        HostEvent::PowerChanged {
+            device,
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Name used by event filters, e.g. `job_failed` or `fault`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostEvent::JobStateChanged { status, .. } => match status {
+                PrintStatus::InProgress => "job_started",
+                PrintStatus::Completed => "job_completed",
+                PrintStatus::Failed => "job_failed",
+                PrintStatus::Cancelled => "job_cancelled",
+            },
+            HostEvent::McuError { .. } => "mcu_error",
+            HostEvent::Fault { .. } => "fault",
+            HostEvent::FileListChanged { .. } => "filelist_changed",
+            HostEvent::GCodeResponse { .. } => "gcode_response",
+            HostEvent::PowerChanged { .. } => "power_changed",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            HostEvent::JobStateChanged { file_path, status, .. } => {
+                format!("Print {} {}", file_path, status.as_str())
+            }
+            HostEvent::McuError { message, .. } | HostEvent::GCodeResponse { message, .. } => message.clone(),
+            HostEvent::Fault { code, message, .. } => format!("{:?}: {}", code, message),
+            HostEvent::FileListChanged { change, .. } => {
+                format!("{} {}/{}", change.action, change.item.root, change.item.path)
+            }
+            HostEvent::PowerChanged { device, .. } => format!("{} switched {}", device.device, device.status),
+        }
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            HostEvent::JobStateChanged { timestamp, .. }
+            | HostEvent::McuError { timestamp, .. }
+            | HostEvent::Fault { timestamp, .. }
+            | HostEvent::FileListChanged { timestamp, .. }
+            | HostEvent::GCodeResponse { timestamp, .. }
+            | HostEvent::PowerChanged { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+/// Publishes an event, ignoring the case where nobody is subscribed yet.
+pub fn publish(bus: &broadcast::Sender<HostEvent>, event: HostEvent) {
+    let _ = bus.send(event);
+}
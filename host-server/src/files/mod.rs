@@ -0,0 +1,464 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::db::HostError;
+
+/// Root holding uploaded print files.
+pub const GCODES: &str = "gcodes";
+/// Root holding `printer.cfg` and its includes.
+pub const CONFIG: &str = "config";
+
+/// A file or directory as Moonraker reports it. `path` is relative to `root`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileItem {
+    pub path: String,
+    pub root: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub modified: f64,
+    pub permissions: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemRef {
+    pub path: String,
+    pub root: String,
+}
+
+/// Result of a change to a file root; also the payload of `notify_filelist_changed`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileChange {
+    pub action: String,
+    pub item: FileItem,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_item: Option<ItemRef>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: u64,
+    pub modified: f64,
+    pub permissions: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirEntry {
+    pub dirname: String,
+    pub size: u64,
+    pub modified: f64,
+    pub permissions: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirFile {
+    pub filename: String,
+    pub size: u64,
+    pub modified: f64,
+    pub permissions: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RootInfo {
+    pub name: String,
+    pub permissions: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryListing {
+    pub dirs: Vec<DirEntry>,
+    pub files: Vec<DirFile>,
+    pub root_info: RootInfo,
+}
+
+/// A request path such as `gcodes/parts/bracket.gcode`, checked and resolved to disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedPath {
+    pub root: String,
+    /// Path inside the root, `/`-separated; empty for the root itself.
+    pub rel: String,
+    pub full: PathBuf,
+}
+
+impl ResolvedPath {
+    pub fn item_ref(&self) -> ItemRef {
+        ItemRef {
+            path: self.rel.clone(),
+            root: self.root.clone(),
+        }
+    }
+
+    fn is_root(&self) -> bool {
+        self.rel.is_empty()
+    }
+
+    fn join(&self, name: &str) -> Self {
+        let rel = if self.rel.is_empty() { name.to_string() } else { format!("{}/{}", self.rel, name) };
+        Self {
+            root: self.root.clone(),
+            rel,
+            full: self.full.join(name),
+        }
+    }
+}
+
+/// Splits `root/rel` into its root and components. Only plain names are accepted, so a
+/// request can never climb out of its root with `..`.
+pub fn split_path(path: &str) -> Result<(&str, Vec<&str>), HostError> {
+    let mut parts = path.split('/').filter(|p| !p.is_empty() && *p != ".");
+    let root = parts
+        .next()
+        .ok_or_else(|| HostError::InvalidRequest("A file root is required".to_string()))?;
+    let rel: Vec<&str> = parts.collect();
+    if rel.iter().any(|p| *p == ".." || p.contains('\\') || p.contains('\0')) {
+        return Err(HostError::InvalidRequest(format!("Invalid path: {}", path)));
+    }
+    Ok((root, rel))
+}
+
+/// Fails unless `path`, or the deepest part of it that exists, is inside `root` once
+/// symlinks are resolved.
+pub(crate) async fn ensure_inside(root: &Path, path: &Path) -> Result<(), HostError> {
+    let root = fs::canonicalize(root).await.map_err(io_error)?;
+    let mut existing = path.to_path_buf();
+    loop {
+        match fs::canonicalize(&existing).await {
+            Ok(real) if real.starts_with(&root) => return Ok(()),
+            Ok(_) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if !existing.pop() {
+                    break;
+                }
+            }
+            Err(e) => return Err(io_error(e)),
+        }
+    }
+    Err(HostError::InvalidRequest(format!("Path escapes its root: {}", path.display())))
+}
+
+/// The Moonraker-style file roots served by `/server/files`.
+pub struct FileManager {
+    roots: BTreeMap<String, PathBuf>,
+}
+
+impl FileManager {
+    pub fn new(gcodes_dir: PathBuf, config_dir: PathBuf) -> Self {
+        let roots = [(GCODES.to_string(), gcodes_dir), (CONFIG.to_string(), config_dir)];
+        Self {
+            roots: roots.into_iter().collect(),
+        }
+    }
+
+    pub fn root_dir(&self, root: &str) -> Result<&Path, HostError> {
+        self.roots
+            .get(root)
+            .map(PathBuf::as_path)
+            .ok_or_else(|| HostError::InvalidRequest(format!("Unknown file root: {}", root)))
+    }
+
+    pub async fn resolve(&self, path: &str) -> Result<ResolvedPath, HostError> {
+        let (root, rel) = split_path(path)?;
+        let root_dir = self.root_dir(root)?;
+        fs::create_dir_all(root_dir).await.map_err(io_error)?;
+
+        let full = rel.iter().fold(root_dir.to_path_buf(), |full, part| full.join(part));
+        ensure_inside(root_dir, &full).await?;
+        Ok(ResolvedPath {
+            root: root.to_string(),
+            rel: rel.join("/"),
+            full,
+        })
+    }
+
+    pub async fn item(&self, path: &ResolvedPath) -> Result<FileItem, HostError> {
+        let metadata = metadata(&path.full).await?;
+        Ok(FileItem {
+            path: path.rel.clone(),
+            root: path.root.clone(),
+            size: metadata.len(),
+            modified: modified(&metadata),
+            permissions: permissions(&metadata),
+        })
+    }
+
+    /// Every file below `root`, recursively. Hidden files and directories are skipped.
+    pub async fn list(&self, root: &str) -> Result<Vec<FileEntry>, HostError> {
+        let base = self.resolve(root).await?;
+        let mut files = Vec::new();
+        let mut pending = vec![base];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir.full).await.map_err(io_error)?;
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+                if name.starts_with('.') {
+                    continue;
+                }
+                let metadata = entry.metadata().await.map_err(io_error)?;
+                let child = dir.join(&name);
+                if metadata.is_dir() {
+                    pending.push(child);
+                } else if metadata.is_file() {
+                    files.push(FileEntry {
+                        path: child.rel,
+                        size: metadata.len(),
+                        modified: modified(&metadata),
+                        permissions: permissions(&metadata),
+                    });
+                }
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    pub async fn get_directory(&self, path: &str) -> Result<DirectoryListing, HostError> {
+        let dir = self.resolve(path).await?;
+        if !metadata(&dir.full).await?.is_dir() {
+            return Err(HostError::InvalidRequest(format!("Not a directory: {}", path)));
+        }
+        let mut listing = DirectoryListing {
+            dirs: Vec::new(),
+            files: Vec::new(),
+            root_info: RootInfo {
+                name: dir.root.clone(),
+                permissions: "rw".to_string(),
+            },
+        };
+        let mut entries = fs::read_dir(&dir.full).await.map_err(io_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+            if name.starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata().await.map_err(io_error)?;
+            let (size, modified, permissions) = (metadata.len(), modified(&metadata), permissions(&metadata));
+            if metadata.is_dir() {
+                listing.dirs.push(DirEntry { dirname: name, size, modified, permissions });
+            } else if metadata.is_file() {
+                listing.files.push(DirFile { filename: name, size, modified, permissions });
+            }
+        }
+        listing.dirs.sort_by(|a, b| a.dirname.cmp(&b.dirname));
+        listing.files.sort_by(|a, b| a.filename.cmp(&b.filename));
+        Ok(listing)
+    }
+
+    pub async fn post_directory(&self, path: &str) -> Result<FileChange, HostError> {
+        let dir = self.resolve(path).await?;
+        if exists(&dir.full).await {
+            return Err(HostError::Conflict(format!("{} already exists", path)));
+        }
+        fs::create_dir(&dir.full).await.map_err(io_error)?;
+        self.change("create_dir", &dir, None).await
+    }
+
+    /// Deletes a directory; one that is not empty is only removed with `force`.
+    pub async fn delete_directory(&self, path: &str, force: bool) -> Result<FileChange, HostError> {
+        let dir = self.resolve(path).await?;
+        if dir.is_root() {
+            return Err(HostError::InvalidRequest("Cannot delete a file root".to_string()));
+        }
+        if !metadata(&dir.full).await?.is_dir() {
+            return Err(HostError::InvalidRequest(format!("Not a directory: {}", path)));
+        }
+        let item = self.item(&dir).await?;
+        if force {
+            fs::remove_dir_all(&dir.full).await.map_err(io_error)?;
+        } else {
+            let mut entries = fs::read_dir(&dir.full).await.map_err(io_error)?;
+            if entries.next_entry().await.map_err(io_error)?.is_some() {
+                return Err(HostError::Conflict(format!("{} is not empty", path)));
+            }
+            fs::remove_dir(&dir.full).await.map_err(io_error)?;
+        }
+        Ok(FileChange { action: "delete_dir".to_string(), item, source_item: None })
+    }
+
+    pub async fn delete_file(&self, path: &str) -> Result<FileChange, HostError> {
+        let file = self.resolve(path).await?;
+        if file.is_root() || !metadata(&file.full).await?.is_file() {
+            return Err(HostError::InvalidRequest(format!("Not a file: {}", path)));
+        }
+        let item = self.item(&file).await?;
+        fs::remove_file(&file.full).await.map_err(io_error)?;
+        Ok(FileChange { action: "delete_file".to_string(), item, source_item: None })
+    }
+
+    /// Moves or renames `source`. A `dest` that is an existing directory receives the source
+    /// under its current name; any other existing `dest` is left alone.
+    pub async fn move_item(&self, source: &str, dest: &str) -> Result<FileChange, HostError> {
+        let (source, dest) = self.transfer_paths(source, dest).await?;
+        let is_dir = metadata(&source.full).await?.is_dir();
+        fs::rename(&source.full, &dest.full).await.map_err(io_error)?;
+        let action = if is_dir { "move_dir" } else { "move_file" };
+        self.change(action, &dest, Some(source.item_ref())).await
+    }
+
+    pub async fn copy_item(&self, source: &str, dest: &str) -> Result<FileChange, HostError> {
+        let (source, dest) = self.transfer_paths(source, dest).await?;
+        let action = if metadata(&source.full).await?.is_dir() {
+            copy_dir(&source.full, &dest.full).await?;
+            "create_dir"
+        } else {
+            fs::copy(&source.full, &dest.full).await.map_err(io_error)?;
+            "create_file"
+        };
+        self.change(action, &dest, None).await
+    }
+
+    async fn transfer_paths(&self, source: &str, dest: &str) -> Result<(ResolvedPath, ResolvedPath), HostError> {
+        let source = self.resolve(source).await?;
+        let mut dest = self.resolve(dest).await?;
+        if source.is_root() {
+            return Err(HostError::InvalidRequest("Cannot move or copy a file root".to_string()));
+        }
+        metadata(&source.full).await?;
+        if fs::metadata(&dest.full).await.is_ok_and(|m| m.is_dir()) {
+            let name = source.rel.rsplit('/').next().unwrap_or_default();
+            dest = dest.join(name);
+        }
+        if exists(&dest.full).await {
+            return Err(HostError::Conflict(format!("{}/{} already exists", dest.root, dest.rel)));
+        }
+        if dest.full.starts_with(&source.full) {
+            return Err(HostError::InvalidRequest("Cannot move or copy a directory into itself".to_string()));
+        }
+        Ok((source, dest))
+    }
+
+    async fn change(&self, action: &str, path: &ResolvedPath, source_item: Option<ItemRef>) -> Result<FileChange, HostError> {
+        Ok(FileChange {
+            action: action.to_string(),
+            item: self.item(path).await?,
+            source_item,
+        })
+    }
+}
+
+async fn copy_dir(from: &Path, to: &Path) -> Result<(), HostError> {
+    let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+    while let Some((from, to)) = pending.pop() {
+        fs::create_dir(&to).await.map_err(io_error)?;
+        let mut entries = fs::read_dir(&from).await.map_err(io_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let target = to.join(entry.file_name());
+            if entry.file_type().await.map_err(io_error)?.is_dir() {
+                pending.push((entry.path(), target));
+            } else {
+                fs::copy(entry.path(), target).await.map_err(io_error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).await.is_ok()
+}
+
+async fn metadata(path: &Path) -> Result<std::fs::Metadata, HostError> {
+    fs::metadata(path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => HostError::NotFound(format!("{} does not exist", path.display())),
+        _ => io_error(e),
+    })
+}
+
+fn modified(metadata: &std::fs::Metadata) -> f64 {
+    metadata
+        .modified()
+        .map(|t| DateTime::<Utc>::from(t).timestamp_millis() as f64 / 1000.0)
+        .unwrap_or_default()
+}
+
+fn permissions(metadata: &std::fs::Metadata) -> String {
+    if metadata.permissions().readonly() { "r" } else { "rw" }.to_string()
+}
+
+fn io_error(e: std::io::Error) -> HostError {
+    HostError::Other(e.to_string())
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn manager() -> (FileManager, PathBuf) {
+        let base = std::env::temp_dir().join(format!("r_klipp_files_{}", uuid::Uuid::new_v4().simple()));
+        let manager = FileManager::new(base.join("gcodes"), base.join("config"));
+        fs::create_dir_all(base.join("gcodes/parts")).await.unwrap();
+        fs::write(base.join("gcodes/parts/bracket.gcode"), "G28\n").await.unwrap();
+        fs::write(base.join("gcodes/benchy.gcode"), "G28\nG1 X10\n").await.unwrap();
+        fs::write(base.join("gcodes/.thumbs"), "").await.unwrap();
+        (manager, base)
+    }
+
+    #[tokio::test]
+    async fn paths_cannot_leave_their_root() {
+        let (files, base) = manager().await;
+        fs::write(base.join("secret.txt"), "").await.unwrap();
+        for bad in ["gcodes/../secret.txt", "gcodes/parts/../../secret.txt", "", "/", "logs/klippy.log", "gcodes/a\\b"] {
+            assert!(files.resolve(bad).await.is_err(), "{:?} should be rejected", bad);
+        }
+
+        std::os::unix::fs::symlink(&base, base.join("gcodes/escape")).unwrap();
+        assert!(files.resolve("gcodes/escape/secret.txt").await.is_err());
+        assert!(files.resolve("gcodes/escape/new_dir").await.is_err());
+
+        let ok = files.resolve("gcodes//parts/./bracket.gcode").await.unwrap();
+        assert_eq!((ok.root.as_str(), ok.rel.as_str()), ("gcodes", "parts/bracket.gcode"));
+        fs::remove_dir_all(base).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_and_get_directory_skip_hidden_files() {
+        let (files, base) = manager().await;
+        let listed: Vec<_> = files.list(GCODES).await.unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(listed, vec!["benchy.gcode", "parts/bracket.gcode"]);
+
+        let dir = files.get_directory("gcodes").await.unwrap();
+        assert_eq!(dir.dirs.len(), 1);
+        assert_eq!(dir.dirs[0].dirname, "parts");
+        assert_eq!(dir.files.len(), 1);
+        assert_eq!(dir.files[0].size, 11);
+        assert!(files.get_directory("gcodes/benchy.gcode").await.is_err());
+        fs::remove_dir_all(base).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_copy_and_delete_report_moonraker_changes() {
+        let (files, base) = manager().await;
+
+        let moved = files.move_item("gcodes/benchy.gcode", "gcodes/parts").await.unwrap();
+        assert_eq!(moved.action, "move_file");
+        assert_eq!(moved.item.path, "parts/benchy.gcode");
+        assert_eq!(moved.source_item, Some(ItemRef { path: "benchy.gcode".into(), root: "gcodes".into() }));
+
+        let copied = files.copy_item("gcodes/parts", "gcodes/archive").await.unwrap();
+        assert_eq!((copied.action.as_str(), copied.item.path.as_str()), ("create_dir", "archive"));
+        assert!(base.join("gcodes/archive/benchy.gcode").is_file());
+
+        assert!(matches!(files.copy_item("gcodes/parts", "gcodes/parts/inner").await, Err(HostError::InvalidRequest(_))));
+        assert!(matches!(
+            files.move_item("gcodes/archive/benchy.gcode", "gcodes/parts/benchy.gcode").await,
+            Err(HostError::Conflict(_))
+        ));
+
+        let created = files.post_directory("config/macros").await.unwrap();
+        assert_eq!((created.action.as_str(), created.item.root.as_str()), ("create_dir", "config"));
+        assert!(matches!(files.post_directory("config/macros").await, Err(HostError::Conflict(_))));
+
+        assert!(matches!(files.delete_directory("gcodes/parts", false).await, Err(HostError::Conflict(_))));
+        assert!(files.delete_directory("gcodes", true).await.is_err());
+        let deleted = files.delete_directory("gcodes/parts", true).await.unwrap();
+        assert_eq!((deleted.action.as_str(), deleted.item.path.as_str()), ("delete_dir", "parts"));
+
+        let deleted = files.delete_file("gcodes/archive/bracket.gcode").await.unwrap();
+        assert_eq!(deleted.action, "delete_file");
+        assert!(matches!(files.delete_file("gcodes/archive/bracket.gcode").await, Err(HostError::NotFound(_))));
+        fs::remove_dir_all(base).await.unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, Notify};
+use uuid::Uuid;
+
+use crate::api::{start_job, AppState, JobControl};
+use crate::db::HostError;
+use crate::events::HostEvent;
+
+/// How often the queue is re-checked even without a job ending or a new submission.
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A printer managed by this host-server.
+pub struct FleetPrinter {
+    pub id: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub state: web::Data<AppState>,
+}
+
+impl FleetPrinter {
+    pub fn is_compatible(&self, requires: &[String]) -> bool {
+        is_compatible(&self.id, &self.tags, requires)
+    }
+}
+
+/// True when a printer has every tag in `requires`. The printer id counts as a tag, so a
+/// job can be pinned to one printer. Tags are compared case-insensitively.
+pub fn is_compatible(id: &str, tags: &[String], requires: &[String]) -> bool {
+    requires
+        .iter()
+        .all(|tag| tag.eq_ignore_ascii_case(id) || tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+}
+
+/// A job waiting for a compatible printer to become idle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String,
+    /// Relative to the `gcodes` root.
+    pub filename: String,
+    /// Resolved on submission; what `start_job` opens.
+    pub file_path: String,
+    pub requires: Vec<String>,
+    pub submitted_by: String,
+    pub submitted: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrinterOverview {
+    pub id: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    /// `idle`, `printing` or `paused`.
+    pub state: &'static str,
+    pub filename: Option<String>,
+    pub progress: f32,
+    pub nozzle_temp: f32,
+    pub bed_temp: f32,
+}
+
+/// Pairs queued jobs with idle printers, oldest job first. Each printer takes at most one
+/// job; a job no idle printer can run is skipped, so it does not hold up the jobs behind it.
+/// `printers` are the idle printers as `(id, tags)`; returns `(job index, printer index)` pairs.
+pub fn assign(jobs: &[QueuedJob], printers: &[(&str, &[String])]) -> Vec<(usize, usize)> {
+    let mut free = vec![true; printers.len()];
+    let mut assignments = Vec::new();
+    for (job_index, job) in jobs.iter().enumerate() {
+        let printer = (0..printers.len())
+            .find(|&i| free[i] && is_compatible(printers[i].0, printers[i].1, &job.requires));
+        if let Some(printer_index) = printer {
+            free[printer_index] = false;
+            assignments.push((job_index, printer_index));
+        }
+    }
+    assignments
+}
+
+/// The printers of this host-server and the shared job queue.
+pub struct Fleet {
+    printers: Vec<FleetPrinter>,
+    queue: Mutex<Vec<QueuedJob>>,
+    queue_path: PathBuf,
+    wake: Notify,
+}
+
+impl Fleet {
+    /// `printers` must not be empty; the first one answers the un-namespaced routes.
+    /// The queue is persisted to `queue_path` so it survives a restart.
+    pub async fn new(printers: Vec<FleetPrinter>, queue_path: PathBuf) -> Self {
+        assert!(!printers.is_empty(), "A fleet needs at least one printer");
+        let queue = match tokio::fs::read(&queue_path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable job queue {}: {}", queue_path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            printers,
+            queue: Mutex::new(queue),
+            queue_path,
+            wake: Notify::new(),
+        }
+    }
+
+    pub fn printers(&self) -> &[FleetPrinter] {
+        &self.printers
+    }
+
+    pub fn default_printer(&self) -> &FleetPrinter {
+        &self.printers[0]
+    }
+
+    pub async fn overview(&self) -> Vec<PrinterOverview> {
+        let mut overview = Vec::with_capacity(self.printers.len());
+        for printer in &self.printers {
+            let machine = printer.state.machine_state.read().await;
+            let paused = *printer.state.job_control.borrow() == JobControl::Pause;
+            let state = match (&machine.current_print_file, paused) {
+                (None, _) => "idle",
+                (Some(_), true) => "paused",
+                (Some(_), false) => "printing",
+            };
+            overview.push(PrinterOverview {
+                id: printer.id.clone(),
+                name: printer.name.clone(),
+                tags: printer.tags.clone(),
+                state,
+                filename: machine.current_print_file.clone(),
+                progress: machine.print_progress,
+                nozzle_temp: machine.nozzle_temp,
+                bed_temp: machine.bed_temp,
+            });
+        }
+        overview
+    }
+
+    pub async fn queued_jobs(&self) -> Vec<QueuedJob> {
+        self.queue.lock().await.clone()
+    }
+
+    /// Queues `filename` for the next idle printer with every tag in `requires`.
+    pub async fn enqueue(&self, filename: &str, requires: Vec<String>, submitted_by: &str) -> Result<QueuedJob, HostError> {
+        if !self.printers.iter().any(|p| p.is_compatible(&requires)) {
+            return Err(HostError::InvalidRequest(format!(
+                "No printer has all of the required tags {:?}",
+                requires
+            )));
+        }
+        let files = &self.default_printer().state.files;
+        let file = files.resolve(&format!("{}/{}", crate::files::GCODES, filename)).await?;
+        if !tokio::fs::metadata(&file.full).await.is_ok_and(|m| m.is_file()) {
+            return Err(HostError::NotFound(format!("No file gcodes/{}", file.rel)));
+        }
+
+        let job = QueuedJob {
+            id: Uuid::new_v4().simple().to_string(),
+            filename: file.rel,
+            file_path: file.full.to_string_lossy().into_owned(),
+            requires,
+            submitted_by: submitted_by.to_string(),
+            submitted: Utc::now(),
+        };
+        let mut queue = self.queue.lock().await;
+        queue.push(job.clone());
+        self.save(&queue).await;
+        drop(queue);
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    pub async fn remove(&self, job_id: &str) -> Result<QueuedJob, HostError> {
+        let mut queue = self.queue.lock().await;
+        let index = queue
+            .iter()
+            .position(|job| job.id == job_id)
+            .ok_or_else(|| HostError::NotFound(format!("No queued job {}", job_id)))?;
+        let job = queue.remove(index);
+        self.save(&queue).await;
+        Ok(job)
+    }
+
+    /// Starts every queued job that an idle compatible printer can take right now.
+    pub async fn dispatch(&self) {
+        let mut queue = self.queue.lock().await;
+        if queue.is_empty() {
+            return;
+        }
+        let mut idle = Vec::new();
+        for printer in &self.printers {
+            if printer.state.machine_state.read().await.current_print_file.is_none() {
+                idle.push(printer);
+            }
+        }
+
+        let candidates: Vec<(&str, &[String])> = idle.iter().map(|p| (p.id.as_str(), p.tags.as_slice())).collect();
+        let mut started = Vec::new();
+        for (job_index, printer_index) in assign(&queue, &candidates) {
+            let (job, printer) = (&queue[job_index], idle[printer_index]);
+            printer.state.power.power_on_for_job(&printer.id).await;
+            match start_job(&printer.state, job.file_path.clone()).await {
+                Ok(warning) => {
+                    info!("Dispatched queued job {} ({}) to {}.", job.id, job.filename, printer.id);
+                    if let Some(warning) = warning {
+                        warn!("Queued job {} on {}: {}", job.id, printer.id, warning);
+                    }
+                    started.push(job_index);
+                }
+                // Someone started a print on it directly; try again on the next pass.
+                Err(HostError::Conflict(_)) => {}
+                Err(e) => {
+                    error!("Dropping queued job {} ({}): {:?}", job.id, job.filename, e);
+                    started.push(job_index);
+                }
+            }
+        }
+        if started.is_empty() {
+            return;
+        }
+        let mut index = 0;
+        queue.retain(|_| {
+            index += 1;
+            !started.contains(&(index - 1))
+        });
+        self.save(&queue).await;
+    }
+
+    async fn save(&self, queue: &[QueuedJob]) {
+        let result = match serde_json::to_vec_pretty(queue) {
+            Ok(bytes) => tokio::fs::write(&self.queue_path, bytes).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            error!("Failed to save job queue to {}: {}", self.queue_path.display(), e);
+        }
+    }
+}
+
+/// Dispatches queued jobs whenever a job is queued, a printer finishes a job, or every
+/// `DISPATCH_INTERVAL` as a fallback.
+pub async fn run_dispatcher(fleet: std::sync::Arc<Fleet>) {
+    for printer in fleet.printers() {
+        let mut rx = printer.state.event_bus.subscribe();
+        let fleet = fleet.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(HostEvent::JobStateChanged { .. }) => fleet.wake.notify_one(),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    loop {
+        fleet.dispatch().await;
+        tokio::select! {
+            _ = fleet.wake.notified() => {}
+            _ = tokio::time::sleep(DISPATCH_INTERVAL) => {}
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(filename: &str, requires: &[&str]) -> QueuedJob {
+        QueuedJob {
+            id: filename.to_string(),
+            filename: filename.to_string(),
+            file_path: format!("./uploads/{}", filename),
+            requires: requires.iter().map(|t| t.to_string()).collect(),
+            submitted_by: "farm".to_string(),
+            submitted: Utc::now(),
+        }
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn compatibility_needs_every_required_tag() {
+        let mk4 = tags(&["PLA", "petg", "nozzle_0.4"]);
+        assert!(is_compatible("mk4-01", &mk4, &[]));
+        assert!(is_compatible("mk4-01", &mk4, &tags(&["pla", "nozzle_0.4"])));
+        assert!(!is_compatible("mk4-01", &mk4, &tags(&["pla", "nozzle_0.6"])));
+        assert!(is_compatible("mk4-01", &mk4, &tags(&["MK4-01"])), "the id pins a job to one printer");
+        assert!(!is_compatible("mk4-02", &mk4, &tags(&["mk4-01"])));
+    }
+
+    #[test]
+    fn jobs_go_to_the_first_idle_compatible_printer_in_queue_order() {
+        let (pla, abs) = (tags(&["pla"]), tags(&["abs", "enclosed"]));
+        let printers: Vec<(&str, &[String])> = vec![("p1", &pla), ("p2", &abs), ("p3", &pla)];
+        let jobs = vec![
+            job("enclosure.gcode", &["abs"]),
+            job("benchy.gcode", &["pla"]),
+            job("tpu.gcode", &["tpu"]),
+            job("clip.gcode", &[]),
+            job("cube.gcode", &["pla"]),
+        ];
+        // The TPU job has no printer and is skipped; the cube job waits for a free printer.
+        assert_eq!(assign(&jobs, &printers), vec![(0, 1), (1, 0), (3, 2)]);
+        assert_eq!(assign(&jobs, &printers[..1]), vec![(1, 0)]);
+        assert!(assign(&jobs, &[]).is_empty());
+    }
+}
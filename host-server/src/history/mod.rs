@@ -0,0 +1,440 @@
+use chrono::{DateTime, Datelike, Utc};
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::db::models::{PrintHistory, PrintStatus, PrintTelemetrySummary};
+
+// --- Telemetry Accumulation ---
+
+/// Running max/average of the temperatures seen on the telemetry broadcaster.
+#[derive(Debug, Default)]
+pub struct TelemetryAccumulator {
+    max_nozzle_temp: Option<f32>,
+    max_bed_temp: Option<f32>,
+    nozzle_temp_sum: f64,
+    bed_temp_sum: f64,
+    samples: u32,
+}
+
+impl TelemetryAccumulator {
+    /// Folds one broadcast telemetry frame (a serialized `bridge::Telemetry`) into the totals.
+    pub fn record(&mut self, telemetry: &serde_json::Value) {
+        let (Some(nozzle), Some(bed)) = (
+            telemetry["nozzle_temp"].as_f64(),
+            telemetry["bed_temp"].as_f64(),
+        ) else {
+            return; // Not a telemetry frame
+        };
+
+        self.max_nozzle_temp = Some(self.max_nozzle_temp.map_or(nozzle as f32, |m| m.max(nozzle as f32)));
+        self.max_bed_temp = Some(self.max_bed_temp.map_or(bed as f32, |m| m.max(bed as f32)));
+        self.nozzle_temp_sum += nozzle;
+        self.bed_temp_sum += bed;
+        self.samples += 1;
+    }
+
+    pub fn summary(&self, filament_used: f32, duration_secs: f64) -> PrintTelemetrySummary {
+        let avg = |sum: f64| (self.samples > 0).then(|| (sum / self.samples as f64) as f32);
+        PrintTelemetrySummary {
+            max_nozzle_temp: self.max_nozzle_temp,
+            max_bed_temp: self.max_bed_temp,
+            avg_nozzle_temp: avg(self.nozzle_temp_sum),
+            avg_bed_temp: avg(self.bed_temp_sum),
+            total_filament_used: Some(filament_used),
+            print_duration: Some(duration_secs),
+            telemetry_samples: self.samples,
+        }
+    }
+}
+
+/// Klipper's name for extruder `index`: `extruder`, `extruder1`, `extruder2`, ...
+pub fn extruder_name(index: usize) -> String {
+    if index == 0 {
+        "extruder".to_string()
+    } else {
+        format!("extruder{}", index)
+    }
+}
+
+/// The words of a G-code line as letter and value, split the way the firmware's G-code
+/// lexer splits them: words may run together as in `G1X10E2`, comments and a `*` checksum
+/// end them, and a lowercase `e` after a number only starts an exponent when a sign follows.
+/// Stops at anything else, such as the text of `M117`.
+fn words(line: &str) -> Vec<(char, f32)> {
+    let bytes = line.as_bytes();
+    let mut words = Vec::new();
+    let mut pos = 0;
+    while let Some(&c) = bytes.get(pos) {
+        match c {
+            b'(' => match line[pos..].find(')') {
+                Some(close) => pos += close + 1,
+                None => break,
+            },
+            c if c.is_ascii_whitespace() => pos += 1,
+            c if c.is_ascii_alphabetic() => {
+                let start = pos + 1;
+                let mut end = start;
+                if matches!(bytes.get(end), Some(b'+' | b'-')) {
+                    end += 1;
+                }
+                while matches!(bytes.get(end), Some(b'0'..=b'9' | b'.')) {
+                    end += 1;
+                }
+                let exponent = bytes.get(end) == Some(&b'e')
+                    && matches!(bytes.get(end + 1), Some(b'+' | b'-'))
+                    && matches!(bytes.get(end + 2), Some(b'0'..=b'9'));
+                if exponent {
+                    end += 2;
+                    while matches!(bytes.get(end), Some(b'0'..=b'9')) {
+                        end += 1;
+                    }
+                }
+                let Ok(value) = line[start..end].parse() else {
+                    break;
+                };
+                words.push((c.to_ascii_uppercase() as char, value));
+                pos = end;
+            }
+            _ => break,
+        }
+    }
+    words
+}
+
+/// The number of a `G`, `M` or `T` word; `None` for `G92.1` and the like.
+fn code(value: f32) -> Option<u32> {
+    (value >= 0.0 && value.fract() == 0.0).then_some(value as u32)
+}
+
+/// Tracks filament pushed by the extruder from the G-code lines streamed to the MCU.
+/// Handles absolute (G90, M82) and relative (G91, M83) E modes, G92 resets and `T<n>` tool
+/// changes, in lines with `N` numbers and checksums as the firmware receives them.
+#[derive(Debug, Default)]
+pub struct ExtrusionTracker {
+    relative: bool,
+    last_e: f32,
+    total: f32,
+    tool: usize,
+    /// Extrusion per tool since the last `take_by_extruder`.
+    pending: Vec<f32>,
+}
+
+impl ExtrusionTracker {
+    pub fn observe(&mut self, line: &str) {
+        let words: Vec<_> = words(line).into_iter().filter(|&(letter, _)| letter != 'N').collect();
+        let e = words.iter().find_map(|&(letter, value)| (letter == 'E').then_some(value));
+        // On M-codes other than M6 `T` names a heater or extruder, as in `M104 T1 S200`.
+        let selects_tool = !words.iter().any(|&(letter, value)| letter == 'M' && code(value) != Some(6));
+        let mut moves = false;
+        let mut sets_position = false;
+        for &(letter, value) in &words {
+            match (letter, code(value)) {
+                ('G', Some(0..=3)) => moves = true,
+                ('G', Some(90)) | ('M', Some(82)) => self.relative = false,
+                ('G', Some(91)) | ('M', Some(83)) => self.relative = true,
+                ('G', Some(92)) => sets_position = true,
+                ('T', Some(index)) if selects_tool => self.tool = index as usize,
+                _ => {}
+            }
+        }
+
+        if sets_position {
+            if let Some(e) = e {
+                self.last_e = e;
+            } else if words.len() == 1 {
+                // A bare G92 zeroes every axis.
+                self.last_e = 0.0;
+            }
+        } else if let (true, Some(e)) = (moves, e) {
+            let delta = if self.relative { e } else { e - self.last_e };
+            if !self.relative {
+                self.last_e = e;
+            }
+            // Net extrusion: a retraction is paid back by the following prime.
+            self.total += delta;
+            if self.pending.len() <= self.tool {
+                self.pending.resize(self.tool + 1, 0.0);
+            }
+            self.pending[self.tool] += delta;
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+
+    /// Extrusion per extruder since the previous call, for extruders that moved.
+    pub fn take_by_extruder(&mut self) -> Vec<(String, f32)> {
+        self.pending
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, length)| **length != 0.0)
+            .map(|(index, length)| (extruder_name(index), std::mem::take(length)))
+            .collect()
+    }
+}
+
+// --- Job Recorder ---
+
+/// Collects the telemetry summary of a single print job.
+///
+/// Subscribes to the host's own telemetry broadcaster for the lifetime of the job,
+/// and turns into a `PrintHistory` record when the job ends.
+pub struct JobRecorder {
+    file_path: String,
+    start_time: DateTime<Utc>,
+    started: Instant,
+    extrusion: ExtrusionTracker,
+    stop: oneshot::Sender<()>,
+    telemetry_task: JoinHandle<TelemetryAccumulator>,
+}
+
+impl JobRecorder {
+    pub fn start(file_path: String, telemetry_broadcaster: &broadcast::Sender<serde_json::Value>) -> Self {
+        let mut rx = telemetry_broadcaster.subscribe();
+        let (stop, mut stop_rx) = oneshot::channel();
+
+        let telemetry_task = tokio::spawn(async move {
+            let mut accumulator = TelemetryAccumulator::default();
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    telemetry = rx.recv() => match telemetry {
+                        Ok(data) => accumulator.record(&data),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("Print history recorder lagged, skipped {} telemetry frames.", n);
+                        }
+                        Err(_) => break, // Broadcaster closed
+                    }
+                }
+            }
+            accumulator
+        });
+
+        Self {
+            file_path,
+            start_time: Utc::now(),
+            started: Instant::now(),
+            extrusion: ExtrusionTracker::default(),
+            stop,
+            telemetry_task,
+        }
+    }
+
+    /// Feeds a G-code line that was sent to the MCU.
+    pub fn observe_gcode(&mut self, line: &str) {
+        self.extrusion.observe(line);
+    }
+
+    /// Filament each extruder pushed since the previous call; see `ExtrusionTracker`.
+    pub fn take_extruded(&mut self) -> Vec<(String, f32)> {
+        self.extrusion.take_by_extruder()
+    }
+
+    pub async fn finish(self, status: PrintStatus) -> PrintHistory {
+        let duration = self.started.elapsed().as_secs_f64();
+        let _ = self.stop.send(());
+        let accumulator = self.telemetry_task.await.unwrap_or_default();
+
+        PrintHistory {
+            id: None,
+            file_path: self.file_path,
+            printer: None,
+            start_time: self.start_time,
+            end_time: Some(Utc::now()),
+            status,
+            telemetry_summary: accumulator.summary(self.extrusion.total(), duration),
+        }
+    }
+}
+
+// --- Statistics ---
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct JobTotals {
+    pub jobs: u32,
+    pub completed: u32,
+    pub print_time: f64,
+    pub filament_used: f64,
+}
+
+impl JobTotals {
+    fn add(&mut self, job: &PrintHistory) {
+        self.jobs += 1;
+        if job.status == PrintStatus::Completed {
+            self.completed += 1;
+        }
+        self.print_time += job.telemetry_summary.print_duration.unwrap_or_default();
+        self.filament_used += job.telemetry_summary.total_filament_used.unwrap_or_default() as f64;
+    }
+}
+
+/// Aggregate statistics over the whole print history, as returned by `server.history.totals`.
+#[derive(Debug, Default, Serialize)]
+pub struct HistoryTotals {
+    pub total: JobTotals,
+    pub longest_job: f64,
+    pub by_file: BTreeMap<String, JobTotals>,
+    pub by_month: BTreeMap<String, JobTotals>, // Keyed "YYYY-MM"
+}
+
+impl HistoryTotals {
+    pub fn from_jobs(jobs: &[PrintHistory]) -> Self {
+        let mut totals = Self::default();
+        for job in jobs {
+            totals.total.add(job);
+            totals.by_file.entry(job.file_path.clone()).or_default().add(job);
+            let month = format!("{:04}-{:02}", job.start_time.year(), job.start_time.month());
+            totals.by_month.entry(month).or_default().add(job);
+            totals.longest_job = totals
+                .longest_job
+                .max(job.telemetry_summary.print_duration.unwrap_or_default());
+        }
+        totals
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn job(file: &str, month: u32, status: PrintStatus, duration: f64, filament: f32) -> PrintHistory {
+        PrintHistory {
+            id: None,
+            file_path: file.to_string(),
+            printer: None,
+            start_time: Utc.with_ymd_and_hms(2024, month, 1, 12, 0, 0).unwrap(),
+            end_time: None,
+            status,
+            telemetry_summary: PrintTelemetrySummary {
+                print_duration: Some(duration),
+                total_filament_used: Some(filament),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_accumulator_max_and_average() {
+        let mut acc = TelemetryAccumulator::default();
+        acc.record(&json!({ "nozzle_temp": 200.0, "bed_temp": 60.0 }));
+        acc.record(&json!({ "nozzle_temp": 210.0, "bed_temp": 70.0 }));
+        acc.record(&json!({ "event": "not telemetry" }));
+
+        let summary = acc.summary(12.5, 30.0);
+        assert_eq!(summary.max_nozzle_temp, Some(210.0));
+        assert_eq!(summary.max_bed_temp, Some(70.0));
+        assert_eq!(summary.avg_nozzle_temp, Some(205.0));
+        assert_eq!(summary.avg_bed_temp, Some(65.0));
+        assert_eq!(summary.total_filament_used, Some(12.5));
+        assert_eq!(summary.telemetry_samples, 2);
+    }
+
+    #[test]
+    fn test_extrusion_absolute_and_relative() {
+        let mut tracker = ExtrusionTracker::default();
+        for line in [
+            "G1 X10 E5 ; prime",
+            "G1 E3",     // retract 2mm
+            "G1 X20 E8", // 2mm unretract + 3mm extrusion
+            "G92 E0",
+            "G1 X30 E1.5",
+            "M83",
+            "G1 X40 E2",
+            "g1 x50 e-1",
+            "M82",
+            "G92 E0",
+            "G1 X60 E4",
+            "G92",
+            "G1 X70 E0.5",
+            "G92 X0",
+            "G1 X10 E1",
+        ] {
+            tracker.observe(line);
+        }
+        assert!((tracker.total() - 15.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_extrusion_is_split_by_tool() {
+        let mut tracker = ExtrusionTracker::default();
+        for line in ["M83", "G1 X10 E4", "T1", "G1 X20 E6", "G1 E-1"] {
+            tracker.observe(line);
+        }
+        assert_eq!(
+            tracker.take_by_extruder(),
+            vec![("extruder".to_string(), 4.0), ("extruder1".to_string(), 5.0)]
+        );
+        assert!(tracker.take_by_extruder().is_empty(), "taken extrusion is not reported twice");
+        tracker.observe("t0");
+        tracker.observe("G1 X30 E2.5");
+        assert_eq!(tracker.take_by_extruder(), vec![("extruder".to_string(), 2.5)]);
+        assert!((tracker.total() - 11.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_extrusion_reads_lines_as_the_firmware_does() {
+        let mut tracker = ExtrusionTracker::default();
+        for line in [
+            "N12 G1 X5 E2*57",
+            "G01 X10 E3",
+            "G1X15E4.5",
+            "g1x20e5",
+            "G1 X25 (wipe) E6 ; comment E100",
+            "G91",
+            "G1 X5 E1",
+            "G1 E-0.5",
+            "G90",
+            "G92 E0",
+            "G1 X30 E1e+0",
+            "M117 Extruding E50",
+            "N13 M104 T1 S200*91",
+            "G1 X35 E2",
+        ] {
+            tracker.observe(line);
+        }
+        assert!((tracker.total() - 8.5).abs() < 1e-4, "{}", tracker.total());
+        assert_eq!(tracker.take_by_extruder(), vec![("extruder".to_string(), 8.5)]);
+    }
+
+    #[tokio::test]
+    async fn test_job_recorder_subscribes_to_telemetry() {
+        let (tx, _rx) = broadcast::channel(16);
+        let mut recorder = JobRecorder::start("./uploads/cube.gcode".to_string(), &tx);
+
+        tx.send(json!({ "nozzle_temp": 215.0, "bed_temp": 60.0 })).unwrap();
+        recorder.observe_gcode("G1 X1 E4");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let history = recorder.finish(PrintStatus::Completed).await;
+        assert_eq!(history.file_path, "./uploads/cube.gcode");
+        assert_eq!(history.status, PrintStatus::Completed);
+        assert_eq!(history.telemetry_summary.max_nozzle_temp, Some(215.0));
+        assert_eq!(history.telemetry_summary.total_filament_used, Some(4.0));
+    }
+
+    #[test]
+    fn test_totals_per_file_and_month() {
+        let jobs = [
+            job("a.gcode", 1, PrintStatus::Completed, 100.0, 10.0),
+            job("a.gcode", 2, PrintStatus::Failed, 50.0, 5.0),
+            job("b.gcode", 2, PrintStatus::Completed, 300.0, 30.0),
+        ];
+        let totals = HistoryTotals::from_jobs(&jobs);
+
+        assert_eq!(totals.total.jobs, 3);
+        assert_eq!(totals.total.completed, 2);
+        assert_eq!(totals.longest_job, 300.0);
+        assert_eq!(totals.by_file["a.gcode"].jobs, 2);
+        assert_eq!(totals.by_file["a.gcode"].filament_used, 15.0);
+        assert_eq!(totals.by_month["2024-02"].print_time, 350.0);
+        assert_eq!(totals.by_month["2024-01"].completed, 1);
+    }
+}
@@ -1,79 +1,184 @@
+use actix_web::web;
 use anyhow::Result;
 use log::{error, info};
 use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch, RwLock};
 
 mod api;
+mod auth;
 mod bridge;
+mod config;
+mod config_files;
 mod db;
+mod events;
+mod files;
+mod fleet;
+mod history;
+mod metrics;
+mod mqtt;
+mod notifications;
+mod power;
+mod spools;
+mod timeseries;
 
 fn main() -> Result<()> {
     env_logger::init();
     info!("Starting r_klipp host-server...");
 
+    let config_path = std::env::var("RKLIPP_CONFIG").unwrap_or_else(|_| config::DEFAULT_CONFIG_PATH.to_string());
+    let config = Arc::new(config::HostConfig::load(std::path::Path::new(&config_path))?);
+
     // Create a new Tokio runtime for background tasks (Actix-Web, SerialBridge)
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()?;
 
-    // Shared state for all components
-    let (telemetry_tx, _telemetry_rx) = broadcast::channel(1024); // For MCU -> API/UI telemetry
-    let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(1024); // For API/UI -> MCU commands
-    let machine_state = Arc::new(RwLock::new(api::MachineState::default()));
-
-    // Clone senders for background tasks
-    let api_telemetry_tx = telemetry_tx.clone();
-    let api_mcu_cmd_tx = mcu_cmd_tx.clone();
-    let api_machine_state = machine_state.clone();
-
-    let bridge_telemetry_tx = telemetry_tx.clone();
-    let bridge_mcu_cmd_rx = mcu_cmd_rx; // Only one receiver for mpsc
-    let bridge_machine_state = machine_state.clone();
+    // Channels and metrics for each printer. The bridge owns the command receiver; everything else
+    // talks to the printer through the senders.
+    let printers = config.printers();
+    let mut channels = Vec::with_capacity(printers.len());
+    for _ in &printers {
+        let (telemetry_tx, _telemetry_rx) = broadcast::channel(1024); // For MCU -> API/UI telemetry
+        let (mcu_cmd_tx, mcu_cmd_rx) = mpsc::channel(1024); // For API/UI -> MCU commands
+        let (event_tx, _event_rx) = broadcast::channel(256); // Job state, MCU errors and faults
+        let host_metrics = Arc::new(metrics::HostMetrics::new()?);
+        channels.push((telemetry_tx, mcu_cmd_tx, mcu_cmd_rx, event_tx, host_metrics));
+    }
+    // The touchscreen UI drives the first printer
+    let ui_mcu_cmd_tx = channels[0].1.clone();
 
     // Spawn background tasks onto the Tokio runtime
     rt.spawn(async move {
         // 1. Initialize SurrealDB
-        let db_path = "./data/r_klipp.db";
-        let db = Arc::new(db::Database::new(db_path).await.expect("Failed to initialize SurrealDB"));
+        let db_path = config.server.data_dir.join("r_klipp.db");
+        let db_path = db_path.to_string_lossy();
+        let db = Arc::new(db::Database::new(&db_path).await.expect("Failed to initialize SurrealDB"));
         info!("SurrealDB initialized at {}", db_path);
 
         // Run schema migrations
         db.init_schema().await.expect("Failed to initialize SurrealDB schema");
         info!("SurrealDB schema initialized.");
 
-        // 2. Initialize and spawn SerialBridge
-        let serial_port_path = "/dev/ttyUSB0".to_string(); // TODO: Make configurable
-        let baud_rate = 115200; // TODO: Make configurable
-        let serial_bridge = bridge::SerialBridge::new(
-            serial_port_path.clone(),
-            baud_rate,
-            bridge_telemetry_tx,
-            bridge_mcu_cmd_rx,
-            bridge_machine_state,
+        // 2. Authentication is shared by every printer
+        let auth = Arc::new(
+            auth::Authenticator::new(db.clone(), &config.auth).expect("Invalid auth configuration"),
         );
-        tokio::spawn(async move {
-            if let Err(e) = serial_bridge.run().await {
-                error!("SerialBridge task failed: {:?}", e);
+        if let Err(e) = auth.bootstrap_admin().await {
+            error!("Failed to bootstrap admin account: {:?}", e);
+        }
+
+        // Power devices are shared too; each one follows the jobs of its printer
+        let buses: Vec<_> = printers
+            .iter()
+            .zip(&channels)
+            .map(|(printer, channel)| (printer.id.clone(), channel.3.clone()))
+            .collect();
+        let power = Arc::new(power::PowerManager::new(&config.power, &buses));
+
+        let single = printers.len() == 1;
+        let mut fleet_printers = Vec::with_capacity(printers.len());
+        for (printer, (telemetry_tx, mcu_cmd_tx, mcu_cmd_rx, event_tx, host_metrics)) in printers.into_iter().zip(channels) {
+            let machine_state = Arc::new(RwLock::new(api::MachineState::default()));
+
+            // 3. Initialize and spawn this printer's SerialBridge
+            let serial_port_path = printer.serial.port.clone();
+            let serial_bridge = bridge::SerialBridge::new(
+                printer.serial.clone(),
+                telemetry_tx.clone(),
+                event_tx.clone(),
+                mcu_cmd_rx,
+                machine_state.clone(),
+                host_metrics.clone(),
+            );
+            let mcu_priority_tx = serial_bridge.priority_sender();
+            tokio::spawn(async move {
+                if let Err(e) = serial_bridge.run().await {
+                    error!("SerialBridge task failed: {:?}", e);
+                }
+            });
+            info!("SerialBridge task spawned for {} on {}", printer.id, serial_port_path);
+
+            // 4. Load the telemetry time-series store and feed it from the broadcaster
+            let store_name = if single {
+                "telemetry_store.json".to_string()
+            } else {
+                format!("telemetry_store_{}.json", printer.id)
+            };
+            let store_path = config.server.data_dir.join(store_name);
+            let telemetry_store = Arc::new(RwLock::new(timeseries::open_store(&store_path).await));
+            tokio::spawn(timeseries::run_store(
+                telemetry_store.clone(),
+                telemetry_tx.clone(),
+                store_path,
+                Duration::from_secs(60),
+            ));
+            tokio::spawn(metrics::run_event_counter(host_metrics.clone(), event_tx.clone()));
+            tokio::spawn(power::run_power_policy(power.clone(), printer.id.clone(), event_tx.subscribe()));
+
+            // 5. Build the state shared by the REST API, WebSocket and MQTT bridge
+            let config_dir = printer.config_dir.clone().unwrap_or_else(|| config.server.config_dir.clone());
+            let (job_control, _) = watch::channel(api::JobControl::Run);
+            let config_files = Arc::new(config_files::ConfigManager::new(&printer.id, config_dir.clone(), db.clone()));
+            let files = Arc::new(files::FileManager::new(config.server.gcodes_dir.clone(), config_dir));
+            let spools = Arc::new(spools::SpoolManager::new(&printer.id, db.clone()));
+            let app_state = web::Data::new(api::AppState {
+                printer_id: printer.id.clone(),
+                db: db.clone(),
+                telemetry_broadcaster: telemetry_tx,
+                event_bus: event_tx,
+                mcu_cmd_sender: mcu_cmd_tx,
+                mcu_priority_sender: mcu_priority_tx,
+                machine_state,
+                telemetry_store,
+                auth: auth.clone(),
+                job_control,
+                metrics: host_metrics,
+                config_files,
+                files,
+                spools,
+                power: power.clone(),
+            });
+
+            // 6. Bridge telemetry, job state and commands to MQTT when configured
+            if let Some(mut mqtt_config) = config.mqtt.clone() {
+                if !single {
+                    mqtt_config.topic_prefix = format!("{}/{}", mqtt_config.topic_prefix, printer.id);
+                    mqtt_config.client_id = format!("{}-{}", mqtt_config.client_id, printer.id);
+                }
+                tokio::spawn(mqtt::run_mqtt(mqtt_config, app_state.clone()));
             }
-        });
-        info!("SerialBridge task spawned for {}", serial_port_path);
-
-        // 3. Spawn Actix-Web server
-        if let Err(e) = api::run_api_server(
-            db,
-            api_telemetry_tx,
-            api_mcu_cmd_tx,
-            api_machine_state,
-        )
-        .await
-        {
+
+            fleet_printers.push(fleet::FleetPrinter {
+                id: printer.id,
+                name: printer.name,
+                tags: printer.tags,
+                state: app_state,
+            });
+        }
+
+        // 7. Deliver notifications for job state changes, MCU errors and faults
+        let buses = fleet_printers
+            .iter()
+            .map(|printer| (printer.id.clone(), printer.state.event_bus.clone()))
+            .collect();
+        tokio::spawn(notifications::run_notifier(config.notifications.clone(), buses));
+
+        // 8. Start queued jobs on idle printers
+        let fleet = web::Data::new(
+            fleet::Fleet::new(fleet_printers, config.server.data_dir.join("fleet_queue.json")).await,
+        );
+        tokio::spawn(fleet::run_dispatcher(fleet.clone().into_inner()));
+
+        // 9. Spawn Actix-Web server
+        if let Err(e) = api::run_api_server(fleet, config).await {
             error!("Actix-Web server failed: {:?}", e);
         }
     });
 
     // Run Slint UI on the main thread
     info!("Starting Slint UI on main thread...");
-    if let Err(e) = rt.block_on(host_ui::run_ui(mcu_cmd_tx)) {
+    if let Err(e) = rt.block_on(host_ui::run_ui(ui_mcu_cmd_tx)) {
         error!("Slint UI failed: {:?}", e);
     }
 
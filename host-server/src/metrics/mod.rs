@@ -0,0 +1,251 @@
+use anyhow::Result;
+use log::warn;
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tokio::sync::broadcast;
+
+use crate::bridge::Telemetry;
+use crate::events::HostEvent;
+
+/// Prometheus metrics for host and MCU health, served on `/metrics`.
+pub struct HostMetrics {
+    registry: Registry,
+    heater_temperature: GaugeVec,
+    heater_target: GaugeVec,
+    heater_pwm: GaugeVec,
+    link_rtt: Histogram,
+    link_rtt_us: IntGauge,
+    buffer_fill_percent: IntGauge,
+    dropped_packets: IntGauge,
+    decode_errors: IntCounterVec,
+    jobs: IntCounterVec,
+    faults: IntCounterVec,
+    pub websocket_clients: IntGauge,
+    gcode_queue_depth: IntGauge,
+    gcode_queue_capacity: IntGauge,
+}
+
+/// Where a serial frame failed to decode.
+#[derive(Debug, Clone, Copy)]
+pub enum DecodeStage {
+    Cobs,
+    Postcard,
+    Overflow,
+}
+
+impl DecodeStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DecodeStage::Cobs => "cobs",
+            DecodeStage::Postcard => "postcard",
+            DecodeStage::Overflow => "overflow",
+        }
+    }
+}
+
+impl HostMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("rklipp".to_string()), None)?;
+
+        let heater_temperature = GaugeVec::new(
+            Opts::new("heater_temperature_celsius", "Measured heater temperature."),
+            &["heater"],
+        )?;
+        let heater_target = GaugeVec::new(
+            Opts::new("heater_target_celsius", "Heater target temperature; 0 when off."),
+            &["heater"],
+        )?;
+        let heater_pwm = GaugeVec::new(
+            Opts::new("heater_pwm_duty_ratio", "Heater PWM duty cycle from 0 to 1."),
+            &["heater"],
+        )?;
+        let link_rtt = Histogram::with_opts(
+            HistogramOpts::new("link_rtt_seconds", "Host to MCU round-trip time.").buckets(vec![
+                0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1,
+            ]),
+        )?;
+        let link_rtt_us = IntGauge::new("link_rtt_microseconds", "Latest host to MCU round-trip time.")?;
+        let buffer_fill_percent =
+            IntGauge::new("mcu_buffer_fill_percent", "Fill level of the MCU motion buffer.")?;
+        let dropped_packets = IntGauge::new(
+            "mcu_dropped_packets",
+            "Packets the MCU reports as dropped since it started.",
+        )?;
+        let decode_errors = IntCounterVec::new(
+            Opts::new("serial_decode_errors_total", "Serial frames from the MCU that could not be decoded."),
+            &["stage"],
+        )?;
+        let jobs = IntCounterVec::new(
+            Opts::new("jobs_total", "Print jobs by status; in_progress counts started jobs."),
+            &["status"],
+        )?;
+        let faults = IntCounterVec::new(Opts::new("faults_total", "Faults by code."), &["code"])?;
+        let websocket_clients = IntGauge::new("websocket_clients", "Connected WebSocket clients.")?;
+        let gcode_queue_depth =
+            IntGauge::new("gcode_queue_depth", "Commands waiting to be written to the MCU.")?;
+        let gcode_queue_capacity =
+            IntGauge::new("gcode_queue_capacity", "Maximum commands the MCU queue can hold.")?;
+
+        registry.register(Box::new(heater_temperature.clone()))?;
+        registry.register(Box::new(heater_target.clone()))?;
+        registry.register(Box::new(heater_pwm.clone()))?;
+        registry.register(Box::new(link_rtt.clone()))?;
+        registry.register(Box::new(link_rtt_us.clone()))?;
+        registry.register(Box::new(buffer_fill_percent.clone()))?;
+        registry.register(Box::new(dropped_packets.clone()))?;
+        registry.register(Box::new(decode_errors.clone()))?;
+        registry.register(Box::new(jobs.clone()))?;
+        registry.register(Box::new(faults.clone()))?;
+        registry.register(Box::new(websocket_clients.clone()))?;
+        registry.register(Box::new(gcode_queue_depth.clone()))?;
+        registry.register(Box::new(gcode_queue_capacity.clone()))?;
+
+        Ok(Self {
+            registry,
+            heater_temperature,
+            heater_target,
+            heater_pwm,
+            link_rtt,
+            link_rtt_us,
+            buffer_fill_percent,
+            dropped_packets,
+            decode_errors,
+            jobs,
+            faults,
+            websocket_clients,
+            gcode_queue_depth,
+            gcode_queue_capacity,
+        })
+    }
+
+    pub fn observe_telemetry(&self, telemetry: &Telemetry) {
+        let heaters = [
+            ("extruder", telemetry.nozzle_temp, telemetry.nozzle_target, telemetry.nozzle_pwm),
+            ("heater_bed", telemetry.bed_temp, telemetry.bed_target, telemetry.bed_pwm),
+        ];
+        for (heater, actual, target, pwm) in heaters {
+            self.heater_temperature.with_label_values(&[heater]).set(actual as f64);
+            self.heater_target.with_label_values(&[heater]).set(target as f64);
+            self.heater_pwm.with_label_values(&[heater]).set(pwm as f64);
+        }
+
+        let link = &telemetry.link_health;
+        self.link_rtt.observe(link.rtt_us as f64 / 1_000_000.0);
+        self.link_rtt_us.set(link.rtt_us as i64);
+        self.buffer_fill_percent.set(link.buffer_fill_percent as i64);
+        self.dropped_packets.set(link.dropped_packets as i64);
+    }
+
+    pub fn decode_error(&self, stage: DecodeStage) {
+        self.decode_errors.with_label_values(&[stage.as_str()]).inc();
+    }
+
+    pub fn observe_event(&self, event: &HostEvent) {
+        match event {
+            HostEvent::JobStateChanged { status, .. } => {
+                self.jobs.with_label_values(&[status.as_str()]).inc();
+            }
+            HostEvent::Fault { code, .. } => {
+                self.faults.with_label_values(&[&format!("{:?}", code)]).inc();
+            }
+            HostEvent::McuError { .. }
+            | HostEvent::FileListChanged { .. }
+            | HostEvent::GCodeResponse { .. }
+            | HostEvent::PowerChanged { .. } => {}
+        }
+    }
+
+    /// Records the MCU command queue, sampled at scrape time.
+    pub fn set_gcode_queue(&self, depth: usize, capacity: usize) {
+        self.gcode_queue_depth.set(depth as i64);
+        self.gcode_queue_capacity.set(capacity as i64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Counts jobs by status and faults by code as they are published on the event bus.
+pub async fn run_event_counter(metrics: std::sync::Arc<HostMetrics>, event_bus: broadcast::Sender<HostEvent>) {
+    let mut rx = event_bus.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => metrics.observe_event(&event),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("Metrics missed {} host events.", n);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::{FaultCode, LinkHealth};
+    use crate::db::models::PrintStatus;
+
+    fn telemetry() -> Telemetry {
+        Telemetry {
+            nozzle_temp: 214.5,
+            bed_temp: 59.8,
+            nozzle_target: 215.0,
+            bed_target: 60.0,
+            nozzle_pwm: 0.42,
+            bed_pwm: 0.9,
+            x_pos: 0.0,
+            y_pos: 0.0,
+            z_pos: 0.0,
+            link_health: LinkHealth {
+                rtt_us: 1500,
+                buffer_fill_percent: 75,
+                dropped_packets: 3,
+            },
+        }
+    }
+
+    #[test]
+    fn telemetry_is_exposed_as_gauges_and_histogram() {
+        let metrics = HostMetrics::new().unwrap();
+        metrics.observe_telemetry(&telemetry());
+        metrics.set_gcode_queue(12, 1024);
+        metrics.websocket_clients.inc();
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains("# TYPE rklipp_heater_temperature_celsius gauge"));
+        assert!(text.contains(r#"rklipp_heater_temperature_celsius{heater="extruder"} 214.5"#));
+        assert!(text.contains(r#"rklipp_heater_target_celsius{heater="heater_bed"} 60"#));
+        assert!(text.contains(r#"rklipp_heater_pwm_duty_ratio{heater="extruder"} 0.41999998688697815"#));
+        assert!(text.contains("rklipp_link_rtt_microseconds 1500"));
+        assert!(text.contains(r#"rklipp_link_rtt_seconds_bucket{le="0.002"} 1"#));
+        assert!(text.contains(r#"rklipp_link_rtt_seconds_bucket{le="0.001"} 0"#));
+        assert!(text.contains("rklipp_mcu_buffer_fill_percent 75"));
+        assert!(text.contains("rklipp_mcu_dropped_packets 3"));
+        assert!(text.contains("rklipp_gcode_queue_depth 12"));
+        assert!(text.contains("rklipp_websocket_clients 1"));
+    }
+
+    #[test]
+    fn events_and_decode_errors_are_counted() {
+        let metrics = HostMetrics::new().unwrap();
+        metrics.observe_event(&HostEvent::job("a.gcode", PrintStatus::InProgress));
+        metrics.observe_event(&HostEvent::job("a.gcode", PrintStatus::Completed));
+        metrics.observe_event(&HostEvent::job("b.gcode", PrintStatus::InProgress));
+        metrics.observe_event(&HostEvent::fault(FaultCode::ThermalRunaway, "E0"));
+        metrics.decode_error(DecodeStage::Postcard);
+        metrics.decode_error(DecodeStage::Postcard);
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains("# TYPE rklipp_jobs_total counter"));
+        assert!(text.contains(r#"rklipp_jobs_total{status="in_progress"} 2"#));
+        assert!(text.contains(r#"rklipp_jobs_total{status="completed"} 1"#));
+        assert!(text.contains(r#"rklipp_faults_total{code="ThermalRunaway"} 1"#));
+        assert!(text.contains(r#"rklipp_serial_decode_errors_total{stage="postcard"} 2"#));
+    }
+}
@@ -0,0 +1,452 @@
+use actix_web::web;
+use log::{error, info, warn};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::api::{self, AppState};
+use crate::auth::{AuthError, Authenticator, Identity};
+use crate::events::HostEvent;
+
+const STATUS_ONLINE: &str = "online";
+const STATUS_OFFLINE: &str = "offline";
+
+/// Broker connection and topic layout, configured under `[mqtt]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prepended to every topic, e.g. `factory/line1/printer3`.
+    pub topic_prefix: String,
+    pub topics: MqttTopics,
+    /// 0, 1 or 2. Anything else falls back to 1.
+    pub qos: u8,
+    pub keep_alive_secs: u64,
+    pub reconnect_delay_ms: u64,
+    /// Minimum gap between telemetry messages; 0 forwards every sample.
+    pub telemetry_interval_ms: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "r-klipp-host".to_string(),
+            username: None,
+            password: None,
+            topic_prefix: "r_klipp".to_string(),
+            topics: MqttTopics::default(),
+            qos: 1,
+            keep_alive_secs: 30,
+            reconnect_delay_ms: 5000,
+            telemetry_interval_ms: 1000,
+        }
+    }
+}
+
+/// Topic names relative to `topic_prefix`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttTopics {
+    pub telemetry: String,
+    /// Retained; always holds the latest job state.
+    pub job: String,
+    pub fault: String,
+    /// Retained `online`/`offline`, with `offline` as the last will.
+    pub status: String,
+    pub command: String,
+    pub response: String,
+}
+
+impl Default for MqttTopics {
+    fn default() -> Self {
+        Self {
+            telemetry: "telemetry".to_string(),
+            job: "job".to_string(),
+            fault: "fault".to_string(),
+            status: "status".to_string(),
+            command: "command".to_string(),
+            response: "response".to_string(),
+        }
+    }
+}
+
+impl MqttConfig {
+    /// Full topic name for `topic`.
+    pub fn topic(&self, topic: &str) -> String {
+        let prefix = self.topic_prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            topic.to_string()
+        } else {
+            format!("{}/{}", prefix, topic)
+        }
+    }
+
+    fn qos(&self) -> QoS {
+        match self.qos {
+            0 => QoS::AtMostOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::AtLeastOnce,
+        }
+    }
+
+    fn options(&self) -> MqttOptions {
+        let mut options = MqttOptions::new(&self.client_id, &self.host, self.port);
+        options.set_keep_alive(Duration::from_secs(self.keep_alive_secs.max(5)));
+        options.set_last_will(LastWill::new(
+            self.topic(&self.topics.status),
+            STATUS_OFFLINE,
+            self.qos(),
+            true,
+        ));
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            options.set_credentials(username, password);
+        }
+        options
+    }
+}
+
+/// Resolves the caller of an MQTT command from its `token` (JWT) or `api_key` field.
+/// MQTT has no per-message headers, so credentials travel in the payload.
+async fn authenticate(request: &Value, auth: &Authenticator) -> Result<Identity, AuthError> {
+    if let Some(token) = request["token"].as_str() {
+        return auth.verify_token(token).await;
+    }
+    if let Some(key) = request["api_key"].as_str() {
+        return auth.verify_api_key(key).await;
+    }
+    Err(AuthError::Unauthorized)
+}
+
+/// Runs one command from the command topic through the same authorization and
+/// JSON-RPC dispatch as `/api/rpc`, returning the reply to publish.
+async fn handle_command(payload: Vec<u8>, state: web::Data<AppState>) -> Value {
+    let request: Value = match serde_json::from_slice(&payload) {
+        Ok(request) => request,
+        Err(e) => return api::rpc_parse_error(&e),
+    };
+    match authenticate(&request, &state.auth).await {
+        Ok(identity) => api::rpc_reply(&request, &identity, state).await,
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": { "code": 401, "message": e.to_string() }
+        }),
+    }
+}
+
+/// Bridges host-server to an MQTT broker until the process exits.
+pub async fn run_mqtt(config: MqttConfig, state: web::Data<AppState>) {
+    let telemetry = state.telemetry_broadcaster.subscribe();
+    let events = state.event_bus.subscribe();
+    run_client(config, telemetry, events, move |payload| {
+        handle_command(payload, state.clone())
+    })
+    .await
+}
+
+/// Publishes telemetry and host events, and answers commands with `handle`.
+///
+/// The broker is reconnected after `reconnect_delay_ms` whenever the connection drops.
+/// Every (re)connect republishes the retained `online` status and latest job state and
+/// renews the command subscription, since sessions are not persisted across connects.
+async fn run_client<F, Fut>(
+    config: MqttConfig,
+    mut telemetry: broadcast::Receiver<Value>,
+    mut events: broadcast::Receiver<HostEvent>,
+    handle: F,
+) where
+    F: Fn(Vec<u8>) -> Fut,
+    Fut: Future<Output = Value> + Send + 'static,
+{
+    let qos = config.qos();
+    let status_topic = config.topic(&config.topics.status);
+    let command_topic = config.topic(&config.topics.command);
+    let response_topic = config.topic(&config.topics.response);
+    let telemetry_topic = config.topic(&config.topics.telemetry);
+    let job_topic = config.topic(&config.topics.job);
+    let fault_topic = config.topic(&config.topics.fault);
+    let telemetry_interval = Duration::from_millis(config.telemetry_interval_ms);
+    let reconnect_delay = Duration::from_millis(config.reconnect_delay_ms);
+
+    let (client, mut eventloop) = AsyncClient::new(config.options(), 64);
+    let mut connected = false;
+    let mut last_job_state: Option<String> = None;
+    let mut last_telemetry: Option<Instant> = None;
+
+    info!("Connecting to MQTT broker {}:{}", config.host, config.port);
+    loop {
+        tokio::select! {
+            polled = eventloop.poll() => match polled {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", config.host, config.port);
+                    connected = true;
+                    let mut requests = vec![
+                        client.try_publish(&status_topic, qos, true, STATUS_ONLINE),
+                        client.try_subscribe(&command_topic, qos),
+                    ];
+                    if let Some(job) = &last_job_state {
+                        requests.push(client.try_publish(&job_topic, qos, true, job.as_str()));
+                    }
+                    for result in requests {
+                        if let Err(e) = result {
+                            warn!("Failed to queue MQTT request after connect: {}", e);
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                    let reply = handle(publish.payload.to_vec());
+                    let client = client.clone();
+                    let response_topic = response_topic.clone();
+                    tokio::spawn(async move {
+                        let reply = reply.await;
+                        if let Err(e) = client.publish(response_topic, qos, false, reply.to_string()).await {
+                            error!("Failed to publish MQTT command response: {}", e);
+                        }
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if connected {
+                        warn!("MQTT connection lost: {}", e);
+                    } else {
+                        warn!("MQTT connection failed: {}", e);
+                    }
+                    connected = false;
+                    tokio::time::sleep(reconnect_delay).await;
+                }
+            },
+            sample = telemetry.recv() => match sample {
+                Ok(sample) => {
+                    let due = last_telemetry.is_none_or(|t| t.elapsed() >= telemetry_interval);
+                    if connected && due {
+                        last_telemetry = Some(Instant::now());
+                        if let Err(e) = client.try_publish(&telemetry_topic, qos, false, sample.to_string()) {
+                            warn!("Dropping MQTT telemetry: {}", e);
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let name = event.name();
+                    let payload = serde_json::to_string(&event).unwrap_or_default();
+                    let result = match event {
+                        HostEvent::JobStateChanged { .. } => {
+                            last_job_state = Some(payload.clone());
+                            if !connected {
+                                continue; // Republished on the next connect
+                            }
+                            client.try_publish(&job_topic, qos, true, payload)
+                        }
+                        HostEvent::McuError { .. } | HostEvent::Fault { .. } => {
+                            client.try_publish(&fault_topic, qos, false, payload)
+                        }
+                        HostEvent::FileListChanged { .. }
+                        | HostEvent::GCodeResponse { .. }
+                        | HostEvent::PowerChanged { .. } => continue,
+                    };
+                    if let Err(e) = result {
+                        warn!("Failed to publish {} over MQTT: {}", name, e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("MQTT publisher missed {} host events.", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    info!("MQTT publisher stopped.");
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::PrintStatus;
+    use bytes::BytesMut;
+    use rumqttc::{mqttbytes, ConnAck, ConnectReturnCode, PingResp, PubAck, Publish, SubAck, SubscribeReasonCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::timeout;
+
+    /// Broker-side end of one client connection, speaking just enough MQTT 3.1.1 for the tests.
+    struct BrokerConn {
+        stream: TcpStream,
+        buf: BytesMut,
+    }
+
+    impl BrokerConn {
+        async fn accept(listener: &TcpListener) -> Self {
+            let (stream, _) = timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+            Self { stream, buf: BytesMut::new() }
+        }
+
+        async fn send(&mut self, write: impl FnOnce(&mut BytesMut)) {
+            let mut out = BytesMut::new();
+            write(&mut out);
+            self.stream.write_all(&out).await.unwrap();
+        }
+
+        /// Reads the next packet, acknowledging publishes, subscriptions and pings.
+        async fn next(&mut self) -> Packet {
+            let packet = loop {
+                match mqttbytes::v4::read(&mut self.buf, 1 << 20) {
+                    Ok(packet) => break packet,
+                    Err(mqttbytes::Error::InsufficientBytes(_)) => {
+                        let n = timeout(Duration::from_secs(5), self.stream.read_buf(&mut self.buf))
+                            .await
+                            .unwrap()
+                            .unwrap();
+                        assert!(n > 0, "client closed the connection");
+                    }
+                    Err(e) => panic!("bad packet: {:?}", e),
+                }
+            };
+            match &packet {
+                Packet::Publish(p) if p.qos == QoS::AtLeastOnce => {
+                    let ack = PubAck::new(p.pkid);
+                    self.send(|out| { ack.write(out).unwrap(); }).await;
+                }
+                Packet::Subscribe(s) => {
+                    let ack = SubAck::new(s.pkid, vec![SubscribeReasonCode::Success(QoS::AtLeastOnce)]);
+                    self.send(|out| { ack.write(out).unwrap(); }).await;
+                }
+                Packet::PingReq => self.send(|out| { PingResp.write(out).unwrap(); }).await,
+                _ => {}
+            }
+            packet
+        }
+
+        /// Completes the handshake and returns the client's last will.
+        async fn connect(&mut self) -> LastWill {
+            let Packet::Connect(connect) = self.next().await else { panic!("expected CONNECT") };
+            let ack = ConnAck::new(ConnectReturnCode::Success, false);
+            self.send(|out| { ack.write(out).unwrap(); }).await;
+            connect.last_will.expect("client must register a last will")
+        }
+
+        /// Collects publishes until `topic` is seen; returns every publish read.
+        async fn until_publish(&mut self, topic: &str) -> Vec<Publish> {
+            let mut seen = Vec::new();
+            loop {
+                if let Packet::Publish(p) = self.next().await {
+                    let done = p.topic == topic;
+                    seen.push(p);
+                    if done {
+                        return seen;
+                    }
+                }
+            }
+        }
+
+        /// Reads until the client has subscribed to `topic`, returning publishes seen on the way.
+        async fn until_subscribed(&mut self, topic: &str) -> Vec<Publish> {
+            let mut seen = Vec::new();
+            loop {
+                match self.next().await {
+                    Packet::Subscribe(s) if s.filters.iter().any(|f| f.path == topic) => return seen,
+                    Packet::Publish(p) => seen.push(p),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn test_config(port: u16) -> MqttConfig {
+        MqttConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            topic_prefix: "shop/printer1".to_string(),
+            reconnect_delay_ms: 50,
+            telemetry_interval_ms: 0,
+            ..MqttConfig::default()
+        }
+    }
+
+    #[test]
+    fn topics_are_joined_under_the_prefix() {
+        let mut config = MqttConfig { topic_prefix: "shop/printer1/".to_string(), ..MqttConfig::default() };
+        assert_eq!(config.topic(&config.topics.job), "shop/printer1/job");
+        config.topic_prefix.clear();
+        assert_eq!(config.topic("status"), "status");
+    }
+
+    #[tokio::test]
+    async fn publishes_state_answers_commands_and_survives_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = test_config(listener.local_addr().unwrap().port());
+        let (telemetry_tx, _) = broadcast::channel(16);
+        let (event_tx, _) = broadcast::channel(16);
+        let client = tokio::spawn(run_client(
+            config,
+            telemetry_tx.subscribe(),
+            event_tx.subscribe(),
+            |payload| async move {
+                let request: Value = serde_json::from_slice(&payload).unwrap();
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["method"] })
+            },
+        ));
+
+        let mut conn = BrokerConn::accept(&listener).await;
+        let will = conn.connect().await;
+        assert_eq!(will.topic, "shop/printer1/status");
+        assert_eq!(&will.message[..], b"offline");
+        assert!(will.retain);
+
+        let published = conn.until_subscribed("shop/printer1/command").await;
+        let status = published.iter().find(|p| p.topic == "shop/printer1/status").unwrap();
+        assert_eq!(&status.payload[..], b"online");
+        assert!(status.retain);
+
+        telemetry_tx.send(json!({ "nozzle_temp": 215.0 })).unwrap();
+        let telemetry = conn.until_publish("shop/printer1/telemetry").await.pop().unwrap();
+        assert!(!telemetry.retain);
+        let sample: Value = serde_json::from_slice(&telemetry.payload).unwrap();
+        assert_eq!(sample["nozzle_temp"], 215.0);
+
+        event_tx.send(HostEvent::job("benchy.gcode", PrintStatus::InProgress)).unwrap();
+        let job = conn.until_publish("shop/printer1/job").await.pop().unwrap();
+        assert!(job.retain);
+        let state: Value = serde_json::from_slice(&job.payload).unwrap();
+        assert_eq!(state["event"], "job_state_changed");
+        assert_eq!(state["status"], "in_progress");
+
+        let mut command = Publish::new(
+            "shop/printer1/command",
+            QoS::AtLeastOnce,
+            r#"{"id":7,"method":"printer.print.pause","api_key":"k"}"#,
+        );
+        command.pkid = 1;
+        conn.send(|out| { command.write(out).unwrap(); }).await;
+        let response = conn.until_publish("shop/printer1/response").await.pop().unwrap();
+        let reply: Value = serde_json::from_slice(&response.payload).unwrap();
+        assert_eq!(reply["id"], 7);
+        assert_eq!(reply["result"], "printer.print.pause");
+
+        // Drop the connection: the client must reconnect, resubscribe and restore retained state.
+        drop(conn);
+        let mut conn = BrokerConn::accept(&listener).await;
+        conn.connect().await;
+        let mut published = conn.until_subscribed("shop/printer1/command").await;
+        if !published.iter().any(|p| p.topic == "shop/printer1/job") {
+            published.extend(conn.until_publish("shop/printer1/job").await);
+        }
+        assert!(published.iter().any(|p| p.topic == "shop/printer1/status" && &p.payload[..] == b"online"));
+        let job = published.iter().find(|p| p.topic == "shop/printer1/job").unwrap();
+        assert!(job.retain);
+
+        client.abort();
+    }
+}
@@ -0,0 +1,414 @@
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use crate::events::HostEvent;
+
+// --- Configuration ---
+
+const DEFAULT_BODY: &str = r#"{"event": "{event}", "message": "{message}", "timestamp": "{timestamp}"}"#;
+
+/// Longest wait between delivery attempts, however many retries are configured.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// One `[[notifications]]` entry of the machine config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    /// Event names to deliver (`job_started`, `job_completed`, `job_failed`, `job_cancelled`,
+    /// `mcu_error`, `fault`, `filelist_changed`, `power_changed`, `gcode_response`), or `*` for
+    /// all of them but `gcode_response`, which fires for every answered command.
+    pub events: Vec<String>,
+    /// JSON body template. `{event}`, `{printer}`, `{message}`, `{file}`, `{status}`, `{fault}`
+    /// and `{timestamp}` are replaced with JSON-escaped values.
+    pub body: String,
+    pub headers: BTreeMap<String, String>,
+    pub max_retries: u32,
+    pub retry_delay_ms: u64,
+    /// At most `rate_limit` deliveries per `rate_period_secs`; extra events are dropped.
+    pub rate_limit: usize,
+    pub rate_period_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            url: String::new(),
+            events: vec!["job_failed".to_string(), "mcu_error".to_string(), "fault".to_string()],
+            body: DEFAULT_BODY.to_string(),
+            headers: BTreeMap::new(),
+            max_retries: 3,
+            retry_delay_ms: 1000,
+            rate_limit: 10,
+            rate_period_secs: 60,
+        }
+    }
+}
+
+// --- Templating ---
+
+/// Escapes a value for substitution inside a JSON string literal.
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+pub fn render_template(template: &str, printer: &str, event: &HostEvent) -> String {
+    let (file, status, fault) = match event {
+        HostEvent::JobStateChanged { file_path, status, .. } => (file_path.clone(), status.as_str().to_string(), String::new()),
+        HostEvent::Fault { code, .. } => (String::new(), String::new(), format!("{:?}", code)),
+        HostEvent::McuError { .. }
+        | HostEvent::FileListChanged { .. }
+        | HostEvent::GCodeResponse { .. }
+        | HostEvent::PowerChanged { .. } => {
+            (String::new(), String::new(), String::new())
+        }
+    };
+    let values = [
+        ("{event}", event.name().to_string()),
+        ("{printer}", printer.to_string()),
+        ("{message}", event.message()),
+        ("{file}", file),
+        ("{status}", status),
+        ("{fault}", fault),
+        ("{timestamp}", event.timestamp().to_rfc3339()),
+    ];
+    // One pass over the template, so placeholders inside substituted values stay as they are.
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        body.push_str(&rest[..open]);
+        rest = &rest[open..];
+        match values.iter().find(|(placeholder, _)| rest.starts_with(placeholder)) {
+            Some((placeholder, value)) => {
+                body.push_str(&json_escape(value));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                body.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    body.push_str(rest);
+    body
+}
+
+/// Wait before retry `attempt` (from 0): `base_ms` doubled per attempt, up to `MAX_RETRY_DELAY`.
+fn retry_delay(base_ms: u64, attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+    Duration::from_millis(base_ms.saturating_mul(factor)).min(MAX_RETRY_DELAY)
+}
+
+// --- Rate Limiting ---
+
+/// Sliding-window limiter: at most `limit` events per `period`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: usize,
+    period: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize, period: Duration) -> Self {
+        Self { limit, period, sent: VecDeque::new() }
+    }
+
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        while self.sent.front().is_some_and(|t| now.duration_since(*t) >= self.period) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() < self.limit {
+            self.sent.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// --- Webhook Targets ---
+
+pub struct WebhookTarget {
+    config: WebhookConfig,
+    client: Client,
+    limiter: Mutex<RateLimiter>,
+}
+
+impl WebhookTarget {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let limiter = RateLimiter::new(config.rate_limit, Duration::from_secs(config.rate_period_secs));
+        Ok(Self { config, client, limiter: Mutex::new(limiter) })
+    }
+
+    pub fn accepts(&self, event: &HostEvent) -> bool {
+        let chatty = matches!(event, HostEvent::GCodeResponse { .. });
+        self.config.events.iter().any(|e| (e == "*" && !chatty) || e == event.name())
+    }
+
+    /// POSTs `body`, retrying with capped exponential backoff on connection errors, 5xx and 429.
+    pub async fn deliver(&self, body: String) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .post(&self.config.url)
+                .header("Content-Type", "application/json")
+                .body(body.clone());
+            for (key, value) in &self.config.headers {
+                request = request.header(key, value);
+            }
+
+            let retryable = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    warn!("Webhook {} returned {}", self.config.name, status);
+                    if !(status.is_server_error() || status.as_u16() == 429) {
+                        return Err(anyhow!("Webhook {} rejected notification: {}", self.config.name, status));
+                    }
+                    true
+                }
+                Err(e) => {
+                    warn!("Webhook {} delivery failed: {}", self.config.name, e);
+                    true
+                }
+            };
+
+            if !retryable || attempt >= self.config.max_retries {
+                return Err(anyhow!("Webhook {} gave up after {} attempts", self.config.name, attempt + 1));
+            }
+            tokio::time::sleep(retry_delay(self.config.retry_delay_ms, attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    async fn notify(self: Arc<Self>, printer: &str, event: HostEvent) {
+        if !self.limiter.lock().await.try_acquire(Instant::now()) {
+            warn!("Webhook {} rate limited, dropping {} notification.", self.config.name, event.name());
+            return;
+        }
+        let body = render_template(&self.config.body, printer, &event);
+        if serde_json::from_str::<serde_json::Value>(&body).is_err() {
+            warn!("Webhook {} body template does not render valid JSON.", self.config.name);
+        }
+        // Deliver in the background so a slow endpoint never delays other targets or events.
+        tokio::spawn(async move {
+            if let Err(e) = self.deliver(body).await {
+                error!("{:?}", e);
+            }
+        });
+    }
+}
+
+/// Delivers every matching event from the printers' event buses to the configured webhook
+/// targets. Targets are shared, so rate limits apply across the whole fleet.
+pub async fn run_notifier(configs: Vec<WebhookConfig>, printers: Vec<(String, broadcast::Sender<HostEvent>)>) {
+    let targets: Vec<Arc<WebhookTarget>> = configs
+        .into_iter()
+        .filter_map(|config| match WebhookTarget::new(config.clone()) {
+            Ok(target) => Some(Arc::new(target)),
+            Err(e) => {
+                error!("Invalid webhook {}: {:?}", config.name, e);
+                None
+            }
+        })
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+    info!("Notifier delivering to {} webhook target(s).", targets.len());
+
+    let (tx, mut events) = mpsc::unbounded_channel();
+    for (printer, event_bus) in printers {
+        let mut rx = event_bus.subscribe();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if tx.send((printer.clone(), event)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Notifier lagged, skipped {} events of {}.", n, printer);
+                    }
+                    Err(_) => break, // Event bus closed
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    while let Some((printer, event)) = events.recv().await {
+        for target in targets.iter().filter(|t| t.accepts(&event)) {
+            target.clone().notify(&printer, event.clone()).await;
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::FaultCode;
+    use crate::db::models::PrintStatus;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP stand-in: answers each request with the next status from `statuses`
+    /// (200 once exhausted) and reports the request bodies it received.
+    async fn stand_in_server(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                            .and_then(|l| l.trim().parse().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                let status = statuses.next().unwrap_or(200);
+                let _ = tx.send(body);
+                let response = format!("HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn target(url: String) -> WebhookConfig {
+        WebhookConfig {
+            name: "test".to_string(),
+            url,
+            retry_delay_ms: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template_escapes_values() {
+        let event = HostEvent::mcu_error("heater \"extruder\" not heating\nat expected rate");
+        let body = render_template(r#"{"text": "{event}: {message}"}"#, "default", &event);
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["text"], "mcu_error: heater \"extruder\" not heating\nat expected rate");
+
+        let event = HostEvent::job("./uploads/cube.gcode", PrintStatus::Failed);
+        let body = render_template(r#"{"file": "{file}", "status": "{status}", "printer": "{printer}"}"#, "mk4-02", &event);
+        assert_eq!(body, r#"{"file": "./uploads/cube.gcode", "status": "failed", "printer": "mk4-02"}"#);
+
+        // Placeholders in a substituted value are not expanded again.
+        let event = HostEvent::mcu_error("bad {file} in {printer}");
+        let body = render_template(r#"{"text": "{message}", "printer": "{printer}"} {x}"#, "mk4-02", &event);
+        assert_eq!(body, r#"{"text": "bad {file} in {printer}", "printer": "mk4-02"} {x}"#);
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_a_cap() {
+        assert_eq!(retry_delay(10, 0), Duration::from_millis(10));
+        assert_eq!(retry_delay(10, 2), Duration::from_millis(40));
+        assert_eq!(retry_delay(1000, 20), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(1000, 64), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u64::MAX, 1), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_event_filters() {
+        let hook = WebhookTarget::new(target("http://localhost/".to_string())).unwrap();
+        assert!(hook.accepts(&HostEvent::job("a.gcode", PrintStatus::Failed)));
+        assert!(!hook.accepts(&HostEvent::job("a.gcode", PrintStatus::Completed)));
+        assert!(hook.accepts(&HostEvent::fault(FaultCode::ThermalRunaway, "extruder")));
+
+        let all = WebhookTarget::new(WebhookConfig { events: vec!["*".to_string()], ..target(String::new()) }).unwrap();
+        assert!(all.accepts(&HostEvent::job("a.gcode", PrintStatus::Completed)));
+        assert!(!all.accepts(&HostEvent::gcode_response("ok")), "`*` skips G-code responses");
+    }
+
+    #[test]
+    fn test_rate_limiter_window() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(30)));
+        assert!(limiter.try_acquire(start + Duration::from_secs(61)));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_retries_server_errors() {
+        let (url, mut bodies) = stand_in_server(vec![500, 503]).await;
+        let hook = WebhookTarget::new(target(url)).unwrap();
+
+        hook.deliver(r#"{"event": "fault"}"#.to_string()).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(bodies.recv().await.unwrap(), r#"{"event": "fault"}"#);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery_gives_up_on_client_error() {
+        let (url, mut bodies) = stand_in_server(vec![404]).await;
+        let hook = WebhookTarget::new(target(url)).unwrap();
+
+        assert!(hook.deliver("{}".to_string()).await.is_err());
+        assert_eq!(bodies.recv().await.unwrap(), "{}");
+        assert!(bodies.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_notifier_filters_and_rate_limits_events() {
+        let (url, mut bodies) = stand_in_server(vec![]).await;
+        let config = WebhookConfig {
+            rate_limit: 2,
+            body: r#"{"event": "{event}", "fault": "{fault}"}"#.to_string(),
+            ..target(url)
+        };
+        let (event_bus, _) = broadcast::channel(16);
+        tokio::spawn(run_notifier(vec![config], vec![("default".to_string(), event_bus.clone())]));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        event_bus.send(HostEvent::job("a.gcode", PrintStatus::Completed)).unwrap(); // Filtered out
+        event_bus.send(HostEvent::fault(FaultCode::ThermalRunaway, "extruder")).unwrap();
+        event_bus.send(HostEvent::fault(FaultCode::EmergencyStop, "button")).unwrap();
+        event_bus.send(HostEvent::mcu_error("third event")).unwrap(); // Rate limited
+
+        let mut received = vec![bodies.recv().await.unwrap(), bodies.recv().await.unwrap()];
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                r#"{"event": "fault", "fault": "EmergencyStop"}"#,
+                r#"{"event": "fault", "fault": "ThermalRunaway"}"#,
+            ]
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(bodies.try_recv().is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! GPIO power devices through the Linux sysfs interface or the GPIO character device.
+
+use async_trait::async_trait;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+use super::PowerBackend;
+use crate::db::HostError;
+
+pub const SYSFS_ROOT: &str = "/sys/class/gpio";
+
+fn gpio_error(what: &str, e: std::io::Error) -> HostError {
+    HostError::Other(format!("GPIO {}: {}", what, e))
+}
+
+/// A line under `/sys/class/gpio`, exported and set to output on first use.
+pub struct SysfsGpio {
+    root: PathBuf,
+    pin: u32,
+    inverted: bool,
+}
+
+impl SysfsGpio {
+    pub fn new(root: PathBuf, pin: u32, inverted: bool) -> Self {
+        Self { root, pin, inverted }
+    }
+
+    async fn line_dir(&self) -> Result<PathBuf, HostError> {
+        let dir = self.root.join(format!("gpio{}", self.pin));
+        if !fs::try_exists(&dir).await.unwrap_or(false) {
+            fs::write(self.root.join("export"), self.pin.to_string())
+                .await
+                .map_err(|e| gpio_error(&format!("export {}", self.pin), e))?;
+        }
+        Ok(dir)
+    }
+}
+
+#[async_trait]
+impl PowerBackend for SysfsGpio {
+    async fn set(&self, on: bool) -> Result<(), HostError> {
+        let dir = self.line_dir().await?;
+        // `high`/`low` sets the direction and value together, without a glitch.
+        let level = if on != self.inverted { "high" } else { "low" };
+        fs::write(dir.join("direction"), level)
+            .await
+            .map_err(|e| gpio_error(&format!("set {}", self.pin), e))
+    }
+
+    async fn is_on(&self) -> Result<bool, HostError> {
+        let value = fs::read_to_string(self.line_dir().await?.join("value"))
+            .await
+            .map_err(|e| gpio_error(&format!("read {}", self.pin), e))?;
+        Ok((value.trim() == "1") != self.inverted)
+    }
+}
+
+// Linux GPIO character device ABI v1, see `include/uapi/linux/gpio.h`.
+const GPIO_GET_LINEHANDLE_IOCTL: u64 = 0xC16C_B403;
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: u64 = 0xC040_B409;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+const GPIOHANDLES_MAX: usize = 64;
+
+#[repr(C)]
+struct GpioHandleRequest {
+    lineoffsets: [u32; GPIOHANDLES_MAX],
+    flags: u32,
+    default_values: [u8; GPIOHANDLES_MAX],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+#[repr(C)]
+struct GpioHandleData {
+    values: [u8; GPIOHANDLES_MAX],
+}
+
+/// A line of `/dev/gpiochipN`. The kernel releases a line when its handle is closed, so the
+/// handle is claimed on first use, with `initial_state`, and held from then on.
+pub struct ChardevGpio {
+    chip: PathBuf,
+    pin: u32,
+    inverted: bool,
+    initial_state: bool,
+    /// The line handle and the state it was last set to.
+    line: Mutex<Option<(File, bool)>>,
+}
+
+impl ChardevGpio {
+    pub fn new(chip: PathBuf, pin: u32, inverted: bool, initial_state: bool) -> Self {
+        Self {
+            chip,
+            pin,
+            inverted,
+            initial_state,
+            line: Mutex::new(None),
+        }
+    }
+
+    fn level(&self, on: bool) -> u8 {
+        (on != self.inverted) as u8
+    }
+
+    fn request_line(&self, on: bool) -> Result<File, HostError> {
+        let chip = File::open(&self.chip).map_err(|e| gpio_error(&self.chip.display().to_string(), e))?;
+        let mut request = GpioHandleRequest {
+            lineoffsets: [0; GPIOHANDLES_MAX],
+            flags: GPIOHANDLE_REQUEST_OUTPUT,
+            default_values: [0; GPIOHANDLES_MAX],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        request.lineoffsets[0] = self.pin;
+        request.default_values[0] = self.level(on);
+        request.consumer_label[..7].copy_from_slice(b"r_klipp");
+        // SAFETY: `request` matches the kernel's `struct gpiohandle_request` and outlives the call.
+        let result = unsafe { libc::ioctl(chip.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL as _, &mut request) };
+        if result < 0 {
+            return Err(gpio_error(&format!("request line {}", self.pin), std::io::Error::last_os_error()));
+        }
+        // SAFETY: on success the kernel returns a new file descriptor that nothing else owns.
+        Ok(unsafe { <File as std::os::fd::FromRawFd>::from_raw_fd(request.fd) })
+    }
+}
+
+#[async_trait]
+impl PowerBackend for ChardevGpio {
+    async fn set(&self, on: bool) -> Result<(), HostError> {
+        let mut line = self.line.lock().await;
+        match line.as_mut() {
+            None => *line = Some((self.request_line(on)?, on)),
+            Some((handle, state)) => {
+                let mut data = GpioHandleData { values: [0; GPIOHANDLES_MAX] };
+                data.values[0] = self.level(on);
+                // SAFETY: `data` matches the kernel's `struct gpiohandle_data` and outlives the call.
+                let result = unsafe { libc::ioctl(handle.as_raw_fd(), GPIOHANDLE_SET_LINE_VALUES_IOCTL as _, &mut data) };
+                if result < 0 {
+                    return Err(gpio_error(&format!("set {}", self.pin), std::io::Error::last_os_error()));
+                }
+                *state = on;
+            }
+        }
+        Ok(())
+    }
+
+    async fn is_on(&self) -> Result<bool, HostError> {
+        let mut line = self.line.lock().await;
+        if line.is_none() {
+            *line = Some((self.request_line(self.initial_state)?, self.initial_state));
+        }
+        Ok(line.as_ref().is_some_and(|(_, on)| *on))
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sysfs_lines_are_exported_and_driven() {
+        let root = std::env::temp_dir().join(format!("r_klipp_gpio_{}", uuid::Uuid::new_v4().simple()));
+        tokio::fs::create_dir_all(root.join("gpio17")).await.unwrap();
+        tokio::fs::write(root.join("gpio17/value"), "1\n").await.unwrap();
+
+        let gpio = SysfsGpio::new(root.clone(), 17, true);
+        assert!(!gpio.is_on().await.unwrap(), "an inverted line is off when high");
+        gpio.set(true).await.unwrap();
+        assert_eq!(tokio::fs::read_to_string(root.join("gpio17/direction")).await.unwrap(), "low");
+
+        // Lines that are not exported yet are exported first.
+        let gpio = SysfsGpio::new(root.clone(), 22, false);
+        assert!(gpio.set(true).await.is_err(), "the fake sysfs does not create gpio22");
+        assert_eq!(tokio::fs::read_to_string(root.join("export")).await.unwrap(), "22");
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
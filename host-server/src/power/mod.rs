@@ -0,0 +1,628 @@
+mod gpio;
+
+use async_trait::async_trait;
+use log::{error, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Instant;
+
+use crate::db::models::PrintStatus;
+use crate::db::HostError;
+use crate::events::{self, HostEvent};
+
+pub use gpio::{ChardevGpio, SysfsGpio};
+
+// --- Configuration ---
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One `[[power]]` entry of the machine config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerDeviceConfig {
+    pub name: String,
+    /// Printer whose jobs drive the device; defaults to the first printer.
+    #[serde(default)]
+    pub printer: Option<String>,
+    #[serde(flatten)]
+    pub backend: PowerBackendConfig,
+    /// Switch on before the job queue starts a job on the printer.
+    #[serde(default)]
+    pub on_when_job_queued: bool,
+    /// Switch off this many seconds after a job on the printer ends, unless another starts.
+    #[serde(default)]
+    pub off_when_idle_secs: Option<u64>,
+    /// Refuse to switch off while the printer runs a job.
+    #[serde(default)]
+    pub locked_while_printing: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PowerBackendConfig {
+    /// A GPIO line. Uses the character device `/dev/<chip>` when `chip` is set (e.g.
+    /// `gpiochip0`), the sysfs interface otherwise.
+    Gpio {
+        pin: u32,
+        #[serde(default)]
+        chip: Option<String>,
+        #[serde(default)]
+        inverted: bool,
+        /// State the chardev line is claimed with; sysfs lines keep their current state.
+        #[serde(default)]
+        initial_state: bool,
+    },
+    Tasmota {
+        address: String,
+        #[serde(default = "default_tasmota_output")]
+        output_id: u32,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Shelly Gen1 relay API.
+    Shelly {
+        address: String,
+        #[serde(default)]
+        output_id: u32,
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Any relay that switches on a GET request.
+    Http {
+        on_url: String,
+        off_url: String,
+        /// Without one, the last state set by host-server is reported.
+        #[serde(default)]
+        status_url: Option<String>,
+        /// Text in the status response that means on; otherwise `on`, `1` or `true`.
+        #[serde(default)]
+        on_response: Option<String>,
+    },
+    /// Shell commands. `status` prints `on`, `1` or `true` when the device is on.
+    Command {
+        on: String,
+        off: String,
+        #[serde(default)]
+        status: Option<String>,
+    },
+}
+
+fn default_tasmota_output() -> u32 {
+    1
+}
+
+impl PowerBackendConfig {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PowerBackendConfig::Gpio { .. } => "gpio",
+            PowerBackendConfig::Tasmota { .. } => "tasmota",
+            PowerBackendConfig::Shelly { .. } => "shelly",
+            PowerBackendConfig::Http { .. } => "http",
+            PowerBackendConfig::Command { .. } => "command",
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn PowerBackend> {
+        match self.clone() {
+            PowerBackendConfig::Gpio { pin, chip: Some(chip), inverted, initial_state } => {
+                Box::new(ChardevGpio::new(PathBuf::from("/dev").join(chip), pin, inverted, initial_state))
+            }
+            PowerBackendConfig::Gpio { pin, chip: None, inverted, .. } => {
+                Box::new(SysfsGpio::new(PathBuf::from(gpio::SYSFS_ROOT), pin, inverted))
+            }
+            PowerBackendConfig::Tasmota { address, output_id, password } => Box::new(HttpRelay::tasmota(&address, output_id, password)),
+            PowerBackendConfig::Shelly { address, output_id, user, password } => {
+                Box::new(HttpRelay::shelly(&address, output_id, user, password))
+            }
+            PowerBackendConfig::Http { on_url, off_url, status_url, on_response } => Box::new(HttpRelay {
+                on_url,
+                off_url,
+                status_url,
+                status: StatusFormat::Text(on_response),
+                auth: None,
+                client: http_client(),
+                last: Mutex::new(false),
+            }),
+            PowerBackendConfig::Command { on, off, status } => Box::new(ShellCommand { on, off, status, last: Mutex::new(false) }),
+        }
+    }
+}
+
+// --- Backends ---
+
+/// Switches one device. Implementations report the state they read back from the device.
+#[async_trait]
+pub trait PowerBackend: Send + Sync {
+    async fn set(&self, on: bool) -> Result<(), HostError>;
+    async fn is_on(&self) -> Result<bool, HostError>;
+}
+
+fn http_client() -> Client {
+    Client::builder().timeout(HTTP_TIMEOUT).build().unwrap_or_default()
+}
+
+/// `on`, `1` or `true`, ignoring case and surrounding whitespace.
+fn truthy(text: &str) -> bool {
+    matches!(text.trim().to_ascii_lowercase().as_str(), "on" | "1" | "true")
+}
+
+/// How a relay reports its state.
+enum StatusFormat {
+    /// `{"POWER1": "ON"}`, or `{"POWER": "ON"}` on single-relay devices.
+    Tasmota,
+    /// `{"ison": true}`
+    Shelly,
+    /// Contains the given text, or is `truthy`.
+    Text(Option<String>),
+}
+
+impl StatusFormat {
+    fn parse(&self, body: &str) -> Result<bool, HostError> {
+        let json = || serde_json::from_str::<serde_json::Value>(body).map_err(|e| HostError::Other(format!("Unexpected relay response: {}", e)));
+        match self {
+            StatusFormat::Tasmota => Ok(json()?
+                .as_object()
+                .into_iter()
+                .flatten()
+                .any(|(key, value)| key.starts_with("POWER") && value.as_str().is_some_and(|v| v.eq_ignore_ascii_case("on")))),
+            StatusFormat::Shelly => Ok(json()?["ison"].as_bool().unwrap_or(false)),
+            StatusFormat::Text(Some(on)) => Ok(body.contains(on.as_str())),
+            StatusFormat::Text(None) => Ok(truthy(body)),
+        }
+    }
+}
+
+/// A relay switched over HTTP: Tasmota, Shelly or a generic URL pair.
+pub struct HttpRelay {
+    on_url: String,
+    off_url: String,
+    status_url: Option<String>,
+    status: StatusFormat,
+    auth: Option<(String, Option<String>)>,
+    client: Client,
+    /// Reported when there is no status URL.
+    last: Mutex<bool>,
+}
+
+impl HttpRelay {
+    pub fn tasmota(address: &str, output_id: u32, password: Option<String>) -> Self {
+        let base = match password {
+            Some(password) => format!("http://{}/cm?user=admin&password={}&cmnd=Power{}", address, password, output_id),
+            None => format!("http://{}/cm?cmnd=Power{}", address, output_id),
+        };
+        Self {
+            on_url: format!("{}%20On", base),
+            off_url: format!("{}%20Off", base),
+            status_url: Some(base),
+            status: StatusFormat::Tasmota,
+            auth: None,
+            client: http_client(),
+            last: Mutex::new(false),
+        }
+    }
+
+    pub fn shelly(address: &str, output_id: u32, user: Option<String>, password: Option<String>) -> Self {
+        let base = format!("http://{}/relay/{}", address, output_id);
+        Self {
+            on_url: format!("{}?turn=on", base),
+            off_url: format!("{}?turn=off", base),
+            status_url: Some(base),
+            status: StatusFormat::Shelly,
+            auth: user.map(|user| (user, password)),
+            client: http_client(),
+            last: Mutex::new(false),
+        }
+    }
+
+    async fn get(&self, url: &str) -> Result<String, HostError> {
+        let mut request = self.client.get(url);
+        if let Some((user, password)) = &self.auth {
+            request = request.basic_auth(user, password.as_ref());
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| HostError::Other(format!("Relay request failed: {}", e)))?;
+        response.text().await.map_err(|e| HostError::Other(format!("Relay request failed: {}", e)))
+    }
+}
+
+#[async_trait]
+impl PowerBackend for HttpRelay {
+    async fn set(&self, on: bool) -> Result<(), HostError> {
+        self.get(if on { &self.on_url } else { &self.off_url }).await?;
+        *self.last.lock().await = on;
+        Ok(())
+    }
+
+    async fn is_on(&self) -> Result<bool, HostError> {
+        match &self.status_url {
+            Some(url) => self.status.parse(&self.get(url).await?),
+            None => Ok(*self.last.lock().await),
+        }
+    }
+}
+
+/// A device switched by shell commands run with `sh -c`.
+pub struct ShellCommand {
+    on: String,
+    off: String,
+    status: Option<String>,
+    /// Reported when there is no status command.
+    last: Mutex<bool>,
+}
+
+impl ShellCommand {
+    async fn run(command: &str) -> Result<String, HostError> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .await
+            .map_err(|e| HostError::Other(format!("Failed to run {:?}: {}", command, e)))?;
+        if !output.status.success() {
+            return Err(HostError::Other(format!(
+                "{:?} failed with {}: {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[async_trait]
+impl PowerBackend for ShellCommand {
+    async fn set(&self, on: bool) -> Result<(), HostError> {
+        Self::run(if on { &self.on } else { &self.off }).await?;
+        *self.last.lock().await = on;
+        Ok(())
+    }
+
+    async fn is_on(&self) -> Result<bool, HostError> {
+        match &self.status {
+            Some(command) => Ok(truthy(&Self::run(command).await?)),
+            None => Ok(*self.last.lock().await),
+        }
+    }
+}
+
+// --- Devices ---
+
+/// A device as reported to clients; also the payload of `notify_power_changed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PowerDeviceInfo {
+    pub device: String,
+    /// `on`, `off` or `error`.
+    pub status: String,
+    pub locked_while_printing: bool,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+pub struct PowerDevice {
+    pub name: String,
+    pub printer: String,
+    pub kind: &'static str,
+    pub on_when_job_queued: bool,
+    pub off_when_idle: Option<Duration>,
+    pub locked_while_printing: bool,
+    backend: Box<dyn PowerBackend>,
+    /// The bound printer's event bus, for `power_changed` events.
+    events: broadcast::Sender<HostEvent>,
+}
+
+impl PowerDevice {
+    fn info(&self, status: &str) -> PowerDeviceInfo {
+        PowerDeviceInfo {
+            device: self.name.clone(),
+            status: status.to_string(),
+            locked_while_printing: self.locked_while_printing,
+            kind: self.kind.to_string(),
+        }
+    }
+}
+
+fn status_name(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// Every power device of the host, and which printers are running a job.
+pub struct PowerManager {
+    devices: Vec<PowerDevice>,
+    /// Printers with a job running, kept up to date by `run_power_policy`.
+    printing: std::sync::Mutex<HashSet<String>>,
+}
+
+impl PowerManager {
+    /// `printers` are the printer ids with their event buses; the first one is the default.
+    pub fn new(configs: &[PowerDeviceConfig], printers: &[(String, broadcast::Sender<HostEvent>)]) -> Self {
+        let devices = configs
+            .iter()
+            .filter_map(|config| {
+                let printer = config.printer.as_deref().unwrap_or(printers[0].0.as_str());
+                let Some((printer, events)) = printers.iter().find(|(id, _)| id == printer) else {
+                    error!("Power device {} belongs to unknown printer {}.", config.name, printer);
+                    return None;
+                };
+                Some(PowerDevice {
+                    name: config.name.clone(),
+                    printer: printer.clone(),
+                    kind: config.backend.kind(),
+                    on_when_job_queued: config.on_when_job_queued,
+                    off_when_idle: config.off_when_idle_secs.map(Duration::from_secs),
+                    locked_while_printing: config.locked_while_printing,
+                    backend: config.backend.build(),
+                    events: events.clone(),
+                })
+            })
+            .collect();
+        Self::from_devices(devices)
+    }
+
+    pub fn from_devices(devices: Vec<PowerDevice>) -> Self {
+        Self {
+            devices,
+            printing: std::sync::Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn device(&self, name: &str) -> Result<&PowerDevice, HostError> {
+        self.devices
+            .iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| HostError::NotFound(format!("No power device {}", name)))
+    }
+
+    fn is_printing(&self, printer: &str) -> bool {
+        self.printing.lock().unwrap().contains(printer)
+    }
+
+    fn set_printing(&self, printer: &str, printing: bool) {
+        let mut busy = self.printing.lock().unwrap();
+        if printing {
+            busy.insert(printer.to_string());
+        } else {
+            busy.remove(printer);
+        }
+    }
+
+    /// Current state of `name`: `on`, `off`, or `error` when the device cannot be reached.
+    pub async fn status(&self, name: &str) -> Result<&'static str, HostError> {
+        let device = self.device(name)?;
+        Ok(match device.backend.is_on().await {
+            Ok(on) => status_name(on),
+            Err(e) => {
+                warn!("Failed to read power device {}: {:?}", name, e);
+                "error"
+            }
+        })
+    }
+
+    pub async fn devices(&self) -> Vec<PowerDeviceInfo> {
+        let mut devices = Vec::with_capacity(self.devices.len());
+        for device in &self.devices {
+            let status = self.status(&device.name).await.unwrap_or("error");
+            devices.push(device.info(status));
+        }
+        devices
+    }
+
+    /// Switches `name` on or off and returns the new state. A device locked while printing
+    /// stays on while its printer runs a job.
+    pub async fn set(&self, name: &str, on: bool) -> Result<&'static str, HostError> {
+        let device = self.device(name)?;
+        if !on && device.locked_while_printing && self.is_printing(&device.printer) {
+            return Err(HostError::Conflict(format!(
+                "{} is locked while {} is printing",
+                name, device.printer
+            )));
+        }
+        device.backend.set(on).await?;
+        let status = status_name(on);
+        info!("Power device {} switched {}.", name, status);
+        events::publish(&device.events, HostEvent::power_changed(device.info(status)));
+        Ok(status)
+    }
+
+    pub async fn toggle(&self, name: &str) -> Result<&'static str, HostError> {
+        let on = self.device(name)?.backend.is_on().await?;
+        self.set(name, !on).await
+    }
+
+    /// Switches on the devices of `printer` marked `on_when_job_queued`. Failures are logged;
+    /// the job is started regardless.
+    pub async fn power_on_for_job(&self, printer: &str) {
+        for device in self.devices.iter().filter(|d| d.printer == printer && d.on_when_job_queued) {
+            match device.backend.is_on().await {
+                Ok(true) => {}
+                _ => {
+                    if let Err(e) = self.set(&device.name, true).await {
+                        error!("Failed to power on {} for a queued job: {:?}", device.name, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Tracks jobs on `printer` and switches its `off_when_idle` devices off once the printer
+/// has been idle for their delay.
+pub async fn run_power_policy(power: std::sync::Arc<PowerManager>, printer: String, mut events: broadcast::Receiver<HostEvent>) {
+    // (device index, when to switch it off)
+    let mut pending: Vec<(usize, Instant)> = Vec::new();
+    loop {
+        let next = pending.iter().map(|(_, at)| *at).min();
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(HostEvent::JobStateChanged { status, .. }) => {
+                    let printing = status == PrintStatus::InProgress;
+                    power.set_printing(&printer, printing);
+                    pending.clear();
+                    if !printing {
+                        let now = Instant::now();
+                        pending = power
+                            .devices
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, d)| d.printer == printer)
+                            .filter_map(|(index, d)| d.off_when_idle.map(|delay| (index, now + delay)))
+                            .collect();
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Power policy for {} missed {} host events.", printer, n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                let now = Instant::now();
+                for (index, _) in pending.iter().filter(|(_, at)| *at <= now) {
+                    let name = &power.devices[*index].name;
+                    info!("{} has been idle, switching {} off.", printer, name);
+                    if let Err(e) = power.set(name, false).await {
+                        error!("Failed to switch {} off: {:?}", name, e);
+                    }
+                }
+                pending.retain(|(_, at)| *at > now);
+            }
+        }
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A GPIO line that only remembers its state.
+    #[derive(Clone, Default)]
+    struct MockGpio {
+        on: Arc<AtomicBool>,
+        switches: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl PowerBackend for MockGpio {
+        async fn set(&self, on: bool) -> Result<(), HostError> {
+            self.on.store(on, Ordering::SeqCst);
+            self.switches.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn is_on(&self) -> Result<bool, HostError> {
+            Ok(self.on.load(Ordering::SeqCst))
+        }
+    }
+
+    fn device(name: &str, gpio: &MockGpio, events: &broadcast::Sender<HostEvent>) -> PowerDevice {
+        PowerDevice {
+            name: name.to_string(),
+            printer: "mk4".to_string(),
+            kind: "gpio",
+            on_when_job_queued: true,
+            off_when_idle: Some(Duration::from_millis(50)),
+            locked_while_printing: true,
+            backend: Box::new(gpio.clone()),
+            events: events.clone(),
+        }
+    }
+
+    /// Answers every HTTP request with `body` and reports the request targets.
+    async fn relay_stand_in(body: &'static str) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = vec![0; 4096];
+                let n = socket.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..n]);
+                let target = request.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let _ = tx.send(target);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        (address, rx)
+    }
+
+    #[tokio::test]
+    async fn tasmota_and_shelly_relays_use_their_http_apis() {
+        let (address, mut requests) = relay_stand_in(r#"{"POWER2":"ON"}"#).await;
+        let relay = HttpRelay::tasmota(&address, 2, None);
+        relay.set(true).await.unwrap();
+        assert_eq!(requests.recv().await.unwrap(), "/cm?cmnd=Power2%20On");
+        assert!(relay.is_on().await.unwrap());
+        assert_eq!(requests.recv().await.unwrap(), "/cm?cmnd=Power2");
+
+        let (address, mut requests) = relay_stand_in(r#"{"ison": false, "has_timer": false}"#).await;
+        let relay = HttpRelay::shelly(&address, 0, Some("admin".to_string()), Some("secret".to_string()));
+        relay.set(false).await.unwrap();
+        assert_eq!(requests.recv().await.unwrap(), "/relay/0?turn=off");
+        assert!(!relay.is_on().await.unwrap());
+    }
+
+    #[test]
+    fn relay_status_formats() {
+        assert!(StatusFormat::Tasmota.parse(r#"{"POWER":"ON"}"#).unwrap());
+        assert!(!StatusFormat::Tasmota.parse(r#"{"POWER1":"OFF"}"#).unwrap());
+        assert!(StatusFormat::Text(None).parse(" ON\n").unwrap());
+        assert!(StatusFormat::Text(Some("\"state\":1".to_string())).parse(r#"{"state":1}"#).unwrap());
+        assert!(StatusFormat::Shelly.parse("<html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn devices_follow_the_printer_jobs() {
+        let (events, _) = broadcast::channel(16);
+        let (psu, light) = (MockGpio::default(), MockGpio::default());
+        let mut lamp = device("light", &light, &events);
+        lamp.on_when_job_queued = false;
+        lamp.off_when_idle = None;
+        lamp.locked_while_printing = false;
+        let power = Arc::new(PowerManager::from_devices(vec![device("psu", &psu, &events), lamp]));
+        tokio::spawn(run_power_policy(power.clone(), "mk4".to_string(), events.subscribe()));
+        let mut notifications = events.subscribe();
+
+        power.power_on_for_job("mk4").await;
+        assert!(psu.on.load(Ordering::SeqCst));
+        assert!(!light.on.load(Ordering::SeqCst), "only devices marked on_when_job_queued switch on");
+        assert!(matches!(notifications.recv().await.unwrap(), HostEvent::PowerChanged { .. }));
+        power.power_on_for_job("mk4").await;
+        assert_eq!(psu.switches.load(Ordering::SeqCst), 1, "a device that is on is left alone");
+
+        events.send(HostEvent::job("a.gcode", PrintStatus::InProgress)).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(matches!(power.set("psu", false).await, Err(HostError::Conflict(_))));
+        assert_eq!(power.toggle("light").await.unwrap(), "on");
+
+        events.send(HostEvent::job("a.gcode", PrintStatus::Completed)).unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(psu.on.load(Ordering::SeqCst), "still within the idle delay");
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        assert!(!psu.on.load(Ordering::SeqCst));
+        assert!(light.on.load(Ordering::SeqCst));
+        assert!(matches!(power.status("fan").await, Err(HostError::NotFound(_))));
+    }
+}
@@ -0,0 +1,380 @@
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::DEFAULT_PRINTER_ID;
+use crate::db::models::{ActiveSpool, Spool};
+use crate::db::{Database, HostError};
+
+/// The extruder a spool is set for when a request does not name one.
+pub const DEFAULT_EXTRUDER: &str = "extruder";
+
+/// mm of filament in `weight` grams.
+pub fn length_for_weight(weight: f32, diameter: f32, density: f32) -> f32 {
+    let area = PI * (diameter / 2.0).powi(2); // mm²
+    weight / density * 1000.0 / area
+}
+
+/// Grams of filament in `length` mm.
+pub fn weight_for_length(length: f32, diameter: f32, density: f32) -> f32 {
+    let area = PI * (diameter / 2.0).powi(2);
+    length * area * density / 1000.0
+}
+
+impl Spool {
+    pub fn remaining_length(&self) -> f32 {
+        length_for_weight(self.remaining_weight, self.diameter, self.density)
+    }
+
+    /// The Spoolman representation. This inventory has no separate filament and vendor
+    /// records, so each spool carries its own, sharing the spool id.
+    pub fn to_spoolman(&self) -> Value {
+        let used_weight = self.initial_weight - self.remaining_weight;
+        json!({
+            "id": self.spool_id,
+            "registered": self.registered,
+            "first_used": self.first_used,
+            "last_used": self.last_used,
+            "filament": {
+                "id": self.spool_id,
+                "registered": self.registered,
+                "name": self.name,
+                "vendor": self.vendor.as_ref().map(|name| json!({ "id": self.spool_id, "name": name })),
+                "material": self.material,
+                "density": self.density,
+                "diameter": self.diameter,
+                "weight": self.initial_weight,
+                "color_hex": self.color_hex,
+            },
+            "initial_weight": self.initial_weight,
+            "remaining_weight": self.remaining_weight,
+            "used_weight": used_weight,
+            "remaining_length": self.remaining_length(),
+            "used_length": length_for_weight(used_weight, self.diameter, self.density),
+            "lot_nr": self.lot_nr,
+            "comment": self.comment,
+            "archived": self.archived,
+        })
+    }
+}
+
+/// The warning for starting a job that needs `required` mm of filament from `spool`.
+pub fn shortage_warning(spool: &Spool, extruder: &str, required: f32) -> Option<String> {
+    let remaining = spool.remaining_length();
+    (required > remaining).then(|| {
+        format!(
+            "The job needs {:.2} m of filament but spool {} in {} only has {:.2} m left",
+            required / 1000.0,
+            spool.spool_id,
+            extruder,
+            remaining.max(0.0) / 1000.0
+        )
+    })
+}
+
+/// Fields of a new spool, or the changed fields of an existing one.
+#[derive(Debug, Default, Deserialize)]
+pub struct SpoolFields {
+    pub name: Option<String>,
+    pub material: Option<String>,
+    pub vendor: Option<String>,
+    pub color_hex: Option<String>,
+    pub diameter: Option<f32>,
+    pub density: Option<f32>,
+    pub initial_weight: Option<f32>,
+    pub remaining_weight: Option<f32>,
+    pub lot_nr: Option<String>,
+    pub comment: Option<String>,
+    pub archived: Option<bool>,
+}
+
+impl SpoolFields {
+    fn apply(self, spool: &mut Spool) -> Result<(), HostError> {
+        let positive = |name: &str, value: Option<f32>| match value {
+            Some(v) if !(v > 0.0 && v.is_finite()) => {
+                Err(HostError::InvalidRequest(format!("{} must be a positive number", name)))
+            }
+            _ => Ok(value),
+        };
+        let color_hex = self.color_hex.map(|c| c.trim_start_matches('#').to_ascii_lowercase());
+        if let Some(color) = &color_hex {
+            if color.len() != 6 || !color.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(HostError::InvalidRequest(format!("Invalid colour {}", color)));
+            }
+        }
+
+        if let Some(diameter) = positive("diameter", self.diameter)? {
+            spool.diameter = diameter;
+        }
+        if let Some(density) = positive("density", self.density)? {
+            spool.density = density;
+        }
+        if let Some(weight) = positive("initial_weight", self.initial_weight)? {
+            spool.initial_weight = weight;
+        }
+        if let Some(weight) = self.remaining_weight {
+            spool.remaining_weight = weight;
+        }
+        if let Some(material) = self.material {
+            spool.material = material;
+        }
+        spool.name = self.name.or(spool.name.take());
+        spool.vendor = self.vendor.or(spool.vendor.take());
+        spool.color_hex = color_hex.or(spool.color_hex.take());
+        spool.lot_nr = self.lot_nr.or(spool.lot_nr.take());
+        spool.comment = self.comment.or(spool.comment.take());
+        spool.archived = self.archived.unwrap_or(spool.archived);
+        Ok(())
+    }
+}
+
+/// Filament taken off a spool by hand, as length or weight.
+#[derive(Debug, Deserialize)]
+pub struct SpoolUse {
+    pub use_length: Option<f32>,
+    pub use_weight: Option<f32>,
+}
+
+/// The spool inventory, shared by every printer, and the spools loaded in this printer.
+pub struct SpoolManager {
+    printer_id: String,
+    db: Arc<Database>,
+    // Serializes read-modify-write updates, so concurrent jobs never lose usage.
+    update_lock: Mutex<()>,
+}
+
+impl SpoolManager {
+    pub fn new(printer_id: &str, db: Arc<Database>) -> Self {
+        Self {
+            printer_id: printer_id.to_string(),
+            db,
+            update_lock: Mutex::new(()),
+        }
+    }
+
+    /// Key the active spool of `extruder` is stored under; fleet printers share the database.
+    fn active_key(&self, extruder: &str) -> String {
+        if self.printer_id == DEFAULT_PRINTER_ID {
+            extruder.to_string()
+        } else {
+            format!("{}/{}", self.printer_id, extruder)
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Spool>, HostError> {
+        self.db.get_spools().await
+    }
+
+    pub async fn get(&self, spool_id: u32) -> Result<Spool, HostError> {
+        self.db
+            .get_spool(spool_id)
+            .await?
+            .ok_or_else(|| HostError::NotFound(format!("No spool {}", spool_id)))
+    }
+
+    /// Adds a spool. Material and initial weight are required; a 1.75 mm PLA spool is assumed
+    /// for anything else, and a new spool is full unless told otherwise.
+    pub async fn create(&self, fields: SpoolFields) -> Result<Spool, HostError> {
+        if fields.material.is_none() || fields.initial_weight.is_none() {
+            return Err(HostError::InvalidRequest("material and initial_weight are required".to_string()));
+        }
+        let _guard = self.update_lock.lock().await;
+        let spool_id = self.db.get_spools().await?.iter().map(|s| s.spool_id).max().unwrap_or(0) + 1;
+        let mut spool = Spool {
+            spool_id,
+            registered: Utc::now(),
+            first_used: None,
+            last_used: None,
+            name: None,
+            material: String::new(),
+            vendor: None,
+            color_hex: None,
+            diameter: 1.75,
+            density: 1.24,
+            initial_weight: 0.0,
+            remaining_weight: 0.0,
+            lot_nr: None,
+            comment: None,
+            archived: false,
+        };
+        let full = fields.remaining_weight.is_none();
+        fields.apply(&mut spool)?;
+        if full {
+            spool.remaining_weight = spool.initial_weight;
+        }
+        self.db.save_spool(spool.clone()).await?;
+        info!("Added spool {} ({} {}g).", spool.spool_id, spool.material, spool.initial_weight);
+        Ok(spool)
+    }
+
+    pub async fn update(&self, spool_id: u32, fields: SpoolFields) -> Result<Spool, HostError> {
+        let _guard = self.update_lock.lock().await;
+        let mut spool = self.get(spool_id).await?;
+        fields.apply(&mut spool)?;
+        self.db.save_spool(spool.clone()).await?;
+        Ok(spool)
+    }
+
+    pub async fn delete(&self, spool_id: u32) -> Result<Spool, HostError> {
+        self.db
+            .delete_spool(spool_id)
+            .await?
+            .ok_or_else(|| HostError::NotFound(format!("No spool {}", spool_id)))
+    }
+
+    /// Takes filament off a spool. Remaining weight may go negative when the spool was
+    /// heavier than recorded; it is not clamped so the error stays visible.
+    pub async fn use_filament(&self, spool_id: u32, usage: SpoolUse) -> Result<Spool, HostError> {
+        let _guard = self.update_lock.lock().await;
+        let mut spool = self.get(spool_id).await?;
+        let weight = match (usage.use_weight, usage.use_length) {
+            (Some(weight), _) => weight,
+            (None, Some(length)) => weight_for_length(length, spool.diameter, spool.density),
+            (None, None) => {
+                return Err(HostError::InvalidRequest("Expected use_length or use_weight".to_string()))
+            }
+        };
+        let now = Utc::now();
+        spool.remaining_weight -= weight;
+        spool.first_used = spool.first_used.or(Some(now));
+        spool.last_used = Some(now);
+        self.db.save_spool(spool.clone()).await?;
+        Ok(spool)
+    }
+
+    pub async fn active_spool(&self, extruder: &str) -> Result<Option<u32>, HostError> {
+        Ok(self.db.get_active_spool(&self.active_key(extruder)).await?.map(|a| a.spool_id))
+    }
+
+    /// Loads `spool_id` into `extruder`, or unloads it with `None`.
+    pub async fn set_active_spool(&self, extruder: &str, spool_id: Option<u32>) -> Result<(), HostError> {
+        let key = self.active_key(extruder);
+        match spool_id {
+            Some(spool_id) => {
+                self.get(spool_id).await?;
+                let active = ActiveSpool { extruder: extruder.to_string(), spool_id };
+                self.db.save_active_spool(&key, active).await?;
+                info!("Spool {} loaded in {} of {}.", spool_id, extruder, self.printer_id);
+            }
+            None => self.db.delete_active_spool(&key).await?,
+        }
+        Ok(())
+    }
+
+    /// Charges the filament a job extruded to the spools loaded in each extruder.
+    pub async fn record_extrusion(&self, extruded: Vec<(String, f32)>) {
+        for (extruder, length) in extruded {
+            let result = match self.active_spool(&extruder).await {
+                Ok(Some(spool_id)) => self
+                    .use_filament(spool_id, SpoolUse { use_length: Some(length), use_weight: None })
+                    .await
+                    .map(drop),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                error!("Failed to record {}mm of filament used by {}: {:?}", length, extruder, e);
+            }
+        }
+    }
+
+    /// A warning when the file at `file_path` needs more filament than the spool in the
+    /// first extruder has left. Files without a known filament length are never flagged.
+    pub async fn job_warning(&self, file_path: &str) -> Result<Option<String>, HostError> {
+        let Some(required) = self
+            .db
+            .get_gcode_file(file_path)
+            .await?
+            .and_then(|file| file.metadata.filament_length)
+        else {
+            return Ok(None);
+        };
+        let Some(spool_id) = self.active_spool(DEFAULT_EXTRUDER).await? else {
+            return Ok(None);
+        };
+        let warning = shortage_warning(&self.get(spool_id).await?, DEFAULT_EXTRUDER, required);
+        if let Some(warning) = &warning {
+            warn!("{}: {}", file_path, warning);
+        }
+        Ok(warning)
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spool(remaining_weight: f32) -> Spool {
+        let mut spool = Spool {
+            spool_id: 3,
+            registered: Utc::now(),
+            first_used: None,
+            last_used: None,
+            name: None,
+            material: String::new(),
+            vendor: None,
+            color_hex: None,
+            diameter: 1.0,
+            density: 1.0,
+            initial_weight: 0.0,
+            remaining_weight: 0.0,
+            lot_nr: None,
+            comment: None,
+            archived: false,
+        };
+        let fields = SpoolFields {
+            material: Some("PETG".to_string()),
+            vendor: Some("Prusament".to_string()),
+            color_hex: Some("#FF8800".to_string()),
+            diameter: Some(1.75),
+            density: Some(1.27),
+            initial_weight: Some(1000.0),
+            remaining_weight: Some(remaining_weight),
+            lot_nr: Some("B2024-117".to_string()),
+            ..SpoolFields::default()
+        };
+        fields.apply(&mut spool).unwrap();
+        spool
+    }
+
+    #[test]
+    fn weight_and_length_convert_through_the_filament_cross_section() {
+        // 1 kg of 1.75 mm PLA is about 330 m.
+        let length = length_for_weight(1000.0, 1.75, 1.24);
+        assert!((length / 1000.0 - 335.3).abs() < 0.1, "{}", length);
+        assert!((weight_for_length(length, 1.75, 1.24) - 1000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn fields_are_validated_and_normalized() {
+        let spool = spool(250.0);
+        assert_eq!(spool.color_hex.as_deref(), Some("ff8800"));
+        assert_eq!(spool.material, "PETG");
+
+        let mut copy = spool.clone();
+        let bad = SpoolFields { diameter: Some(0.0), ..SpoolFields::default() };
+        assert!(matches!(bad.apply(&mut copy), Err(HostError::InvalidRequest(_))));
+        let bad = SpoolFields { color_hex: Some("orange".to_string()), ..SpoolFields::default() };
+        assert!(matches!(bad.apply(&mut copy), Err(HostError::InvalidRequest(_))));
+
+        let json = spool.to_spoolman();
+        assert_eq!(json["id"], 3);
+        assert_eq!(json["filament"]["material"], "PETG");
+        assert_eq!(json["filament"]["vendor"]["name"], "Prusament");
+        assert_eq!(json["used_weight"], 750.0);
+        assert_eq!(json["lot_nr"], "B2024-117");
+    }
+
+    #[test]
+    fn jobs_needing_more_than_the_spool_holds_are_flagged() {
+        let spool = spool(10.0); // About 3.3 m of 1.75 mm PETG
+        assert!(shortage_warning(&spool, "extruder", 3000.0).is_none());
+        let warning = shortage_warning(&spool, "extruder", 4000.0).unwrap();
+        assert!(warning.contains("4.00 m") && warning.contains("spool 3"), "{}", warning);
+    }
+}
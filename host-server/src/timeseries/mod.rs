@@ -0,0 +1,424 @@
+use anyhow::Result;
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::{broadcast, RwLock};
+
+// --- Data Types ---
+
+/// One point returned by a query. Raw samples have `min == max == avg`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DataPoint {
+    pub time: i64, // Unix time in milliseconds (bucket start for downsampled data)
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Sample {
+    time: i64,
+    value: f32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Bucket {
+    start: i64,
+    min: f32,
+    max: f32,
+    sum: f64,
+    count: u32,
+}
+
+impl Bucket {
+    fn new(start: i64, value: f32) -> Self {
+        Self { start, min: value, max: value, sum: value as f64, count: 1 }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as f64;
+        self.count += 1;
+    }
+
+    fn point(&self) -> DataPoint {
+        DataPoint {
+            time: self.start,
+            min: self.min,
+            max: self.max,
+            avg: (self.sum / self.count as f64) as f32,
+        }
+    }
+}
+
+// --- Configuration ---
+
+/// Retention of one downsampled tier.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TierConfig {
+    pub resolution_ms: i64,
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreConfig {
+    /// Number of full-resolution samples kept per metric.
+    pub raw_capacity: usize,
+    /// Downsampled tiers, finest first.
+    pub tiers: Vec<TierConfig>,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            raw_capacity: 3600, // ~1 hour at 1 Hz telemetry
+            tiers: vec![
+                TierConfig { resolution_ms: 10_000, capacity: 2160 },  // 6 hours
+                TierConfig { resolution_ms: 60_000, capacity: 1440 },  // 24 hours
+                TierConfig { resolution_ms: 600_000, capacity: 1008 }, // 7 days
+            ],
+        }
+    }
+}
+
+/// Telemetry fields kept as metrics. Anything else in a frame, such as link counters or
+/// fields added later, is not stored, so the store only grows when this list does.
+pub const RECORDED_METRICS: [&str; 9] = [
+    "nozzle_temp",
+    "bed_temp",
+    "nozzle_target",
+    "bed_target",
+    "nozzle_pwm",
+    "bed_pwm",
+    "x_pos",
+    "y_pos",
+    "z_pos",
+];
+
+// --- Series ---
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Tier {
+    resolution_ms: i64,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+    open: Option<Bucket>,
+}
+
+impl Tier {
+    fn new(config: TierConfig) -> Self {
+        Self {
+            resolution_ms: config.resolution_ms,
+            capacity: config.capacity,
+            buckets: VecDeque::with_capacity(config.capacity),
+            open: None,
+        }
+    }
+
+    fn add(&mut self, time: i64, value: f32) {
+        let start = time - time.rem_euclid(self.resolution_ms);
+        match &mut self.open {
+            Some(bucket) if bucket.start == start => bucket.add(value),
+            Some(bucket) if start < bucket.start => {} // Out-of-order sample for a closed bucket
+            open => {
+                if let Some(closed) = open.replace(Bucket::new(start, value)) {
+                    if self.buckets.len() == self.capacity {
+                        self.buckets.pop_front();
+                    }
+                    self.buckets.push_back(closed);
+                }
+            }
+        }
+    }
+
+    /// True if the tier holds everything from `start` on: it reaches back that far, or it
+    /// has never dropped a bucket.
+    fn covers(&self, start: i64) -> bool {
+        self.buckets.len() < self.capacity
+            || self.buckets.front().or(self.open.as_ref()).is_some_and(|b| b.start <= start)
+    }
+
+    fn query(&self, start: i64, end: i64) -> Vec<DataPoint> {
+        self.buckets
+            .iter()
+            .chain(self.open.iter())
+            .filter(|b| b.start + self.resolution_ms > start && b.start <= end)
+            .map(Bucket::point)
+            .collect()
+    }
+}
+
+/// Full-resolution ring buffer plus progressively coarser min/max/avg tiers for one metric.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Series {
+    raw_capacity: usize,
+    raw: VecDeque<Sample>,
+    tiers: Vec<Tier>,
+}
+
+impl Series {
+    fn new(config: &StoreConfig) -> Self {
+        Self {
+            raw_capacity: config.raw_capacity,
+            raw: VecDeque::with_capacity(config.raw_capacity),
+            tiers: config.tiers.iter().copied().map(Tier::new).collect(),
+        }
+    }
+
+    fn add(&mut self, time: i64, value: f32) {
+        if self.raw.len() == self.raw_capacity {
+            self.raw.pop_front();
+        }
+        self.raw.push_back(Sample { time, value });
+        for tier in &mut self.tiers {
+            tier.add(time, value);
+        }
+    }
+
+    /// Answers from the finest resolution that still covers `start`. A store younger than
+    /// the query has dropped nothing yet, so it answers from the raw samples.
+    fn query(&self, start: i64, end: i64) -> Vec<DataPoint> {
+        let raw_covers = self.raw.len() < self.raw_capacity || self.raw.front().is_some_and(|s| s.time <= start);
+        if raw_covers || self.tiers.is_empty() {
+            return self
+                .raw
+                .iter()
+                .filter(|s| s.time >= start && s.time <= end)
+                .map(|s| DataPoint { time: s.time, min: s.value, max: s.value, avg: s.value })
+                .collect();
+        }
+
+        let tier = self
+            .tiers
+            .iter()
+            .find(|t| t.covers(start))
+            .unwrap_or_else(|| self.tiers.last().unwrap());
+        tier.query(start, end)
+    }
+}
+
+// --- Store ---
+
+/// In-process time-series store fed by the telemetry broadcaster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSeriesStore {
+    config: StoreConfig,
+    series: BTreeMap<String, Series>,
+}
+
+impl Default for TimeSeriesStore {
+    fn default() -> Self {
+        Self::new(StoreConfig::default())
+    }
+}
+
+impl TimeSeriesStore {
+    pub fn new(config: StoreConfig) -> Self {
+        Self { config, series: BTreeMap::new() }
+    }
+
+    pub fn insert(&mut self, metric: &str, time: i64, value: f32) {
+        if !self.series.contains_key(metric) {
+            self.series.insert(metric.to_string(), Series::new(&self.config));
+        }
+        self.series.get_mut(metric).unwrap().add(time, value);
+    }
+
+    /// Stores the fields of a telemetry frame named in `RECORDED_METRICS`.
+    pub fn record_telemetry(&mut self, time: i64, telemetry: &serde_json::Value) {
+        for name in RECORDED_METRICS {
+            if let Some(v) = telemetry[name].as_f64() {
+                self.insert(name, time, v as f32);
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Vec<&str> {
+        self.series.keys().map(String::as_str).collect()
+    }
+
+    pub fn query(&self, metric: &str, start: i64, end: i64) -> Option<Vec<DataPoint>> {
+        self.series.get(metric).map(|s| s.query(start, end))
+    }
+
+    /// Resamples the last `count` seconds of a metric to one value per second,
+    /// holding the previous value over gaps, as Moonraker's temperature store does.
+    pub fn resample_seconds(&self, metric: &str, now: i64, count: usize) -> Vec<f32> {
+        let start = now - count as i64 * 1000;
+        let points = self.query(metric, start, now).unwrap_or_default();
+        let mut values = Vec::with_capacity(count);
+        let mut next = points.iter().peekable();
+        let mut last = points.first().map(|p| p.avg).unwrap_or_default();
+        for i in 1..=count as i64 {
+            let slot_end = start + i * 1000;
+            while let Some(p) = next.next_if(|p| p.time <= slot_end) {
+                last = p.avg;
+            }
+            values.push(last);
+        }
+        values
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path).await?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        // Write then rename so a crash mid-save never leaves a truncated store behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+// --- Background Task ---
+
+/// Loads the persisted store, or starts an empty one if there is none yet.
+pub async fn open_store(path: &Path) -> TimeSeriesStore {
+    match TimeSeriesStore::load(path).await {
+        Ok(store) => {
+            info!("Loaded telemetry store from {}", path.display());
+            store
+        }
+        Err(e) => {
+            warn!("Starting empty telemetry store ({}): {}", path.display(), e);
+            TimeSeriesStore::default()
+        }
+    }
+}
+
+/// Feeds the store from the telemetry broadcaster and periodically persists it to disk.
+pub async fn run_store(
+    store: Arc<RwLock<TimeSeriesStore>>,
+    telemetry_broadcaster: broadcast::Sender<serde_json::Value>,
+    path: PathBuf,
+    persist_interval: Duration,
+) {
+    let mut rx = telemetry_broadcaster.subscribe();
+    let mut persist = tokio::time::interval(persist_interval);
+
+    loop {
+        tokio::select! {
+            telemetry = rx.recv() => match telemetry {
+                Ok(data) => {
+                    let now = Utc::now().timestamp_millis();
+                    store.write().await.record_telemetry(now, &data);
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Telemetry store lagged, skipped {} telemetry frames.", n);
+                }
+                Err(_) => break, // Broadcaster closed
+            },
+            _ = persist.tick() => {
+                let snapshot = store.read().await.clone();
+                if let Err(e) = snapshot.save(&path).await {
+                    error!("Failed to persist telemetry store: {:?}", e);
+                }
+            }
+        }
+    }
+
+    if let Err(e) = store.read().await.save(&path).await {
+        error!("Failed to persist telemetry store: {:?}", e);
+    }
+}
+
+// --- Tests ---
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn small_store() -> TimeSeriesStore {
+        TimeSeriesStore::new(StoreConfig {
+            raw_capacity: 10,
+            tiers: vec![
+                TierConfig { resolution_ms: 10_000, capacity: 6 },
+                TierConfig { resolution_ms: 60_000, capacity: 10 },
+            ],
+        })
+    }
+
+    #[test]
+    fn test_recent_queries_use_raw_samples() {
+        let mut store = small_store();
+        for i in 0..5 {
+            store.insert("nozzle_temp", i * 1000, 200.0 + i as f32);
+        }
+        let points = store.query("nozzle_temp", 1000, 3000).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0], DataPoint { time: 1000, min: 201.0, max: 201.0, avg: 201.0 });
+        assert!(store.query("unknown", 0, 1000).is_none());
+    }
+
+    #[test]
+    fn test_young_stores_answer_from_raw_samples() {
+        // Five minutes of data, queried over the last hour.
+        let mut store = TimeSeriesStore::default();
+        let now = 3_600_000;
+        for i in 0..300 {
+            store.insert("nozzle_temp", now - 299_000 + i * 1000, i as f32);
+        }
+        let points = store.query("nozzle_temp", 0, now).unwrap();
+        assert_eq!(points.len(), 300);
+        assert_eq!(points.last().unwrap().avg, 299.0);
+        assert_eq!(store.resample_seconds("nozzle_temp", now, 2), vec![298.0, 299.0]);
+    }
+
+    #[test]
+    fn test_older_queries_fall_back_to_downsampled_tiers() {
+        let mut store = small_store();
+        // 2 minutes at 1 Hz: raw ring keeps only the last 10 seconds.
+        for i in 0..120 {
+            store.insert("bed_temp", i * 1000, i as f32);
+        }
+
+        // 10s tier only holds the last ~60 s, so a query from t=0 lands on the 60s tier.
+        let points = store.query("bed_temp", 0, 120_000).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0], DataPoint { time: 0, min: 0.0, max: 59.0, avg: 29.5 });
+
+        let points = store.query("bed_temp", 70_000, 120_000).unwrap();
+        assert_eq!(points[0].time, 70_000);
+        assert_eq!(points[0].min, 70.0);
+        assert_eq!(points[0].max, 79.0);
+    }
+
+    #[test]
+    fn test_record_telemetry_and_resample() {
+        let mut store = small_store();
+        store.record_telemetry(0, &json!({ "nozzle_temp": 20.0, "bed_temp": 25.0, "label": "x" }));
+        store.record_telemetry(
+            2500,
+            &json!({ "nozzle_temp": 30.0, "bed_temp": 25.0, "fan_rpm": 4200, "link_health": { "rtt_us": 800 } }),
+        );
+        assert_eq!(store.metrics(), vec!["bed_temp", "nozzle_temp"]);
+
+        let values = store.resample_seconds("nozzle_temp", 4000, 4);
+        assert_eq!(values, vec![20.0, 20.0, 30.0, 30.0]);
+    }
+
+    #[tokio::test]
+    async fn test_persist_roundtrip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("r_klipp_ts_{}.json", std::process::id()));
+        let mut store = small_store();
+        store.insert("nozzle_temp", 1000, 210.0);
+        store.save(&path).await?;
+
+        let loaded = TimeSeriesStore::load(&path).await?;
+        assert_eq!(loaded.query("nozzle_temp", 0, 2000), store.query("nozzle_temp", 0, 2000));
+        fs::remove_file(&path).await?;
+        Ok(())
+    }
+}